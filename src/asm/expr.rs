@@ -0,0 +1,236 @@
+// Assembler expressions: numbers, symbols, `$` for the current address,
+// C operators and parentheses. Evaluation is deferred to the second pass
+// so that labels may be used before they are defined.
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Symbol(String),
+    Here,
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Op(char),
+    Open,
+    Close,
+}
+
+// Binding strength of the binary operators; `<` and `>` stand for << and >>.
+fn precedence(op: char) -> Option<u8> {
+    match op {
+        '|' => Some(1),
+        '^' => Some(2),
+        '&' => Some(3),
+        '<' | '>' => Some(4),
+        '+' | '-' => Some(5),
+        '*' | '/' | '%' => Some(6),
+        _ => None,
+    }
+}
+
+// Errors carry the character offset they were found at.
+pub fn parse(text: &str) -> Result<Expr, (usize, String)> {
+    let tokens = tokenize(text)?;
+    let mut pos = 0;
+    let expr = binary(&tokens, &mut pos, 0)?;
+    match tokens.get(pos) {
+        None => Ok(expr),
+        Some(&(at, ref token)) => Err((at, format!("Unexpected {:?}", token))),
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, (usize, String)> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let word = |mut j: usize| {
+            while j < chars.len() && (chars[j].is_alphanumeric() || chars[j] == '_' || chars[j] == '.') {
+                j += 1;
+            }
+            j
+        };
+        let token = if c.is_digit(10) {
+            i = word(i);
+            let text: String = chars[start..i].iter().collect();
+            Token::Number(number(&text).map_err(|e| (start, e))?)
+        } else if c == '$' || c == '%' && i + 1 < chars.len() && (chars[i + 1] == '0' || chars[i + 1] == '1') &&
+                  (tokens.is_empty() || is_operator(&tokens)) {
+            i = word(i + 1);
+            let text: String = chars[start..i].iter().collect();
+            match text.as_str() {
+                "$" => Token::Symbol("$".to_owned()),
+                _ => Token::Number(number(&text).map_err(|e| (start, e))?),
+            }
+        } else if c.is_alphabetic() || c == '_' || c == '.' {
+            i = word(i);
+            Token::Symbol(chars[start..i].iter().collect())
+        } else if c == '(' {
+            i += 1;
+            Token::Open
+        } else if c == ')' {
+            i += 1;
+            Token::Close
+        } else if (c == '<' || c == '>') && i + 1 < chars.len() && chars[i + 1] == c {
+            i += 2;
+            Token::Op(c)
+        } else if "+-*/%&|^~".contains(c) {
+            i += 1;
+            Token::Op(c)
+        } else {
+            return Err((start, format!("Unexpected character '{}'", c)));
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+// True when the last token leaves us expecting an operand.
+fn is_operator(tokens: &[(usize, Token)]) -> bool {
+    match tokens.last() {
+        Some(&(_, Token::Op(_))) | Some(&(_, Token::Open)) => true,
+        _ => false,
+    }
+}
+
+// 0x1F, $1F, 0b101, %101 and decimal.
+pub fn number(text: &str) -> Result<i64, String> {
+    let lower = text.to_lowercase().replace('_', "");
+    let result = if lower.starts_with("0x") {
+        i64::from_str_radix(&lower[2..], 16)
+    } else if lower.starts_with('$') {
+        i64::from_str_radix(&lower[1..], 16)
+    } else if lower.starts_with("0b") {
+        i64::from_str_radix(&lower[2..], 2)
+    } else if lower.starts_with('%') {
+        i64::from_str_radix(&lower[1..], 2)
+    } else {
+        lower.parse()
+    };
+    result.map_err(|_| format!("Bad number: {}", text))
+}
+
+// A literal with an optional leading minus, for operands that are not parsed
+// as whole expressions.
+pub fn signed_number(text: &str) -> Result<i64, String> {
+    match text.starts_with('-') {
+        true => number(&text[1..]).map(|n| -n),
+        false => number(text),
+    }
+}
+
+fn binary(tokens: &[(usize, Token)], pos: &mut usize, min: u8) -> Result<Expr, (usize, String)> {
+    let mut left = unary(tokens, pos)?;
+    loop {
+        let op = match tokens.get(*pos) {
+            Some(&(_, Token::Op(op))) => op,
+            _ => break,
+        };
+        let level = match precedence(op) {
+            Some(level) if level >= min => level,
+            _ => break,
+        };
+        *pos += 1;
+        let right = binary(tokens, pos, level + 1)?;
+        left = Expr::Binary(op, Box::new(left), Box::new(right));
+    }
+    Ok(left)
+}
+
+fn unary(tokens: &[(usize, Token)], pos: &mut usize) -> Result<Expr, (usize, String)> {
+    let end = tokens.last().map(|&(at, _)| at + 1).unwrap_or(0);
+    let (at, token) = match tokens.get(*pos) {
+        Some(&(at, ref token)) => (at, token.clone()),
+        None => return Err((end, "Expected a value".to_owned())),
+    };
+    *pos += 1;
+    match token {
+        Token::Number(n) => Ok(Expr::Number(n)),
+        Token::Symbol(ref name) if name == "$" => Ok(Expr::Here),
+        Token::Symbol(name) => Ok(Expr::Symbol(name)),
+        Token::Op('-') => Ok(Expr::Neg(Box::new(unary(tokens, pos)?))),
+        Token::Op('~') => Ok(Expr::Not(Box::new(unary(tokens, pos)?))),
+        Token::Op('+') => unary(tokens, pos),
+        Token::Open => {
+            let inner = binary(tokens, pos, 0)?;
+            match tokens.get(*pos) {
+                Some(&(_, Token::Close)) => {
+                    *pos += 1;
+                    Ok(inner)
+                },
+                _ => Err((at, "Unclosed (".to_owned())),
+            }
+        },
+        token => Err((at, format!("Unexpected {:?}", token))),
+    }
+}
+
+impl Expr {
+    // `lookup` resolves symbols; `here` is the address of the current line.
+    pub fn eval<F>(&self, here: u16, lookup: &F) -> Result<i64, String>
+        where F: Fn(&str) -> Result<i64, String>
+    {
+        Ok(match *self {
+            Expr::Number(n) => n,
+            Expr::Symbol(ref name) => lookup(name)?,
+            Expr::Here => here as i64,
+            Expr::Neg(ref e) => -e.eval(here, lookup)?,
+            Expr::Not(ref e) => !e.eval(here, lookup)?,
+            Expr::Binary(op, ref l, ref r) => {
+                let l = l.eval(here, lookup)?;
+                let r = r.eval(here, lookup)?;
+                match op {
+                    '|' => l | r,
+                    '^' => l ^ r,
+                    '&' => l & r,
+                    '<' => l << (r & 63),
+                    '>' => l >> (r & 63),
+                    '+' => l + r,
+                    '-' => l - r,
+                    '*' => l * r,
+                    '/' | '%' if r == 0 => return Err("Division by zero".to_owned()),
+                    '/' => l / r,
+                    _ => l % r,
+                }
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+fn eval(text: &str) -> i64 {
+    let mut symbols = ::std::collections::HashMap::new();
+    symbols.insert("width".to_owned(), 64);
+    symbols.insert("sprite.end".to_owned(), 0x30A);
+    parse(text).unwrap().eval(0x200, &|name: &str| symbols.get(name).cloned().ok_or(name.to_owned())).unwrap()
+}
+
+#[test]
+fn test_assembler_expressions() {
+    assert_eq!(eval("1 + 2 * 3"), 7);
+    assert_eq!(eval("(1 + 2) * 3"), 9);
+    assert_eq!(eval("width / 2 - 4"), 28);
+    assert_eq!(eval("sprite.end - $"), 0x10A);
+    assert_eq!(eval("1 << 4 | 0b1"), 17);
+    assert_eq!(eval("%1010 + $10"), 26);
+    assert_eq!(eval("10 % 4"), 2);
+    assert_eq!(eval("-1 & 0xFF"), 0xFF);
+    assert_eq!(eval("~0 & 0xF"), 0xF);
+    assert_eq!(parse("1 +").unwrap_err().0, 3);
+    assert_eq!(parse("(1 + 2").unwrap_err().0, 0);
+    assert_eq!(parse("2 @ 3").unwrap_err().0, 2);
+    assert!(parse("0xZZ").is_err());
+}
@@ -0,0 +1,667 @@
+// A two-pass assembler for Cowgod's syntax, the same one `disasm` prints:
+//
+//   ; comments run to the end of the line
+//   WIDTH = 64                  ; constants, also `WIDTH equ 64`
+//   start:  LD V0, WIDTH / 2 - 4
+//           LD I, digit
+//           DRW V0, V1, digit.end - digit
+//           JP $                ; $ is the address of the current line
+//   digit:  sprite "####...."   ; one byte per row, # or 1 for a set pixel
+//           db 0x90, %10010000, "text"
+//           dw 0x1234, start
+//   digit.end:
+//           include "more.asm"  ; relative to the including file
+//           org 0x300           ; pads with zeros up to the address
+//
+// The first pass sizes every line and places the labels, the second
+// evaluates operands, so labels can be used before they are defined.
+pub mod expr;
+pub mod octo;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use disasm::ORIGIN;
+use opcodes::{self, Op};
+use self::expr::Expr;
+
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+    }
+}
+
+pub struct Program {
+    pub binary: Vec<u8>,
+    // Label addresses; constants are not included.
+    pub symbols: BTreeMap<String, u16>,
+    // Instruction sets beyond CHIP-8 the program uses, e.g. "SUPER-CHIP".
+    pub extensions: BTreeSet<&'static str>,
+    // Source file and line of each instruction, by address.
+    pub lines: BTreeMap<u16, (String, usize)>,
+}
+
+impl Program {
+    // One `0x20A name` line per label, in address order.
+    pub fn symbol_file(&self) -> String {
+        let mut symbols: Vec<(&u16, &String)> = self.symbols.iter().map(|(name, address)| (address, name)).collect();
+        symbols.sort();
+        symbols.iter().map(|&(address, name)| format!("0x{:03X} {}\n", address, name)).collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Location {
+    file: String,
+    line: usize,
+}
+
+// Registers and the other named operands, or an expression that is
+// evaluated in the second pass.
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Named(opcodes::Operand),
+    Value(Expr),
+}
+
+enum Item {
+    Instruction(String, Vec<(usize, Operand)>),
+    Bytes(Vec<(usize, Expr)>),
+    Words(Vec<(usize, Expr)>),
+    Raw(Vec<u8>),
+}
+
+impl Item {
+    fn size(&self) -> usize {
+        match *self {
+            Item::Instruction(..) => 2,
+            Item::Bytes(ref values) => values.len(),
+            Item::Words(ref values) => values.len() * 2,
+            Item::Raw(ref bytes) => bytes.len(),
+        }
+    }
+}
+
+struct Statement {
+    location: Location,
+    column: usize,
+    address: u16,
+    item: Item,
+}
+
+enum Symbol {
+    Label(u16),
+    // The expression and the address it was defined at, for `$`.
+    Constant(Expr, u16),
+}
+
+struct Assembler {
+    statements: Vec<Statement>,
+    symbols: HashMap<String, (Location, usize, Symbol)>,
+    errors: Vec<Error>,
+    address: usize,
+    depth: usize,
+}
+
+pub fn assemble_file(path: &Path) -> Result<Program, Vec<Error>> {
+    match read_source(path) {
+        Ok(source) => assemble(&path.to_string_lossy(), &source),
+        Err(message) => Err(vec![Error { file: path.to_string_lossy().into_owned(), line: 0, column: 0, message: message }]),
+    }
+}
+
+// `name` is used in error messages and to resolve includes.
+pub fn assemble(name: &str, source: &str) -> Result<Program, Vec<Error>> {
+    let mut assembler = Assembler {
+        statements: Vec::new(),
+        symbols: HashMap::new(),
+        errors: Vec::new(),
+        address: ORIGIN as usize,
+        depth: 0,
+    };
+    assembler.source(name, source);
+    let program = assembler.emit();
+    match assembler.errors.is_empty() {
+        true => Ok(program),
+        false => Err(assembler.errors),
+    }
+}
+
+fn read_source(path: &Path) -> Result<String, String> {
+    let mut source = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut source))
+        .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    Ok(source)
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' || c == '.' => {},
+        _ => return false,
+    }
+    chars.all(|c| c.is_alphanumeric() || c == '_' || c == '.')
+}
+
+// Names that are operands and so cannot be used as symbols.
+fn is_reserved(name: &str) -> bool {
+    opcodes::Operand::named(name).is_some()
+}
+
+fn register(text: &str) -> Option<u16> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v'), Some(x), None) | (Some('V'), Some(x), None) => x.to_digit(16).map(|x| x as u16),
+        _ => None,
+    }
+}
+
+// Splits at commas outside quotes and parentheses, keeping each piece's
+// offset into `text`.
+fn split_operands(text: &str) -> Vec<(usize, &str)> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    let mut quoted = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                pieces.push((start, &text[start..i]));
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    pieces.push((start, &text[start..]));
+    pieces.into_iter()
+        .map(|(at, piece)| (at + piece.len() - piece.trim_start().len(), piece.trim()))
+        .filter(|&(_, piece)| !piece.is_empty())
+        .collect()
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => {},
+        }
+    }
+    line
+}
+
+fn string(text: &str) -> Option<&str> {
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        Some(&text[1..text.len() - 1])
+    } else {
+        None
+    }
+}
+
+// "##..##.." or "11001100": one byte, leftmost pixel in the top bit.
+fn sprite_row(text: &str) -> Result<u8, String> {
+    if text.chars().count() > 8 {
+        return Err(format!("Sprite rows are 8 pixels wide: \"{}\"", text));
+    }
+    let mut byte = 0;
+    for (bit, c) in text.chars().enumerate() {
+        match c {
+            '#' | '1' | 'X' | 'x' => byte |= 0x80 >> bit,
+            '.' | '0' | '_' | ' ' => {},
+            _ => return Err(format!("Bad sprite pixel '{}'", c)),
+        }
+    }
+    Ok(byte)
+}
+
+impl Assembler {
+    fn error(&mut self, location: &Location, column: usize, message: String) {
+        self.errors.push(Error {
+            file: location.file.clone(),
+            line: location.line,
+            column: column,
+            message: message,
+        });
+    }
+
+    fn define(&mut self, location: &Location, column: usize, name: &str, symbol: Symbol) {
+        if is_reserved(name) {
+            return self.error(location, column, format!("{} is a reserved name", name));
+        }
+        if let Some(&(ref first, _, _)) = self.symbols.get(name) {
+            let message = format!("{} is already defined at {}:{}", name, first.file, first.line);
+            self.error(location, column, message);
+            return;
+        }
+        self.symbols.insert(name.to_owned(), (location.clone(), column, symbol));
+    }
+
+    fn source(&mut self, name: &str, source: &str) {
+        for (number, line) in source.lines().enumerate() {
+            let location = Location { file: name.to_owned(), line: number + 1 };
+            self.line(&location, line);
+        }
+    }
+
+    // Columns are 1-based character positions in `raw`.
+    fn line(&mut self, location: &Location, raw: &str) {
+        let column = |at: usize| raw[..at].chars().count() + 1;
+        let text = strip_comment(raw);
+        let mut at = text.len() - text.trim_start().len();
+
+        // Labels: `name:` at the start of the line, as many as given.
+        loop {
+            let rest = &text[at..];
+            let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.')).unwrap_or(rest.len());
+            if end == 0 || !rest[end..].starts_with(':') || !is_identifier(&rest[..end]) {
+                break;
+            }
+            let address = self.address as u16;
+            self.define(location, column(at), &rest[..end], Symbol::Label(address));
+            at += end + 1;
+            at += text[at..].len() - text[at..].trim_start().len();
+        }
+
+        let rest = &text[at..];
+        if rest.trim().is_empty() {
+            return;
+        }
+        let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let word = &rest[..word_end];
+        let args_at = at + word_end + (rest[word_end..].len() - rest[word_end..].trim_start().len());
+        let args = text[args_at..].trim_end();
+
+        // Constants: `NAME = expr` or `NAME equ expr`.
+        let (constant, value_at) = if args.starts_with('=') && !args.starts_with("==") {
+            (true, args_at + 1)
+        } else if args.len() > 3 && args[..3].eq_ignore_ascii_case("equ") &&
+                  args[3..].starts_with(char::is_whitespace) {
+            (true, args_at + 3)
+        } else {
+            (false, 0)
+        };
+        if constant {
+            if !is_identifier(word) {
+                return self.error(location, column(at), format!("Bad constant name: {}", word));
+            }
+            match self.expression(location, &text[value_at..], value_at, raw) {
+                Some(expr) => {
+                    let symbol = Symbol::Constant(expr, self.address as u16);
+                    self.define(location, column(at), word, symbol)
+                },
+                None => {},
+            }
+            return;
+        }
+
+        let operands = split_operands(args);
+        let item = match word.to_lowercase().as_str() {
+            "db" | "byte" => {
+                let mut values = Vec::new();
+                for &(offset, operand) in operands.iter() {
+                    match string(operand) {
+                        Some(text) => {
+                            for byte in text.bytes() {
+                                values.push((column(args_at + offset), Expr::Number(byte as i64)));
+                            }
+                        },
+                        None => match self.expression(location, operand, args_at + offset, raw) {
+                            Some(expr) => values.push((column(args_at + offset), expr)),
+                            None => return,
+                        },
+                    }
+                }
+                Item::Bytes(values)
+            },
+            "dw" | "word" => {
+                let mut values = Vec::new();
+                for &(offset, operand) in operands.iter() {
+                    match self.expression(location, operand, args_at + offset, raw) {
+                        Some(expr) => values.push((column(args_at + offset), expr)),
+                        None => return,
+                    }
+                }
+                Item::Words(values)
+            },
+            "sprite" => {
+                let mut bytes = Vec::new();
+                for &(offset, operand) in operands.iter() {
+                    let row = match string(operand) {
+                        Some(row) => sprite_row(row),
+                        None => Err("Sprite rows are quoted strings".to_owned()),
+                    };
+                    match row {
+                        Ok(byte) => bytes.push(byte),
+                        Err(message) => return self.error(location, column(args_at + offset), message),
+                    }
+                }
+                Item::Raw(bytes)
+            },
+            "include" => {
+                let file = match operands.first().and_then(|&(_, operand)| string(operand)) {
+                    Some(file) if operands.len() == 1 => file,
+                    _ => return self.error(location, column(args_at), "Usage: include \"file\"".to_owned()),
+                };
+                return self.include(location, column(args_at), file);
+            },
+            "org" => {
+                let target = match operands.first() {
+                    Some(&(offset, operand)) if operands.len() == 1 => {
+                        match self.expression(location, operand, args_at + offset, raw) {
+                            // Forward references cannot be sized in the first pass.
+                            Some(expr) => expr.eval(self.address as u16, &|name: &str| self.constant(name, 0)),
+                            None => return,
+                        }
+                    },
+                    _ => Err("Usage: org <address>".to_owned()),
+                };
+                match target {
+                    Ok(target) if target >= self.address as i64 && target <= 0x1000 => {
+                        Item::Raw(vec![0; target as usize - self.address])
+                    },
+                    Ok(target) => return self.error(location, column(args_at),
+                        format!("org 0x{:X} is behind the current address 0x{:03X} or past 0xFFF", target, self.address)),
+                    Err(message) => return self.error(location, column(args_at), message),
+                }
+            },
+            _ => {
+                let mut parsed = Vec::new();
+                for &(offset, operand) in operands.iter() {
+                    match self.operand(location, operand, args_at + offset, raw) {
+                        Some(operand) => parsed.push((column(args_at + offset), operand)),
+                        None => return,
+                    }
+                }
+                Item::Instruction(word.to_uppercase(), parsed)
+            },
+        };
+        let size = item.size();
+        self.statements.push(Statement {
+            location: location.clone(),
+            column: column(at),
+            address: self.address as u16,
+            item: item,
+        });
+        self.address += size;
+    }
+
+    fn include(&mut self, location: &Location, column: usize, file: &str) {
+        if self.depth == MAX_INCLUDE_DEPTH {
+            return self.error(location, column, format!("Includes nested too deeply at {}", file));
+        }
+        let path = match Path::new(&location.file).parent() {
+            Some(dir) => dir.join(file),
+            None => PathBuf::from(file),
+        };
+        match read_source(&path) {
+            Ok(source) => {
+                self.depth += 1;
+                self.source(&path.to_string_lossy(), &source);
+                self.depth -= 1;
+            },
+            Err(message) => self.error(location, column, message),
+        }
+    }
+
+    // `at` is the byte offset of `text` within `raw`, for error columns.
+    fn expression(&mut self, location: &Location, text: &str, at: usize, raw: &str) -> Option<Expr> {
+        match expr::parse(text) {
+            Ok(expr) => Some(expr),
+            Err((offset, message)) => {
+                let leading = text.len() - text.trim_start().len();
+                let column = raw[..at].chars().count() + text[..leading].chars().count() + offset + 1;
+                self.error(location, column, message);
+                None
+            },
+        }
+    }
+
+    fn operand(&mut self, location: &Location, text: &str, at: usize, raw: &str) -> Option<Operand> {
+        match opcodes::Operand::named(text.trim()) {
+            Some(operand) => Some(Operand::Named(operand)),
+            None => Some(Operand::Value(self.expression(location, text, at, raw)?)),
+        }
+    }
+
+    fn constant(&self, name: &str, depth: usize) -> Result<i64, String> {
+        if depth > self.symbols.len() {
+            return Err(format!("{} is defined in terms of itself", name));
+        }
+        match self.symbols.get(name) {
+            Some(&(_, _, Symbol::Label(address))) => Ok(address as i64),
+            Some(&(_, _, Symbol::Constant(ref expr, here))) => {
+                expr.eval(here, &|name: &str| self.constant(name, depth + 1))
+            },
+            None => Err(format!("Undefined symbol: {}", name)),
+        }
+    }
+
+    fn value(&self, expr: &Expr, here: u16, min: i64, max: i64) -> Result<u16, String> {
+        let value = expr.eval(here, &|name: &str| self.constant(name, 0))?;
+        if value < min || value > max {
+            return Err(format!("{} is out of range {}..{}", value, min, max));
+        }
+        Ok((value & 0xFFFF) as u16)
+    }
+
+    fn emit(&mut self) -> Program {
+        let mut binary = vec![0; self.address.saturating_sub(ORIGIN as usize)];
+        let mut errors = Vec::new();
+        for statement in self.statements.iter() {
+            let offset = (statement.address - ORIGIN) as usize;
+            let here = statement.address;
+            let result = match statement.item {
+                Item::Instruction(ref mnemonic, ref operands) => {
+                    self.encode(mnemonic, operands, here).map(|word| vec![(word >> 8) as u8, word as u8])
+                },
+                Item::Bytes(ref values) => values.iter()
+                    .map(|&(column, ref expr)| {
+                        self.value(expr, here, -128, 0xFF).map(|v| v as u8).map_err(|e| (column, e))
+                    })
+                    .collect(),
+                Item::Words(ref values) => {
+                    let mut bytes = Vec::new();
+                    for &(column, ref expr) in values.iter() {
+                        let word = self.value(expr, here, -0x8000, 0xFFFF).map_err(|e| (column, e));
+                        match word {
+                            Ok(word) => bytes.extend_from_slice(&[(word >> 8) as u8, word as u8]),
+                            Err(error) => push_error(&mut errors, statement, error),
+                        }
+                    }
+                    Ok(bytes)
+                },
+                Item::Raw(ref bytes) => Ok(bytes.clone()),
+            };
+            match result {
+                Ok(bytes) => binary[offset..offset + bytes.len()].copy_from_slice(&bytes),
+                Err(error) => push_error(&mut errors, statement, error),
+            }
+        }
+        self.errors.extend(errors);
+        if self.address > 0x1000 {
+            let location = Location { file: String::new(), line: 0 };
+            self.error(&location, 0, format!("Program ends at 0x{:X}, past the end of memory", self.address));
+        }
+        let symbols = self.symbols.iter()
+            .filter_map(|(name, &(_, _, ref symbol))| match *symbol {
+                Symbol::Label(address) => Some((name.clone(), address)),
+                Symbol::Constant(..) => None,
+            })
+            .collect();
+        let lines = self.statements.iter()
+            .filter(|statement| match statement.item {
+                Item::Instruction(..) => true,
+                _ => false,
+            })
+            .map(|statement| (statement.address, (statement.location.file.clone(), statement.location.line)))
+            .collect();
+        Program {
+            binary: binary,
+            symbols: symbols,
+            extensions: BTreeSet::new(),
+            lines: lines,
+        }
+    }
+
+    // Errors carry the column of the offending operand.
+    fn encode(&self, mnemonic: &str, operands: &[(usize, Operand)], here: u16) -> Result<u16, (usize, String)> {
+        let mut values = Vec::new();
+        for &(column, ref operand) in operands.iter() {
+            values.push(match *operand {
+                Operand::Named(operand) => operand,
+                Operand::Value(ref expr) => {
+                    let value = expr.eval(here, &|name: &str| self.constant(name, 0)).map_err(|e| (column, e))?;
+                    opcodes::Operand::Value(value)
+                },
+            });
+        }
+        Op::assemble(mnemonic, &values)
+            .map(|op| op.encode())
+            .map_err(|(index, message)| (index.map(|i| operands[i].0).unwrap_or(0), message))
+    }
+}
+
+// Column 0 stands for the statement's own column.
+fn push_error(errors: &mut Vec<Error>, statement: &Statement, (column, message): (usize, String)) {
+    errors.push(Error {
+        file: statement.location.file.clone(),
+        line: statement.location.line,
+        column: if column == 0 { statement.column } else { column },
+        message: message,
+    });
+}
+
+#[cfg(test)]
+fn binary(source: &str) -> Vec<u8> {
+    match assemble("test.asm", source) {
+        Ok(program) => program.binary,
+        Err(errors) => panic!("{:?}", errors),
+    }
+}
+
+#[cfg(test)]
+fn errors(source: &str) -> Vec<String> {
+    assemble("test.asm", source).err().unwrap().iter().map(|e| e.to_string()).collect()
+}
+
+#[test]
+fn test_assemble_program() {
+    let source = "\
+        ; draws a digit\n\
+        WIDTH = 64\n\
+        HALF equ WIDTH / 2\n\
+        start:  CLS\n\
+                LD V0, HALF - 4     ; centred\n\
+                LD I, digit\n\
+                DRW V0, V1, digit.end - digit\n\
+                JP $\n\
+        digit:  sprite \"####....\", \"#..#....\"\n\
+                db %11110000, 2, \"A\"\n\
+        digit.end:\n\
+                dw start, 0xFFFF\n";
+    let program = assemble("test.asm", source).unwrap();
+    assert_eq!(program.binary, vec![
+        0x00, 0xE0, 0x60, 0x1C, 0xA2, 0x0A, 0xD0, 0x15, 0x12, 0x08,
+        0xF0, 0x90, 0xF0, 0x02, 0x41, 0x02, 0x00, 0xFF, 0xFF,
+    ]);
+    assert_eq!(program.symbols.get("digit"), Some(&0x20A));
+    assert_eq!(program.symbol_file(), "0x200 start\n0x20A digit\n0x20F digit.end\n");
+    let lines: Vec<(u16, usize)> = program.lines.iter().map(|(&address, &(_, line))| (address, line)).collect();
+    assert_eq!(lines, vec![(0x200, 4), (0x202, 5), (0x204, 6), (0x206, 7), (0x208, 8)]);
+}
+
+#[test]
+fn test_every_instruction_form() {
+    let cases = [
+        ("SYS 0x123", 0x0123), ("JP V0, 0x300", 0xB300), ("CALL 0x345", 0x2345),
+        ("SE VA, 0x10", 0x3A10), ("SNE VA, -1", 0x4AFF), ("SE V1, V2", 0x5120),
+        ("add v3, 1", 0x7301), ("LD V1, V2", 0x8120), ("OR V1, V2", 0x8121),
+        ("AND V1, V2", 0x8122), ("XOR V1, V2", 0x8123), ("ADD V1, V2", 0x8124),
+        ("SUB V1, V2", 0x8125), ("SHR V1", 0x8116), ("SUBN V1, V2", 0x8127),
+        ("SHL V1, V2", 0x812E), ("SNE V1, V2", 0x9120), ("RND V3, 0x0F", 0xC30F),
+        ("SKP V5", 0xE59E), ("SKNP V5", 0xE5A1), ("LD V4, DT", 0xF407),
+        ("LD V4, K", 0xF40A), ("LD DT, V4", 0xF415), ("LD ST, V4", 0xF418),
+        ("ADD I, V4", 0xF41E), ("LD F, V4", 0xF429), ("LD B, V4", 0xF433),
+        ("LD [I], V4", 0xF455), ("LD V4, [ I ]", 0xF465),
+    ];
+    for &(text, value) in cases.iter() {
+        assert_eq!(binary(text), vec![(value >> 8) as u8, value as u8], "{}", text);
+    }
+}
+
+#[test]
+fn test_org_and_include() {
+    let dir = ::std::env::temp_dir().join("chip8_asm_include");
+    ::std::fs::create_dir_all(&dir).unwrap();
+    ::std::fs::write(dir.join("font.asm"), "font: db 1, 2\n").unwrap();
+    let main = dir.join("main.asm");
+    ::std::fs::write(&main, "LD I, font\norg 0x204\ninclude \"font.asm\"\n").unwrap();
+    let program = assemble_file(&main).unwrap();
+    assert_eq!(program.binary, vec![0xA2, 0x04, 0x00, 0x00, 0x01, 0x02]);
+
+    ::std::fs::write(&main, "include \"main.asm\"\n").unwrap();
+    let errors = assemble_file(&main).err().unwrap();
+    assert!(errors[0].message.contains("nested too deeply"));
+    ::std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_errors_have_line_and_column() {
+    assert_eq!(errors("CLS\n  LD V1, 0x100"), vec!["test.asm:2:10: 256 is out of range -128..255"]);
+    assert_eq!(errors("JP nowhere"), vec!["test.asm:1:4: Undefined symbol: nowhere"]);
+    assert_eq!(errors("  FROB V1"), vec!["test.asm:1:3: Unknown instruction: FROB"]);
+    assert_eq!(errors("DRW V1, V2"), vec!["test.asm:1:5: DRW does not take operands V,V"]);
+    assert_eq!(errors("LD V1, 2 +"), vec!["test.asm:1:11: Expected a value"]);
+    assert_eq!(errors("a: CLS\na: CLS"), vec!["test.asm:2:1: a is already defined at test.asm:1"]);
+    assert_eq!(errors("ONE = TWO\nTWO = ONE\nLD V0, ONE"), vec!["test.asm:3:8: TWO is defined in terms of itself"]);
+    assert_eq!(errors("B = 1"), vec!["test.asm:1:1: B is a reserved name"]);
+    assert_eq!(errors("sprite \"#########\"").len(), 1);
+    assert_eq!(errors("CLS\nCLS\norg 0x200"), vec!["test.asm:3:5: org 0x200 is behind the current address 0x204 or past 0xFFF"]);
+}
+
+#[test]
+fn test_round_trip_through_disassembler() {
+    use disasm::{disassemble, listing, Labels, Syntax};
+
+    // The decoder ignores unused bits (0x01E0 is CLS), so compare text.
+    let labels = Labels::new();
+    for value in 0..0x10000u32 {
+        let value = value as u16;
+        if Op::decode(value).is_err() {
+            continue;
+        }
+        let text = disassemble(value, Syntax::Cowgod, &labels);
+        let bytes = binary(&text);
+        let word = (bytes[0] as u16) << 8 | bytes[1] as u16;
+        assert_eq!(disassemble(word, Syntax::Cowgod, &labels), text);
+    }
+
+    // Listings keep the address and raw word in front; labels stand alone.
+    let rom = binary("start: CALL sub\nloop: JP loop\ndw 0xFFFF\nsub: LD I, sub\nRET\n");
+    let text: String = listing(&rom, Syntax::Cowgod).lines()
+        .map(|line| match line.starts_with("0x") {
+            true => format!("{}\n", &line[13..]),
+            false => format!("{}\n", line),
+        })
+        .collect();
+    assert!(text.contains("CALL sub_206"));
+    assert_eq!(binary(&text), rom);
+}
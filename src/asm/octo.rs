@@ -0,0 +1,926 @@
+// Compiles Octo source (.8o). Supported:
+//
+//   : label   :const NAME value   :alias name vX   :calc NAME { expr }
+//   :macro name args { body }     :next label      :unpack N label
+//   :org addr   :byte value       :call label      bare label names call
+//   loop ... while cond ... again       if cond then statement
+//   if cond begin ... else ... end      comparisons < > <= >= via vF
+//
+// plus the SUPER-CHIP and XO-CHIP statements (hires, scroll-*, bighex,
+// saveflags, i := long, plane, audio, pitch, save vX - vY). The emulator
+// only executes CHIP-8, so programs using those are flagged in
+// `Program::extensions`.
+//
+// Tokens are separated by whitespace, as in Octo, so `:calc` parentheses
+// need spaces around them. `:calc` evaluates right to left without
+// operator precedence: `{ 2 * 3 + 1 }` is 8.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+use disasm::ORIGIN;
+use super::{expr, read_source, register, Error, Program};
+
+
+const MAX_EXPANSIONS: usize = 10000;
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+enum Fixup {
+    // The low 12 bits of an instruction.
+    Address,
+    // A whole word, after `i := long`.
+    Long,
+    // `v0 := N << 4 | label >> 8` and `v1 := label` as two words.
+    Unpack(u16),
+}
+
+enum Control {
+    // Start address and the addresses of the jumps out from `while`.
+    Loop(u16, Vec<u16>),
+    // Address of the jump over the `if` body or the `else` body.
+    If(u16),
+    Else(u16),
+}
+
+enum Side {
+    Reg(u16),
+    Byte(u16),
+}
+
+// Instructions that skip the next one when the condition holds and when it
+// does not, and any setup they need first.
+struct Condition {
+    setup: Vec<u16>,
+    skip_if: u16,
+    skip_unless: u16,
+}
+
+struct Compiler {
+    file: String,
+    // Reversed, so that the next token is last.
+    tokens: Vec<Token>,
+    last: Token,
+    rom: Vec<u8>,
+    here: usize,
+    labels: BTreeMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u16>,
+    macros: HashMap<String, (Vec<String>, Vec<Token>)>,
+    fixups: Vec<(usize, Fixup, Token)>,
+    controls: Vec<(Control, Token)>,
+    next: Option<Token>,
+    extensions: BTreeSet<&'static str>,
+    expansions: usize,
+    lines: BTreeMap<u16, (String, usize)>,
+}
+
+pub fn compile_file(path: &Path) -> Result<Program, Vec<Error>> {
+    match read_source(path) {
+        Ok(source) => compile(&path.to_string_lossy(), &source),
+        Err(message) => Err(vec![Error { file: path.to_string_lossy().into_owned(), line: 0, column: 0, message: message }]),
+    }
+}
+
+// Octo stops at the first error, so there is at most one.
+pub fn compile(name: &str, source: &str) -> Result<Program, Vec<Error>> {
+    let mut compiler = Compiler {
+        file: name.to_owned(),
+        tokens: tokenize(source),
+        last: Token { text: String::new(), line: 1, column: 1 },
+        rom: Vec::new(),
+        here: ORIGIN as usize,
+        labels: BTreeMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        controls: Vec::new(),
+        next: None,
+        extensions: BTreeSet::new(),
+        expansions: 0,
+        lines: BTreeMap::new(),
+    };
+    match compiler.run() {
+        Ok(()) => Ok(Program {
+            binary: compiler.rom,
+            symbols: compiler.labels,
+            extensions: compiler.extensions,
+            lines: compiler.lines,
+        }),
+        Err(error) => Err(vec![error]),
+    }
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (number, line) in source.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(pos) => &line[..pos],
+            None => line,
+        };
+        let mut start = None;
+        for (column, c) in line.chars().chain(Some(' ')).enumerate() {
+            match (c.is_whitespace(), start) {
+                (false, None) => start = Some(column),
+                (true, Some(first)) => {
+                    let text: String = line.chars().skip(first).take(column - first).collect();
+                    tokens.push(Token { text: text, line: number + 1, column: first + 1 });
+                    start = None;
+                },
+                _ => {},
+            }
+        }
+    }
+    tokens.reverse();
+    tokens
+}
+
+fn is_number(text: &str) -> bool {
+    let digits = if text.starts_with('-') { &text[1..] } else { text };
+    digits.chars().next().map(|c| c.is_digit(10)).unwrap_or(false)
+}
+
+
+impl Compiler {
+    fn error(&self, token: &Token, message: String) -> Error {
+        Error {
+            file: self.file.clone(),
+            line: token.line,
+            column: token.column,
+            message: message,
+        }
+    }
+
+    fn next_token(&mut self) -> Option<Token> {
+        let token = self.tokens.pop();
+        if let Some(ref token) = token {
+            self.last = token.clone();
+        }
+        token
+    }
+
+    fn expect(&mut self, what: &str) -> Result<Token, Error> {
+        match self.next_token() {
+            Some(token) => Ok(token),
+            None => {
+                let last = self.last.clone();
+                Err(self.error(&last, format!("Expected {} after {}", what, last.text)))
+            },
+        }
+    }
+
+    fn expect_text(&mut self, text: &str) -> Result<Token, Error> {
+        let token = self.expect(text)?;
+        match token.text == text {
+            true => Ok(token),
+            false => Err(self.error(&token, format!("Expected {}, found {}", text, token.text))),
+        }
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.last().map(|t| t.text == text).unwrap_or(false)
+    }
+
+    fn run(&mut self) -> Result<(), Error> {
+        while let Some(token) = self.next_token() {
+            self.statement(token)?;
+        }
+        if let Some((control, token)) = self.controls.pop() {
+            let message = match control {
+                Control::Loop(..) => "loop without again",
+                Control::If(_) | Control::Else(_) => "begin without end",
+            };
+            return Err(self.error(&token, message.to_owned()));
+        }
+        if let Some(token) = self.next.take() {
+            return Err(self.error(&token, ":next without a following instruction".to_owned()));
+        }
+        let fixups: Vec<(usize, Fixup, Token)> = self.fixups.drain(..).collect();
+        for (address, fixup, token) in fixups {
+            let value = match self.labels.get(&token.text) {
+                Some(&address) => address as i64,
+                None if is_number(&token.text) || self.constants.contains_key(&token.text) => self.value(&token)?,
+                None => return Err(self.error(&token, format!("Undefined label: {}", token.text))),
+            };
+            let max = match fixup {
+                Fixup::Long => 0xFFFF,
+                _ => 0xFFF,
+            };
+            if value < 0 || value > max {
+                return Err(self.error(&token, format!("Address 0x{:X} is out of range", value)));
+            }
+            let value = value as u16;
+            match fixup {
+                Fixup::Address => self.patch(address, value),
+                Fixup::Long => self.put_word(address, value),
+                Fixup::Unpack(n) => {
+                    self.put_word(address, 0x6000 | n << 4 | value >> 8);
+                    self.put_word(address + 2, 0x6100 | value & 0xFF);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn extension(&mut self, name: &'static str) {
+        self.extensions.insert(name);
+    }
+
+    fn put_word(&mut self, address: usize, value: u16) {
+        let offset = address - ORIGIN as usize;
+        self.rom[offset] = (value >> 8) as u8;
+        self.rom[offset + 1] = value as u8;
+    }
+
+    // Sets the 12-bit address of the instruction at `address`.
+    fn patch(&mut self, address: usize, target: u16) {
+        let offset = address - ORIGIN as usize;
+        self.rom[offset] = self.rom[offset] & 0xF0 | (target >> 8) as u8 & 0x0F;
+        self.rom[offset + 1] = target as u8;
+    }
+
+    fn byte(&mut self, value: u8) -> Result<(), Error> {
+        if self.here > 0xFFFF {
+            let last = self.last.clone();
+            return Err(self.error(&last, "Program is larger than 64K".to_owned()));
+        }
+        let offset = self.here - ORIGIN as usize;
+        if self.rom.len() <= offset {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = value;
+        self.here += 1;
+        Ok(())
+    }
+
+    // Emits one instruction word, resolving a pending `:next` label.
+    fn word(&mut self, value: u16) -> Result<(), Error> {
+        if let Some(token) = self.next.take() {
+            let address = self.here + 1;
+            self.define(&token, address)?;
+        }
+        if self.here <= 0xFFFF {
+            self.lines.insert(self.here as u16, (self.file.clone(), self.last.line));
+        }
+        self.byte((value >> 8) as u8)?;
+        self.byte(value as u8)
+    }
+
+    // Emits an instruction whose address is filled in at the end.
+    fn word_to(&mut self, value: u16, target: Token) -> Result<(), Error> {
+        self.fixups.push((self.here, Fixup::Address, target));
+        self.word(value)
+    }
+
+    fn define(&mut self, token: &Token, address: usize) -> Result<(), Error> {
+        if self.labels.contains_key(&token.text) || self.constants.contains_key(&token.text) {
+            return Err(self.error(token, format!("{} is already defined", token.text)));
+        }
+        self.labels.insert(token.text.clone(), address as u16);
+        Ok(())
+    }
+
+    fn constant(&mut self, token: &Token, value: f64) -> Result<(), Error> {
+        if self.labels.contains_key(&token.text) {
+            return Err(self.error(token, format!("{} is already a label", token.text)));
+        }
+        self.constants.insert(token.text.clone(), value);
+        Ok(())
+    }
+
+    fn register(&self, text: &str) -> Option<u16> {
+        register(text).or_else(|| self.aliases.get(text).cloned())
+    }
+
+    fn reg(&self, token: &Token) -> Result<u16, Error> {
+        self.register(&token.text).ok_or_else(|| self.error(token, format!("Expected a register, found {}", token.text)))
+    }
+
+    // A number, constant or label defined so far.
+    fn value(&self, token: &Token) -> Result<i64, Error> {
+        if is_number(&token.text) {
+            return expr::signed_number(&token.text).map_err(|e| self.error(token, e));
+        }
+        match (self.constants.get(&token.text), self.labels.get(&token.text)) {
+            (Some(value), _) => Ok(value.floor() as i64),
+            (None, Some(&address)) => Ok(address as i64),
+            _ => Err(self.error(token, format!("Undefined name: {}", token.text))),
+        }
+    }
+
+    fn ranged(&self, token: &Token, value: i64, min: i64, max: i64) -> Result<u16, Error> {
+        if value < min || value > max {
+            return Err(self.error(token, format!("{} is out of range {}..{}", value, min, max)));
+        }
+        Ok((value & max) as u16)
+    }
+
+    fn byte_value(&self, token: &Token) -> Result<u16, Error> {
+        let value = self.value(token)?;
+        self.ranged(token, value, -128, 0xFF)
+    }
+
+    fn nibble(&self, token: &Token) -> Result<u16, Error> {
+        let value = self.value(token)?;
+        self.ranged(token, value, 0, 0xF)
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), Error> {
+        if let Some(x) = self.register(&token.text) {
+            return self.assignment(x);
+        }
+        if is_number(&token.text) {
+            let value = self.byte_value(&token)?;
+            return self.byte(value as u8);
+        }
+        match token.text.as_str() {
+            ":" => {
+                let name = self.expect("a label name")?;
+                let here = self.here;
+                self.define(&name, here)
+            },
+            ":const" => {
+                let name = self.expect("a name")?;
+                let value = self.expect("a value")?;
+                let value = self.value(&value)?;
+                self.constant(&name, value as f64)
+            },
+            ":calc" => {
+                let name = self.expect("a name")?;
+                let open = self.expect_text("{")?;
+                let value = self.calc(&open)?;
+                self.constant(&name, value)
+            },
+            ":alias" => {
+                let name = self.expect("a name")?;
+                let target = self.expect("a register")?;
+                let x = self.reg(&target)?;
+                self.aliases.insert(name.text, x);
+                Ok(())
+            },
+            ":macro" => self.macro_definition(),
+            ":org" => {
+                let target = self.expect("an address")?;
+                let address = self.value(&target)?;
+                self.here = self.ranged(&target, address, ORIGIN as i64, 0xFFFF)? as usize;
+                Ok(())
+            },
+            ":byte" => {
+                let value = self.expect("a value")?;
+                let byte = match value.text.as_str() {
+                    "{" => {
+                        let result = self.calc(&value)?.floor() as i64;
+                        self.ranged(&value, result, -128, 0xFF)?
+                    },
+                    _ => self.byte_value(&value)?,
+                };
+                self.byte(byte as u8)
+            },
+            ":unpack" => {
+                let n = self.expect("a nibble")?;
+                let n = self.nibble(&n)?;
+                let label = self.expect("a label")?;
+                self.fixups.push((self.here, Fixup::Unpack(n), label));
+                self.word(0x6000)?;
+                self.word(0x6100)
+            },
+            ":next" => {
+                self.next = Some(self.expect("a label name")?);
+                Ok(())
+            },
+            ":call" => {
+                let target = self.expect("a label")?;
+                self.word_to(0x2000, target)
+            },
+            ":breakpoint" => self.expect("a name").map(|_| ()),
+            ":monitor" => {
+                self.expect("an address")?;
+                self.expect("a format").map(|_| ())
+            },
+            "clear" => self.word(0x00E0),
+            "return" | ";" => self.word(0x00EE),
+            "jump" | "jump0" => {
+                let target = self.expect("a label")?;
+                let op = if token.text == "jump" { 0x1000 } else { 0xB000 };
+                self.word_to(op, target)
+            },
+            "sprite" => {
+                let x = self.expect("a register")?;
+                let y = self.expect("a register")?;
+                let n = self.expect("a height")?;
+                let value = 0xD000 | self.reg(&x)? << 8 | self.reg(&y)? << 4 | self.nibble(&n)?;
+                self.word(value)
+            },
+            "bcd" | "save" | "load" | "saveflags" | "loadflags" => {
+                let x = self.expect("a register")?;
+                let x = self.reg(&x)?;
+                if (token.text == "save" || token.text == "load") && self.peek_is("-") {
+                    self.next_token();
+                    let y = self.expect("a register")?;
+                    let y = self.reg(&y)?;
+                    self.extension("XO-CHIP");
+                    let op = if token.text == "save" { 0x5002 } else { 0x5003 };
+                    return self.word(op | x << 8 | y << 4);
+                }
+                let op = match token.text.as_str() {
+                    "bcd" => 0xF033,
+                    "save" => 0xF055,
+                    "load" => 0xF065,
+                    "saveflags" => 0xF075,
+                    _ => 0xF085,
+                };
+                if op == 0xF075 || op == 0xF085 {
+                    self.extension("SUPER-CHIP");
+                }
+                self.word(op | x << 8)
+            },
+            "delay" | "buzzer" | "pitch" => {
+                self.expect_text(":=")?;
+                let x = self.expect("a register")?;
+                let x = self.reg(&x)?;
+                let op = match token.text.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => {
+                        self.extension("XO-CHIP");
+                        0xF03A
+                    },
+                };
+                self.word(op | x << 8)
+            },
+            "i" => self.index(),
+            "hires" | "lores" | "exit" | "scroll-left" | "scroll-right" => {
+                self.extension("SUPER-CHIP");
+                self.word(match token.text.as_str() {
+                    "hires" => 0x00FF,
+                    "lores" => 0x00FE,
+                    "exit" => 0x00FD,
+                    "scroll-left" => 0x00FC,
+                    _ => 0x00FB,
+                })
+            },
+            "scroll-down" | "scroll-up" => {
+                let n = self.expect("a row count")?;
+                let n = self.nibble(&n)?;
+                match token.text.as_str() {
+                    "scroll-down" => {
+                        self.extension("SUPER-CHIP");
+                        self.word(0x00C0 | n)
+                    },
+                    _ => {
+                        self.extension("XO-CHIP");
+                        self.word(0x00D0 | n)
+                    },
+                }
+            },
+            "plane" => {
+                let n = self.expect("a plane mask")?;
+                let n = self.nibble(&n)?;
+                self.extension("XO-CHIP");
+                self.word(0xF001 | n << 8)
+            },
+            "audio" => {
+                self.extension("XO-CHIP");
+                self.word(0xF002)
+            },
+            "if" => {
+                let condition = self.condition()?;
+                for value in condition.setup.iter() {
+                    self.word(*value)?;
+                }
+                let then = self.expect("then or begin")?;
+                match then.text.as_str() {
+                    "then" => self.word(condition.skip_unless),
+                    "begin" => {
+                        self.word(condition.skip_if)?;
+                        self.controls.push((Control::If(self.here as u16), token));
+                        self.word(0x1000)
+                    },
+                    _ => Err(self.error(&then, format!("Expected then or begin, found {}", then.text))),
+                }
+            },
+            "else" => match self.controls.pop() {
+                Some((Control::If(jump), _)) => {
+                    let over = self.here as u16;
+                    self.controls.push((Control::Else(over), token));
+                    self.word(0x1000)?;
+                    let here = self.here as u16;
+                    self.patch(jump as usize, here);
+                    Ok(())
+                },
+                _ => Err(self.error(&token, "else without if ... begin".to_owned())),
+            },
+            "end" => match self.controls.pop() {
+                Some((Control::If(jump), _)) | Some((Control::Else(jump), _)) => {
+                    let here = self.here as u16;
+                    self.patch(jump as usize, here);
+                    Ok(())
+                },
+                _ => Err(self.error(&token, "end without if ... begin".to_owned())),
+            },
+            "loop" => {
+                self.controls.push((Control::Loop(self.here as u16, Vec::new()), token));
+                Ok(())
+            },
+            "while" => {
+                if !self.controls.iter().any(|&(ref c, _)| match *c { Control::Loop(..) => true, _ => false }) {
+                    return Err(self.error(&token, "while outside of a loop".to_owned()));
+                }
+                let condition = self.condition()?;
+                for value in condition.setup.iter() {
+                    self.word(*value)?;
+                }
+                self.word(condition.skip_if)?;
+                let jump = self.here as u16;
+                for &mut (ref mut control, _) in self.controls.iter_mut().rev() {
+                    if let Control::Loop(_, ref mut breaks) = *control {
+                        breaks.push(jump);
+                        break;
+                    }
+                }
+                self.word(0x1000)
+            },
+            "again" => match self.controls.pop() {
+                Some((Control::Loop(start, breaks), _)) => {
+                    self.word(0x1000 | start)?;
+                    let here = self.here as u16;
+                    for jump in breaks {
+                        self.patch(jump as usize, here);
+                    }
+                    Ok(())
+                },
+                _ => Err(self.error(&token, "again without loop".to_owned())),
+            },
+            _ if self.macros.contains_key(&token.text) => self.expand(token),
+            _ if token.text.starts_with(|c: char| c.is_alphabetic() || c == '_') => self.word_to(0x2000, token),
+            _ => Err(self.error(&token, format!("Unexpected {}", token.text))),
+        }
+    }
+
+    fn assignment(&mut self, x: u16) -> Result<(), Error> {
+        let op = self.expect("an operator")?;
+        let rhs = self.expect("a value")?;
+        let x = x << 8;
+        let y = self.register(&rhs.text).map(|y| y << 4);
+        let value = match (op.text.as_str(), y) {
+            (":=", Some(y)) => 0x8000 | x | y,
+            (":=", None) => match rhs.text.as_str() {
+                "random" => {
+                    let mask = self.expect("a mask")?;
+                    0xC000 | x | self.byte_value(&mask)?
+                },
+                "delay" => 0xF007 | x,
+                "key" => 0xF00A | x,
+                _ => 0x6000 | x | self.byte_value(&rhs)?,
+            },
+            ("+=", Some(y)) => 0x8004 | x | y,
+            ("+=", None) => 0x7000 | x | self.byte_value(&rhs)?,
+            ("-=", Some(y)) => 0x8005 | x | y,
+            ("-=", None) => 0x7000 | x | (self.byte_value(&rhs)?.wrapping_neg() & 0xFF),
+            ("|=", Some(y)) => 0x8001 | x | y,
+            ("&=", Some(y)) => 0x8002 | x | y,
+            ("^=", Some(y)) => 0x8003 | x | y,
+            (">>=", Some(y)) => 0x8006 | x | y,
+            ("=-", Some(y)) => 0x8007 | x | y,
+            ("<<=", Some(y)) => 0x800E | x | y,
+            ("|=", None) | ("&=", None) | ("^=", None) | (">>=", None) | ("=-", None) | ("<<=", None) => {
+                return Err(self.error(&rhs, format!("Expected a register, found {}", rhs.text)));
+            },
+            _ => return Err(self.error(&op, format!("Unknown operator {}", op.text))),
+        };
+        self.word(value)
+    }
+
+    fn index(&mut self) -> Result<(), Error> {
+        let op = self.expect("an operator")?;
+        let rhs = self.expect("a value")?;
+        match (op.text.as_str(), rhs.text.as_str()) {
+            (":=", "hex") | (":=", "bighex") => {
+                let x = self.expect("a register")?;
+                let x = self.reg(&x)? << 8;
+                match rhs.text.as_str() {
+                    "hex" => self.word(0xF029 | x),
+                    _ => {
+                        self.extension("SUPER-CHIP");
+                        self.word(0xF030 | x)
+                    },
+                }
+            },
+            (":=", "long") => {
+                let target = self.expect("an address")?;
+                self.extension("XO-CHIP");
+                self.word(0xF000)?;
+                self.fixups.push((self.here, Fixup::Long, target));
+                self.byte(0)?;
+                self.byte(0)
+            },
+            (":=", _) => self.word_to(0xA000, rhs),
+            ("+=", _) => {
+                let x = self.reg(&rhs)?;
+                self.word(0xF01E | x << 8)
+            },
+            _ => Err(self.error(&op, format!("Unknown operator {}", op.text))),
+        }
+    }
+
+    fn side(&self, token: &Token) -> Result<Side, Error> {
+        match self.register(&token.text) {
+            Some(x) => Ok(Side::Reg(x)),
+            None => Ok(Side::Byte(self.byte_value(token)?)),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, Error> {
+        let lhs = self.expect("a register")?;
+        let x = self.reg(&lhs)?;
+        let op = self.expect("a comparison")?;
+        let (skip_if, skip_unless) = match op.text.as_str() {
+            "key" => (0xE09E | x << 8, 0xE0A1 | x << 8),
+            "-key" => (0xE0A1 | x << 8, 0xE09E | x << 8),
+            "==" | "!=" => {
+                let rhs = self.expect("a value")?;
+                let (equal, unequal) = match self.side(&rhs)? {
+                    Side::Reg(y) => (0x5000 | x << 8 | y << 4, 0x9000 | x << 8 | y << 4),
+                    Side::Byte(n) => (0x3000 | x << 8 | n, 0x4000 | x << 8 | n),
+                };
+                match op.text.as_str() {
+                    "==" => (equal, unequal),
+                    _ => (unequal, equal),
+                }
+            },
+            "<" | ">" | "<=" | ">=" => {
+                let rhs = self.expect("a value")?;
+                let rhs = self.side(&rhs)?;
+                // vF := 1 when the first side is >= the second, 0 otherwise.
+                let setup = match (op.text.as_str(), rhs) {
+                    ("<", Side::Reg(y)) | (">=", Side::Reg(y)) => vec![0x8F00 | x << 4, 0x8F05 | y << 4],
+                    ("<", Side::Byte(n)) | (">=", Side::Byte(n)) => vec![0x6F00 | n, 0x8F07 | x << 4],
+                    (_, Side::Reg(y)) => vec![0x8F00 | y << 4, 0x8F05 | x << 4],
+                    (_, Side::Byte(n)) => vec![0x6F00 | n, 0x8F05 | x << 4],
+                };
+                let (zero, set) = (0x3F00, 0x4F00);
+                return Ok(match op.text.as_str() {
+                    "<" | ">" => Condition { setup: setup, skip_if: zero, skip_unless: set },
+                    _ => Condition { setup: setup, skip_if: set, skip_unless: zero },
+                });
+            },
+            _ => return Err(self.error(&op, format!("Unknown comparison {}", op.text))),
+        };
+        Ok(Condition { setup: Vec::new(), skip_if: skip_if, skip_unless: skip_unless })
+    }
+
+    fn macro_definition(&mut self) -> Result<(), Error> {
+        let name = self.expect("a macro name")?;
+        let mut args = Vec::new();
+        loop {
+            let arg = self.expect("{")?;
+            if arg.text == "{" {
+                break;
+            }
+            args.push(arg.text);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.expect("}")?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {},
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(token);
+        }
+        self.macros.insert(name.text, (args, body));
+        Ok(())
+    }
+
+    fn expand(&mut self, name: Token) -> Result<(), Error> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(self.error(&name, format!("Too many macro expansions at {}", name.text)));
+        }
+        let (params, body) = self.macros[&name.text].clone();
+        let mut args = HashMap::new();
+        for param in params {
+            let arg = self.expect("a macro argument")?;
+            args.insert(param, arg);
+        }
+        for token in body.into_iter().rev() {
+            self.tokens.push(args.get(&token.text).cloned().unwrap_or(token));
+        }
+        Ok(())
+    }
+
+    // Evaluates the tokens up to the `}` matching `open`.
+    fn calc(&mut self, open: &Token) -> Result<f64, Error> {
+        let mut tokens = Vec::new();
+        loop {
+            match self.next_token() {
+                Some(ref token) if token.text == "}" => break,
+                Some(token) => tokens.push(token),
+                None => return Err(self.error(open, "Unclosed {".to_owned())),
+            }
+        }
+        let mut pos = 0;
+        let value = self.calc_expression(&tokens, &mut pos, open)?;
+        match tokens.get(pos) {
+            Some(token) => Err(self.error(token, format!("Unexpected {}", token.text))),
+            None => Ok(value),
+        }
+    }
+
+    fn calc_expression(&self, tokens: &[Token], pos: &mut usize, open: &Token) -> Result<f64, Error> {
+        let left = self.calc_term(tokens, pos, open)?;
+        let op = match tokens.get(*pos) {
+            Some(token) if token.text != ")" => token.clone(),
+            _ => return Ok(left),
+        };
+        *pos += 1;
+        let right = self.calc_expression(tokens, pos, open)?;
+        let (l, r) = (left as i64, right as i64);
+        Ok(match op.text.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" if right == 0.0 => return Err(self.error(&op, "Division by zero".to_owned())),
+            "/" => left / right,
+            "%" if r == 0 => return Err(self.error(&op, "Division by zero".to_owned())),
+            "%" => (l % r) as f64,
+            "&" => (l & r) as f64,
+            "|" => (l | r) as f64,
+            "^" => (l ^ r) as f64,
+            "<<" => (l << (r & 63)) as f64,
+            ">>" => (l >> (r & 63)) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            "!=" => (left != right) as i64 as f64,
+            _ => return Err(self.error(&op, format!("Unknown operator {}", op.text))),
+        })
+    }
+
+    fn calc_term(&self, tokens: &[Token], pos: &mut usize, open: &Token) -> Result<f64, Error> {
+        let token = match tokens.get(*pos) {
+            Some(token) => token.clone(),
+            None => return Err(self.error(open, "Expected a value before }".to_owned())),
+        };
+        *pos += 1;
+        let unary = |f: &dyn Fn(f64) -> f64, pos: &mut usize| self.calc_term(tokens, pos, open).map(|v| f(v));
+        match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expression(tokens, pos, open)?;
+                match tokens.get(*pos) {
+                    Some(close) if close.text == ")" => {
+                        *pos += 1;
+                        Ok(value)
+                    },
+                    _ => Err(self.error(&token, "Unclosed (".to_owned())),
+                }
+            },
+            "-" => unary(&|v| -v, pos),
+            "~" => unary(&|v| !(v as i64) as f64, pos),
+            "!" => unary(&|v| (v == 0.0) as i64 as f64, pos),
+            "abs" => unary(&|v| v.abs(), pos),
+            "sqrt" => unary(&|v| v.sqrt(), pos),
+            "floor" => unary(&|v| v.floor(), pos),
+            "ceil" => unary(&|v| v.ceil(), pos),
+            "@" => {
+                let address = self.calc_term(tokens, pos, open)? as i64;
+                let offset = address - ORIGIN as i64;
+                Ok(match offset >= 0 && (offset as usize) < self.rom.len() {
+                    true => self.rom[offset as usize] as f64,
+                    false => 0.0,
+                })
+            },
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(::std::f64::consts::PI),
+            "E" => Ok(::std::f64::consts::E),
+            _ => match self.constants.get(&token.text) {
+                Some(value) => Ok(*value),
+                None => self.value(&token).map(|v| v as f64),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+fn binary(source: &str) -> Vec<u8> {
+    match compile("test.8o", source) {
+        Ok(program) => program.binary,
+        Err(errors) => panic!("{}", errors[0]),
+    }
+}
+
+#[cfg(test)]
+fn error(source: &str) -> String {
+    compile("test.8o", source).err().unwrap()[0].to_string()
+}
+
+#[test]
+fn test_octo_program() {
+    let source = "
+        :const SPEED 2
+        :alias x v1
+        :calc DOUBLE { SPEED * 2 + 1 }  # right to left: 2 * 3
+        :macro twice OP { OP OP }
+        : main
+          x := SPEED
+          loop
+            x += 1
+            while x != 10
+            if x == DOUBLE begin
+              v2 := 1
+            else
+              v2 := 0
+            end
+          again
+          square
+          :unpack 1 square
+          :next target
+          v3 := 0
+          ;
+        : square
+          i := square
+          sprite v0 v1 1
+          return
+          0xF0
+          :byte { target & 0xFF }
+          twice return
+    ";
+    let program = compile("test.8o", source).unwrap();
+    assert_eq!(program.binary, vec![
+        0x61, 0x02, 0x71, 0x01, 0x41, 0x0A, 0x12, 0x14, 0x31, 0x06, 0x12, 0x10,
+        0x62, 0x01, 0x12, 0x12, 0x62, 0x00, 0x12, 0x02, 0x22, 0x1E, 0x60, 0x12,
+        0x61, 0x1E, 0x63, 0x00, 0x00, 0xEE, 0xA2, 0x1E, 0xD0, 0x11, 0x00, 0xEE,
+        0xF0, 0x1B, 0x00, 0xEE, 0x00, 0xEE,
+    ]);
+    assert_eq!(program.symbols.get("main"), Some(&0x200));
+    assert_eq!(program.symbols.get("target"), Some(&0x21B));
+    assert_eq!(program.symbols.get("square"), Some(&0x21E));
+    assert!(program.extensions.is_empty());
+    assert_eq!(program.lines[&0x200], ("test.8o".to_owned(), 7));
+    assert_eq!(program.lines[&0x21E], ("test.8o".to_owned(), 23));
+    assert!(!program.lines.contains_key(&0x224));
+}
+
+#[test]
+fn test_octo_comparisons() {
+    assert_eq!(binary("if v1 < 5 then v2 := 1"), vec![0x6F, 0x05, 0x8F, 0x17, 0x4F, 0x00, 0x62, 0x01]);
+    assert_eq!(binary("if v1 > v2 then v3 := 1"), vec![0x8F, 0x20, 0x8F, 0x15, 0x4F, 0x00, 0x63, 0x01]);
+    assert_eq!(binary("if v1 >= v2 then clear"), vec![0x8F, 0x10, 0x8F, 0x25, 0x3F, 0x00, 0x00, 0xE0]);
+    assert_eq!(binary("if v1 <= 7 then clear"), vec![0x6F, 0x07, 0x8F, 0x15, 0x3F, 0x00, 0x00, 0xE0]);
+    assert_eq!(binary("v1 -= 1"), vec![0x71, 0xFF]);
+}
+
+#[test]
+fn test_octo_extensions() {
+    let program = compile("test.8o", "hires scroll-down 4 i := long 0x1234 save v1 - v3").unwrap();
+    assert_eq!(program.binary, vec![0x00, 0xFF, 0x00, 0xC4, 0xF0, 0x00, 0x12, 0x34, 0x51, 0x32]);
+    assert_eq!(program.extensions.iter().cloned().collect::<Vec<_>>(), vec!["SUPER-CHIP", "XO-CHIP"]);
+}
+
+#[test]
+fn test_octo_errors() {
+    assert_eq!(error("v1 := 1\nv1 ^= 3"), "test.8o:2:7: Expected a register, found 3");
+    assert_eq!(error("jump nowhere"), "test.8o:1:6: Undefined label: nowhere");
+    assert_eq!(error("loop\n  v1 += 1"), "test.8o:1:1: loop without again");
+    assert_eq!(error("v1 := 300"), "test.8o:1:7: 300 is out of range -128..255");
+    assert_eq!(error("  else"), "test.8o:1:3: else without if ... begin");
+    assert_eq!(error(": a\n: a"), "test.8o:2:3: a is already defined");
+    assert_eq!(error(":macro inc R { R += 1 }\ninc v1 inc x"), "test.8o:1:18: Unexpected +=");
+    assert_eq!(error(":macro m { m }\nm"), "test.8o:1:12: Too many macro expansions at m");
+}
+
+#[test]
+fn test_octo_round_trip_through_disassembler() {
+    use disasm::{disassemble, listing, Labels, Syntax};
+    use opcodes::Op;
+
+    let labels = Labels::new();
+    for value in 0..0x10000u32 {
+        let value = value as u16;
+        if Op::decode(value).is_err() {
+            continue;
+        }
+        let text = disassemble(value, Syntax::Octo, &labels);
+        let source = if text.ends_with("then") { format!("{} clear", text) } else { text.clone() };
+        let bytes = binary(&source);
+        let word = (bytes[0] as u16) << 8 | bytes[1] as u16;
+        assert_eq!(disassemble(word, Syntax::Octo, &labels), text);
+    }
+
+    let rom = [0x22, 0x06, 0x12, 0x02, 0xFF, 0xFF, 0xA2, 0x06, 0x00, 0xEE];
+    assert_eq!(binary(&listing(&rom, Syntax::Octo)), rom.to_vec());
+}
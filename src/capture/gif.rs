@@ -0,0 +1,295 @@
+// Animated GIF encoder. Frames are buffered as palette indices so the global
+// colour table can be written once every colour in the clip is known.
+use std::collections::HashMap;
+use filters::{Frame, Rgb};
+
+const MAX_COLORS: usize = 256;
+const MAX_CODE: u16 = 4095;
+
+
+pub struct GifEncoder {
+    width: usize,
+    height: usize,
+    colors: Vec<Rgb>,
+    lookup: HashMap<(u8, u8, u8), u8>,
+    frames: Vec<(Vec<u8>, u32)>,
+}
+
+impl GifEncoder {
+    pub fn new(width: usize, height: usize) -> GifEncoder {
+        GifEncoder {
+            width: width,
+            height: height,
+            colors: Vec::new(),
+            lookup: HashMap::new(),
+            frames: Vec::new(),
+        }
+    }
+    // Adds one 60Hz frame. Repeats of the previous frame only extend its delay.
+    pub fn add_frame(&mut self, frame: &Frame) {
+        let indices: Vec<u8> = frame.data.iter().map(|rgb| self.index_of(*rgb)).collect();
+        if let Some(last) = self.frames.last_mut() {
+            if last.0 == indices {
+                last.1 += 1;
+                return;
+            }
+        }
+        self.frames.push((indices, 1));
+    }
+    pub fn finish(&self) -> Vec<u8> {
+        let bits = table_bits(self.colors.len());
+        let mut out = b"GIF89a".to_vec();
+        push_u16(&mut out, self.width as u16);
+        push_u16(&mut out, self.height as u16);
+        out.push(0x80 | (bits - 1));
+        out.push(0);
+        out.push(0);
+        for i in 0..(1 << bits) {
+            let rgb = self.colors.get(i).cloned().unwrap_or(Rgb(0, 0, 0));
+            out.extend_from_slice(&[rgb.0, rgb.1, rgb.2]);
+        }
+
+        // NETSCAPE2.0 application extension: loop forever.
+        out.extend_from_slice(&[0x21, 0xFF, 0x0B]);
+        out.extend_from_slice(b"NETSCAPE2.0");
+        out.extend_from_slice(&[0x03, 0x01, 0x00, 0x00, 0x00]);
+
+        let min_code_size = if bits < 2 { 2 } else { bits };
+        let mut ticks = 0;
+        let mut elapsed = 0;
+        for &(ref indices, frames) in &self.frames {
+            // Delays are in centiseconds, so carry the rounding error forward.
+            ticks += frames;
+            let delay = ticks * 100 / 60 - elapsed;
+            elapsed += delay;
+
+            out.extend_from_slice(&[0x21, 0xF9, 0x04, 0x00]);
+            push_u16(&mut out, delay as u16);
+            out.extend_from_slice(&[0x00, 0x00]);
+
+            out.push(0x2C);
+            push_u16(&mut out, 0);
+            push_u16(&mut out, 0);
+            push_u16(&mut out, self.width as u16);
+            push_u16(&mut out, self.height as u16);
+            out.push(0);
+
+            out.push(min_code_size);
+            for block in lzw_encode(indices, min_code_size).chunks(255) {
+                out.push(block.len() as u8);
+                out.extend_from_slice(block);
+            }
+            out.push(0);
+        }
+        out.push(0x3B);
+        out
+    }
+    // Colours past the 256th are mapped to the closest one already in the table.
+    fn index_of(&mut self, rgb: Rgb) -> u8 {
+        let key = (rgb.0, rgb.1, rgb.2);
+        if let Some(index) = self.lookup.get(&key) {
+            return *index;
+        }
+        let index = match self.colors.len() {
+            MAX_COLORS => closest(&self.colors, rgb),
+            len => {
+                self.colors.push(rgb);
+                len as u8
+            }
+        };
+        self.lookup.insert(key, index);
+        index
+    }
+}
+
+fn closest(colors: &[Rgb], rgb: Rgb) -> u8 {
+    let distance = |c: &Rgb| {
+        let dr = c.0 as i32 - rgb.0 as i32;
+        let dg = c.1 as i32 - rgb.1 as i32;
+        let db = c.2 as i32 - rgb.2 as i32;
+        dr * dr + dg * dg + db * db
+    };
+    let mut best = 0;
+    for (i, c) in colors.iter().enumerate() {
+        if distance(c) < distance(&colors[best]) {
+            best = i;
+        }
+    }
+    best as u8
+}
+
+fn table_bits(colors: usize) -> u8 {
+    let mut bits = 1;
+    while (1 << bits) < colors {
+        bits += 1;
+    }
+    bits
+}
+
+fn push_u16(out: &mut Vec<u8>, value: u16) {
+    out.push(value as u8);
+    out.push((value >> 8) as u8);
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, width: u8) {
+        self.acc |= (code as u32) << self.bits;
+        self.bits += width;
+        while self.bits >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.acc as u8);
+        }
+        self.bytes
+    }
+}
+
+fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut writer = BitWriter { bytes: Vec::new(), acc: 0, bits: 0 };
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut width = min_code_size + 1;
+
+    writer.write(clear, width);
+    let mut prefix: Option<u16> = None;
+    for index in indices {
+        let current = match prefix {
+            None => {
+                prefix = Some(*index as u16);
+                continue;
+            },
+            Some(code) => code,
+        };
+        match table.get(&(current, *index)) {
+            Some(code) => prefix = Some(*code),
+            None => {
+                writer.write(current, width);
+                if next >= MAX_CODE {
+                    writer.write(clear, width);
+                    table.clear();
+                    next = end + 1;
+                    width = min_code_size + 1;
+                } else {
+                    table.insert((current, *index), next);
+                    if next == (1 << width) && width < 12 {
+                        width += 1;
+                    }
+                    next += 1;
+                }
+                prefix = Some(*index as u16);
+            }
+        }
+    }
+    if let Some(code) = prefix {
+        writer.write(code, width);
+        if next == (1 << width) && width < 12 {
+            width += 1;
+        }
+    }
+    writer.write(end, width);
+    writer.finish()
+}
+
+#[cfg(test)]
+fn lzw_decode(data: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut out = Vec::new();
+    let mut table: Vec<Vec<u8>> = Vec::new();
+    let mut width = min_code_size + 1;
+    let mut previous: Option<Vec<u8>> = None;
+    let (mut acc, mut bits, mut pos) = (0u32, 0u8, 0);
+    loop {
+        while bits < width {
+            acc |= (data[pos] as u32) << bits;
+            pos += 1;
+            bits += 8;
+        }
+        let code = (acc & ((1 << width) - 1)) as u16;
+        acc >>= width;
+        bits -= width;
+        if code == clear {
+            table = (0..clear).map(|c| vec![c as u8]).collect();
+            table.push(Vec::new());
+            table.push(Vec::new());
+            width = min_code_size + 1;
+            previous = None;
+            continue;
+        }
+        if code == end {
+            return out;
+        }
+        let entry = match (table.get(code as usize).cloned(), previous.clone()) {
+            (Some(entry), _) => entry,
+            (None, Some(prev)) => { let mut e = prev.clone(); e.push(prev[0]); e },
+            (None, None) => panic!("bad code"),
+        };
+        out.extend_from_slice(&entry);
+        if let (Some(mut prev), true) = (previous, table.len() < 4096) {
+            prev.push(entry[0]);
+            table.push(prev);
+            if table.len() == (1 << width) && width < 12 {
+                width += 1;
+            }
+        }
+        previous = Some(entry);
+    }
+}
+
+#[test]
+fn test_lzw_round_trip() {
+    let mut indices = Vec::new();
+    for i in 0..20000u32 {
+        indices.push(((i * 7 / 13) % 5) as u8);
+    }
+    let encoded = lzw_encode(&indices, 3);
+    assert_eq!(lzw_decode(&encoded, 3), indices);
+
+    // Noisy input fills the code table and forces clear codes.
+    let mut seed = 1u32;
+    let noise: Vec<u8> = (0..50000).map(|_| {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        (seed >> 16) as u8
+    }).collect();
+    let encoded = lzw_encode(&noise, 8);
+    assert_eq!(lzw_decode(&encoded, 8), noise);
+}
+
+#[test]
+fn test_gif_layout() {
+    let on = Rgb(230, 230, 230);
+    let off = Rgb(28, 28, 28);
+    let mut gif = GifEncoder::new(2, 1);
+    gif.add_frame(&Frame { width: 2, height: 1, data: vec![on, off] });
+    gif.add_frame(&Frame { width: 2, height: 1, data: vec![on, off] });
+    gif.add_frame(&Frame { width: 2, height: 1, data: vec![off, on] });
+
+    let out = gif.finish();
+    assert_eq!(out.windows(3).filter(|w| *w == [0x21, 0xF9, 0x04]).count(), 2);
+    assert_eq!(&out[0..6], b"GIF89a");
+    assert_eq!(&out[6..11], &[2, 0, 1, 0, 0x80]);
+    assert_eq!(&out[13..19], &[230, 230, 230, 28, 28, 28]);
+    assert_eq!(out[out.len() - 1], 0x3B);
+
+    let gce = out.windows(3).position(|w| w == [0x21, 0xF9, 0x04]).unwrap();
+    assert_eq!(&out[gce + 4..gce + 6], &[3, 0]);
+    let image = gce + 8;
+    assert_eq!(out[image], 0x2C);
+    let data = image + 10;
+    assert_eq!(out[data], 2);
+    let len = out[data + 1] as usize;
+    assert_eq!(lzw_decode(&out[data + 2..data + 2 + len], 2), vec![0, 1]);
+}
@@ -0,0 +1,160 @@
+mod gif;
+mod png;
+mod pnm;
+mod recorder;
+mod y4m;
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use display::Display;
+
+pub use self::recorder::Recorder;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Pbm,
+    Pgm,
+}
+
+impl ImageFormat {
+    pub fn from_name(name: &str) -> Result<ImageFormat, String> {
+        match name.to_lowercase().as_str() {
+            "png" => Ok(ImageFormat::Png),
+            "pbm" => Ok(ImageFormat::Pbm),
+            "pgm" => Ok(ImageFormat::Pgm),
+            _ => Err(format!("Unknown image format: {}", name)),
+        }
+    }
+    pub fn from_path(path: &Path) -> Result<ImageFormat, String> {
+        match path.extension() {
+            Some(ext) => ImageFormat::from_name(&ext.to_string_lossy()),
+            None => Err(format!("No image format for {}", path.display())),
+        }
+    }
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            ImageFormat::Png => "png",
+            ImageFormat::Pbm => "pbm",
+            ImageFormat::Pgm => "pgm",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VideoFormat {
+    Gif,
+    Y4m,
+}
+
+impl VideoFormat {
+    pub fn from_path(path: &Path) -> Result<VideoFormat, String> {
+        let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        match ext.as_ref().map(|e| e.as_str()) {
+            Some("gif") => Ok(VideoFormat::Gif),
+            Some("y4m") => Ok(VideoFormat::Y4m),
+            _ => Err(format!("No video format for {}", path.display())),
+        }
+    }
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            VideoFormat::Gif => "gif",
+            VideoFormat::Y4m => "y4m",
+        }
+    }
+}
+
+// Settings for captures of the framebuffer. PNGs and recordings go through the
+// display's filter, overlay and palette and are then scaled up; the Netpbm
+// formats are always the raw framebuffer at native resolution.
+pub struct CaptureSettings {
+    pub image: ImageFormat,
+    pub video: VideoFormat,
+    pub scale: usize,
+    pub directory: PathBuf,
+}
+
+impl CaptureSettings {
+    pub fn new() -> CaptureSettings {
+        CaptureSettings {
+            image: ImageFormat::Png,
+            video: VideoFormat::Gif,
+            scale: 8,
+            directory: PathBuf::from("screenshots"),
+        }
+    }
+    pub fn save_screenshot(&self, display: &Display) -> Result<PathBuf, String> {
+        let path = self.next_path(self.image.extension())?;
+        write_file(&path, &encode_image(display, self.image, self.scale))?;
+        Ok(path)
+    }
+    pub fn start_recording(&self, display: &Display) -> Result<Recorder, String> {
+        let path = self.next_path(self.video.extension())?;
+        Recorder::start(&path, display, self.scale)
+    }
+    fn next_path(&self, extension: &str) -> Result<PathBuf, String> {
+        fs::create_dir_all(&self.directory).map_err(|e| e.to_string())?;
+        Ok(self.directory.join(format!("rip8-{}.{}", timestamp(), extension)))
+    }
+}
+
+pub fn encode_image(display: &Display, format: ImageFormat, scale: usize) -> Vec<u8> {
+    match format {
+        ImageFormat::Png => png::encode(&display.frame().scale(scale)),
+        ImageFormat::Pbm => pnm::encode_pbm(display.pixels(), display.width(), display.height()),
+        ImageFormat::Pgm => pnm::encode_pgm(display.pixels(), display.width(), display.height()),
+    }
+}
+
+// Writes a screenshot to an explicit path, picking the format from its extension.
+pub fn save_image(display: &Display, path: &Path, scale: usize) -> Result<(), String> {
+    let format = ImageFormat::from_path(path)?;
+    write_file(path, &encode_image(display, format, scale))
+}
+
+pub fn write_file(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    let mut file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    file.write_all(bytes).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// UTC time as YYYYMMDD-HHMMSS-mmm, for file names that sort chronologically.
+pub fn timestamp() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    format_timestamp(now.as_secs(), now.subsec_nanos() / 1_000_000)
+}
+
+fn format_timestamp(secs: u64, millis: u32) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Civil-from-days, after Howard Hinnant's date algorithms.
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}",
+            year, month, day, rem / 3600, rem % 3600 / 60, rem % 60, millis)
+}
+
+#[test]
+fn test_format_timestamp() {
+    assert_eq!(format_timestamp(0, 0), "19700101-000000-000");
+    assert_eq!(format_timestamp(951_782_400 + 3661, 42), "20000229-010101-042");
+    assert_eq!(format_timestamp(1_700_000_000, 999), "20231114-221320-999");
+}
+
+#[test]
+fn test_image_format_from_path() {
+    assert_eq!(ImageFormat::from_path(Path::new("shot.PNG")), Ok(ImageFormat::Png));
+    assert_eq!(ImageFormat::from_path(Path::new("a/b.pgm")), Ok(ImageFormat::Pgm));
+    assert!(ImageFormat::from_path(Path::new("shot")).is_err());
+}
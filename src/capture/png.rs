@@ -0,0 +1,115 @@
+// Minimal PNG encoder for 8-bit RGB images. The zlib stream uses stored
+// (uncompressed) deflate blocks, which keeps the encoder dependency free.
+use filters::Frame;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+
+pub fn encode(frame: &Frame) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(frame.height * (frame.width * 3 + 1));
+    for row in frame.data.chunks(frame.width) {
+        raw.push(0);
+        for rgb in row {
+            raw.push(rgb.0);
+            raw.push(rgb.1);
+            raw.push(rgb.2);
+        }
+    }
+
+    let mut header = Vec::new();
+    push_u32(&mut header, frame.width as u32);
+    push_u32(&mut header, frame.height as u32);
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    push_u32(out, data.len() as u32);
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    push_u32(out, crc);
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.push(len as u8);
+        out.push((len >> 8) as u8);
+        out.push(!len as u8);
+        out.push((!len >> 8) as u8);
+        out.extend_from_slice(block);
+    }
+    push_u32(&mut out, adler32(data));
+    out
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.push((value >> 24) as u8);
+    out.push((value >> 16) as u8);
+    out.push((value >> 8) as u8);
+    out.push(value as u8);
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xEDB8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[test]
+fn test_checksums() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+    assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+}
+
+#[test]
+fn test_encode_layout() {
+    use filters::Rgb;
+    let frame = Frame { width: 2, height: 1, data: vec![Rgb(1, 2, 3), Rgb(4, 5, 6)] };
+    let png = encode(&frame);
+
+    assert_eq!(&png[0..8], &SIGNATURE);
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(&png[16..24], &[0, 0, 0, 2, 0, 0, 0, 1]);
+    assert_eq!(&png[png.len() - 12..], &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+
+    let idat = 8 + 12 + 13;
+    assert_eq!(&png[idat + 4..idat + 8], b"IDAT");
+    let zlib = &png[idat + 8..idat + 8 + 2 + 5 + 7 + 4];
+    assert_eq!(&zlib[0..7], &[0x78, 0x01, 1, 7, 0, 0xF8, 0xFF]);
+    assert_eq!(&zlib[7..14], &[0, 1, 2, 3, 4, 5, 6]);
+}
@@ -0,0 +1,42 @@
+// Netpbm writers for the raw framebuffer at native resolution.
+
+
+// P4 bitmap. The bits are the framebuffer bits, so lit pixels come out black.
+pub fn encode_pbm(pixels: &[bool], width: usize, height: usize) -> Vec<u8> {
+    let mut out = format!("P4\n{} {}\n", width, height).into_bytes();
+    for row in pixels.chunks(width).take(height) {
+        for byte in row.chunks(8) {
+            let mut packed = 0u8;
+            for (bit, set) in byte.iter().enumerate() {
+                packed |= (*set as u8) << (7 - bit);
+            }
+            out.push(packed);
+        }
+    }
+    out
+}
+
+// P5 greymap with lit pixels at full white.
+pub fn encode_pgm(pixels: &[bool], width: usize, height: usize) -> Vec<u8> {
+    let mut out = format!("P5\n{} {}\n255\n", width, height).into_bytes();
+    out.extend(pixels.iter().take(width * height).map(|p| if *p { 0xFF } else { 0x00 }));
+    out
+}
+
+#[test]
+fn test_encode_pbm() {
+    let mut pixels = vec![false; 10 * 2];
+    pixels[0] = true;
+    pixels[9] = true;
+    pixels[10 + 7] = true;
+    let out = encode_pbm(&pixels, 10, 2);
+    let header = b"P4\n10 2\n".len();
+    assert_eq!(&out[..header], b"P4\n10 2\n");
+    assert_eq!(&out[header..], &[0b1000_0000, 0b0100_0000, 0b0000_0001, 0b0000_0000]);
+}
+
+#[test]
+fn test_encode_pgm() {
+    let out = encode_pgm(&[true, false, false, true], 2, 2);
+    assert_eq!(out, b"P5\n2 2\n255\n\xFF\x00\x00\xFF".to_vec());
+}
@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use display::Display;
+use filters::Frame;
+use capture::{write_file, VideoFormat};
+use capture::gif::GifEncoder;
+use capture::y4m;
+
+
+enum Sink {
+    Gif(GifEncoder),
+    Y4m(BufWriter<File>),
+}
+
+// Captures one frame per emulated 60Hz tick. GIFs are assembled in memory and
+// written when the recording stops; Y4M frames are streamed straight to disk.
+pub struct Recorder {
+    path: PathBuf,
+    scale: usize,
+    width: usize,
+    height: usize,
+    frames: u32,
+    sink: Sink,
+}
+
+impl Recorder {
+    pub fn start(path: &Path, display: &Display, scale: usize) -> Result<Recorder, String> {
+        let size = display.frame().scale(scale);
+        let sink = match VideoFormat::from_path(path)? {
+            VideoFormat::Gif => Sink::Gif(GifEncoder::new(size.width, size.height)),
+            VideoFormat::Y4m => {
+                let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                let mut out = BufWriter::new(file);
+                out.write_all(&y4m::header(size.width, size.height)).map_err(|e| e.to_string())?;
+                Sink::Y4m(out)
+            },
+        };
+        Ok(Recorder {
+            path: path.to_path_buf(),
+            scale: scale,
+            width: size.width,
+            height: size.height,
+            frames: 0,
+            sink: sink,
+        })
+    }
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+    pub fn capture(&mut self, display: &Display) -> Result<(), String> {
+        let mut frame = display.frame().scale(self.scale);
+        if frame.width != self.width || frame.height != self.height {
+            // The filter changed mid-recording; keep the clip's original size.
+            frame = resize(&frame, self.width, self.height);
+        }
+        self.frames += 1;
+        match self.sink {
+            Sink::Gif(ref mut gif) => {
+                gif.add_frame(&frame);
+                Ok(())
+            },
+            Sink::Y4m(ref mut out) => y4m::write_frame(out, &frame),
+        }
+    }
+    pub fn finish(self) -> Result<PathBuf, String> {
+        match self.sink {
+            Sink::Gif(gif) => write_file(&self.path, &gif.finish())?,
+            Sink::Y4m(mut out) => out.flush().map_err(|e| e.to_string())?,
+        }
+        Ok(self.path)
+    }
+}
+
+fn resize(frame: &Frame, width: usize, height: usize) -> Frame {
+    let mut data = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let sx = x * frame.width / width;
+            let sy = y * frame.height / height;
+            data.push(frame.data[sy * frame.width + sx]);
+        }
+    }
+    Frame { width: width, height: height, data: data }
+}
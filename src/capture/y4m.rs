@@ -0,0 +1,48 @@
+// YUV4MPEG2 writer. Frames are streamed as full-resolution 4:4:4 BT.601 so
+// external tools can encode them without any loss of the pixel grid.
+use std::io::Write;
+use filters::{Frame, Rgb};
+
+
+pub fn header(width: usize, height: usize) -> Vec<u8> {
+    format!("YUV4MPEG2 W{} H{} F60:1 Ip A1:1 C444\n", width, height).into_bytes()
+}
+
+pub fn write_frame<W: Write>(out: &mut W, frame: &Frame) -> Result<(), String> {
+    let mut planes = vec![0u8; frame.data.len() * 3];
+    let len = frame.data.len();
+    for (i, rgb) in frame.data.iter().enumerate() {
+        let (y, u, v) = to_yuv(*rgb);
+        planes[i] = y;
+        planes[len + i] = u;
+        planes[len * 2 + i] = v;
+    }
+    out.write_all(b"FRAME\n").map_err(|e| e.to_string())?;
+    out.write_all(&planes).map_err(|e| e.to_string())
+}
+
+fn to_yuv(rgb: Rgb) -> (u8, u8, u8) {
+    let r = rgb.0 as i32;
+    let g = rgb.1 as i32;
+    let b = rgb.2 as i32;
+    let y = (66 * r + 129 * g + 25 * b + 128) / 256 + 16;
+    let u = (-38 * r - 74 * g + 112 * b + 128) / 256 + 128;
+    let v = (112 * r - 94 * g - 18 * b + 128) / 256 + 128;
+    (y as u8, u as u8, v as u8)
+}
+
+#[test]
+fn test_to_yuv() {
+    assert_eq!(to_yuv(Rgb(0, 0, 0)), (16, 128, 128));
+    assert_eq!(to_yuv(Rgb(255, 255, 255)), (235, 128, 128));
+}
+
+#[test]
+fn test_write_frame() {
+    let frame = Frame { width: 2, height: 1, data: vec![Rgb(0, 0, 0), Rgb(255, 255, 255)] };
+    let mut out = header(2, 1);
+    write_frame(&mut out, &frame).unwrap();
+    let mut expected = b"YUV4MPEG2 W2 H1 F60:1 Ip A1:1 C444\nFRAME\n".to_vec();
+    expected.extend_from_slice(&[16, 235, 128, 128, 128, 128]);
+    assert_eq!(out, expected);
+}
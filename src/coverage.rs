@@ -0,0 +1,324 @@
+// Which instructions ran, and which way each skip went.
+//
+//   chip8 PONG --coverage pong.cov
+//   chip8 coverage merge <out> <in>...
+//   chip8 coverage report <rom> <data>... [--format listing|lcov]
+//                         [--symbols FILE] [--source FILE] [--output FILE]
+//
+// Coverage files are the magic `C8CV` and a version byte (1), then for each
+// of the 4096 addresses the times an instruction ran from it, then the times
+// it skipped the next one, all u32 little-endian. Merging adds the counts.
+//
+// The listing shows the ROM's code, found by following control flow from
+// 0x200 and from every address that ran, with how often each instruction
+// ran: `#####` for never, and `!` beside skips that only ever went one way.
+// lcov reports hang the counts on source lines: with `--source` the program
+// is compiled for its line numbers; with only `--symbols` the ROM stands in
+// for the source and each code address is its own line number.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use disasm::{self, analyse, word, Kind, Labels, Syntax, ORIGIN};
+use opcodes::Op;
+
+
+const MAGIC: &'static [u8] = b"C8CV";
+const VERSION: u8 = 1;
+const ADDRESSES: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coverage {
+    // Executions of the instruction at each address.
+    pub hits: Vec<u32>,
+    // Executions of a skip instruction that skipped.
+    pub skips: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Listing,
+    Lcov,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Result<Format, String> {
+        match name.to_lowercase().as_str() {
+            "listing" => Ok(Format::Listing),
+            "lcov"    => Ok(Format::Lcov),
+            _ => Err(format!("Unknown coverage format: {}", name)),
+        }
+    }
+}
+
+fn is_skip(op: Op) -> bool {
+    match op {
+        Op::SeByte(..) | Op::SneByte(..) | Op::SeReg(..) | Op::SneReg(..) | Op::Skp(_) | Op::Sknp(_) => true,
+        _ => false,
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            hits: vec![0; ADDRESSES],
+            skips: vec![0; ADDRESSES],
+        }
+    }
+    // Called by the CPU after each instruction, with the address it ran
+    // from and the PC it left behind.
+    pub fn record(&mut self, pc: u16, op: Op, next: u16) {
+        let at = pc as usize % ADDRESSES;
+        self.hits[at] = self.hits[at].saturating_add(1);
+        if is_skip(op) && next == pc.wrapping_add(4) {
+            self.skips[at] = self.skips[at].saturating_add(1);
+        }
+    }
+    pub fn merge(&mut self, other: &Coverage) {
+        for at in 0..ADDRESSES {
+            self.hits[at] = self.hits[at].saturating_add(other.hits[at]);
+            self.skips[at] = self.skips[at].saturating_add(other.skips[at]);
+        }
+    }
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        for &count in self.hits.iter().chain(self.skips.iter()) {
+            out.extend_from_slice(&[count as u8, (count >> 8) as u8, (count >> 16) as u8, (count >> 24) as u8]);
+        }
+        out
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Coverage, String> {
+        if bytes.len() < MAGIC.len() + 1 || &bytes[..MAGIC.len()] != MAGIC {
+            return Err("Not a coverage file".to_owned());
+        }
+        if bytes[MAGIC.len()] != VERSION {
+            return Err(format!("Unsupported coverage version {}", bytes[MAGIC.len()]));
+        }
+        let counts = &bytes[MAGIC.len() + 1..];
+        if counts.len() != ADDRESSES * 8 {
+            return Err(format!("Coverage data is {} bytes, expected {}", counts.len(), ADDRESSES * 8));
+        }
+        let count = |i: usize| {
+            let b = &counts[i * 4..i * 4 + 4];
+            b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24
+        };
+        Ok(Coverage {
+            hits: (0..ADDRESSES).map(|i| count(i)).collect(),
+            skips: (0..ADDRESSES).map(|i| count(ADDRESSES + i)).collect(),
+        })
+    }
+    pub fn read(path: &Path) -> Result<Coverage, String> {
+        let mut bytes = Vec::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        Coverage::from_bytes(&bytes).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        File::create(path)?.write_all(&self.bytes())
+    }
+    // Times the skip at `address` skipped and fell through.
+    fn directions(&self, address: u16) -> (u32, u32) {
+        let at = address as usize;
+        (self.skips[at], self.hits[at].saturating_sub(self.skips[at]))
+    }
+}
+
+// Addresses inside the ROM that hold code, in order.
+fn code(rom: &[u8], coverage: &Coverage) -> Vec<u16> {
+    let end = ORIGIN as usize + rom.len();
+    let entries: Vec<u16> = (ORIGIN as usize..end).filter(|&a| coverage.hits[a] > 0).map(|a| a as u16).collect();
+    let analysis = analyse(rom, &entries);
+    (0..rom.len()).filter(|&o| analysis.kinds[o] == Kind::Code).map(|o| ORIGIN + o as u16).collect()
+}
+
+fn skip_at(rom: &[u8], address: u16) -> bool {
+    Op::decode(word(rom, (address - ORIGIN) as usize)).map(is_skip).unwrap_or(false)
+}
+
+fn percent(part: usize, whole: usize) -> f64 {
+    part as f64 * 100.0 / whole.max(1) as f64
+}
+
+// `symbols` name addresses ahead of the generated `sub_`/`label_` names.
+pub fn listing(rom: &[u8], coverage: &Coverage, symbols: &Labels) -> String {
+    let code = code(rom, coverage);
+    let mut labels = disasm::labels(rom);
+    labels.extend(symbols.iter().map(|(&address, name)| (address, name.clone())));
+
+    let skips: Vec<u16> = code.iter().cloned().filter(|&a| skip_at(rom, a)).collect();
+    let ran = code.iter().filter(|&&a| coverage.hits[a as usize] > 0).count();
+    let taken: usize = skips.iter()
+        .map(|&a| coverage.directions(a))
+        .map(|(skipped, fell)| (skipped > 0) as usize + (fell > 0) as usize)
+        .sum();
+    let mut out = format!("Instructions: {} of {} ({:.1}%)\nBranches: {} of {} ({:.1}%)\n\n",
+                          ran, code.len(), percent(ran, code.len()),
+                          taken, skips.len() * 2, percent(taken, skips.len() * 2));
+
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = ORIGIN + offset as u16;
+        if let Some(name) = labels.get(&address) {
+            out.push_str(&format!("{}:\n", name));
+        }
+        if code.binary_search(&address).is_err() {
+            let mut end = offset + 1;
+            while end < rom.len() && code.binary_search(&(ORIGIN + end as u16)).is_err() &&
+                  !labels.contains_key(&(ORIGIN + end as u16)) {
+                end += 1;
+            }
+            out.push_str(&format!("{:>9}   0x{:03X}  {} bytes of data\n", "-", address, end - offset));
+            offset = end;
+            continue;
+        }
+        let value = word(rom, offset);
+        let hits = coverage.hits[address as usize];
+        let count = if hits == 0 { "#####".to_owned() } else { hits.to_string() };
+        let mut text = disasm::disassemble(value, Syntax::Cowgod, &labels);
+        let mut flag = ' ';
+        if hits > 0 && skips.contains(&address) {
+            let (skipped, fell) = coverage.directions(address);
+            if skipped == 0 || fell == 0 {
+                flag = '!';
+            }
+            text = format!("{:<24} ; skipped {} of {}", text, skipped, hits);
+        }
+        out.push_str(&format!("{:>9} {} 0x{:03X}  {:04X}  {}\n", count, flag, address, value, text));
+        offset += 2;
+    }
+    let end = ORIGIN as usize + rom.len();
+    let outside = (0..ADDRESSES).filter(|&a| (a < ORIGIN as usize || a >= end) && coverage.hits[a] > 0).count();
+    if outside > 0 {
+        out.push_str(&format!("; {} instructions ran outside the ROM\n", outside));
+    }
+    out
+}
+
+// `lines` is a compiled program's source map; without one, every code
+// address is a line of `name`.
+pub fn lcov(rom: &[u8], name: &str, coverage: &Coverage, symbols: &Labels,
+            lines: Option<&BTreeMap<u16, (String, usize)>>) -> String {
+    let lines = match lines {
+        Some(lines) => lines.clone(),
+        None => code(rom, coverage).into_iter().map(|a| (a, (name.to_owned(), a as usize))).collect(),
+    };
+    // File, then line, then the instructions on it.
+    let mut files: BTreeMap<&str, BTreeMap<usize, Vec<u16>>> = BTreeMap::new();
+    for (&address, &(ref file, line)) in lines.iter() {
+        files.entry(file.as_str()).or_insert_with(BTreeMap::new).entry(line).or_insert_with(Vec::new).push(address);
+    }
+    let skip = |address: u16| {
+        address >= ORIGIN && ((address - ORIGIN) as usize) < rom.len() && skip_at(rom, address)
+    };
+
+    let mut out = String::new();
+    for (file, file_lines) in files {
+        out.push_str(&format!("TN:\nSF:{}\n", file));
+        let functions: Vec<(&String, u16, usize)> = symbols.iter()
+            .filter_map(|(&address, name)| match lines.get(&address) {
+                Some(&(ref f, line)) if f == file => Some((name, address, line)),
+                _ => None,
+            })
+            .collect();
+        for &(name, _, line) in functions.iter() {
+            out.push_str(&format!("FN:{},{}\n", line, name));
+        }
+        for &(name, address, _) in functions.iter() {
+            out.push_str(&format!("FNDA:{},{}\n", coverage.hits[address as usize], name));
+        }
+        let hit = functions.iter().filter(|&&(_, a, _)| coverage.hits[a as usize] > 0).count();
+        out.push_str(&format!("FNF:{}\nFNH:{}\n", functions.len(), hit));
+
+        let (mut branches, mut branches_hit) = (0, 0);
+        for (&line, addresses) in file_lines.iter() {
+            for &address in addresses.iter().filter(|&&a| skip(a)) {
+                let (skipped, fell) = coverage.directions(address);
+                for (branch, &count) in [skipped, fell].iter().enumerate() {
+                    let taken = if coverage.hits[address as usize] == 0 { "-".to_owned() } else { count.to_string() };
+                    out.push_str(&format!("BRDA:{},{},{},{}\n", line, address, branch, taken));
+                    branches += 1;
+                    branches_hit += (count > 0) as usize;
+                }
+            }
+        }
+        out.push_str(&format!("BRF:{}\nBRH:{}\n", branches, branches_hit));
+
+        let mut lines_hit = 0;
+        for (&line, addresses) in file_lines.iter() {
+            let count = addresses.iter().map(|&a| coverage.hits[a as usize]).max().unwrap_or(0);
+            out.push_str(&format!("DA:{},{}\n", line, count));
+            lines_hit += (count > 0) as usize;
+        }
+        out.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", file_lines.len(), lines_hit));
+    }
+    out
+}
+
+#[cfg(test)]
+fn run(program: &[u8], cycles: usize) -> Coverage {
+    use cpu::CPU;
+    let mut cpu = CPU::headless();
+    cpu.load_program(program);
+    cpu.coverage = Some(Coverage::new());
+    for _ in 0..cycles {
+        cpu.cycle();
+    }
+    cpu.coverage.unwrap()
+}
+
+#[cfg(test)]
+const PROGRAM: [u8; 12] = [
+    0x70, 0x01,     // 200: ADD V0, 1
+    0x30, 0x03,     // 202: SE V0, 3
+    0x12, 0x00,     // 204: JP 0x200
+    0x12, 0x06,     // 206: JP 0x206
+    0x00, 0xE0,     // 208: CLS (never runs)
+    0xF0, 0x0F,     // 20A: data
+];
+
+#[test]
+fn test_coverage_recording() {
+    let coverage = run(&PROGRAM, 10);
+    assert_eq!(&coverage.hits[0x200..0x20A], &[3, 0, 3, 0, 2, 0, 2, 0, 0, 0]);
+    assert_eq!(coverage.directions(0x202), (1, 2));
+
+    let mut merged = run(&PROGRAM, 3);
+    merged.merge(&coverage);
+    assert_eq!(merged.hits[0x200], 4);
+    assert_eq!(Coverage::from_bytes(&merged.bytes()), Ok(merged.clone()));
+    assert!(Coverage::from_bytes(b"C8CV\x01").is_err());
+
+    // A hand-made file can claim more skips than hits.
+    merged.skips[0x202] = merged.hits[0x202] + 1;
+    assert_eq!(merged.directions(0x202).1, 0);
+}
+
+#[test]
+fn test_coverage_reports() {
+    let coverage = run(&PROGRAM, 3);
+    let mut symbols = Labels::new();
+    symbols.insert(0x200, "count".to_owned());
+    let text = listing(&PROGRAM, &coverage, &symbols);
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines[0], "Instructions: 3 of 4 (75.0%)");
+    assert_eq!(lines[1], "Branches: 1 of 2 (50.0%)");
+    assert_eq!(lines[3], "count:");
+    assert_eq!(lines[5], "        1 ! 0x202  3003  SE V0, 0x03              ; skipped 0 of 1");
+    assert_eq!(lines[8], "    #####   0x206  1206  JP label_206");
+    assert_eq!(lines[9], "        -   0x208  4 bytes of data");
+
+    let report = lcov(&PROGRAM, "test.ch8", &coverage, &symbols, None);
+    assert!(report.starts_with("TN:\nSF:test.ch8\nFN:512,count\nFNDA:1,count\nFNF:1\nFNH:1\n"));
+    assert!(report.contains("BRDA:514,514,0,0\nBRDA:514,514,1,1\nBRF:2\nBRH:1\n"));
+    assert!(report.ends_with("DA:518,0\nLF:4\nLH:3\nend_of_record\n"));
+
+    let mut source = BTreeMap::new();
+    source.insert(0x200, ("count.8o".to_owned(), 3));
+    source.insert(0x202, ("count.8o".to_owned(), 4));
+    source.insert(0x204, ("count.8o".to_owned(), 4));
+    let report = lcov(&PROGRAM, "test.ch8", &coverage, &symbols, Some(&source));
+    assert!(report.starts_with("TN:\nSF:count.8o\nFN:3,count\n"));
+    assert!(report.ends_with("DA:3,1\nDA:4,1\nLF:2\nLH:2\nend_of_record\n"));
+}
@@ -3,13 +3,17 @@ extern crate sdl2;
 
 use std::fs::File;
 use std::io::Read;
-use opcodes::{Instruction, Opcode};
+use opcodes::{Op, Opcode};
 use device::Device;
 use utils::Timer;
 use std::thread::sleep;
 use std::time::Duration;
 use self::rand::random;
 use utils::Stack;
+use trace::Tracer;
+use profile::Profiler;
+use coverage::Coverage;
+use quirks::Quirks;
 
 
 pub struct CPU<'cpu> {
@@ -21,11 +25,68 @@ pub struct CPU<'cpu> {
     pub stack: Stack,
     pub opcode: Opcode,
     pub pc: u16,
+    pub cycles: u64,
     pub device: Device<'cpu>,
     pub delay_timer: Timer,
     pub sound_timer: Timer,
+    // When set, every register, index and memory access made by the current
+    // instruction is recorded in `accesses`, and a sprite it drew in `draw`.
+    pub watching: bool,
+    pub accesses: Vec<Access>,
+    pub draw: Option<Draw>,
+    // Streams a record of every instruction before it runs.
+    pub tracer: Option<Tracer>,
+    // Counts every instruction before it runs.
+    pub profiler: Option<Profiler>,
+    // Counts every instruction and which way each skip went.
+    pub coverage: Option<Coverage>,
+    pub quirks: Quirks,
+    // Timers count down once per frame instead of by wall clock, and RND
+    // draws from a seeded generator, so that runs can be replayed exactly.
+    pub deterministic: bool,
+    rng: u32,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Location {
+    Reg(usize),
+    Index,
+    Mem(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Access {
+    pub location: Location,
+    pub kind: AccessKind,
+    pub old: u16,
+    pub new: u16,
+    pub pc: u16,
+    pub opcode: u16,
+}
+
+// A DXYN: where it drew, from where, and the pixels its set bits landed on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Draw {
+    pub pc: u16,
+    // VX and VY as the instruction read them.
+    pub x: u8,
+    pub y: u8,
+    pub height: u8,
+    pub address: u16,
+    pub collision: bool,
+    pub pixels: Vec<usize>,
+}
+
+const STATE_MAGIC: &'static [u8] = b"C8ST";
+const STATE_VERSION: u8 = 1;
+const STATE_HEADER: usize = 5;
+
 const FONT_SET: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -47,6 +108,12 @@ const FONT_SET: [u8; 80] = [
 
 impl<'cpu> CPU <'cpu>{
     pub fn new() -> CPU<'cpu> {
+        CPU::with_device(Device::new())
+    }
+    pub fn headless() -> CPU<'cpu> {
+        CPU::with_device(Device::headless())
+    }
+    pub fn with_device(device: Device<'cpu>) -> CPU<'cpu> {
         let hz = 500;
         let pg = Duration::new(0, ((1.0 / hz as f64) * 1000000000.0) as u32);
         let mut cpu = CPU {
@@ -58,9 +125,19 @@ impl<'cpu> CPU <'cpu>{
             index:  0x200,
             opcode: Opcode::from_code(0),
             pc:     0x200,
+            cycles: 0,
             delay_timer: Timer::new(16_666_667),
             sound_timer: Timer::new(2_000_000),
-            device: Device::new(),
+            device: device,
+            watching: false,
+            accesses: Vec::new(),
+            draw: None,
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            quirks: Quirks::new(),
+            deterministic: false,
+            rng: 0x2A6F_1D35,
         };
         cpu.set_fonts();
         cpu.opcode = cpu.opcode_at_address(0x200);
@@ -73,6 +150,7 @@ impl<'cpu> CPU <'cpu>{
         self.stack.clear();
         self.index = 0x200;
         self.pc = 0x200;
+        self.cycles = 0;
         self.delay_timer = Timer::new(16_666_667);
         self.sound_timer = Timer::new(2_000_000);
     }
@@ -89,6 +167,25 @@ impl<'cpu> CPU <'cpu>{
             self.cycle();
         }
     }
+    // Runs a fixed number of 60Hz frames as fast as possible, for headless use.
+    // Timers tick once per frame, so captures don't depend on the host's speed.
+    pub fn run_frames(&mut self, frames: u32) {
+        self.deterministic = true;
+        for _ in 0..frames {
+            if self.device.quit {
+                break;
+            }
+            for _ in 0..self.cycles_per_frame() {
+                self.cycle();
+            }
+        }
+    }
+    pub fn cycles_per_frame(&self) -> u64 {
+        match self.hz / 60 {
+            0 => 1,
+            n => n as u64,
+        }
+    }
     pub fn set_speed_hz(&mut self, hertz: u32) {
         if hertz > 1 {
             self.program_delay = Duration::new(0, ((1.0 / hertz as f64) * 1000000000.0) as u32);
@@ -96,71 +193,223 @@ impl<'cpu> CPU <'cpu>{
         }
     }
     pub fn cycle(&mut self) {
-        match self.sound_timer.get_delay() {
-            0 => self.device.audio.pause(),
-            _ => self.device.audio.resume(),
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.record(self);
+            self.tracer = Some(tracer);
+        }
+        if let Some(mut profiler) = self.profiler.take() {
+            profiler.record(self);
+            self.profiler = Some(profiler);
         }
+        let beeping = self.sound_timer.get_delay() != 0;
+        self.device.beep(beeping);
         let pc = self.pc as usize;
         self.opcode = self.opcode_at_address(pc);
+        self.accesses.clear();
+        self.draw = None;
         self.device.pump();
-        self.delay_timer.touch();
-        self.sound_timer.touch();
+        if !self.deterministic {
+            self.delay_timer.touch();
+            self.sound_timer.touch();
+        }
         self.run_opcode_instruction();
+        if let Some(ref mut coverage) = self.coverage {
+            coverage.record(pc as u16, self.opcode.op, self.pc);
+        }
+        self.cycles += 1;
+        if self.cycles % self.cycles_per_frame() == 0 {
+            if self.deterministic {
+                self.delay_timer.tick();
+                self.sound_timer.tick();
+            }
+            self.device.end_frame();
+        }
+    }
+    pub fn seed(&mut self, seed: u32) {
+        self.deterministic = true;
+        self.rng = if seed == 0 { 1 } else { seed };
+    }
+    fn random_byte(&mut self) -> u8 {
+        if !self.deterministic {
+            return random::<u8>();
+        }
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng >> 24) as u8
     }
     pub fn load_rom(&mut self, filepath: &str) {
         let mut rom: Vec<u8> = Vec::new();
         let mut file = File::open(filepath).unwrap();
         file.read_to_end(&mut rom);
-
-        for (i, byte) in rom.iter().enumerate() {
+        self.load_program(&rom);
+    }
+    pub fn load_program(&mut self, program: &[u8]) {
+        for (i, byte) in program.iter().enumerate() {
             self.mem[i + 512] = *byte;
         }
     }
+    // Snapshot layout: "C8ST", version, 4096 bytes of memory, V0-VF, I, PC,
+    // 16 stack slots, stack index (0xFF when empty), DT, ST, then one byte
+    // per screen pixel. Multi-byte values are big-endian.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(STATE_MAGIC);
+        out.push(STATE_VERSION);
+        out.extend_from_slice(&self.mem);
+        out.extend_from_slice(&self.regs);
+        let mut words = vec![self.index, self.pc];
+        words.extend((0..16).map(|i| self.stack.peek(i)));
+        for value in words {
+            out.push((value >> 8) as u8);
+            out.push(value as u8);
+        }
+        out.push(self.stack.current_index().map(|i| i as u8).unwrap_or(0xFF));
+        out.push(self.delay_timer.get_delay());
+        out.push(self.sound_timer.get_delay());
+        out.extend(self.device.display.pixels().iter().map(|p| *p as u8));
+        out
+    }
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let pixels = self.device.display.pixels().len();
+        if data.len() != STATE_HEADER + 4096 + 16 + 36 + 3 + pixels || &data[..4] != STATE_MAGIC {
+            return Err("Not a CHIP-8 state snapshot".to_owned());
+        }
+        if data[4] != STATE_VERSION {
+            return Err(format!("Unsupported snapshot version {}", data[4]));
+        }
+        let sp = data[STATE_HEADER + 4096 + 16 + 36];
+        if sp >= 16 && sp != 0xFF {
+            return Err(format!("Bad stack pointer {} in snapshot", sp));
+        }
+        let mut at = STATE_HEADER;
+        self.mem.copy_from_slice(&data[at..at + 4096]);
+        at += 4096;
+        self.regs.copy_from_slice(&data[at..at + 16]);
+        at += 16;
+        let words: Vec<u16> = data[at..at + 36].chunks(2)
+            .map(|pair| (pair[0] as u16) << 8 | pair[1] as u16)
+            .collect();
+        at += 36;
+        self.index = words[0] & 0xFFF;
+        self.pc = words[1] & 0xFFF;
+        let mut stack = [0; 16];
+        stack.copy_from_slice(&words[2..]);
+        let sp = match sp {
+            0xFF => None,
+            sp => Some(sp as usize),
+        };
+        self.stack.restore(stack, sp);
+        self.delay_timer.set_delay(data[at + 1]);
+        self.sound_timer.set_delay(data[at + 2]);
+        at += 3;
+        let screen: Vec<bool> = data[at..].iter().map(|p| *p != 0).collect();
+        self.device.display.set_pixels(&screen);
+        // A snapshot taken while paused on data keeps the last instruction.
+        if let Ok(opcode) = Opcode::decode(self.word_at(self.pc)) {
+            self.opcode = opcode;
+        }
+        Ok(())
+    }
     fn set_fonts(&mut self) {
         for (i, byte) in FONT_SET.into_iter().enumerate() {
             self.mem[i] = *byte;
         }
     }
     pub fn opcode_at_address(&self, address: usize) -> Opcode {
-        Opcode::from_bytes(self.mem[address], self.mem[address + 1])
+        Opcode::from_code(self.word_at(address as u16))
+    }
+    // The big-endian word at `address`, wrapping around the end of memory.
+    pub fn word_at(&self, address: u16) -> u16 {
+        let address = address as usize & 0xFFF;
+        (self.mem[address] as u16) << 8 | self.mem[(address + 1) & 0xFFF] as u16
+    }
+    // The instruction at PC, or an error when PC is on data.
+    pub fn next_op(&self) -> Result<Op, String> {
+        Op::decode(self.word_at(self.pc))
+    }
+    fn report(&mut self, location: Location, kind: AccessKind, old: u16, new: u16) {
+        if self.watching {
+            self.accesses.push(Access {
+                location: location,
+                kind: kind,
+                old: old,
+                new: new,
+                pc: self.pc,
+                opcode: self.opcode.value,
+            });
+        }
+    }
+    // Opcode handlers touch registers, I and memory only through these so
+    // that watchpoints see every access.
+    fn reg(&mut self, x: usize) -> u8 {
+        let value = self.regs[x];
+        self.report(Location::Reg(x), AccessKind::Read, value as u16, value as u16);
+        value
+    }
+    fn set_reg(&mut self, x: usize, value: u8) {
+        let old = self.regs[x];
+        self.regs[x] = value;
+        self.report(Location::Reg(x), AccessKind::Write, old as u16, value as u16);
+    }
+    fn index_reg(&mut self) -> u16 {
+        let value = self.index;
+        self.report(Location::Index, AccessKind::Read, value, value);
+        value
+    }
+    fn set_index(&mut self, value: u16) {
+        let old = self.index;
+        self.index = value;
+        self.report(Location::Index, AccessKind::Write, old, value);
+    }
+    fn read_mem(&mut self, address: usize) -> u8 {
+        let value = self.mem[address];
+        self.report(Location::Mem(address as u16), AccessKind::Read, value as u16, value as u16);
+        value
+    }
+    fn write_mem(&mut self, address: usize, value: u8) {
+        let old = self.mem[address];
+        self.mem[address] = value;
+        self.report(Location::Mem(address as u16), AccessKind::Write, old as u16, value as u16);
     }
     pub fn run_opcode_instruction<'a>(&mut self) {
-        match self.opcode.instr {
-            Instruction::SysAddressJump_0x0NNN           =>  self.system_address_jump(),
-            Instruction::ClearDisplay_0x00E0             =>  self.clear_display(),
-            Instruction::RetFromSubroutine_0x00EE        =>  self.return_from_sub(),
-            Instruction::JumpLocation_0x1NNN             =>  self.jump_to_location(),
-            Instruction::CallSubroutine_0x2NNN           =>  self.call_subroutine(),
-            Instruction::SkipInstrIfVxEqPL_0x3XNN        =>  self.skip_instr_if_vx_eq_pl(),
-            Instruction::SkipInstrIfVxNotEqPL_0x4XNN     =>  self.skip_instr_if_vx_neq_pl(),
-            Instruction::SkipInstrIfVxVy_0x5XY0          =>  self.skip_instr_if_vx_eq_vy(),
-            Instruction::SetVxToPL_0x6XNN                =>  self.set_vx_to_pl(),
-            Instruction::IncrementVxByPL_0x7XNN          =>  self.increment_vx_by_pl(),
-            Instruction::SetVxToVy_0x8XY0                =>  self.set_vx_to_vy(),
-            Instruction::SetVxToVxORVy_0x8XY1            =>  self.set_vx_to_vx_or_vy(),
-            Instruction::SetVxToVxANDVy_0x8XY2           =>  self.set_vx_to_vx_and_vy(),
-            Instruction::SetVxToVxXORVy_0x8XY3           =>  self.set_vx_to_vx_xor_vy(),
-            Instruction::IncrementVxByVyAndCarry_0x8XY4  =>  self.increment_vx_by_vy_carry(),
-            Instruction::DecrementVxByVyNoBorrow_0x8XY5  =>  self.decrement_vx_by_vy_no_borrow(),
-            Instruction::ShiftAndRotateVxRight_0x8XY6    =>  self.shift_and_rotate_vx_right(),
-            Instruction::DecrementVyByVxNoBorrow_0x8XY7  =>  self.decrement_vy_by_vx_no_borrow(),
-            Instruction::ShiftAndRotateVxLeft_0x8XYE     =>  self.shift_and_rotate_vx_left(),
-            Instruction::SkipInstrIfVxNotVy_0x9XY0       =>  self.skip_instr_if_vx_not_vy(),
-            Instruction::SetIndexRegToPL_0xANNN          =>  self.set_index_register_to_pl(),
-            Instruction::JumpToV0PlusPL_0xBNNN           =>  self.jump_to_v0_plus_pl(),
-            Instruction::SetVxRandByteANDPL_0xCXNN       =>  self.set_vx_rand_byte_and_pl(),
-            Instruction::DisplaySpriteSetVfColl_0xDXYN   =>  self.display_sprite_set_vf_collision(),
-            Instruction::SkipInstrIfVxPressed_0xEX9E     =>  self.skip_instr_if_vx_pressed(),
-            Instruction::SkipInstrIfVxNotPressed_0xEXA1  =>  self.skip_instr_if_vx_not_pressed(),
-            Instruction::SetVxToDelayTimerVal_0xFX07     =>  self.set_vx_to_delay_timer_val(),
-            Instruction::WaitForKeyStoreInVx_0xFX0A      =>  self.wait_for_key_and_store_in_vx(),
-            Instruction::SetDelayTimerToVx_0xFX15        =>  self.set_delay_timer_to_vx(),
-            Instruction::SetSoundTimerToVx_0xFX18        =>  self.set_sound_timer_to_vx(),
-            Instruction::IncrementIndexRegByVx_0xFX1E    =>  self.increment_index_register_by_vx(),
-            Instruction::SetIndexRegToVxSprite_0xFX29    =>  self.set_index_register_to_vx_sprite(),
-            Instruction::StoreBCDOfVxIn3Bytes_0xFX33     =>  self.store_bcd_of_vx_3bytes(),
-            Instruction::StoreRegsUptoVx_0xFX55          =>  self.store_registers_through_vx(),
-            Instruction::ReadRegsUptoVx_0xFX65           =>  self.read_registers_through_vx(),   
+        match self.opcode.op {
+            Op::Sys(_)          =>  self.system_address_jump(),
+            Op::Cls             =>  self.clear_display(),
+            Op::Ret             =>  self.return_from_sub(),
+            Op::Jp(nnn)         =>  self.jump_to_location(nnn),
+            Op::Call(nnn)       =>  self.call_subroutine(nnn),
+            Op::SeByte(x, kk)   =>  self.skip_instr_if_vx_eq_pl(x, kk),
+            Op::SneByte(x, kk)  =>  self.skip_instr_if_vx_neq_pl(x, kk),
+            Op::SeReg(x, y)     =>  self.skip_instr_if_vx_eq_vy(x, y),
+            Op::LdByte(x, kk)   =>  self.set_vx_to_pl(x, kk),
+            Op::AddByte(x, kk)  =>  self.increment_vx_by_pl(x, kk),
+            Op::LdReg(x, y)     =>  self.set_vx_to_vy(x, y),
+            Op::Or(x, y)        =>  self.set_vx_to_vx_or_vy(x, y),
+            Op::And(x, y)       =>  self.set_vx_to_vx_and_vy(x, y),
+            Op::Xor(x, y)       =>  self.set_vx_to_vx_xor_vy(x, y),
+            Op::AddReg(x, y)    =>  self.increment_vx_by_vy_carry(x, y),
+            Op::Sub(x, y)       =>  self.decrement_vx_by_vy_no_borrow(x, y),
+            Op::Shr(x, y)       =>  self.shift_and_rotate_vx_right(x, y),
+            Op::Subn(x, y)      =>  self.decrement_vy_by_vx_no_borrow(x, y),
+            Op::Shl(x, y)       =>  self.shift_and_rotate_vx_left(x, y),
+            Op::SneReg(x, y)    =>  self.skip_instr_if_vx_not_vy(x, y),
+            Op::LdI(nnn)        =>  self.set_index_register_to_pl(nnn),
+            Op::JpV0(nnn)       =>  self.jump_to_v0_plus_pl(nnn),
+            Op::Rnd(x, kk)      =>  self.set_vx_rand_byte_and_pl(x, kk),
+            Op::Drw(x, y, n)    =>  self.display_sprite_set_vf_collision(x, y, n),
+            Op::Skp(x)          =>  self.skip_instr_if_vx_pressed(x),
+            Op::Sknp(x)         =>  self.skip_instr_if_vx_not_pressed(x),
+            Op::LdVxDt(x)       =>  self.set_vx_to_delay_timer_val(x),
+            Op::LdVxK(x)        =>  self.wait_for_key_and_store_in_vx(x),
+            Op::LdDtVx(x)       =>  self.set_delay_timer_to_vx(x),
+            Op::LdStVx(x)       =>  self.set_sound_timer_to_vx(x),
+            Op::AddIVx(x)       =>  self.increment_index_register_by_vx(x),
+            Op::LdFVx(x)        =>  self.set_index_register_to_vx_sprite(x),
+            Op::LdBVx(x)        =>  self.store_bcd_of_vx_3bytes(x),
+            Op::StoreRegs(x)    =>  self.store_registers_through_vx(x),
+            Op::ReadRegs(x)     =>  self.read_registers_through_vx(x),
         }
     }
     fn system_address_jump(&mut self) {
@@ -180,202 +429,220 @@ impl<'cpu> CPU <'cpu>{
         self.device.clear_display();
         self.pc += 2;
     }
-    fn jump_to_location(&mut self) {
+    fn jump_to_location(&mut self, nnn: u16) {
         // 1nnn - JP addr
-        self.pc = self.opcode.xyz();
+        self.pc = nnn;
     }
-    fn call_subroutine(&mut self) {
+    fn call_subroutine(&mut self, nnn: u16) {
         // 2nnn - CALL addr
         let pc = self.pc;
         self.stack.push(pc);
-        self.pc = self.opcode.xyz();
+        self.pc = nnn;
     }
-    fn skip_instr_if_vx_eq_pl(&mut self) {
+    fn skip_instr_if_vx_eq_pl(&mut self, x: usize, kk: u8) {
         // 3xkk - SE Vx, byte
-        let vx = self.regs[self.opcode.x()];    
-        if vx == self.opcode.yz() as u8 {
+        let vx = self.reg(x);
+        if vx == kk {
             self.pc += 2;
         }
         self.pc += 2;
     }
-    fn skip_instr_if_vx_neq_pl(&mut self) {
+    fn skip_instr_if_vx_neq_pl(&mut self, x: usize, kk: u8) {
         // 4xkk - SNE Vx, byte
-        let vx = self.regs[self.opcode.x()];
+        let vx = self.reg(x);
 
-        if vx != self.opcode.yz() as u8 {
+        if vx != kk {
             self.pc += 2;
         }
         self.pc += 2;
     }
-    fn skip_instr_if_vx_eq_vy(&mut self) {
+    fn skip_instr_if_vx_eq_vy(&mut self, x: usize, y: usize) {
         // 5xy0 - SE Vx, Vy
-        if self.regs[self.opcode.x()] == self.regs[self.opcode.y()] {
+        if self.reg(x) == self.reg(y) {
             self.pc += 2;
         }
         self.pc += 2;
     }
-    fn set_vx_to_pl(&mut self) {
+    fn set_vx_to_pl(&mut self, x: usize, kk: u8) {
         // 6xkk - LD Vx, byte
-        self.regs[self.opcode.x()] = self.opcode.yz() as u8;
+        self.set_reg(x, kk);
         self.pc += 2;
     }
-    fn increment_vx_by_pl(&mut self) {
+    fn increment_vx_by_pl(&mut self, x: usize, kk: u8) {
         // 7xkk - ADD Vx, byte
-        let x = self.opcode.x();
-        let pl = self.opcode.yz();
-        self.regs[x] = self.regs[x].wrapping_add(pl as u8);
+        let vx = self.reg(x);
+        self.set_reg(x, vx.wrapping_add(kk));
         self.pc += 2;
     }
-    fn set_vx_to_vy(&mut self) {
+    fn set_vx_to_vy(&mut self, x: usize, y: usize) {
         // 8xy0 - LD Vx, Vy
-        self.regs[self.opcode.x()] = self.regs[self.opcode.y()];
+        let vy = self.reg(y);
+        self.set_reg(x, vy);
         self.pc += 2;
     }
-    fn set_vx_to_vx_or_vy(&mut self) {
+    fn set_vx_to_vx_or_vy(&mut self, x: usize, y: usize) {
         // 8xy1 - OR Vx, Vy
-        let x = self.opcode.x();
-        let y = self.opcode.y();
-        self.regs[x] |= self.regs[y];
+        let value = self.reg(x) | self.reg(y);
+        self.set_reg(x, value);
+        if self.quirks.logic_resets_vf {
+            self.set_reg(0xF, 0);
+        }
         self.pc += 2;
     }
-    fn set_vx_to_vx_and_vy(&mut self) {
+    fn set_vx_to_vx_and_vy(&mut self, x: usize, y: usize) {
         // 8xy2 - AND Vx, Vy
-        let x = self.opcode.x();
-        let y = self.opcode.y();
-        self.regs[x] &= self.regs[y];
+        let value = self.reg(x) & self.reg(y);
+        self.set_reg(x, value);
+        if self.quirks.logic_resets_vf {
+            self.set_reg(0xF, 0);
+        }
         self.pc += 2;
     }
-    fn set_vx_to_vx_xor_vy(&mut self) {
+    fn set_vx_to_vx_xor_vy(&mut self, x: usize, y: usize) {
         // 8xy3 - XOR Vx, Vy
-        let x = self.opcode.x();
-        let y = self.opcode.y();
-        self.regs[x] ^= self.regs[y];
+        let value = self.reg(x) ^ self.reg(y);
+        self.set_reg(x, value);
+        if self.quirks.logic_resets_vf {
+            self.set_reg(0xF, 0);
+        }
         self.pc += 2;
     }
-    fn increment_vx_by_vy_carry(&mut self) {
+    fn increment_vx_by_vy_carry(&mut self, x: usize, y: usize) {
         // 8xy4 - ADD Vx, Vy
-        let vx = self.regs[self.opcode.x()];
-        let vy = self.regs[self.opcode.y()];
+        let vx = self.reg(x);
+        let vy = self.reg(y);
 
         if vy > (0xFF - vx) {
-            self.regs[0xF] = 1;
+            self.set_reg(0xF, 1);
         } else {
-            self.regs[0xF] = 0;
+            self.set_reg(0xF, 0);
         }
-        self.regs[self.opcode.x()] = vx.wrapping_add(vy);
+        self.set_reg(x, vx.wrapping_add(vy));
         self.pc += 2;
     }
-    fn decrement_vx_by_vy_no_borrow(&mut self) {
+    fn decrement_vx_by_vy_no_borrow(&mut self, x: usize, y: usize) {
         // 8xy5 - SUB Vx, Vy
-        let vx = self.regs[self.opcode.x()];
-        let vy = self.regs[self.opcode.y()];
+        let vx = self.reg(x);
+        let vy = self.reg(y);
 
         if vx > vy {
-            self.regs[0xF] = 1;
+            self.set_reg(0xF, 1);
         } else {
-            self.regs[0xF] = 0;
+            self.set_reg(0xF, 0);
         }
-        self.regs[self.opcode.x()] = vx.wrapping_sub(vy);
+        self.set_reg(x, vx.wrapping_sub(vy));
 
         self.pc += 2;
     }
-    fn shift_and_rotate_vx_right(&mut self) {
+    fn shift_and_rotate_vx_right(&mut self, x: usize, y: usize) {
         // 8xy6 - SHR Vx {, Vy}
-        let x = self.opcode.x();
-        self.regs[0xF] = self.regs[x] & 0b1;
-        self.regs[x] = self.regs[x] >> 0b1;
+        let vx = self.reg(if self.quirks.shift_in_place { x } else { y });
+        self.set_reg(0xF, vx & 0b1);
+        self.set_reg(x, vx >> 0b1);
         self.pc += 2;
     }
-    fn decrement_vy_by_vx_no_borrow(&mut self) {
+    fn decrement_vy_by_vx_no_borrow(&mut self, x: usize, y: usize) {
         // 8xy7 - SUBN Vx, Vy
-        let x = self.opcode.x();
-        let vx = self.regs[x];
-        let vy = self.regs[self.opcode.y()];
+        let vx = self.reg(x);
+        let vy = self.reg(y);
 
         if vy > vx {
-            self.regs[0xF] = 1;
+            self.set_reg(0xF, 1);
         } else {
-            self.regs[0xF] = 0
+            self.set_reg(0xF, 0);
         }
-        self.regs[x] = vy.wrapping_sub(vx);
+        self.set_reg(x, vy.wrapping_sub(vx));
 
         self.pc += 2;
     }
-    fn shift_and_rotate_vx_left(&mut self) {
+    fn shift_and_rotate_vx_left(&mut self, x: usize, y: usize) {
         // 8xyE - SHL Vx {, Vy}
-        let x = self.opcode.x();
-        self.regs[0xF] = self.regs[x] as u8 >> 7;
-        self.regs[x] = self.regs[x].wrapping_add(self.regs[x]);
+        let vx = self.reg(if self.quirks.shift_in_place { x } else { y });
+        self.set_reg(0xF, vx >> 7);
+        self.set_reg(x, vx.wrapping_add(vx));
         self.pc += 2;
     }
-    fn skip_instr_if_vx_not_vy(&mut self) {
+    fn skip_instr_if_vx_not_vy(&mut self, x: usize, y: usize) {
         // 9xy0 - SNE Vx, Vy
-        let vx = self.regs[self.opcode.x()];
-        let vy = self.regs[self.opcode.y()];
+        let vx = self.reg(x);
+        let vy = self.reg(y);
 
         if vx != vy { self.pc += 2 };
         self.pc += 2;
     }
-    fn set_index_register_to_pl(&mut self) {
+    fn set_index_register_to_pl(&mut self, nnn: u16) {
         // Annn - LD I, addr
-        self.index = self.opcode.xyz();
+        self.set_index(nnn);
         self.pc += 2;
     }
-    fn jump_to_v0_plus_pl(&mut self) {
+    fn jump_to_v0_plus_pl(&mut self, nnn: u16) {
         // Bnnn - JP V0, addr
-        let v0 = self.regs[0] as u16;
-        let nnn = self.opcode.xyz();
+        let x = if self.quirks.jump_vx { (nnn >> 8) as usize } else { 0 };
+        let v0 = self.reg(x) as u16;
         self.pc = nnn.wrapping_add(v0);
     }
-    fn set_vx_rand_byte_and_pl(&mut self) {
+    fn set_vx_rand_byte_and_pl(&mut self, x: usize, kk: u8) {
         // Cxkk - RND Vx, byte
-        let x = self.opcode.x();
-        self.regs[x] = random::<u8>() & self.opcode.yz() as u8;
+        let value = self.random_byte() & kk;
+        self.set_reg(x, value);
         self.pc += 2;
     }
-    fn display_sprite_set_vf_collision(&mut self) {
+    fn display_sprite_set_vf_collision(&mut self, x: usize, y: usize, n: u8) {
         // Dxyn - DRW Vx, Vy, nibble
-        let x = self.regs[self.opcode.x()] as usize;
-        let y = self.regs[self.opcode.y()] as usize;
-        let z = self.opcode.z();
+        let (vx, vy) = (self.reg(x) as usize, self.reg(y) as usize);
 
-        self.regs[0xF] = 0;
+        self.set_reg(0xF, 0);
         let mut new: Vec<u8> = Vec::new();
+        let index = self.index_reg() as usize;
         
-        for i in 0..z {
-            new.push(self.mem[i + self.index as usize]);
+        for i in 0..n as usize {
+            new.push(self.read_mem(i + index));
         }
 
-        self.regs[0xf] = self.device.write_bytes(new, x, y);
+        let clip = self.quirks.clip_sprites;
+        let collision = self.device.write_bytes(new, vx, vy, clip);
+        if self.watching {
+            self.draw = Some(Draw {
+                pc: self.pc,
+                x: vx as u8,
+                y: vy as u8,
+                height: n,
+                address: index as u16,
+                collision: collision != 0,
+                pixels: self.device.display.last_draw().to_vec(),
+            });
+        }
+        self.set_reg(0xf, collision);
         self.device.draw();
         self.pc += 2;
     }
-    fn skip_instr_if_vx_pressed(&mut self) {
+    fn skip_instr_if_vx_pressed(&mut self, x: usize) {
         // Ex9E - SKP Vx
-        let vx = self.regs[self.opcode.x()];
+        let vx = self.reg(x);
         if self.device.keyboard.check_value_pressed(vx) {
             self.pc += 2;
         }
         self.pc += 2;
     }
-    fn skip_instr_if_vx_not_pressed(&mut self) {
+    fn skip_instr_if_vx_not_pressed(&mut self, x: usize) {
         // ExA1 - SKNP Vx
-        let vx = self.regs[self.opcode.x()];
+        let vx = self.reg(x);
         if !self.device.keyboard.check_value_pressed(vx) {
             self.pc += 2;
         }
         self.pc += 2;
     }
-    fn set_vx_to_delay_timer_val(&mut self) {
+    fn set_vx_to_delay_timer_val(&mut self, x: usize) {
         // Fx07 - LD Vx, DT
-        self.regs[self.opcode.x()] = self.delay_timer.get_delay();
+        let delay = self.delay_timer.get_delay();
+        self.set_reg(x, delay);
         self.pc += 2;
     }
-    fn wait_for_key_and_store_in_vx(&mut self) {
+    fn wait_for_key_and_store_in_vx(&mut self, x: usize) {
         // Fx0A - LD Vx, K
         match self.device.keyboard.get_pressed_key() {
             Some(value) => {
-                self.regs[self.opcode.x()] = value;
+                self.set_reg(x, value);
                 self.device.keyboard.reset();
                 self.pc += 2;
             }
@@ -383,56 +650,68 @@ impl<'cpu> CPU <'cpu>{
         }
 
     }
-    fn set_delay_timer_to_vx(&mut self) {
+    fn set_delay_timer_to_vx(&mut self, x: usize) {
         // Fx15 - LD DT, Vx
-        let vx = self.regs[self.opcode.x()];
+        let vx = self.reg(x);
         self.delay_timer.set_delay(vx);
         self.pc += 2;
     }
-    fn set_sound_timer_to_vx(&mut self) {
+    fn set_sound_timer_to_vx(&mut self, x: usize) {
         // Fx18 - LD ST, Vx
-        let vx = self.regs[self.opcode.x()];
+        let vx = self.reg(x);
         self.sound_timer.set_delay(vx);
         self.pc += 2;
     }
-    fn increment_index_register_by_vx(&mut self) {
+    fn increment_index_register_by_vx(&mut self, x: usize) {
         // Fx1E - ADD I, Vx
-        let x = self.opcode.x();
-        let r: u32 = self.index as u32 + self.regs[x] as u32;
-        self.index = (r & 0xFFF) as u16;
-        self.regs[0xf] = (r > 0xFFF) as u8;
+        let r: u32 = self.index_reg() as u32 + self.reg(x) as u32;
+        self.set_index((r & 0xFFF) as u16);
+        if self.quirks.index_overflow_vf {
+            self.set_reg(0xf, (r > 0xFFF) as u8);
+        }
         self.pc += 2;
     }
-    fn set_index_register_to_vx_sprite(&mut self) {
+    fn set_index_register_to_vx_sprite(&mut self, x: usize) {
         // Fx29 - LD F, Vx
-        let vx = self.regs[self.opcode.x()];
-        self.index = (vx * 5) as u16;
+        let vx = self.reg(x);
+        self.set_index((vx * 5) as u16);
         self.pc += 2;
     }
-    fn store_bcd_of_vx_3bytes(&mut self) {
+    fn store_bcd_of_vx_3bytes(&mut self, x: usize) {
         // Fx33 - LD B, Vx
-        let vx = self.regs[self.opcode.x()];
-        self.mem[self.index as usize] = vx / 100;
-        self.mem[self.index as usize + 1] = (vx % 100) / 10;
-        self.mem[self.index as usize + 2] = vx % 10;
+        let vx = self.reg(x);
+        let index = self.index_reg() as usize;
+        self.write_mem(index, vx / 100);
+        self.write_mem(index + 1, (vx % 100) / 10);
+        self.write_mem(index + 2, vx % 10);
 
         self.pc += 2;
     }
-    fn store_registers_through_vx(&mut self) {
+    fn store_registers_through_vx(&mut self, x: usize) {
         // Fx55 - LD [I], Vx
-        let x = self.opcode.x();
+
+        let index = self.index_reg() as usize;
 
         for i in 0..(x + 1) {
-            self.mem[self.index as usize + i] = self.regs[i as usize];
+            let value = self.reg(i);
+            self.write_mem(index + i, value);
+        }
+        if self.quirks.index_increment {
+            self.set_index((index + x + 1) as u16);
         }
         self.pc += 2;
     }
-    fn read_registers_through_vx(&mut self) {
+    fn read_registers_through_vx(&mut self, x: usize) {
         // Fx65 - LD Vx, [I]
-        let x = self.opcode.x();
+
+        let index = self.index_reg() as usize;
 
         for i in 0..(x + 1) {
-            self.regs[i] = self.mem[self.index as usize + i];
+            let value = self.read_mem(index + i);
+            self.set_reg(i, value);
+        }
+        if self.quirks.index_increment {
+            self.set_index((index + x + 1) as u16);
         }
         self.pc += 2;
     }
@@ -478,3 +757,107 @@ pub fn test_return_from_sub() {
     assert_eq!(cpu.pc, 0x202);
     assert_eq!(cpu.sp, 0);
 }
+
+#[test]
+pub fn test_watching_reports_accesses() {
+    let mut cpu = CPU::headless();
+    cpu.watching = true;
+    cpu.regs[0] = 7;
+    cpu.regs[1] = 9;
+    cpu.index = 0x300;
+    cpu.mem[0x200] = 0xF1;
+    cpu.mem[0x201] = 0x55;
+    cpu.cycle();
+    let writes: Vec<&Access> = cpu.accesses.iter().filter(|a| a.kind == AccessKind::Write).collect();
+    assert_eq!(writes.len(), 2);
+    assert_eq!(writes[1].location, Location::Mem(0x301));
+    assert_eq!((writes[1].old, writes[1].new), (0, 9));
+    assert_eq!((writes[1].pc, writes[1].opcode), (0x200, 0xF155));
+    assert!(cpu.accesses.contains(&Access {
+        location: Location::Index, kind: AccessKind::Read, old: 0x300, new: 0x300, pc: 0x200, opcode: 0xF155,
+    }));
+}
+
+#[test]
+pub fn test_watching_records_draws() {
+    let mut cpu = CPU::headless();
+    cpu.watching = true;
+    // LD V1, 62; LD I, 0x300; DRW V1, V2, 2; DRW V1, V2, 1
+    cpu.load_program(&[0x61, 0x3E, 0xA3, 0x00, 0xD1, 0x22, 0xD1, 0x21]);
+    cpu.mem[0x300] = 0xC0;
+    cpu.mem[0x301] = 0x80;
+    cpu.cycle();
+    cpu.cycle();
+    assert_eq!(cpu.draw, None);
+    cpu.cycle();
+    assert_eq!(cpu.draw, Some(Draw {
+        pc: 0x204, x: 62, y: 0, height: 2, address: 0x300, collision: false, pixels: vec![62, 63, 126],
+    }));
+    cpu.cycle();
+    assert_eq!(cpu.draw.as_ref().map(|d| (d.collision, d.pixels.len())), Some((true, 2)));
+}
+
+#[test]
+pub fn test_state_round_trip() {
+    let mut cpu = CPU::headless();
+    cpu.mem[0x200] = 0x22;
+    cpu.mem[0x201] = 0x10;
+    cpu.cycle();
+    cpu.regs[3] = 0x42;
+    cpu.index = 0x321;
+    cpu.device.display.write_bytes(vec![0xF0], 1, 2, false);
+    let state = cpu.save_state();
+
+    let mut other = CPU::headless();
+    other.load_state(&state).unwrap();
+    assert_eq!(other.pc, 0x210);
+    assert_eq!(other.regs[3], 0x42);
+    assert_eq!(other.index, 0x321);
+    assert_eq!(other.stack.current_index(), cpu.stack.current_index());
+    assert_eq!(other.stack.pop(), 0x200);
+    assert_eq!(other.device.display.pixels(), cpu.device.display.pixels());
+    assert_eq!(other.save_state()[..4096 + 5], state[..4096 + 5]);
+
+    assert!(other.load_state(&state[..100]).is_err());
+
+    // Snapshots paused on data load, PC is kept in memory and a bad stack
+    // pointer is refused.
+    let mut data = state.clone();
+    data[STATE_HEADER + 0x300] = 0xFF;
+    data[STATE_HEADER + 0x301] = 0xFF;
+    data[STATE_HEADER + 4096 + 16 + 2] = 0xF3;
+    data[STATE_HEADER + 4096 + 16 + 3] = 0x00;
+    other.load_state(&data).unwrap();
+    assert_eq!(other.pc, 0x300);
+    data[STATE_HEADER + 4096 + 16 + 36] = 200;
+    assert!(other.load_state(&data).is_err());
+}
+
+#[test]
+fn test_run_frames_ticks_timers_per_frame() {
+    // LD V0, 0x10; LD DT, V0; JP 0x204
+    let mut cpu = CPU::headless();
+    cpu.load_program(&[0x60, 0x10, 0xF0, 0x15, 0x12, 0x04]);
+    cpu.run_frames(4);
+    assert_eq!(cpu.delay_timer.get_delay(), 0x10 - 4);
+}
+
+// PC on the last byte of memory, where the word it fetches wraps around to
+// address 0.
+#[cfg(test)]
+pub fn at_last_byte<'a>(word: u16) -> CPU<'a> {
+    let mut cpu = CPU::headless();
+    cpu.mem[0xFFF] = (word >> 8) as u8;
+    cpu.mem[0x000] = word as u8;
+    cpu.pc = 0xFFF;
+    cpu
+}
+
+#[test]
+fn test_word_at_wraps_around_memory() {
+    let cpu = at_last_byte(0x1234);
+    assert_eq!(cpu.word_at(0xFFF), 0x1234);
+    assert_eq!(cpu.word_at(0x1FFF), 0x1234);
+    assert_eq!(cpu.next_op(), Ok(Op::Jp(0x234)));
+    assert!(at_last_byte(0xFFFF).next_op().is_err());
+}
@@ -0,0 +1,251 @@
+use cpu::CPU;
+use debugger::expr::{self, Expr};
+use debugger::symbols::Symbols;
+
+
+// A breakpoint fires when the PC matches `address` (if any) and `condition`
+// (if any) holds, checked before the instruction at PC executes.
+pub struct Breakpoint {
+    pub id: usize,
+    pub address: Option<u16>,
+    // The symbol at `address`, if it has one.
+    pub name: Option<String>,
+    pub condition: Option<(String, Expr)>,
+    pub enabled: bool,
+    pub hits: u32,
+}
+
+impl Breakpoint {
+    // Accepts `0x2A4`, `draw`, `0x2A4 if v3 == 1` and `if v3 == 0x10 && I > 0x300`.
+    pub fn parse(id: usize, spec: &str, symbols: &Symbols) -> Result<Breakpoint, String> {
+        let spec = spec.trim();
+        let (address, condition) = match spec.find("if ") {
+            Some(0) => ("", &spec[3..]),
+            Some(pos) => (&spec[..pos], &spec[pos + 3..]),
+            None => (spec, ""),
+        };
+        let address = match address.trim() {
+            "" => None,
+            text => Some(parse_address(text, symbols)?),
+        };
+        let condition = match condition.trim() {
+            "" => None,
+            text => Some((text.to_owned(), Expr::parse(text, symbols)?)),
+        };
+        if address.is_none() && condition.is_none() {
+            return Err("A breakpoint needs an address or a condition".to_owned());
+        }
+        Ok(Breakpoint {
+            id: id,
+            address: address,
+            name: address.and_then(|address| symbols.name(address)).map(|name| name.to_owned()),
+            condition: condition,
+            enabled: true,
+            hits: 0,
+        })
+    }
+    pub fn matches(&self, cpu: &CPU) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        if let Some(address) = self.address {
+            if cpu.pc != address {
+                return false;
+            }
+        }
+        match self.condition {
+            Some((_, ref expr)) => expr.is_true(cpu),
+            None => true,
+        }
+    }
+    pub fn describe(&self) -> String {
+        let location = match (self.address, &self.name) {
+            (Some(address), &Some(ref name)) => format!("{} (0x{:03X})", name, address),
+            (Some(address), &None) => format!("0x{:03X}", address),
+            (None, _) => "*".to_owned(),
+        };
+        let condition = match self.condition {
+            Some((ref text, _)) => format!(" if {}", text),
+            None => String::new(),
+        };
+        format!("#{} [{}] {}{} ({} hits)",
+                self.id, if self.enabled { "x" } else { " " }, location, condition, self.hits)
+    }
+}
+
+pub struct Breakpoints {
+    list: Vec<Breakpoint>,
+    next_id: usize,
+}
+
+impl Breakpoints {
+    pub fn new() -> Breakpoints {
+        Breakpoints {
+            list: Vec::new(),
+            next_id: 1,
+        }
+    }
+    pub fn iter(&self) -> ::std::slice::Iter<Breakpoint> {
+        self.list.iter()
+    }
+    pub fn add(&mut self, spec: &str, symbols: &Symbols) -> Result<usize, String> {
+        let breakpoint = Breakpoint::parse(self.next_id, spec, symbols)?;
+        self.next_id += 1;
+        self.list.push(breakpoint);
+        Ok(self.next_id - 1)
+    }
+    pub fn remove(&mut self, id: usize) -> Result<(), String> {
+        let len = self.list.len();
+        self.list.retain(|b| b.id != id);
+        match self.list.len() == len {
+            true => Err(format!("No breakpoint #{}", id)),
+            false => Ok(()),
+        }
+    }
+    pub fn set_enabled(&mut self, id: usize, enabled: bool) -> Result<(), String> {
+        match self.list.iter_mut().find(|b| b.id == id) {
+            Some(b) => {
+                b.enabled = enabled;
+                Ok(())
+            },
+            None => Err(format!("No breakpoint #{}", id)),
+        }
+    }
+    pub fn has_address(&self, address: u16) -> bool {
+        self.list.iter().any(|b| b.address == Some(address))
+    }
+    // Removes the plain breakpoints at `address`, or adds one if there are none.
+    pub fn toggle_address(&mut self, address: u16, symbols: &Symbols) -> String {
+        let len = self.list.len();
+        self.list.retain(|b| b.address != Some(address) || b.condition.is_some());
+        match self.list.len() == len {
+            true => {
+                let id = self.add(&format!("0x{:03X}", address), symbols).unwrap();
+                format!("Added {}", self.get(id).unwrap().describe())
+            },
+            false => format!("Removed breakpoint at 0x{:03X}", address),
+        }
+    }
+    // Counts a hit on every matching breakpoint and returns the first one.
+    pub fn check(&mut self, cpu: &CPU) -> Option<usize> {
+        let mut hit = None;
+        for b in self.list.iter_mut() {
+            if b.matches(cpu) {
+                b.hits += 1;
+                hit = hit.or(Some(b.id));
+            }
+        }
+        hit
+    }
+    pub fn get(&self, id: usize) -> Option<&Breakpoint> {
+        self.list.iter().find(|b| b.id == id)
+    }
+    // Runs one line of the breakpoint command language:
+    //   <spec>          add a breakpoint (see Breakpoint::parse)
+    //   enable N        disable N        delete N
+    pub fn command(&mut self, line: &str, symbols: &Symbols) -> Result<String, String> {
+        let mut words = line.trim().splitn(2, ' ');
+        let verb = words.next().unwrap_or("");
+        let rest = words.next().unwrap_or("").trim();
+        match verb {
+            "enable" | "disable" | "delete" => {
+                let id = expr::parse_number(rest.trim_start_matches('#'))? as usize;
+                match verb {
+                    "enable" => self.set_enabled(id, true)?,
+                    "disable" => self.set_enabled(id, false)?,
+                    _ => self.remove(id)?,
+                }
+                Ok(format!("Breakpoint #{} {}d", id, verb))
+            },
+            _ => {
+                let id = self.add(line, symbols)?;
+                Ok(format!("Added {}", self.get(id).unwrap().describe()))
+            },
+        }
+    }
+}
+
+// A number, a symbol, or a symbol plus an offset: `0x2A4`, `draw`, `draw+6`.
+pub fn parse_address(text: &str, symbols: &Symbols) -> Result<u16, String> {
+    parse_bounded(text, symbols, 0xFFF)
+}
+
+// The exclusive end of a range, which may be one past the last byte.
+pub fn parse_range_end(text: &str, symbols: &Symbols) -> Result<u16, String> {
+    parse_bounded(text, symbols, 0x1000)
+}
+
+fn parse_bounded(text: &str, symbols: &Symbols, limit: i64) -> Result<u16, String> {
+    let (name, offset) = match text.find('+') {
+        Some(pos) => (&text[..pos], expr::parse_number(&text[pos + 1..])?),
+        None => (text, 0),
+    };
+    let value = match symbols.address(name) {
+        Some(address) => address as i64 + offset,
+        None if offset == 0 => expr::parse_number(text)?,
+        None => return Err(format!("Unknown symbol: {}", name)),
+    };
+    if value < 0 || value > limit {
+        return Err(format!("Address out of range: {}", text));
+    }
+    Ok(value as u16)
+}
+
+#[test]
+fn test_parse_breakpoints() {
+    let b = Breakpoint::parse(1, "0x2A4", &Symbols::new()).unwrap();
+    assert_eq!(b.address, Some(0x2A4));
+    assert!(b.condition.is_none());
+
+    let b = Breakpoint::parse(2, "0x2A4 if v3 == 1", &Symbols::new()).unwrap();
+    assert_eq!(b.address, Some(0x2A4));
+    assert_eq!(b.condition.unwrap().0, "v3 == 1");
+
+    let b = Breakpoint::parse(3, "if v3 == 0x10 && I > 0x300", &Symbols::new()).unwrap();
+    assert_eq!(b.address, None);
+
+    assert!(Breakpoint::parse(4, "", &Symbols::new()).is_err());
+    assert!(Breakpoint::parse(5, "0x1000", &Symbols::new()).is_err());
+    assert!(Breakpoint::parse(6, "0x200 if v3 ==", &Symbols::new()).is_err());
+}
+
+#[test]
+fn test_breakpoint_hits_and_toggles() {
+    let mut cpu = CPU::headless();
+    let mut breakpoints = Breakpoints::new();
+    let symbols = Symbols::new();
+    breakpoints.command("0x200", &symbols).unwrap();
+    breakpoints.command("if v1 == 2", &symbols).unwrap();
+
+    assert_eq!(breakpoints.check(&cpu), Some(1));
+    cpu.regs[1] = 2;
+    assert_eq!(breakpoints.check(&cpu), Some(1));
+    assert_eq!(breakpoints.get(1).unwrap().hits, 2);
+    assert_eq!(breakpoints.get(2).unwrap().hits, 1);
+
+    breakpoints.command("disable 1", &symbols).unwrap();
+    assert_eq!(breakpoints.check(&cpu), Some(2));
+    breakpoints.command("delete 2", &symbols).unwrap();
+    assert_eq!(breakpoints.check(&cpu), None);
+    assert!(breakpoints.command("enable 7", &symbols).is_err());
+}
+
+#[test]
+fn test_symbolic_breakpoints() {
+    let mut symbols = Symbols::new();
+    symbols.insert("draw", 0x2A4);
+    assert_eq!(parse_address("draw", &symbols), Ok(0x2A4));
+    assert_eq!(parse_address("DRAW+6", &symbols), Ok(0x2AA));
+    assert_eq!(parse_address("0x2A4", &symbols), Ok(0x2A4));
+    assert!(parse_address("drew", &symbols).is_err());
+    assert!(parse_address("drew+2", &symbols).is_err());
+
+    let mut breakpoints = Breakpoints::new();
+    assert_eq!(breakpoints.command("draw if v0 == draw - 0x200", &symbols),
+               Ok("Added #1 [x] draw (0x2A4) if v0 == draw - 0x200 (0 hits)".to_owned()));
+    assert_eq!(breakpoints.toggle_address(0x2A6, &symbols), "Added #2 [x] 0x2A6 (0 hits)");
+    let mut cpu = CPU::headless();
+    cpu.pc = 0x2A4;
+    cpu.regs[0] = 0xA4;
+    assert_eq!(breakpoints.check(&cpu), Some(1));
+}
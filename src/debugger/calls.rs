@@ -0,0 +1,105 @@
+use cpu::CPU;
+#[cfg(test)]
+use cpu::at_last_byte;
+use opcodes::Op;
+use debugger::symbols::Symbols;
+
+
+// Where a run started by `until`, `next` or `finish` pauses, checked before
+// each instruction like a breakpoint. The run executes one instruction
+// before the first check, so a stop that already holds is not the one met.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    Address(u16),
+    // Once the call stack is no deeper than this.
+    Depth(usize),
+    // At the start of a 60Hz frame.
+    Frame,
+    // After the next DXYN.
+    Draw,
+}
+
+impl Stop {
+    pub fn reached(&self, cpu: &CPU) -> bool {
+        match *self {
+            Stop::Address(address) => cpu.pc == address,
+            Stop::Depth(limit) => depth(cpu) <= limit,
+            Stop::Frame => cpu.cycles % cpu.cycles_per_frame() == 0,
+            Stop::Draw => match cpu.opcode.op {
+                Op::Drw(..) => true,
+                _ => false,
+            },
+        }
+    }
+}
+
+// Where `next` stops when PC is on a CALL. Anything else, data included, is
+// a plain single step.
+pub fn over(cpu: &CPU) -> Option<Stop> {
+    match cpu.next_op() {
+        Ok(Op::Call(_)) => Some(Stop::Depth(depth(cpu))),
+        _ => None,
+    }
+}
+
+// Subroutines being run. CALL pushes its own address and the stack's first
+// push goes to slot 1, so slots 1 to the top are the live frames.
+pub fn depth(cpu: &CPU) -> usize {
+    cpu.stack.current_index().unwrap_or(0)
+}
+
+// Addresses of the CALLs on the stack, innermost first.
+pub fn callers(cpu: &CPU) -> Vec<u16> {
+    (1..depth(cpu) + 1).rev().map(|slot| cpu.stack.peek(slot)).collect()
+}
+
+// PC and then each caller, with the routine it is in when symbols say.
+pub fn rows(cpu: &CPU, symbols: &Symbols, width: usize) -> Vec<String> {
+    let mut addresses = vec![cpu.pc];
+    addresses.extend(callers(cpu));
+    addresses.iter().enumerate().map(|(i, &address)| {
+        let marker = if i == 0 { ">" } else { " " };
+        let row = match symbols.describe(address) {
+            Some(name) => format!("{}{:03X} {}", marker, address, name),
+            None => format!("{}{:03X}", marker, address),
+        };
+        row.chars().take(width).collect()
+    }).collect()
+}
+
+#[test]
+fn test_call_stack() {
+    // CALL 0x206; JP 0x202; 0x206: CALL 0x20A; RET; 0x20A: DRW V0, V0, 1; RET
+    let program = [0x22, 0x06, 0x12, 0x02, 0x00, 0x00, 0x22, 0x0A, 0x00, 0xEE, 0xD0, 0x01, 0x00, 0xEE];
+    let mut cpu = CPU::headless();
+    cpu.load_program(&program);
+    let mut symbols = Symbols::new();
+    symbols.insert("main", 0x200);
+    symbols.insert("outer", 0x206);
+    assert_eq!(rows(&cpu, &symbols, 28), vec![">200 main"]);
+    cpu.cycle();
+    cpu.cycle();
+    assert_eq!(depth(&cpu), 2);
+    assert_eq!(callers(&cpu), vec![0x206, 0x200]);
+    assert_eq!(rows(&cpu, &symbols, 11), vec![">20A outer+", " 206 outer", " 200 main"]);
+    assert!(Stop::Address(0x20A).reached(&cpu));
+    assert!(!Stop::Draw.reached(&cpu));
+    cpu.cycle();
+    assert!(Stop::Draw.reached(&cpu));
+    assert!(!Stop::Depth(1).reached(&cpu));
+    cpu.cycle();
+    assert!(Stop::Depth(1).reached(&cpu));
+    assert_eq!(cpu.pc, 0x208);
+    assert!(!Stop::Frame.reached(&cpu));
+    for _ in 4..cpu.cycles_per_frame() {
+        cpu.cycle();
+    }
+    assert!(Stop::Frame.reached(&cpu));
+}
+
+#[test]
+fn test_step_over_at_last_byte() {
+    assert_eq!(over(&at_last_byte(0xFFFF)), None);
+    assert_eq!(over(&at_last_byte(0x1200)), None);
+    assert_eq!(over(&at_last_byte(0x2300)), Some(Stop::Depth(0)));
+}
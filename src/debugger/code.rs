@@ -0,0 +1,176 @@
+use ::termion::{color, style};
+use cpu::CPU;
+#[cfg(test)]
+use cpu::at_last_byte;
+use opcodes::{Op, Opcode};
+use debugger::breakpoints::Breakpoints;
+use debugger::symbols::Symbols;
+
+
+pub const CODE_WIDTH: usize = 42;
+
+// Disassembly around PC. Unfocused it follows PC; focused it has its own
+// cursor that can follow jumps and calls and come back again.
+pub struct CodePane {
+    pub focused: bool,
+    cursor: Option<u16>,
+    trail: Vec<u16>,
+}
+
+impl CodePane {
+    pub fn new() -> CodePane {
+        CodePane {
+            focused: false,
+            cursor: None,
+            trail: Vec::new(),
+        }
+    }
+    pub fn cursor(&self, cpu: &CPU) -> u16 {
+        self.cursor.unwrap_or(cpu.pc)
+    }
+    pub fn move_cursor(&mut self, cpu: &CPU, delta: i32) {
+        let address = self.cursor(cpu) as i32 + delta * 2;
+        let limit = cpu.mem.len() as i32 - 2;
+        self.cursor = Some(if address < 0 { 0 } else if address > limit { limit } else { address } as u16);
+    }
+    // Back to following PC.
+    pub fn follow_pc(&mut self) {
+        self.cursor = None;
+        self.trail.clear();
+    }
+    // Moves to the target of the jump or call under the cursor.
+    pub fn follow(&mut self, cpu: &CPU) -> Result<u16, String> {
+        let from = self.cursor(cpu);
+        match target(cpu, from) {
+            Some(to) => {
+                self.trail.push(from);
+                self.cursor = Some(to);
+                Ok(to)
+            },
+            None => Err(format!("No jump or call at 0x{:03X}", from)),
+        }
+    }
+    pub fn back(&mut self) {
+        if let Some(from) = self.trail.pop() {
+            self.cursor = Some(from);
+        }
+    }
+    // Symbols head the instructions they name; `describe` names the targets.
+    pub fn rows<F>(&self, cpu: &CPU, breakpoints: &Breakpoints, symbols: &Symbols, count: usize, describe: F)
+        -> Vec<String>
+        where F: Fn(&Opcode) -> String
+    {
+        let center = self.cursor(cpu);
+        let before = (count / 3) as u16 * 2;
+        let mut address = if center > before { center - before } else { center % 2 };
+        let mut rows = Vec::new();
+        while rows.len() < count && (address as usize) < cpu.mem.len() {
+            if let Some(name) = symbols.name(address) {
+                rows.push(format!("{}{}:{}", style::Bold, name, style::Reset));
+                if rows.len() == count {
+                    break;
+                }
+            }
+            let marker = if address == cpu.pc { ">" } else { " " };
+            let bp = match breakpoints.has_address(address) {
+                true => format!("{}*{}", color::Fg(color::Red), style::Reset),
+                false => " ".to_owned(),
+            };
+            let value = cpu.word_at(address);
+            let text = match Opcode::decode(value) {
+                Ok(opcode) => describe(&opcode),
+                Err(_) => "(data)".to_owned(),
+            };
+            let mut line = format!("{}{}{:03X} {:04X} {}", bp, marker, address, value, text);
+            line = pad(&line, CODE_WIDTH);
+            if self.focused && address == center {
+                line = format!("{}{}{}", style::Invert, line, style::Reset);
+            } else if address == cpu.pc {
+                line = format!("{}{}{}", style::Bold, line, style::Reset);
+            }
+            rows.push(line);
+            address += 2;
+        }
+        rows
+    }
+}
+
+// The address a jump or call at `address` would go to.
+pub fn target(cpu: &CPU, address: u16) -> Option<u16> {
+    match Op::decode(cpu.word_at(address)) {
+        Ok(Op::Jp(nnn)) | Ok(Op::Call(nnn)) => Some(nnn),
+        Ok(Op::JpV0(nnn)) => Some((nnn + cpu.regs[0] as u16) & 0xFFF),
+        _ => None,
+    }
+}
+
+// Pads or cuts to `width` visible characters, ignoring escape sequences.
+fn pad(text: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut visible = 0;
+    let mut escape = false;
+    for c in text.chars() {
+        if c == '\x1b' {
+            escape = true;
+        }
+        if !escape {
+            if visible == width {
+                continue;
+            }
+            visible += 1;
+        }
+        out.push(c);
+        if escape && c.is_alphabetic() {
+            escape = false;
+        }
+    }
+    out + &" ".repeat(width - visible)
+}
+
+#[test]
+fn test_follow_jumps() {
+    let mut cpu = CPU::headless();
+    cpu.mem[0x200] = 0x22;
+    cpu.mem[0x201] = 0x40;
+    cpu.mem[0x240] = 0x12;
+    cpu.mem[0x241] = 0x00;
+    let mut code = CodePane::new();
+    assert_eq!(code.follow(&cpu), Ok(0x240));
+    assert_eq!(code.follow(&cpu), Ok(0x200));
+    code.move_cursor(&cpu, 1);
+    assert!(code.follow(&cpu).is_err());
+    code.back();
+    assert_eq!(code.cursor(&cpu), 0x240);
+    code.back();
+    assert_eq!(code.cursor(&cpu), 0x200);
+    code.move_cursor(&cpu, -0x200);
+    assert_eq!(code.cursor(&cpu), 0);
+}
+
+#[test]
+fn test_rows_around_pc() {
+    let mut cpu = CPU::headless();
+    cpu.pc = 0x204;
+    cpu.mem[0x204] = 0x00;
+    cpu.mem[0x205] = 0xE0;
+    cpu.mem[0x206] = 0xFF;
+    cpu.mem[0x207] = 0xFF;
+    let mut breakpoints = Breakpoints::new();
+    breakpoints.add("0x206", &Symbols::new()).unwrap();
+    let mut symbols = Symbols::new();
+    symbols.insert("start", 0x204);
+    let code = CodePane::new();
+    let rows = code.rows(&cpu, &breakpoints, &symbols, 6, |op| format!("op {:04X}", op.value));
+    assert!(rows[0].contains(" 200 0000 op 0000"));
+    assert!(rows[2].contains("start:"));
+    assert!(rows[3].contains(">204 00E0 op 00E0"));
+    assert!(rows[4].contains("*"));
+    assert!(rows[4].contains("206 FFFF (data)"));
+    assert_eq!(rows.len(), 6);
+
+    // The word at the last byte wraps around to address 0.
+    let cpu = at_last_byte(0x1240);
+    let rows = code.rows(&cpu, &breakpoints, &symbols, 6, |op| format!("op {:04X}", op.value));
+    assert!(rows[rows.len() - 1].contains(">FFF 1240 op 1240"));
+    assert_eq!(target(&cpu, 0xFFF), Some(0x240));
+}
@@ -0,0 +1,371 @@
+// Expressions over the machine state, used by conditional breakpoints.
+//
+//   v3 == 0x10 && I > 0x300
+//   [I + 2] != 0 || dt == 0
+//
+// Operands are numbers (decimal, 0x hex, 0b binary), the registers v0-vF, I,
+// PC, SP, DT and ST, memory bytes as [address], and symbol names, which
+// stand for their address. Operators follow C precedence; comparisons and
+// logic yield 1 or 0.
+use cpu::CPU;
+use debugger::symbols::Symbols;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Reg(usize),
+    Index,
+    Pc,
+    Sp,
+    DelayTimer,
+    SoundTimer,
+    Mem(Box<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Op(&'static str),
+    Open,
+    Close,
+    OpenBracket,
+    CloseBracket,
+}
+
+const OPERATORS: [&'static str; 21] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>",
+    "<", ">", "|", "^", "&", "+", "-", "*", "/", "%", "!", "~", "=",
+];
+
+impl Op {
+    fn from_token(token: &str) -> Option<(Op, u8)> {
+        match token {
+            "||" => Some((Op::Or, 1)),
+            "&&" => Some((Op::And, 2)),
+            "|"  => Some((Op::BitOr, 3)),
+            "^"  => Some((Op::BitXor, 4)),
+            "&"  => Some((Op::BitAnd, 5)),
+            "==" | "=" => Some((Op::Eq, 6)),
+            "!=" => Some((Op::Ne, 6)),
+            "<"  => Some((Op::Lt, 7)),
+            "<=" => Some((Op::Le, 7)),
+            ">"  => Some((Op::Gt, 7)),
+            ">=" => Some((Op::Ge, 7)),
+            "<<" => Some((Op::Shl, 8)),
+            ">>" => Some((Op::Shr, 8)),
+            "+"  => Some((Op::Add, 9)),
+            "-"  => Some((Op::Sub, 9)),
+            "*"  => Some((Op::Mul, 10)),
+            "/"  => Some((Op::Div, 10)),
+            "%"  => Some((Op::Rem, 10)),
+            _ => None,
+        }
+    }
+    fn apply(&self, a: i64, b: i64) -> i64 {
+        match *self {
+            Op::Or     => (a != 0 || b != 0) as i64,
+            Op::And    => (a != 0 && b != 0) as i64,
+            Op::Eq     => (a == b) as i64,
+            Op::Ne     => (a != b) as i64,
+            Op::Lt     => (a < b) as i64,
+            Op::Le     => (a <= b) as i64,
+            Op::Gt     => (a > b) as i64,
+            Op::Ge     => (a >= b) as i64,
+            Op::BitOr  => a | b,
+            Op::BitXor => a ^ b,
+            Op::BitAnd => a & b,
+            Op::Shl    => a.wrapping_shl(b as u32),
+            Op::Shr    => a.wrapping_shr(b as u32),
+            Op::Add    => a.wrapping_add(b),
+            Op::Sub    => a.wrapping_sub(b),
+            Op::Mul    => a.wrapping_mul(b),
+            Op::Div    => if b == 0 { 0 } else { a / b },
+            Op::Rem    => if b == 0 { 0 } else { a % b },
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(source: &str, symbols: &Symbols) -> Result<Expr, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens: tokens, pos: 0, symbols: symbols };
+        let expr = parser.expression(0)?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some(token) => Err(format!("Unexpected {:?} in '{}'", token, source)),
+        }
+    }
+    pub fn eval(&self, cpu: &CPU) -> i64 {
+        match *self {
+            Expr::Number(n) => n,
+            Expr::Reg(x) => cpu.regs[x] as i64,
+            Expr::Index => cpu.index as i64,
+            Expr::Pc => cpu.pc as i64,
+            Expr::Sp => cpu.stack.current_index().unwrap_or(0) as i64,
+            Expr::DelayTimer => cpu.delay_timer.get_delay() as i64,
+            Expr::SoundTimer => cpu.sound_timer.get_delay() as i64,
+            Expr::Mem(ref address) => {
+                let address = address.eval(cpu) as usize % cpu.mem.len();
+                cpu.mem[address] as i64
+            },
+            Expr::Not(ref e) => (e.eval(cpu) == 0) as i64,
+            Expr::Neg(ref e) => e.eval(cpu).wrapping_neg(),
+            Expr::Binary(op, ref a, ref b) => op.apply(a.eval(cpu), b.eval(cpu)),
+        }
+    }
+    pub fn is_true(&self, cpu: &CPU) -> bool {
+        self.eval(cpu) != 0
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    symbols: &'a Symbols,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(ref token) if *token == expected => Ok(()),
+            other => Err(format!("Expected {:?}, found {:?}", expected, other)),
+        }
+    }
+    // Precedence climbing: binds operators tighter than `min`.
+    fn expression(&mut self, min: u8) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        loop {
+            let (op, prec) = match self.tokens.get(self.pos) {
+                Some(&Token::Op(token)) => match Op::from_token(token) {
+                    Some(pair) => pair,
+                    None => break,
+                },
+                _ => break,
+            };
+            if prec <= min {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.expression(prec)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Ident(name)) => match name.as_str() {
+                "mem" => {
+                    self.expect(Token::OpenBracket)?;
+                    self.memory()
+                },
+                "v" => {
+                    self.expect(Token::OpenBracket)?;
+                    let x = match self.next() {
+                        Some(Token::Number(n)) if n >= 0 && n < 16 => n as usize,
+                        Some(Token::Ident(ref d)) if d.len() == 1 && d.chars().all(|c| c.is_digit(16)) => {
+                            usize::from_str_radix(d, 16).unwrap()
+                        },
+                        other => return Err(format!("Expected a register number, found {:?}", other)),
+                    };
+                    self.expect(Token::CloseBracket)?;
+                    Ok(Expr::Reg(x))
+                },
+                _ => operand(&name, self.symbols),
+            },
+            Some(Token::Op("!")) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Op("-")) => Ok(Expr::Neg(Box::new(self.unary()?))),
+            Some(Token::Op("~")) => {
+                let e = self.unary()?;
+                Ok(Expr::Binary(Op::BitXor, Box::new(e), Box::new(Expr::Number(-1))))
+            },
+            Some(Token::Open) => {
+                let e = self.expression(0)?;
+                self.expect(Token::Close)?;
+                Ok(e)
+            },
+            Some(Token::OpenBracket) => self.memory(),
+            other => Err(format!("Expected a value, found {:?}", other)),
+        }
+    }
+    fn memory(&mut self) -> Result<Expr, String> {
+        let address = self.expression(0)?;
+        self.expect(Token::CloseBracket)?;
+        Ok(Expr::Mem(Box::new(address)))
+    }
+}
+
+// Register names win over symbols of the same name.
+fn operand(name: &str, symbols: &Symbols) -> Result<Expr, String> {
+    match name {
+        "i" => Ok(Expr::Index),
+        "pc" => Ok(Expr::Pc),
+        "sp" => Ok(Expr::Sp),
+        "dt" => Ok(Expr::DelayTimer),
+        "st" => Ok(Expr::SoundTimer),
+        _ => match (register(name), symbols.address(name)) {
+            (Some(x), _) => Ok(Expr::Reg(x)),
+            (None, Some(address)) => Ok(Expr::Number(address as i64)),
+            (None, None) => Err(format!("Unknown name: {}", name)),
+        },
+    }
+}
+
+// Parses v0-vF register names.
+pub fn register(name: &str) -> Option<usize> {
+    let name = name.to_lowercase();
+    if !name.starts_with('v') || name.len() != 2 {
+        return None;
+    }
+    usize::from_str_radix(&name[1..], 16).ok()
+}
+
+// Parses 0x2A4, 0b1010, $2A4 and decimal numbers.
+pub fn parse_number(text: &str) -> Result<i64, String> {
+    let lower = text.to_lowercase();
+    let result = if lower.starts_with("0x") {
+        i64::from_str_radix(&lower[2..], 16)
+    } else if lower.starts_with('$') {
+        i64::from_str_radix(&lower[1..], 16)
+    } else if lower.starts_with("0b") {
+        i64::from_str_radix(&lower[2..], 2)
+    } else {
+        lower.parse::<i64>()
+    };
+    result.map_err(|_| format!("Bad number: {}", text))
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    'outer: while pos < chars.len() {
+        let c = chars[pos];
+        if c.is_whitespace() {
+            pos += 1;
+            continue;
+        }
+        if c.is_alphanumeric() || c == '_' || c == '$' {
+            let start = pos;
+            // Symbol names like `digit.end` keep their dots; numbers do not.
+            let dots = c.is_alphabetic() || c == '_';
+            while pos < chars.len() && (chars[pos].is_alphanumeric() || chars[pos] == '_' || chars[pos] == '$' ||
+                                        (dots && chars[pos] == '.')) {
+                pos += 1;
+            }
+            let word: String = chars[start..pos].iter().collect();
+            if c.is_digit(10) || c == '$' {
+                tokens.push(Token::Number(parse_number(&word)?));
+            } else {
+                tokens.push(Token::Ident(word.to_lowercase()));
+            }
+            continue;
+        }
+        match c {
+            '(' => { tokens.push(Token::Open); pos += 1; continue },
+            ')' => { tokens.push(Token::Close); pos += 1; continue },
+            '[' => { tokens.push(Token::OpenBracket); pos += 1; continue },
+            ']' => { tokens.push(Token::CloseBracket); pos += 1; continue },
+            _ => {}
+        }
+        for op in OPERATORS.iter() {
+            let len = op.len();
+            if pos + len <= chars.len() {
+                let candidate: String = chars[pos..pos + len].iter().collect();
+                if candidate == *op {
+                    tokens.push(Token::Op(op));
+                    pos += len;
+                    continue 'outer;
+                }
+            }
+        }
+        return Err(format!("Unexpected '{}' in '{}'", c, source));
+    }
+    Ok(tokens)
+}
+
+#[test]
+fn test_parse_precedence() {
+    let e = Expr::parse("v3 == 0x10 && I > 0x300", &Symbols::new()).unwrap();
+    assert_eq!(e, Expr::Binary(Op::And,
+        Box::new(Expr::Binary(Op::Eq, Box::new(Expr::Reg(3)), Box::new(Expr::Number(0x10)))),
+        Box::new(Expr::Binary(Op::Gt, Box::new(Expr::Index), Box::new(Expr::Number(0x300))))));
+
+    let e = Expr::parse("1 + 2 * 3", &Symbols::new()).unwrap();
+    assert_eq!(e, Expr::Binary(Op::Add, Box::new(Expr::Number(1)),
+        Box::new(Expr::Binary(Op::Mul, Box::new(Expr::Number(2)), Box::new(Expr::Number(3))))));
+}
+
+#[test]
+fn test_parse_errors() {
+    assert!(Expr::parse("v3 ==", &Symbols::new()).is_err());
+    assert!(Expr::parse("vg == 1", &Symbols::new()).is_err());
+    assert!(Expr::parse("(v1", &Symbols::new()).is_err());
+    assert!(Expr::parse("v1 # 2", &Symbols::new()).is_err());
+}
+
+#[test]
+fn test_eval_against_cpu() {
+    let mut cpu = CPU::headless();
+    cpu.regs[3] = 0x10;
+    cpu.regs[0xF] = 1;
+    cpu.index = 0x310;
+    cpu.mem[0x312] = 0xAB;
+
+    let yes = ["v3 == 0x10 && I > 0x300", "[I + 2] == 0xAB", "(mem[0x312] & 0xF) == 0xB",
+               "vF", "!(v3 < 16)", "pc == 512", "V[3] >= $10 || 0", "v[f] == 1"];
+    for source in yes.iter() {
+        assert!(Expr::parse(source, &Symbols::new()).unwrap().is_true(&cpu), "{}", source);
+    }
+    let no = ["v3 != 0x10", "I < 0x300 && v3 == 0x10", "dt", "v0 + 1 == 2"];
+    for source in no.iter() {
+        assert!(!Expr::parse(source, &Symbols::new()).unwrap().is_true(&cpu), "{}", source);
+    }
+}
+
+#[test]
+fn test_symbol_operands() {
+    let mut symbols = Symbols::new();
+    symbols.insert("score", 0x2F0);
+    symbols.insert("digit.end", 0x30F);
+    symbols.insert("v3", 0x400);
+    let mut cpu = CPU::headless();
+    cpu.index = 0x2F0;
+    cpu.regs[3] = 7;
+    for source in ["I == score", "[score + 1] == 0", "I < DIGIT.END", "v3 == 7"].iter() {
+        assert!(Expr::parse(source, &symbols).unwrap().is_true(&cpu), "{}", source);
+    }
+    assert!(Expr::parse("missing + 1", &symbols).is_err());
+}
@@ -0,0 +1,104 @@
+use ::termion::style;
+use cpu::CPU;
+#[cfg(test)]
+use cpu::at_last_byte;
+use opcodes::Op;
+
+
+// The keypad as it is laid out on the COSMAC VIP, in two blocks of two rows.
+const KEYPAD: [[u8; 8]; 2] = [
+    [0x1, 0x2, 0x3, 0xC, 0x7, 0x8, 0x9, 0xE],
+    [0x4, 0x5, 0x6, 0xD, 0xA, 0x0, 0xB, 0xF],
+];
+
+// Timer values forced by `hold`, put back after every instruction until
+// released.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Holds {
+    pub delay: Option<u8>,
+    pub sound: Option<u8>,
+}
+
+impl Holds {
+    pub fn new() -> Holds {
+        Holds {
+            delay: None,
+            sound: None,
+        }
+    }
+    pub fn apply(&self, cpu: &mut CPU) {
+        if let Some(value) = self.delay {
+            cpu.delay_timer.set_delay(value);
+        }
+        if let Some(value) = self.sound {
+            cpu.sound_timer.set_delay(value);
+        }
+    }
+}
+
+// Hex key names, or `all`.
+pub fn parse_keys(words: &[&str]) -> Result<Vec<u8>, String> {
+    if words.len() == 1 && words[0] == "all" {
+        return Ok((0..16).collect());
+    }
+    if words.is_empty() {
+        return Err("Expected keys 0-F or all".to_owned());
+    }
+    words.iter().map(|word| match u8::from_str_radix(word, 16) {
+        Ok(value) if value < 16 => Ok(value),
+        _ => Err(format!("Not a key: {}", word)),
+    }).collect()
+}
+
+// Timers with `*` when held, the sound, and the keypad with pressed keys
+// inverted.
+pub fn rows(cpu: &CPU, holds: &Holds) -> Vec<String> {
+    let held = |hold: Option<u8>| if hold.is_some() { "*" } else { " " };
+    let mut rows = vec![format!("DT 0x{:02X}{}  ST 0x{:02X}{}  Sound {}",
+                                cpu.delay_timer.get_delay(), held(holds.delay),
+                                cpu.sound_timer.get_delay(), held(holds.sound),
+                                if cpu.device.is_beeping() { "on" } else { "off" })];
+    for (i, line) in KEYPAD.iter().enumerate() {
+        let keys: Vec<String> = line.iter().map(|&key| match cpu.device.keyboard.is_pressed(key) {
+            true => format!("{}{:X}{}", style::Invert, key, style::Reset),
+            false => format!("{:X}", key),
+        }).collect();
+        let label = if i == 0 { "Keys" } else { "    " };
+        rows.push(format!("{} {}  {}", label, keys[..4].join(" "), keys[4..].join(" ")));
+    }
+    if let Ok(Op::LdVxK(x)) = cpu.next_op() {
+        rows.push(format!("LD V{:X}, K waits for a key", x));
+    }
+    rows
+}
+
+#[test]
+fn test_input_panel() {
+    let mut cpu = CPU::headless();
+    cpu.mem[0x200] = 0xF3;
+    cpu.mem[0x201] = 0x0A;
+    cpu.device.keyboard.press_value(0xA);
+    let mut holds = Holds::new();
+    holds.delay = Some(0x1E);
+    holds.apply(&mut cpu);
+    let panel = rows(&cpu, &holds);
+    assert_eq!(panel[0], "DT 0x1E*  ST 0x00   Sound off");
+    assert_eq!(panel[1], "Keys 1 2 3 C  7 8 9 E");
+    assert_eq!(panel[2], format!("     4 5 6 D  {}A{} 0 B F", style::Invert, style::Reset));
+    assert_eq!(panel[3], "LD V3, K waits for a key");
+
+    // No hint, and no panic, when PC is on data or the last byte.
+    assert_eq!(rows(&at_last_byte(0xFFFF), &holds).len(), 3);
+
+    // A held timer survives the instruction that would have changed it.
+    cpu.cycle();
+    assert_eq!(cpu.regs[3], 0xA);
+    cpu.delay_timer.set_delay(0);
+    holds.apply(&mut cpu);
+    assert_eq!(cpu.delay_timer.get_delay(), 0x1E);
+
+    assert_eq!(parse_keys(&["a", "0"]), Ok(vec![0xA, 0]));
+    assert_eq!(parse_keys(&["all"]).map(|keys| keys.len()), Ok(16));
+    assert!(parse_keys(&["10"]).is_err());
+    assert!(parse_keys(&[]).is_err());
+}
@@ -0,0 +1,247 @@
+use ::termion::{color, style};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use cpu::{AccessKind, CPU, Location};
+use capture::write_file;
+use debugger::breakpoints::{parse_address, parse_range_end};
+use debugger::symbols::Symbols;
+
+
+pub const BYTES_PER_ROW: usize = 8;
+// Writes stay highlighted for this many instructions.
+const RECENT_CYCLES: u64 = 120;
+
+// Hex and ASCII view over the whole of `cpu.mem`, with a cursor for editing
+// bytes in place.
+pub struct MemoryPane {
+    pub focused: bool,
+    pub cursor: usize,
+    top: usize,
+    // High nibble typed while editing the byte under the cursor.
+    nibble: Option<u8>,
+    written: Vec<Option<u64>>,
+}
+
+impl MemoryPane {
+    pub fn new(size: usize) -> MemoryPane {
+        MemoryPane {
+            focused: false,
+            cursor: 0x200,
+            top: 0x200,
+            nibble: None,
+            written: vec![None; size],
+        }
+    }
+    // Notes the bytes written by the instruction that just ran.
+    pub fn record(&mut self, cpu: &CPU) {
+        for access in cpu.accesses.iter().filter(|a| a.kind == AccessKind::Write) {
+            if let Location::Mem(address) = access.location {
+                self.written[address as usize] = Some(cpu.cycles);
+            }
+        }
+    }
+    pub fn clear(&mut self) {
+        for written in self.written.iter_mut() {
+            *written = None;
+        }
+        self.nibble = None;
+    }
+    pub fn goto(&mut self, address: usize) {
+        self.cursor = address % self.written.len();
+        self.nibble = None;
+    }
+    pub fn move_cursor(&mut self, delta: i64) {
+        let size = self.written.len() as i64;
+        self.goto(((self.cursor as i64 + delta) % size + size) as usize);
+    }
+    // Takes one hex digit; every second digit completes the byte and moves on.
+    pub fn edit(&mut self, cpu: &mut CPU, digit: u8) {
+        match self.nibble.take() {
+            None => self.nibble = Some(digit),
+            Some(high) => {
+                cpu.mem[self.cursor] = high << 4 | digit;
+                self.written[self.cursor] = Some(cpu.cycles);
+                self.move_cursor(1);
+            },
+        }
+    }
+    pub fn rows(&mut self, cpu: &CPU, count: usize) -> Vec<String> {
+        let cursor_row = self.cursor / BYTES_PER_ROW * BYTES_PER_ROW;
+        if cursor_row < self.top {
+            self.top = cursor_row;
+        } else if cursor_row >= self.top + count * BYTES_PER_ROW {
+            self.top = cursor_row + BYTES_PER_ROW - count * BYTES_PER_ROW;
+        }
+        let returns: Vec<usize> = match cpu.stack.current_index() {
+            Some(sp) => (0..sp + 1).map(|i| cpu.stack.peek(i) as usize).filter(|a| *a != 0).collect(),
+            None => Vec::new(),
+        };
+        let mut rows = Vec::new();
+        for row in 0..count {
+            let start = self.top + row * BYTES_PER_ROW;
+            if start >= cpu.mem.len() {
+                break;
+            }
+            let bytes = &cpu.mem[start..start + BYTES_PER_ROW];
+            let mut line = format!("{:03X} ", start);
+            for (i, byte) in bytes.iter().enumerate() {
+                let address = start + i;
+                line.push_str(&self.highlight(cpu, &returns, address));
+                if self.focused && address == self.cursor {
+                    line.push_str(&format!("{}", style::Invert));
+                    match self.nibble {
+                        Some(high) => line.push_str(&format!("{:X}_", high)),
+                        None => line.push_str(&format!("{:02X}", byte)),
+                    }
+                } else {
+                    line.push_str(&format!("{:02X}", byte));
+                }
+                line.push_str(&format!("{} ", style::Reset));
+            }
+            line.push_str(&format!(" {}", ascii(bytes)));
+            rows.push(line);
+        }
+        rows
+    }
+    fn highlight(&self, cpu: &CPU, returns: &[usize], address: usize) -> String {
+        let pc = cpu.pc as usize;
+        let recent = match self.written[address] {
+            Some(cycle) => cpu.cycles.saturating_sub(cycle) < RECENT_CYCLES,
+            None => false,
+        };
+        let mut out = String::new();
+        if address == pc || address == pc + 1 {
+            out.push_str(&format!("{}", color::Bg(color::Yellow)));
+        } else if address == cpu.index as usize {
+            out.push_str(&format!("{}", color::Bg(color::Blue)));
+        } else if returns.contains(&address) {
+            out.push_str(&format!("{}", color::Bg(color::Magenta)));
+        }
+        if recent {
+            out.push_str(&format!("{}", color::Fg(color::LightRed)));
+        }
+        out
+    }
+    // Subcommands of the REPL's `mem` command:
+    //   mem <addr>                       move the cursor
+    //   mem find <bytes>                 next match after the cursor, ?? is a wildcard
+    //   mem dump <start>..<end> <file>   write a range to a file
+    //   mem load <file> <addr>           copy a file into memory
+    pub fn command(&mut self, cpu: &mut CPU, line: &str, symbols: &Symbols) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.first().map(|w| *w) {
+            Some("find") => {
+                let pattern = parse_pattern(&words[1..])?;
+                match find(&cpu.mem, &pattern, self.cursor + 1) {
+                    Some(address) => {
+                        self.goto(address);
+                        Ok(format!("Found at 0x{:03X}", address))
+                    },
+                    None => Err("Pattern not found".to_owned()),
+                }
+            },
+            Some("dump") if words.len() == 3 => {
+                let (start, end) = parse_range(words[1], symbols)?;
+                write_file(Path::new(words[2]), &cpu.mem[start..end])?;
+                Ok(format!("Wrote {} bytes to {}", end - start, words[2]))
+            },
+            Some("load") if words.len() == 3 => {
+                let mut data = Vec::new();
+                File::open(words[1]).and_then(|mut file| file.read_to_end(&mut data))
+                    .map_err(|e| format!("{}: {}", words[1], e))?;
+                let start = parse_address(words[2], symbols)? as usize;
+                if start + data.len() > cpu.mem.len() {
+                    return Err(format!("{} bytes do not fit at 0x{:03X}", data.len(), start));
+                }
+                cpu.mem[start..start + data.len()].copy_from_slice(&data);
+                for written in self.written[start..start + data.len()].iter_mut() {
+                    *written = Some(cpu.cycles);
+                }
+                Ok(format!("Loaded {} bytes at 0x{:03X}", data.len(), start))
+            },
+            Some(address) if words.len() == 1 => {
+                self.goto(parse_address(address, symbols)? as usize);
+                Ok(String::new())
+            },
+            _ => Err("Usage: mem <addr> | mem find <bytes> | mem dump <range> <file> | mem load <file> <addr>".to_owned()),
+        }
+    }
+}
+
+fn ascii(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| if *b >= 0x20 && *b < 0x7F { *b as char } else { '.' }).collect()
+}
+
+// Hex bytes separated by spaces, or run together; ?? matches any byte.
+pub fn parse_pattern(words: &[&str]) -> Result<Vec<Option<u8>>, String> {
+    let text: String = words.concat();
+    if text.is_empty() || text.len() % 2 != 0 || !text.is_ascii() {
+        return Err("Expected whole hex bytes".to_owned());
+    }
+    (0..text.len() / 2).map(|i| match &text[i * 2..i * 2 + 2] {
+        "??" => Ok(None),
+        byte => u8::from_str_radix(byte, 16).map(Some).map_err(|_| format!("Bad byte: {}", byte)),
+    }).collect()
+}
+
+// Searches from `from`, wrapping around to the start.
+pub fn find(mem: &[u8], pattern: &[Option<u8>], from: usize) -> Option<usize> {
+    let matches = |start: usize| {
+        start + pattern.len() <= mem.len() &&
+            pattern.iter().enumerate().all(|(i, b)| b.map_or(true, |b| mem[start + i] == b))
+    };
+    (from..mem.len()).chain(0..from).find(|start| matches(*start))
+}
+
+pub fn parse_range(text: &str, symbols: &Symbols) -> Result<(usize, usize), String> {
+    let pos = text.find("..").ok_or(format!("Expected <start>..<end>, found {}", text))?;
+    let start = parse_address(&text[..pos], symbols)? as usize;
+    let end = parse_range_end(&text[pos + 2..], symbols)? as usize;
+    if end <= start {
+        return Err(format!("Empty range: {}", text));
+    }
+    Ok((start, end))
+}
+
+#[test]
+fn test_find_pattern() {
+    let mut mem = [0u8; 64];
+    mem[10] = 0xA2;
+    mem[11] = 0x2A;
+    mem[12] = 0x60;
+    mem[40] = 0xA2;
+    mem[41] = 0x2B;
+    mem[42] = 0x60;
+    let pattern = parse_pattern(&["A2", "??", "60"]).unwrap();
+    assert_eq!(find(&mem, &pattern, 0), Some(10));
+    assert_eq!(find(&mem, &pattern, 11), Some(40));
+    assert_eq!(find(&mem, &pattern, 41), Some(10));
+    assert_eq!(find(&mem, &parse_pattern(&["a22b"]).unwrap(), 0), Some(40));
+    assert_eq!(find(&mem, &parse_pattern(&["FF"]).unwrap(), 0), None);
+    assert!(parse_pattern(&["A"]).is_err());
+    assert!(parse_pattern(&["ZZ"]).is_err());
+    assert!(parse_pattern(&["\u{e9}"]).is_err());
+}
+
+#[test]
+fn test_edit_and_scroll() {
+    let mut cpu = CPU::headless();
+    let mut pane = MemoryPane::new(cpu.mem.len());
+    pane.focused = true;
+    pane.goto(0x300);
+    pane.edit(&mut cpu, 0xA);
+    pane.edit(&mut cpu, 0x5);
+    assert_eq!(cpu.mem[0x300], 0xA5);
+    assert_eq!(pane.cursor, 0x301);
+
+    let rows = pane.rows(&cpu, 4);
+    assert_eq!(rows.len(), 4);
+    assert!(rows[3].starts_with("300 "));
+    pane.move_cursor(-0x302);
+    assert_eq!(pane.cursor, 0xFFF);
+    assert!(pane.rows(&cpu, 4)[3].starts_with("FF8 "));
+    assert_eq!(parse_range("0x200..0x210", &Symbols::new()), Ok((0x200, 0x210)));
+    assert_eq!(parse_range("0x000..0x1000", &Symbols::new()), Ok((0, 0x1000)));
+    assert!(parse_range("0x000..0x1001", &Symbols::new()).is_err());
+}
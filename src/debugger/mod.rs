@@ -1,24 +1,66 @@
 mod view;
 mod history;
+mod expr;
+mod breakpoints;
+mod watchpoints;
+mod repl;
+mod memory;
+mod code;
+mod symbols;
+mod calls;
+mod sprites;
+mod input;
 
+use asm::Program;
 use cpu::CPU;
-use self::view::View;
+use self::view::{Panes, View, CODE_ROWS, MEMORY_ROWS};
+use self::code::CodePane;
+use self::memory::MemoryPane;
+use self::symbols::Symbols;
+use self::calls::Stop;
+use self::sprites::SpritePane;
+use self::input::Holds;
 use ::termion::input::TermRead;
 use ::termion::event::Key;
 use ::termion::async_stdin;
-use opcodes::{Instruction, Opcode};
+use opcodes::{Op, Opcode};
+use filters::{Frame, Rgb};
+use frontend::half_blocks;
 use self::history::LimitedFifoQueue;
+use self::breakpoints::Breakpoints;
+use self::watchpoints::{self as watch, Watchpoints};
+use self::repl::History;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
 use std::thread::sleep;
+use capture;
+use disasm::{self, Labels, Syntax};
+use self::expr::Expr;
 
 
+// Pixels flipped by the last DXYN: ones it switched on and ones it erased.
+const DRAWN_ON: Rgb = Rgb(250, 200, 40);
+const DRAWN_OFF: Rgb = Rgb(150, 50, 50);
+// Pixels of the draw selected in the sprite pane.
+const SELECTED: Rgb = Rgb(60, 200, 230);
+
 #[derive(PartialEq)]
 pub enum Command {
     PlayToggle,
     Step,
+    StepOver,
+    StepOut,
     Back,
     Next,
     Quit,
     Reset,
+    Screenshot,
+    ToggleRecording,
+    Prompt(&'static str),
+    FocusMemory,
+    FocusCode,
+    FocusSprites,
     ChangeSpeed(i32)
 }
 
@@ -36,17 +78,49 @@ pub struct Debugger<'a> {
     record: usize,
     last_command: Option<Command>,
     state: State,
+    breakpoints: Breakpoints,
+    watchpoints: Watchpoints,
+    // The breakpoint that paused execution, if any.
+    triggered: Option<usize>,
+    // Temporary stop set by `until`, `next` or `finish`.
+    stop: Option<Stop>,
+    // Text typed at the command prompt while it is open.
+    input: Option<String>,
+    history: History,
+    rom: Option<String>,
+    memory: MemoryPane,
+    code: CodePane,
+    sprites: SpritePane,
+    symbols: Symbols,
+    holds: Holds,
 }
 
 impl<'a> Debugger<'a> {
     pub fn new() -> Debugger<'a> {
+        Debugger::with_cpu(CPU::new())
+    }
+    pub fn with_cpu(mut cpu: CPU<'a>) -> Debugger<'a> {
+        cpu.watching = true;
+        let memory = MemoryPane::new(cpu.mem.len());
         Debugger {
             lines: LimitedFifoQueue::new(200),
-            cpu: CPU::new(),
+            cpu: cpu,
             view: View::new(),
             record: 0,
             last_command: None,
             state: State::Paused,
+            breakpoints: Breakpoints::new(),
+            watchpoints: Watchpoints::new(),
+            triggered: None,
+            stop: None,
+            input: None,
+            history: History::open(),
+            rom: None,
+            memory: memory,
+            code: CodePane::new(),
+            sprites: SpritePane::new(),
+            symbols: Symbols::new(),
+            holds: Holds::new(),
         }
     }
     fn dump_instr(&self) -> String {
@@ -61,55 +135,180 @@ impl<'a> Debugger<'a> {
         self.record = 0;
         self.last_command = None;
         self.state = State::Paused;
+        self.triggered = None;
+        self.stop = None;
+        self.memory.clear();
+        self.sprites.clear();
+        self.code.follow_pc();
     }
     pub fn load_rom(&mut self, rom: &str) {
         self.cpu.load_rom(rom);
+        self.rom = Some(rom.to_owned());
+    }
+    // A compiled program's labels become the debugger's symbols.
+    pub fn load_program(&mut self, source: &str, program: &Program) {
+        self.cpu.load_program(&program.binary);
+        self.symbols = Symbols::from_program(program);
+        self.rom = Some(source.to_owned());
+    }
+    // Adds to the symbols already known, such as a compiled program's.
+    pub fn add_symbols(&mut self, labels: &Labels) {
+        for (&address, name) in labels.iter() {
+            self.symbols.insert(name, address);
+        }
+    }
+    fn load_symbols(&mut self, path: &str) -> Result<String, String> {
+        self.symbols = Symbols::load(Path::new(path))?;
+        Ok(format!("Loaded {} symbols from {}", self.symbols.len(), path))
     }
     fn step(&mut self, distance: i32) {
         self.state = State::Paused;
         for _ in 0..distance {
-            self.cycle();
+            if self.cycle() {
+                break;
+            }
         }
     }
-    fn cycle(&mut self) {
+    // Returns true when a watchpoint fired or PC is not on an instruction.
+    fn cycle(&mut self) -> bool {
+        if let Err(e) = self.cpu.next_op() {
+            self.state = State::Paused;
+            self.stop = None;
+            self.lines.push(format!("Paused at 0x{:03X}: {}", self.cpu.pc, e));
+            self.redraw();
+            return true;
+        }
         self.cpu.cycle();
+        self.holds.apply(&mut self.cpu);
+        self.memory.record(&self.cpu);
+        self.sprites.record(&self.cpu);
+        let hit = self.check_watchpoints();
         self.render();
+        hit
     }
     fn render(&mut self) {
         let line = self.dump_instr();
-        let strings = vec![
+        self.lines.push(line);
+        self.lines.push(format!("{:?}", self.cpu.regs));
+        self.paint();
+    }
+    // Repaints straight away, bypassing the view's frame limit.
+    fn redraw(&mut self) {
+        self.view.invalidate();
+        self.paint();
+    }
+    fn paint(&mut self) {
+        let mut strings = vec![
             format!(
                     "     Clock Speed: {}hz", self.cpu.hz),
                     "----------------------------".to_owned(),
-                    "    Regs    |     Stack     ".to_owned(),
-                    "------------|---------------".to_owned(),
-            format!("v[0] = 0x{:02X} | s[0] = 0x{:04X}", self.cpu.regs[0], self.cpu.stack.peek(0) as usize),
-            format!("v[1] = 0x{:02X} | s[1] = 0x{:04X}", self.cpu.regs[1], self.cpu.stack.peek(1) as usize),
-            format!("v[2] = 0x{:02X} | s[2] = 0x{:04X}", self.cpu.regs[2], self.cpu.stack.peek(2) as usize),
-            format!("v[3] = 0x{:02X} | s[3] = 0x{:04X}", self.cpu.regs[3], self.cpu.stack.peek(3) as usize),
-            format!("v[4] = 0x{:02X} | s[4] = 0x{:04X}", self.cpu.regs[4], self.cpu.stack.peek(4) as usize),
-            format!("v[5] = 0x{:02X} | s[5] = 0x{:04X}", self.cpu.regs[5], self.cpu.stack.peek(5) as usize),
-            format!("v[6] = 0x{:02X} | s[6] = 0x{:04X}", self.cpu.regs[6], self.cpu.stack.peek(6) as usize),
-            format!("v[7] = 0x{:02X} | s[7] = 0x{:04X}", self.cpu.regs[7], self.cpu.stack.peek(7) as usize),
-            format!("v[8] = 0x{:02X} | s[8] = 0x{:04X}", self.cpu.regs[8], self.cpu.stack.peek(8) as usize),
-            format!("v[9] = 0x{:02X} | s[9] = 0x{:04X}", self.cpu.regs[9], self.cpu.stack.peek(9) as usize),
-            format!("v[A] = 0x{:02X} | s[A] = 0x{:04X}", self.cpu.regs[0xA], self.cpu.stack.peek(0xA) as usize),
-            format!("v[B] = 0x{:02X} | s[B] = 0x{:04X}", self.cpu.regs[0xB], self.cpu.stack.peek(0xB) as usize),
-            format!("v[C] = 0x{:02X} | s[C] = 0x{:04X}", self.cpu.regs[0xC], self.cpu.stack.peek(0xC) as usize),
-            format!("v[D] = 0x{:02X} | s[D] = 0x{:04X}", self.cpu.regs[0xD], self.cpu.stack.peek(0xD) as usize),
-            format!("v[E] = 0x{:02X} | s[E] = 0x{:04X}", self.cpu.regs[0xE], self.cpu.stack.peek(0xE) as usize),
-            format!("v[F] = 0x{:02X} | s[F] = 0x{:04X}", self.cpu.regs[0xF], self.cpu.stack.peek(0xF) as usize),
+                    "         Registers          ".to_owned(),
+                    "----------------------------".to_owned(),
+        ];
+        for x in 0..8 {
+            strings.push(format!("v[{:X}] = 0x{:02X} | v[{:X}] = 0x{:02X}", x, self.cpu.regs[x], x + 8, self.cpu.regs[x + 8]));
+        }
+        strings.extend(vec![
             format!("                         "),
             format!("    PC: 0x{:X} || I: 0x{:X}   ", self.cpu.pc, self.cpu.index as usize),
-        ];
-        self.lines.push(line);
-        self.lines.push(format!("{:?}", self.cpu.regs));
-        self.view.render(&self.lines, strings);
+            format!("                         "),
+                    "      Timers and keypad     ".to_owned(),
+                    "----------------------------".to_owned(),
+        ]);
+        strings.extend(input::rows(&self.cpu, &self.holds));
+        strings.extend(vec![
+            format!("                         "),
+                    "         Call stack         ".to_owned(),
+                    "----------------------------".to_owned(),
+        ]);
+        strings.extend(calls::rows(&self.cpu, &self.symbols, 28));
+        strings.extend(vec![
+                    "        Breakpoints         ".to_owned(),
+                    "----------------------------".to_owned(),
+        ]);
+        for b in self.breakpoints.iter() {
+            let marker = if self.triggered == Some(b.id) { ">" } else { " " };
+            strings.push(format!("{}{}", marker, b.describe()));
+        }
+        if !self.watchpoints.is_empty() {
+            strings.push("        Watchpoints         ".to_owned());
+            strings.push("----------------------------".to_owned());
+            for w in self.watchpoints.iter() {
+                strings.push(format!(" {}", w.describe()));
+            }
+        }
+        let screen = half_blocks(&self.screen_frame());
+        let labels = self.symbols.labels();
+        let (code_title, code) = match self.sprites.visible {
+            true => (self.sprites.title(&self.cpu), self.sprites.rows(&self.cpu, CODE_ROWS)),
+            false => ("Code".to_owned(), self.code.rows(&self.cpu, &self.breakpoints, &self.symbols, CODE_ROWS,
+                                                        |op| disasm::disassemble(op.value, Syntax::Cowgod, labels))),
+        };
+        let panes = Panes {
+            info: strings,
+            screen: screen,
+            memory: self.memory.rows(&self.cpu, MEMORY_ROWS),
+            code: code,
+            code_title: code_title,
+            prompt: self.input.as_ref().map(|text| format!("> {}", text)),
+        };
+        self.view.render(&self.lines, panes);
+    }
+    fn screen_frame(&self) -> Frame {
+        let display = &self.cpu.device.display;
+        let palette = display.palette;
+        let pixels = display.pixels();
+        let mut data: Vec<Rgb> = pixels.iter()
+            .map(|p| if *p { palette.foreground } else { palette.background })
+            .collect();
+        match self.sprites.selected() {
+            Some(draw) => for offset in draw.pixels.iter() {
+                data[*offset] = SELECTED;
+            },
+            None => for offset in display.last_draw() {
+                data[*offset] = if pixels[*offset] { DRAWN_ON } else { DRAWN_OFF };
+            },
+        }
+        Frame { width: display.width(), height: display.height(), data: data }
     }
     fn toggle_play(&mut self) {
         match self.state {
-            State::Paused => self.state = State::Running,
-            _ => self.state = State::Paused,
+            State::Paused => self.resume(),
+            _ => {
+                self.state = State::Paused;
+                self.stop = None;
+            },
+        }
+    }
+    fn resume(&mut self) {
+        self.state = State::Running;
+        // Step off a breakpoint we are sitting on before checking again.
+        if self.triggered.take().is_some() {
+            self.cycle();
+        }
+    }
+    // Runs the next instruction, then on until `stop` or a breakpoint.
+    fn run_until(&mut self, stop: Stop) {
+        self.triggered = None;
+        self.state = State::Running;
+        self.stop = Some(stop);
+        self.cycle();
+    }
+    // Runs a CALL and its subroutine as one step.
+    fn step_over(&mut self) {
+        match calls::over(&self.cpu) {
+            Some(stop) => self.run_until(stop),
+            None => self.step(1),
+        }
+    }
+    // Runs until the current subroutine returns to its caller.
+    fn step_out(&mut self) -> Result<String, String> {
+        match calls::depth(&self.cpu) {
+            0 => Err("Not in a subroutine".to_owned()),
+            depth => {
+                self.run_until(Stop::Depth(depth - 1));
+                Ok(String::new())
+            },
         }
     }
     fn quit(&mut self) {
@@ -119,13 +318,336 @@ impl<'a> Debugger<'a> {
         let current_hz = self.cpu.hz as i32;
         self.cpu.set_speed_hz((current_hz + hertz) as u32);
     }
+    fn screenshot(&mut self) {
+        let line = match self.cpu.device.take_screenshot() {
+            Ok(path) => format!("Saved screenshot to {}", path),
+            Err(e) => format!("Could not save screenshot: {}", e),
+        };
+        self.lines.push(line);
+    }
+    fn toggle_recording(&mut self) {
+        let line = match self.cpu.device.toggle_recording() {
+            Ok(status) => status,
+            Err(e) => format!("Could not record: {}", e),
+        };
+        self.lines.push(line);
+    }
+    fn open_prompt(&mut self, text: &str) {
+        self.input = Some(text.to_owned());
+        self.redraw();
+    }
+    fn submit_prompt(&mut self) {
+        if let Some(line) = self.input.take() {
+            self.history.push(&line);
+            self.lines.push(format!("> {}", line));
+            self.execute(&line);
+        }
+        self.redraw();
+    }
+    fn complete_prompt(&mut self) {
+        let candidates = match self.input {
+            Some(ref text) => repl::complete(text),
+            None => return,
+        };
+        if candidates.len() > 1 {
+            self.lines.push(candidates.join("  "));
+        }
+        let prefix = repl::common_prefix(&candidates);
+        if prefix.len() > self.input.as_ref().unwrap().len() {
+            self.input = Some(prefix);
+        }
+        self.redraw();
+    }
+    // Runs one REPL line, reporting the outcome in the log pane.
+    fn execute(&mut self, line: &str) {
+        let command = match repl::parse(line, &self.symbols) {
+            Ok(Some(command)) => command,
+            Ok(None) => return,
+            Err(e) => {
+                self.lines.push(format!("Error: {}", e));
+                return;
+            },
+        };
+        let result = match command {
+            repl::Command::Break(spec) => self.breakpoints.command(&spec, &self.symbols),
+            repl::Command::Watch(spec) => self.watchpoints.command(&spec, &self.symbols),
+            repl::Command::Memory(args) => self.memory.command(&mut self.cpu, &args, &self.symbols),
+            repl::Command::Sprite(args) => self.sprites.command(&args, &self.symbols),
+            repl::Command::Press(keys) => {
+                for key in keys {
+                    self.cpu.device.keyboard.press_value(key);
+                }
+                Ok(String::new())
+            },
+            repl::Command::Release(keys) => {
+                for key in keys {
+                    self.cpu.device.keyboard.release_value(key);
+                }
+                Ok(String::new())
+            },
+            repl::Command::Hold(timer, value) => self.hold(&timer, value),
+            repl::Command::Set(target, value) => self.set(&target, &value),
+            repl::Command::Examine(count, address) => {
+                let address = address.eval(&self.cpu);
+                for line in self.examine(address, count) {
+                    self.lines.push(line);
+                }
+                Ok(String::new())
+            },
+            repl::Command::Step(count) => {
+                self.step(count as i32);
+                Ok(String::new())
+            },
+            repl::Command::Continue => {
+                self.resume();
+                Ok(String::new())
+            },
+            repl::Command::Next => {
+                self.step_over();
+                Ok(String::new())
+            },
+            repl::Command::Finish => self.step_out(),
+            repl::Command::Until(stop) => {
+                self.run_until(stop);
+                Ok(String::new())
+            },
+            repl::Command::Save(path) => self.save_state(&path),
+            repl::Command::Load(path) => self.load_state(&path),
+            repl::Command::Symbols(path) => self.load_symbols(&path),
+            repl::Command::Reset => {
+                self.reset();
+                Ok("Reset".to_owned())
+            },
+            repl::Command::Help => {
+                for line in repl::HELP.iter() {
+                    self.lines.push(line.to_string());
+                }
+                Ok(String::new())
+            },
+            repl::Command::Quit => {
+                self.quit();
+                Ok(String::new())
+            },
+        };
+        match result {
+            Ok(ref status) if status.is_empty() => {},
+            Ok(status) => self.lines.push(status),
+            Err(e) => self.lines.push(format!("Error: {}", e)),
+        }
+    }
+    // Pins a timer at a value after every instruction, or lets it run again.
+    fn hold(&mut self, timer: &Expr, value: Option<Expr>) -> Result<String, String> {
+        let value = value.map(|value| value.eval(&self.cpu) as u8);
+        match *timer {
+            Expr::DelayTimer => self.holds.delay = value,
+            _ => self.holds.sound = value,
+        }
+        self.holds.apply(&mut self.cpu);
+        Ok(String::new())
+    }
+    fn set(&mut self, target: &Expr, value: &Expr) -> Result<String, String> {
+        let value = value.eval(&self.cpu);
+        match *target {
+            Expr::Reg(x) => self.cpu.regs[x] = value as u8,
+            Expr::Index => self.cpu.index = value as u16 & 0xFFF,
+            Expr::Pc => self.cpu.pc = value as u16 & 0xFFF,
+            Expr::DelayTimer => self.cpu.delay_timer.set_delay(value as u8),
+            Expr::SoundTimer => self.cpu.sound_timer.set_delay(value as u8),
+            Expr::Mem(ref address) => {
+                let address = address.eval(&self.cpu) as usize & 0xFFF;
+                self.cpu.mem[address] = value as u8;
+            },
+            _ => return Err("Not a settable location".to_owned()),
+        }
+        Ok(format!("Set to 0x{:X}", value))
+    }
+    fn examine(&self, address: i64, count: usize) -> Vec<String> {
+        let start = address as usize & 0xFFF;
+        let end = ::std::cmp::min(start + count, self.cpu.mem.len());
+        self.cpu.mem[start..end].chunks(8).enumerate().map(|(row, bytes)| {
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            format!("0x{:03X}: {}", start + row * 8, hex.join(" "))
+        }).collect()
+    }
+    fn save_state(&mut self, path: &str) -> Result<String, String> {
+        capture::write_file(Path::new(path), &self.cpu.save_state())?;
+        Ok(format!("Saved state to {}", path))
+    }
+    fn load_state(&mut self, path: &str) -> Result<String, String> {
+        let mut data = Vec::new();
+        File::open(path).and_then(|mut file| file.read_to_end(&mut data))
+            .map_err(|e| format!("{}: {}", path, e))?;
+        self.cpu.load_state(&data)?;
+        self.triggered = None;
+        self.render();
+        Ok(format!("Loaded state from {}", path))
+    }
+    // Runs `<rom>.dbg` next to the ROM, if there is one.
+    fn run_startup_script(&mut self) {
+        let script = match self.rom {
+            Some(ref rom) => Path::new(rom).with_extension("dbg"),
+            None => return,
+        };
+        if let Ok(file) = File::open(&script) {
+            self.lines.push(format!("Running {}", script.display()));
+            for line in BufReader::new(file).lines() {
+                match line {
+                    Ok(line) => self.execute(&line),
+                    Err(e) => {
+                        self.lines.push(format!("Error: {}: {}", script.display(), e));
+                        break;
+                    },
+                }
+            }
+        }
+    }
+    // Returns true and pauses if a breakpoint matches the next instruction.
+    fn check_breakpoints(&mut self) -> bool {
+        if let Some(stop) = self.stop {
+            if stop.reached(&self.cpu) {
+                self.stop = None;
+                self.state = State::Paused;
+                let place = match self.symbols.describe(self.cpu.pc) {
+                    Some(name) => format!("0x{:03X} ({})", self.cpu.pc, name),
+                    None => format!("0x{:03X}", self.cpu.pc),
+                };
+                self.lines.push(format!("Reached {} at cycle {}", place, self.cpu.cycles));
+                self.redraw();
+                return true;
+            }
+        }
+        match self.breakpoints.check(&self.cpu) {
+            Some(id) => {
+                self.state = State::Paused;
+                self.stop = None;
+                self.triggered = Some(id);
+                self.lines.push(format!("Breakpoint #{} hit at 0x{:03X}", id, self.cpu.pc));
+                self.redraw();
+                true
+            },
+            None => false,
+        }
+    }
+    // Pauses after an instruction that touched a watched location.
+    fn check_watchpoints(&mut self) -> bool {
+        match self.watchpoints.check(&self.cpu.accesses) {
+            Some((id, access)) => {
+                self.state = State::Paused;
+                self.stop = None;
+                self.lines.push(watch::report(id, &access));
+                self.view.invalidate();
+                true
+            },
+            None => false,
+        }
+    }
+    // Keys while the memory pane has focus: arrows and page keys move the
+    // cursor, hex digits overwrite the byte under it, Esc returns.
+    fn memory_key(&mut self, key: Option<Result<Key, ::std::io::Error>>) {
+        match key {
+            Some(Ok(Key::Esc))      => self.memory.focused = false,
+            Some(Ok(Key::Left))     => self.memory.move_cursor(-1),
+            Some(Ok(Key::Right))    => self.memory.move_cursor(1),
+            Some(Ok(Key::Up))       => self.memory.move_cursor(-(memory::BYTES_PER_ROW as i64)),
+            Some(Ok(Key::Down))     => self.memory.move_cursor(memory::BYTES_PER_ROW as i64),
+            Some(Ok(Key::PageUp))   => self.memory.move_cursor(-((MEMORY_ROWS * memory::BYTES_PER_ROW) as i64)),
+            Some(Ok(Key::PageDown)) => self.memory.move_cursor((MEMORY_ROWS * memory::BYTES_PER_ROW) as i64),
+            Some(Ok(Key::Char(':'))) => {
+                self.open_prompt("mem ");
+                return;
+            },
+            Some(Ok(Key::Char(c))) => match c.to_digit(16) {
+                Some(digit) => self.memory.edit(&mut self.cpu, digit as u8),
+                None => return,
+            },
+            _ => return,
+        }
+        self.redraw();
+    }
+    // Keys while the code pane has focus: arrows and page keys scroll, Enter
+    // follows the jump or call under the cursor and Backspace comes back,
+    // B toggles a breakpoint, U runs to the cursor, '.' returns to PC and
+    // Esc leaves.
+    fn code_key(&mut self, key: Option<Result<Key, ::std::io::Error>>) {
+        match key {
+            Some(Ok(Key::Esc))       => {
+                self.code.focused = false;
+                self.code.follow_pc();
+            },
+            Some(Ok(Key::Up))        => self.code.move_cursor(&self.cpu, -1),
+            Some(Ok(Key::Down))      => self.code.move_cursor(&self.cpu, 1),
+            Some(Ok(Key::PageUp))    => self.code.move_cursor(&self.cpu, -(CODE_ROWS as i32)),
+            Some(Ok(Key::PageDown))  => self.code.move_cursor(&self.cpu, CODE_ROWS as i32),
+            Some(Ok(Key::Char('\n'))) => if let Err(e) = self.code.follow(&self.cpu) {
+                self.lines.push(e);
+            },
+            Some(Ok(Key::Backspace)) => self.code.back(),
+            Some(Ok(Key::Char('.'))) => self.code.follow_pc(),
+            Some(Ok(Key::Char('b'))) => {
+                let address = self.code.cursor(&self.cpu);
+                let status = self.breakpoints.toggle_address(address, &self.symbols);
+                self.lines.push(status);
+            },
+            Some(Ok(Key::Char('u'))) => {
+                let address = self.code.cursor(&self.cpu);
+                self.code.focused = false;
+                self.code.follow_pc();
+                self.run_until(Stop::Address(address));
+            },
+            Some(Ok(Key::Char(':'))) => {
+                self.open_prompt("");
+                return;
+            },
+            _ => return,
+        }
+        self.redraw();
+    }
+    // Keys while the sprite pane has focus: Up and Down pick a draw from
+    // the log to preview and highlight, Esc leaves with it still picked.
+    fn sprite_key(&mut self, key: Option<Result<Key, ::std::io::Error>>) {
+        match key {
+            Some(Ok(Key::Esc))       => self.sprites.focused = false,
+            Some(Ok(Key::Up))        => self.sprites.move_selection(-1),
+            Some(Ok(Key::Down))      => self.sprites.move_selection(1),
+            Some(Ok(Key::PageUp))    => self.sprites.move_selection(-(CODE_ROWS as i32)),
+            Some(Ok(Key::PageDown))  => self.sprites.move_selection(CODE_ROWS as i32),
+            Some(Ok(Key::Backspace)) => self.sprites.deselect(),
+            Some(Ok(Key::Char(':'))) => {
+                self.open_prompt("sprite ");
+                return;
+            },
+            _ => return,
+        }
+        self.redraw();
+    }
     fn handle_command(&mut self) {
         match self.last_command {
             Some(Command::Next)             => self.step(1),
             Some(Command::Back)             => self.step(-1),
             Some(Command::PlayToggle)       => self.toggle_play(),
             Some(Command::Step)             => self.step(1),
+            Some(Command::StepOver)         => self.step_over(),
+            Some(Command::StepOut)          => if let Err(e) = self.step_out() {
+                self.lines.push(format!("Error: {}", e));
+            },
             Some(Command::Reset)            => self.reset(),
+            Some(Command::Screenshot)       => self.screenshot(),
+            Some(Command::ToggleRecording)  => self.toggle_recording(),
+            Some(Command::Prompt(text))     => self.open_prompt(text),
+            Some(Command::FocusMemory)      => {
+                self.memory.focused = true;
+                self.redraw();
+            },
+            Some(Command::FocusCode)        => {
+                self.code.focused = true;
+                self.sprites.visible = false;
+                self.redraw();
+            },
+            Some(Command::FocusSprites)     => {
+                self.sprites.visible = true;
+                self.sprites.focused = true;
+                self.redraw();
+            },
             Some(Command::Quit)             => self.quit(),
             Some(Command::ChangeSpeed(val)) => self.change_speed(val),
             None => {}
@@ -134,26 +656,82 @@ impl<'a> Debugger<'a> {
     pub fn run(&mut self) {
         self.cpu.initialize();
         self.view.initialize();
+        self.run_startup_script();
         self.render();
         let stdin = async_stdin();
         let mut events = stdin.keys();
         loop {
-            self.cpu.delay_timer.touch();
             if self.state == State::Running {
                 sleep(self.cpu.program_delay);
-                self.cycle();
+                if !self.check_breakpoints() {
+                    self.cycle();
+                }
             }
             if self.state == State::Quitting {
                 self.view.clear();
                 break;
             }
+            if self.input.is_some() {
+                match events.next() {
+                    Some(Ok(Key::Char('\n')))  => self.submit_prompt(),
+                    Some(Ok(Key::Esc))         => {
+                        self.input = None;
+                        self.redraw();
+                    },
+                    Some(Ok(Key::Backspace))   => {
+                        self.input.as_mut().unwrap().pop();
+                        self.redraw();
+                    },
+                    Some(Ok(Key::Char('\t')))  => self.complete_prompt(),
+                    Some(Ok(Key::Up))          => {
+                        if let Some(line) = self.history.previous() {
+                            self.input = Some(line.to_owned());
+                        }
+                        self.redraw();
+                    },
+                    Some(Ok(Key::Down))        => {
+                        if let Some(line) = self.history.next() {
+                            self.input = Some(line.to_owned());
+                        }
+                        self.redraw();
+                    },
+                    Some(Ok(Key::Char(c)))     => {
+                        self.input.as_mut().unwrap().push(c);
+                        self.redraw();
+                    },
+                    _ => {}
+                }
+                continue;
+            }
+            if self.memory.focused {
+                self.memory_key(events.next());
+                continue;
+            }
+            if self.code.focused {
+                self.code_key(events.next());
+                continue;
+            }
+            if self.sprites.focused {
+                self.sprite_key(events.next());
+                continue;
+            }
             self.last_command = match events.next() {
                 Some(Ok(Key::Right))        => Some(Command::Next),
                 Some(Ok(Key::Left))         => Some(Command::Back),
                 Some(Ok(Key::Char('p')))    => Some(Command::PlayToggle),
                 Some(Ok(Key::Char('n')))    => Some(Command::Step),
+                Some(Ok(Key::Char('o')))    => Some(Command::StepOver),
+                Some(Ok(Key::Char('f')))    => Some(Command::StepOut),
                 Some(Ok(Key::Char('=')))    => Some(Command::ChangeSpeed(10)),
                 Some(Ok(Key::Char('-')))    => Some(Command::ChangeSpeed(-10)),
+                Some(Ok(Key::Char('s')))    => Some(Command::Screenshot),
+                Some(Ok(Key::Char('r')))    => Some(Command::ToggleRecording),
+                Some(Ok(Key::Char(':')))    => Some(Command::Prompt("")),
+                Some(Ok(Key::Char('b')))    => Some(Command::Prompt("break ")),
+                Some(Ok(Key::Char('w')))    => Some(Command::Prompt("watch ")),
+                Some(Ok(Key::Char('m')))    => Some(Command::FocusMemory),
+                Some(Ok(Key::Char('c')))    => Some(Command::FocusCode),
+                Some(Ok(Key::Char('d')))    => Some(Command::FocusSprites),
                 Some(Ok(Key::Backspace))    => Some(Command::Reset),
                 Some(Ok(Key::Esc))          => Some(Command::Quit),
                 _                           => None
@@ -162,42 +740,42 @@ impl<'a> Debugger<'a> {
         }
     }
     pub fn disassemble_opcode(&self, opcode: &Opcode, cpu: &CPU) -> String {
-        match opcode.instr {
-            Instruction::SysAddressJump_0x0NNN          => { format!("Jump to address {xyz:03X}", xyz=opcode.xyz()) },
-            Instruction::ClearDisplay_0x00E0            => { format!("Clear the display") },
-            Instruction::RetFromSubroutine_0x00EE       => { format!("Return from sub: set pc = stack[sp] and set pc -= 1") },
-            Instruction::JumpLocation_0x1NNN            => { format!("Jump to address: set PC = 0x{xyz:03X}", xyz=opcode.xyz()) },
-            Instruction::CallSubroutine_0x2NNN          => { format!("Call subroutine: set PC = 0x{xyz:03X}, set sp += 1, set pc = {xyz:03X}", xyz=opcode.xyz()) },
-            Instruction::SkipInstrIfVxEqPL_0x3XNN       => { format!("Skip instruction if v[{x:01X}] == 0x{yz:02X}", x=opcode.x(), yz=opcode.yz()) },
-            Instruction::SkipInstrIfVxNotEqPL_0x4XNN    => { format!("Skip instruction if v[{x:01X}] != 0x{yz:02X}", x=opcode.x(), yz=opcode.yz()) },
-            Instruction::SkipInstrIfVxVy_0x5XY0         => { format!("Skip instruction if v[{x:01X}] == v[{y:01X}]", x=opcode.x(), y=opcode.y()) },
-            Instruction::SetVxToPL_0x6XNN               => { format!("Set v[{x:01X}] to 0x{yz:02X}", x=opcode.x(), yz=opcode.yz()) },
-            Instruction::IncrementVxByPL_0x7XNN         => { format!("Increment v[{x:01X}] by 0x{yz:02X}", x=opcode.x(), yz=opcode.yz()) },
-            Instruction::SetVxToVy_0x8XY0               => { format!("Set v[{x:01X}] to v[{y:01X}]", x=opcode.x(), y=opcode.y()) },
-            Instruction::SetVxToVxORVy_0x8XY1           => { format!("Set v[{x:01X}] to v[{x:01X}] | v[{y:01X}]", x=opcode.x(), y=opcode.y()) },
-            Instruction::SetVxToVxANDVy_0x8XY2          => { format!("Set v[{x:01X}] to v[{x:01X}] & v[{y:01X}]", x=opcode.x(), y=opcode.y()) },
-            Instruction::SetVxToVxXORVy_0x8XY3          => { format!("Set v[{x:01X}] to v[{x:01X}] ^ v[{y:01X}]", x=opcode.x(), y=opcode.y()) },
-            Instruction::IncrementVxByVyAndCarry_0x8XY4 => { format!("Increment v[{x:01X}] by v[{y:01X}](yy) and set v[F] = 1 if overflow", x=opcode.x(), y=opcode.y()) },
-            Instruction::DecrementVxByVyNoBorrow_0x8XY5 => { format!("Decrement v[{x:01X}] by v[{y:01X}](yy) and set v[F] = 1 if v[x] > v[{y:01X}]", x=opcode.x(), y=opcode.y()) },
-            Instruction::ShiftAndRotateVxRight_0x8XY6   => { format!("Shift and rotate v[{x:01X}] right", x=opcode.x()) },
-            Instruction::DecrementVyByVxNoBorrow_0x8XY7 => { format!("Decrement v[{y:01X}](yy) by v[{x:01X}] and set v[F] = 1 if v[{y:01X}] > v[{x:01X}]", x=opcode.x(), y=opcode.y()) },
-            Instruction::ShiftAndRotateVxLeft_0x8XYE    => { format!("Shift and rotate v[{x:01X}] left", x=opcode.x()) },
-            Instruction::SkipInstrIfVxNotVy_0x9XY0      => { format!("Skip instruction if v[{x:01X}] != v[{y:01X}]", x=opcode.x(), y=opcode.y()) },
-            Instruction::SetIndexRegToPL_0xANNN         => { format!("Set index to 0x{xyz:03X}", xyz=opcode.xyz()) },
-            Instruction::JumpToV0PlusPL_0xBNNN          => { format!("Jump to location: set pc = v[0] + 0x{xyz:03X}", xyz=opcode.xyz()) },
-            Instruction::SetVxRandByteANDPL_0xCXNN      => { format!("Set v[{x:01X}] to randbyte(0xNNN) & 0x{yz:02X}", x=opcode.x(), yz=opcode.yz()) },
-            Instruction::DisplaySpriteSetVfColl_0xDXYN  => { format!("Display {z}-byte sprite at (v[{x:01X}], v[{y:01X}]). Set v[F] = 1 if collision", x=opcode.x(), y=opcode.y(), z=opcode.z()) },
-            Instruction::SkipInstrIfVxPressed_0xEX9E    => { format!("Skip instruction if v[{x:01X}](keycode) pressed", x=opcode.x()) },
-            Instruction::SkipInstrIfVxNotPressed_0xEXA1 => { format!("Skip instruction if v[{x:01X}](keycode) not pressed", x=opcode.x()) },
-            Instruction::SetVxToDelayTimerVal_0xFX07    => { format!("Set v[{x:01X}] to value of delay timer (xxx)", x=opcode.x()) },
-            Instruction::WaitForKeyStoreInVx_0xFX0A     => { format!("Wait for key and store it's value in v[{x:01X}]", x=opcode.x()) },
-            Instruction::SetDelayTimerToVx_0xFX15       => { format!("Set delay timer to v[{x:01X}]", x=opcode.x()) },
-            Instruction::SetSoundTimerToVx_0xFX18       => { format!("Set sound timer to v[{x:01X}]", x=opcode.x()) },
-            Instruction::IncrementIndexRegByVx_0xFX1E   => { format!("Set index = index + v[{x:01X}]", x=opcode.x()) },
-            Instruction::SetIndexRegToVxSprite_0xFX29   => { format!("Set index equal to the v[{x:01X}]th sprite (v[{x:01X}] * 5)", x=opcode.x()) },
-            Instruction::StoreBCDOfVxIn3Bytes_0xFX33    => { format!("Store BCD of v[{x:01X}](xxx) in mem[i], mem[i+1], mem[i+2]", x=opcode.x()) },
-            Instruction::StoreRegsUptoVx_0xFX55         => { format!("Store v[0] through v[{x:01X}] in mem[index] through mem[index + {x:01X}]", x=opcode.x()) },
-            Instruction::ReadRegsUptoVx_0xFX65          => { format!("Store mem[index] through mem[index + {x:01X}] in v[0] through v[{x:01X}]", x=opcode.x()) },
+        match opcode.op {
+            Op::Sys(xyz)        => { format!("Jump to address {xyz:03X}", xyz=xyz) },
+            Op::Cls             => { format!("Clear the display") },
+            Op::Ret             => { format!("Return from sub: set pc = stack[sp] and set pc -= 1") },
+            Op::Jp(xyz)         => { format!("Jump to address: set PC = 0x{xyz:03X}", xyz=xyz) },
+            Op::Call(xyz)       => { format!("Call subroutine: set PC = 0x{xyz:03X}, set sp += 1, set pc = {xyz:03X}", xyz=xyz) },
+            Op::SeByte(x, yz)   => { format!("Skip instruction if v[{x:01X}] == 0x{yz:02X}", x=x, yz=yz) },
+            Op::SneByte(x, yz)  => { format!("Skip instruction if v[{x:01X}] != 0x{yz:02X}", x=x, yz=yz) },
+            Op::SeReg(x, y)     => { format!("Skip instruction if v[{x:01X}] == v[{y:01X}]", x=x, y=y) },
+            Op::LdByte(x, yz)   => { format!("Set v[{x:01X}] to 0x{yz:02X}", x=x, yz=yz) },
+            Op::AddByte(x, yz)  => { format!("Increment v[{x:01X}] by 0x{yz:02X}", x=x, yz=yz) },
+            Op::LdReg(x, y)     => { format!("Set v[{x:01X}] to v[{y:01X}]", x=x, y=y) },
+            Op::Or(x, y)        => { format!("Set v[{x:01X}] to v[{x:01X}] | v[{y:01X}]", x=x, y=y) },
+            Op::And(x, y)       => { format!("Set v[{x:01X}] to v[{x:01X}] & v[{y:01X}]", x=x, y=y) },
+            Op::Xor(x, y)       => { format!("Set v[{x:01X}] to v[{x:01X}] ^ v[{y:01X}]", x=x, y=y) },
+            Op::AddReg(x, y)    => { format!("Increment v[{x:01X}] by v[{y:01X}](yy) and set v[F] = 1 if overflow", x=x, y=y) },
+            Op::Sub(x, y)       => { format!("Decrement v[{x:01X}] by v[{y:01X}](yy) and set v[F] = 1 if v[x] > v[{y:01X}]", x=x, y=y) },
+            Op::Shr(x, _)       => { format!("Shift and rotate v[{x:01X}] right", x=x) },
+            Op::Subn(x, y)      => { format!("Decrement v[{y:01X}](yy) by v[{x:01X}] and set v[F] = 1 if v[{y:01X}] > v[{x:01X}]", y=y, x=x) },
+            Op::Shl(x, _)       => { format!("Shift and rotate v[{x:01X}] left", x=x) },
+            Op::SneReg(x, y)    => { format!("Skip instruction if v[{x:01X}] != v[{y:01X}]", x=x, y=y) },
+            Op::LdI(xyz)        => { format!("Set index to 0x{xyz:03X}", xyz=xyz) },
+            Op::JpV0(xyz)       => { format!("Jump to location: set pc = v[0] + 0x{xyz:03X}", xyz=xyz) },
+            Op::Rnd(x, yz)      => { format!("Set v[{x:01X}] to randbyte(0xNNN) & 0x{yz:02X}", x=x, yz=yz) },
+            Op::Drw(x, y, z)    => { format!("Display {z}-byte sprite at (v[{x:01X}], v[{y:01X}]). Set v[F] = 1 if collision", z=z, x=x, y=y) },
+            Op::Skp(x)          => { format!("Skip instruction if v[{x:01X}](keycode) pressed", x=x) },
+            Op::Sknp(x)         => { format!("Skip instruction if v[{x:01X}](keycode) not pressed", x=x) },
+            Op::LdVxDt(x)       => { format!("Set v[{x:01X}] to value of delay timer (xxx)", x=x) },
+            Op::LdVxK(x)        => { format!("Wait for key and store it's value in v[{x:01X}]", x=x) },
+            Op::LdDtVx(x)       => { format!("Set delay timer to v[{x:01X}]", x=x) },
+            Op::LdStVx(x)       => { format!("Set sound timer to v[{x:01X}]", x=x) },
+            Op::AddIVx(x)       => { format!("Set index = index + v[{x:01X}]", x=x) },
+            Op::LdFVx(x)        => { format!("Set index equal to the v[{x:01X}]th sprite (v[{x:01X}] * 5)", x=x) },
+            Op::LdBVx(x)        => { format!("Store BCD of v[{x:01X}](xxx) in mem[i], mem[i+1], mem[i+2]", x=x) },
+            Op::StoreRegs(x)    => { format!("Store v[0] through v[{x:01X}] in mem[index] through mem[index + {x:01X}]", x=x) },
+            Op::ReadRegs(x)     => { format!("Store mem[index] through mem[index + {x:01X}] in v[0] through v[{x:01X}]", x=x) },
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,344 @@
+// The debugger's command language. Each line is one command:
+//
+//   break 0x2A4 if v3 == 1     watch mem 0x300..0x310 write
+//   set v5 0x20                x/16 I
+//   step 100                   continue                until 0x2B0
+//   next                       finish                  until frame
+//   save state.c8s             load state.c8s          reset
+//   mem 0x300                  mem find A2 ?? 60       mem dump 0x200..0x300 out.bin
+//   symbols game.sym           break draw+4            x/8 score
+//   sprite 5 digits            press 5 a               hold dt 0x1E
+//
+// Addresses and expressions can use the names of loaded symbols. Lines
+// starting with # are comments, so the same syntax works for the per-ROM
+// startup scripts.
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use debugger::breakpoints::parse_address;
+use debugger::calls::Stop;
+use debugger::expr::Expr;
+use debugger::input::parse_keys;
+use debugger::symbols::Symbols;
+
+
+const HISTORY_FILE: &'static str = ".chip8_history";
+const HISTORY_SIZE: usize = 500;
+
+pub const COMMANDS: [&'static str; 20] = [
+    "break", "continue", "finish", "help", "hold", "load", "mem", "next", "press", "quit", "release", "reset",
+    "save", "set", "sprite", "step", "symbols", "until", "watch", "x",
+];
+
+pub const HELP: [&'static str; 14] = [
+    "break <addr> [if <expr>] | break if <expr> | break enable|disable|delete <n>",
+    "watch mem <addr>[..<end>] | v<x> | i  [read|write|access] [== <value>]",
+    "set v<x>|i|pc|dt|st|[<addr>] <expr>",
+    "x/<count> <expr>        dump memory",
+    "step [n] | next | finish | continue    next steps over CALLs, finish runs to RET",
+    "until <addr> | until frame | until draw   a symbol of the same name wins",
+    "save <file> | load <file>   machine state snapshots",
+    "mem <addr> | mem find <bytes> | mem dump <start>..<end> <file> | mem load <file> <addr>",
+    "symbols <file>          load names usable wherever an address is",
+    "sprite [<height> [<addr>]]   preview sprite memory, D picks from the frame's draws",
+    "press <keys> | release <keys>|all   hold keypad keys 0-F down, e.g. press 5 a",
+    "hold dt|st <value> | hold dt|st off   keep a timer at a value until released",
+    "reset | help | quit",
+    "Tab completes, Up/Down walk the history",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Break(String),
+    Watch(String),
+    Memory(String),
+    Sprite(String),
+    Press(Vec<u8>),
+    Release(Vec<u8>),
+    Hold(Expr, Option<Expr>),
+    Set(Expr, Expr),
+    Examine(usize, Expr),
+    Step(u32),
+    Next,
+    Finish,
+    Continue,
+    Until(Stop),
+    Save(String),
+    Load(String),
+    Symbols(String),
+    Reset,
+    Help,
+    Quit,
+}
+
+// Returns None for blank lines and comments.
+pub fn parse(line: &str, symbols: &Symbols) -> Result<Option<Command>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let (word, rest) = match line.find(char::is_whitespace) {
+        Some(pos) => (&line[..pos], line[pos..].trim()),
+        None => (line, ""),
+    };
+    let command = match word {
+        "break" | "b" => Command::Break(required(word, rest)?.to_owned()),
+        "watch" | "w" => Command::Watch(required(word, rest)?.to_owned()),
+        "mem" | "m" => Command::Memory(required(word, rest)?.to_owned()),
+        "sprite" => Command::Sprite(rest.to_owned()),
+        "press" => Command::Press(parse_keys(&words(required(word, rest)?))?),
+        "release" => Command::Release(parse_keys(&words(required(word, rest)?))?),
+        "hold" => {
+            let words = words(required(word, rest)?);
+            if words.len() != 2 {
+                return Err("Usage: hold dt|st <value>|off".to_owned());
+            }
+            let timer = match words[0] {
+                "dt" => Expr::DelayTimer,
+                "st" => Expr::SoundTimer,
+                other => return Err(format!("Can only hold dt or st, not {}", other)),
+            };
+            match words[1] {
+                "off" => Command::Hold(timer, None),
+                value => Command::Hold(timer, Some(Expr::parse(value, symbols)?)),
+            }
+        },
+        "set" => {
+            let rest = required(word, rest)?;
+            let (target, value) = match rest.find(char::is_whitespace) {
+                Some(pos) => (&rest[..pos], rest[pos..].trim()),
+                None => return Err("Usage: set <target> <value>".to_owned()),
+            };
+            let target = Expr::parse(target, symbols)?;
+            match target {
+                Expr::Reg(_) | Expr::Index | Expr::Pc | Expr::DelayTimer | Expr::SoundTimer | Expr::Mem(_) => {},
+                _ => return Err("Can only set v0-vF, i, pc, dt, st or [addr]".to_owned()),
+            }
+            Command::Set(target, Expr::parse(value, symbols)?)
+        },
+        "step" | "s" => match rest {
+            "" => Command::Step(1),
+            count => Command::Step(count.parse().map_err(|_| format!("Bad step count: {}", count))?),
+        },
+        "next" | "n" => Command::Next,
+        "finish" | "f" => Command::Finish,
+        "continue" | "c" => Command::Continue,
+        // A symbol named `frame` or `draw` wins over the keyword.
+        "until" | "u" => {
+            let target = required(word, rest)?;
+            Command::Until(match parse_address(target, symbols) {
+                Ok(address) => Stop::Address(address),
+                Err(_) if target == "frame" => Stop::Frame,
+                Err(_) if target == "draw" => Stop::Draw,
+                Err(e) => return Err(e),
+            })
+        },
+        "save" => Command::Save(required(word, rest)?.to_owned()),
+        "load" => Command::Load(required(word, rest)?.to_owned()),
+        "symbols" => Command::Symbols(required(word, rest)?.to_owned()),
+        "reset" => Command::Reset,
+        "help" | "?" => Command::Help,
+        "quit" | "q" => Command::Quit,
+        _ if word.starts_with("x") => {
+            let count = match &word[1..] {
+                "" => 16,
+                count if count.starts_with('/') => count[1..].parse()
+                    .map_err(|_| format!("Bad count: {}", count))?,
+                _ => return Err(format!("Unknown command: {}", word)),
+            };
+            let address = match rest {
+                "" => Expr::Index,
+                text => Expr::parse(text, symbols)?,
+            };
+            Command::Examine(count, address)
+        },
+        _ => return Err(format!("Unknown command: {}", word)),
+    };
+    Ok(Some(command))
+}
+
+fn required<'a>(command: &str, rest: &'a str) -> Result<&'a str, String> {
+    match rest {
+        "" => Err(format!("{} needs an argument", command)),
+        rest => Ok(rest),
+    }
+}
+
+fn words(rest: &str) -> Vec<&str> {
+    rest.split_whitespace().collect()
+}
+
+// Returns every full line that `line` could complete to.
+pub fn complete(line: &str) -> Vec<String> {
+    let words: Vec<&str> = line.split(' ').collect();
+    let last = words[words.len() - 1];
+    let head = &line[..line.len() - last.len()];
+    let registers: Vec<String> = (0..16).map(|x| format!("v{:x}", x)).collect();
+    let mut candidates: Vec<String> = match (words.len(), words[0]) {
+        (1, _) => COMMANDS.iter().map(|c| format!("{} ", c)).collect(),
+        (2, "break") | (2, "b") => vec!["enable ".to_owned(), "disable ".to_owned(), "delete ".to_owned(), "if ".to_owned()],
+        (2, "watch") | (2, "w") => {
+            let mut names = vec!["mem ".to_owned(), "i ".to_owned()];
+            names.extend(registers.iter().map(|r| format!("{} ", r)));
+            names
+        },
+        (3, "watch") | (3, "w") | (4, "watch") | (4, "w") => {
+            vec!["read".to_owned(), "write".to_owned(), "access".to_owned()]
+        },
+        (2, "mem") | (2, "m") => vec!["find ".to_owned(), "dump ".to_owned(), "load ".to_owned()],
+        (2, "until") | (2, "u") => vec!["frame".to_owned(), "draw".to_owned()],
+        (2, "release") => vec!["all".to_owned()],
+        (2, "hold") => vec!["dt ".to_owned(), "st ".to_owned()],
+        (3, "hold") => vec!["off".to_owned()],
+        (2, "set") => {
+            let mut names = vec!["i ".to_owned(), "pc ".to_owned(), "dt ".to_owned(), "st ".to_owned(), "[".to_owned()];
+            names.extend(registers.iter().map(|r| format!("{} ", r)));
+            names
+        },
+        _ => Vec::new(),
+    };
+    candidates.retain(|c| c.starts_with(&last.to_lowercase()));
+    candidates.iter().map(|c| format!("{}{}", head, c)).collect()
+}
+
+pub fn common_prefix(candidates: &[String]) -> String {
+    let mut prefix = match candidates.first() {
+        Some(first) => first.clone(),
+        None => return String::new(),
+    };
+    for candidate in candidates {
+        while !candidate.starts_with(&prefix) {
+            prefix.pop();
+        }
+    }
+    prefix
+}
+
+// Command history kept across sessions in ~/.chip8_history.
+pub struct History {
+    entries: Vec<String>,
+    position: usize,
+    path: Option<PathBuf>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            entries: Vec::new(),
+            position: 0,
+            path: None,
+        }
+    }
+    pub fn open() -> History {
+        let path = match env::var("HOME") {
+            Ok(home) => PathBuf::from(home).join(HISTORY_FILE),
+            Err(_) => PathBuf::from(HISTORY_FILE),
+        };
+        let mut history = History::new();
+        if let Ok(file) = File::open(&path) {
+            history.entries = BufReader::new(file).lines().map_while(Result::ok).collect();
+            let excess = history.entries.len().saturating_sub(HISTORY_SIZE);
+            history.entries.drain(..excess);
+        }
+        history.position = history.entries.len();
+        history.path = Some(path);
+        history
+    }
+    pub fn push(&mut self, line: &str) {
+        if !line.trim().is_empty() && self.entries.last().map(|l| l.as_str()) != Some(line) {
+            self.entries.push(line.to_owned());
+            if let Some(ref path) = self.path {
+                let file = OpenOptions::new().create(true).append(true).open(path);
+                if let Ok(mut file) = file {
+                    let _ = writeln!(file, "{}", line);
+                }
+            }
+        }
+        self.position = self.entries.len();
+    }
+    pub fn previous(&mut self) -> Option<&str> {
+        if self.position == 0 {
+            return None;
+        }
+        self.position -= 1;
+        Some(&self.entries[self.position])
+    }
+    pub fn next(&mut self) -> Option<&str> {
+        if self.position >= self.entries.len() {
+            return None;
+        }
+        self.position += 1;
+        Some(self.entries.get(self.position).map(|l| l.as_str()).unwrap_or(""))
+    }
+}
+
+#[test]
+fn test_parse_commands() {
+    let mut symbols = Symbols::new();
+    symbols.insert("draw", 0x2A4);
+    assert_eq!(parse("  # comment", &symbols).unwrap(), None);
+    assert_eq!(parse("break 0x2A4", &symbols).unwrap(), Some(Command::Break("0x2A4".to_owned())));
+    assert_eq!(parse("watch mem 0x300..0x310", &symbols).unwrap(), Some(Command::Watch("mem 0x300..0x310".to_owned())));
+    assert_eq!(parse("set v5 0x20", &symbols).unwrap(), Some(Command::Set(Expr::Reg(5), Expr::Number(0x20))));
+    assert_eq!(parse("x/16 I", &symbols).unwrap(), Some(Command::Examine(16, Expr::Index)));
+    assert_eq!(parse("x", &symbols).unwrap(), Some(Command::Examine(16, Expr::Index)));
+    assert_eq!(parse("step 100", &symbols).unwrap(), Some(Command::Step(100)));
+    assert_eq!(parse("step", &symbols).unwrap(), Some(Command::Step(1)));
+    assert_eq!(parse("c", &symbols).unwrap(), Some(Command::Continue));
+    assert_eq!(parse("until 0x2B0", &symbols).unwrap(), Some(Command::Until(Stop::Address(0x2B0))));
+
+    assert_eq!(parse("until draw+2", &symbols).unwrap(), Some(Command::Until(Stop::Address(0x2A6))));
+    assert_eq!(parse("until draw", &symbols).unwrap(), Some(Command::Until(Stop::Address(0x2A4))));
+    assert_eq!(parse("until draw", &Symbols::new()).unwrap(), Some(Command::Until(Stop::Draw)));
+    assert_eq!(parse("u frame", &symbols).unwrap(), Some(Command::Until(Stop::Frame)));
+    assert_eq!(parse("next", &symbols).unwrap(), Some(Command::Next));
+    assert_eq!(parse("sprite 5 draw", &symbols).unwrap(), Some(Command::Sprite("5 draw".to_owned())));
+    assert_eq!(parse("finish", &symbols).unwrap(), Some(Command::Finish));
+    assert_eq!(parse("x/4 draw", &symbols).unwrap(), Some(Command::Examine(4, Expr::Number(0x2A4))));
+    assert_eq!(parse("symbols game.sym", &symbols).unwrap(), Some(Command::Symbols("game.sym".to_owned())));
+
+    assert_eq!(parse("press 5 a", &symbols).unwrap(), Some(Command::Press(vec![5, 0xA])));
+    assert_eq!(parse("release all", &symbols).unwrap(), Some(Command::Release((0..16).collect())));
+    assert_eq!(parse("hold dt 0x1E", &symbols).unwrap(), Some(Command::Hold(Expr::DelayTimer, Some(Expr::Number(0x1E)))));
+    assert_eq!(parse("hold st off", &symbols).unwrap(), Some(Command::Hold(Expr::SoundTimer, None)));
+
+    assert!(parse("set sp 1", &symbols).is_err());
+    assert!(parse("press g", &symbols).is_err());
+    assert!(parse("hold v0 1", &symbols).is_err());
+    assert!(parse("hold dt", &symbols).is_err());
+    assert!(parse("set v5", &symbols).is_err());
+    assert!(parse("x/abc I", &symbols).is_err());
+    assert!(parse("frobnicate", &symbols).is_err());
+    assert!(parse("break", &symbols).is_err());
+}
+
+#[test]
+fn test_complete() {
+    assert_eq!(complete("st"), vec!["step ".to_owned()]);
+    assert_eq!(complete("mem f"), vec!["mem find ".to_owned()]);
+    assert_eq!(complete("s"), vec!["save ".to_owned(), "set ".to_owned(), "sprite ".to_owned(), "step ".to_owned(),
+                                   "symbols ".to_owned()]);
+    assert_eq!(common_prefix(&complete("s")), "s");
+    assert_eq!(complete("watch mem 0x300 wr"), vec!["watch mem 0x300 write".to_owned()]);
+    assert_eq!(complete("set p"), vec!["set pc ".to_owned()]);
+    assert_eq!(complete("until f"), vec!["until frame".to_owned()]);
+    assert_eq!(complete("hold d"), vec!["hold dt ".to_owned()]);
+    assert_eq!(complete("hold st o"), vec!["hold st off".to_owned()]);
+    assert_eq!(complete("break d"), vec!["break disable ".to_owned(), "break delete ".to_owned()]);
+    assert!(complete("step 1").is_empty());
+}
+
+#[test]
+fn test_history_navigation() {
+    let mut history = History::new();
+    history.push("step");
+    history.push("step");
+    history.push("x/8 I");
+    assert_eq!(history.previous(), Some("x/8 I"));
+    assert_eq!(history.previous(), Some("step"));
+    assert_eq!(history.previous(), None);
+    assert_eq!(history.next(), Some("x/8 I"));
+    assert_eq!(history.next(), Some(""));
+    assert_eq!(history.next(), None);
+}
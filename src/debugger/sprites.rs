@@ -0,0 +1,198 @@
+use ::termion::style;
+use cpu::{CPU, Draw};
+#[cfg(test)]
+use cpu::at_last_byte;
+use opcodes::Op;
+use debugger::breakpoints::parse_address;
+use debugger::code::CODE_WIDTH;
+use debugger::expr;
+use debugger::symbols::Symbols;
+
+
+// Preview columns: two characters per bit.
+const PREVIEW_WIDTH: usize = 16;
+
+// Every DXYN of the latest frame that drew anything, beside a preview of
+// sprite memory. The preview shows the selected draw's sprite, else the
+// bytes at I that the DXYN at PC (or the last one) would draw.
+pub struct SpritePane {
+    pub visible: bool,
+    pub focused: bool,
+    frame: u64,
+    draws: Vec<Draw>,
+    selected: Option<usize>,
+    // Height and address fixed by the `sprite` command.
+    fixed: Option<(usize, Option<u16>)>,
+}
+
+impl SpritePane {
+    pub fn new() -> SpritePane {
+        SpritePane {
+            visible: false,
+            focused: false,
+            frame: 0,
+            draws: Vec::new(),
+            selected: None,
+            fixed: None,
+        }
+    }
+    // Logs the sprite drawn by the instruction that just ran, starting
+    // over when it is the first of a new frame.
+    pub fn record(&mut self, cpu: &CPU) {
+        if let Some(ref draw) = cpu.draw {
+            let frame = (cpu.cycles - 1) / cpu.cycles_per_frame();
+            if frame != self.frame {
+                self.draws.clear();
+                self.selected = None;
+                self.frame = frame;
+            }
+            self.draws.push(draw.clone());
+        }
+    }
+    pub fn clear(&mut self) {
+        self.draws.clear();
+        self.selected = None;
+    }
+    pub fn selected(&self) -> Option<&Draw> {
+        self.selected.and_then(|i| self.draws.get(i))
+    }
+    pub fn move_selection(&mut self, delta: i32) {
+        if self.draws.is_empty() {
+            return;
+        }
+        let last = self.draws.len() as i32 - 1;
+        let next = match self.selected {
+            Some(i) => i as i32 + delta,
+            None if delta < 0 => last,
+            None => 0,
+        };
+        self.selected = Some(if next < 0 { 0 } else if next > last { last } else { next } as usize);
+    }
+    pub fn deselect(&mut self) {
+        self.selected = None;
+    }
+    // The REPL's `sprite` command:
+    //   sprite <height> [<addr>]   preview that many bytes at I or the address
+    //   sprite                     back to following the next DXYN
+    pub fn command(&mut self, line: &str, symbols: &Symbols) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        self.visible = true;
+        match words.len() {
+            0 => {
+                self.fixed = None;
+                Ok("Sprite preview follows DXYN".to_owned())
+            },
+            1 | 2 => {
+                let height = expr::parse_number(words[0])?;
+                if height < 1 || height > 15 {
+                    return Err(format!("Sprites are 1 to 15 bytes high, not {}", words[0]));
+                }
+                let address = match words.get(1) {
+                    Some(text) => Some(parse_address(text, symbols)?),
+                    None => None,
+                };
+                self.fixed = Some((height as usize, address));
+                Ok(String::new())
+            },
+            _ => Err("Usage: sprite [<height> [<addr>]]".to_owned()),
+        }
+    }
+    // Address and height of the sprite to preview.
+    fn preview(&self, cpu: &CPU) -> (u16, usize) {
+        if let Some(draw) = self.selected() {
+            return (draw.address, draw.height as usize);
+        }
+        if let Some((height, address)) = self.fixed {
+            return (address.unwrap_or(cpu.index), height);
+        }
+        let height = match cpu.next_op() {
+            Ok(Op::Drw(_, _, n)) => n as usize,
+            _ => self.draws.last().map_or(8, |draw| draw.height as usize),
+        };
+        (cpu.index, height)
+    }
+    pub fn title(&self, cpu: &CPU) -> String {
+        let (address, height) = self.preview(cpu);
+        format!("Sprite {:03X} x{:<2}    Frame {} draws: {}", address, height, self.frame, self.draws.len())
+    }
+    // The preview on the left, the draw log on the right: PC, VX,VY, height,
+    // source address and `*` for a collision.
+    pub fn rows(&self, cpu: &CPU, count: usize) -> Vec<String> {
+        let (address, height) = self.preview(cpu);
+        let first = match self.selected {
+            Some(i) if i >= count => i + 1 - count,
+            _ => 0,
+        };
+        (0..count).map(|row| {
+            let preview = match row < height {
+                true => {
+                    let byte = cpu.mem[(address as usize + row) & 0xFFF];
+                    (0..8).map(|bit| if byte & (0x80 >> bit) != 0 { "\u{2588}\u{2588}" } else { " ." }).collect()
+                },
+                false => " ".repeat(PREVIEW_WIDTH),
+            };
+            let width = CODE_WIDTH - PREVIEW_WIDTH - 3;
+            let entry = match self.draws.get(first + row) {
+                Some(draw) => {
+                    let text = format!("{:03X} {:>2},{:>2} x{:<2} {:03X} {}", draw.pc, draw.x, draw.y, draw.height,
+                                       draw.address, if draw.collision { "*" } else { " " });
+                    match self.selected == Some(first + row) {
+                        true => format!("{}{:<w$}{}", style::Invert, text, style::Reset, w=width),
+                        false => format!("{:<w$}", text, w=width),
+                    }
+                },
+                None => " ".repeat(width),
+            };
+            format!("{} | {}", preview, entry)
+        }).collect()
+    }
+}
+
+#[test]
+fn test_draw_log() {
+    // LD I, 0x300; DRW V0, V1, 3; ADD V0, 8; JP 0x202
+    let mut cpu = CPU::headless();
+    cpu.watching = true;
+    cpu.load_program(&[0xA3, 0x00, 0xD0, 0x13, 0x70, 0x08, 0x12, 0x02]);
+    cpu.mem[0x300] = 0x81;
+    let mut pane = SpritePane::new();
+    for _ in 0..7 {
+        cpu.cycle();
+        pane.record(&cpu);
+    }
+    assert_eq!(pane.draws.len(), 2);
+    assert_eq!((pane.draws[1].x, pane.draws[1].height), (8, 3));
+    assert_eq!(pane.rows(&cpu, 4)[1], format!("{} | {:<23}", " .".repeat(8), "202  8, 0 x3  300"));
+    assert!(pane.rows(&cpu, 4)[0].starts_with("\u{2588}\u{2588} . . . . . .\u{2588}\u{2588} | 202  0, 0"));
+    assert_eq!(pane.rows(&cpu, 4)[3], format!("{} | {}", " ".repeat(16), " ".repeat(23)));
+
+    pane.move_selection(-1);
+    assert_eq!(pane.selected().map(|d| d.x), Some(8));
+    pane.move_selection(5);
+    assert_eq!(pane.selected().map(|d| d.x), Some(8));
+
+    let symbols = Symbols::new();
+    assert!(pane.command("16", &symbols).is_err());
+    pane.deselect();
+    pane.command("2 0x310", &symbols).unwrap();
+    assert_eq!(pane.preview(&cpu), (0x310, 2));
+    pane.command("", &symbols).unwrap();
+    assert_eq!(pane.preview(&cpu), (0x300, 3));
+
+    // PC on data or at the last byte falls back to the last draw's height.
+    let data = at_last_byte(0xFFFF);
+    assert_eq!(pane.preview(&data), (data.index, 3));
+
+    // The first draw of a new frame starts a new log.
+    while cpu.cycles % cpu.cycles_per_frame() != 0 {
+        cpu.cycle();
+        pane.record(&cpu);
+    }
+    let drawn = pane.draws.len();
+    for _ in 0..3 {
+        cpu.cycle();
+        pane.record(&cpu);
+    }
+    assert!(drawn > 2);
+    assert_eq!((pane.frame, pane.draws.len()), (1, 1));
+}
@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+use std::path::Path;
+use asm::Program;
+use disasm::{self, Labels};
+
+
+// Names for addresses, from a symbol file or a compiled program. Names are
+// looked up without regard to case, as expressions are read in lower case.
+pub struct Symbols {
+    labels: Labels,
+    addresses: HashMap<String, u16>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols {
+            labels: Labels::new(),
+            addresses: HashMap::new(),
+        }
+    }
+    pub fn from_labels(labels: &Labels) -> Symbols {
+        let mut symbols = Symbols::new();
+        for (&address, name) in labels.iter() {
+            symbols.insert(name, address);
+        }
+        symbols
+    }
+    pub fn from_program(program: &Program) -> Symbols {
+        let mut symbols = Symbols::new();
+        for (name, &address) in program.symbols.iter() {
+            symbols.insert(name, address);
+        }
+        symbols
+    }
+    pub fn load(path: &Path) -> Result<Symbols, String> {
+        disasm::read_symbols(path).map(|labels| Symbols::from_labels(&labels))
+    }
+    // An address can have several names; the first one shows in listings.
+    pub fn insert(&mut self, name: &str, address: u16) {
+        self.labels.entry(address).or_insert_with(|| name.to_owned());
+        self.addresses.insert(name.to_lowercase(), address);
+    }
+    pub fn labels(&self) -> &Labels {
+        &self.labels
+    }
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.addresses.get(&name.to_lowercase()).cloned()
+    }
+    pub fn name(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(|name| name.as_str())
+    }
+    // The nearest name at or before `address`, as `draw` or `draw+0x6`.
+    pub fn describe(&self, address: u16) -> Option<String> {
+        self.labels.range(..address.saturating_add(1)).next_back().map(|(&at, name)| match address - at {
+            0 => name.clone(),
+            offset => format!("{}+0x{:X}", name, offset),
+        })
+    }
+}
+
+#[test]
+fn test_symbol_lookups() {
+    let mut symbols = Symbols::from_labels(&disasm::parse_symbols("main = 0x200\n0x2A4 Draw\n").unwrap());
+    symbols.insert("draw_alias", 0x2A4);
+    assert_eq!(symbols.address("draw"), Some(0x2A4));
+    assert_eq!(symbols.address("DRAW_ALIAS"), Some(0x2A4));
+    assert_eq!(symbols.address("missing"), None);
+    assert_eq!(symbols.name(0x2A4), Some("Draw"));
+    assert_eq!(symbols.describe(0x200), Some("main".to_owned()));
+    assert_eq!(symbols.describe(0x2AA), Some("Draw+0x6".to_owned()));
+    assert_eq!(symbols.describe(0x100), None);
+    assert_eq!(symbols.len(), 3);
+}
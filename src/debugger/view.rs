@@ -5,7 +5,23 @@ use std::io::{Write, stdout, BufWriter, Stdout};
 use std::fmt;
 use std::time::{Instant, Duration};
 
-const OPTION_MENU: &'static str = "| Instructions <Left | Right> | State <P> (Pause/Play) | Quit <ESC> | Speed <UP | DWN> |";
+const SCREEN_TOP: u16 = 2;
+const MEMORY_LEFT: u16 = 67;
+const MEMORY_WIDTH: u16 = 42;
+pub const MEMORY_ROWS: usize = 16;
+pub const CODE_ROWS: usize = 15;
+const OPTION_MENU: &'static str = "| Instructions <Left | Right> | Over <O> | Out <F> | State <P> (Pause/Play) | Screenshot <S> | Record <R> | Command <:> | Break <B> | Watch <W> | Memory <M> | Code <C> | Draws <D> | Quit <ESC> | Speed <UP | DWN> |";
+
+// Everything drawn besides the scrolling log, rebuilt on each render.
+pub struct Panes {
+    pub info: Vec<String>,
+    pub screen: Vec<String>,
+    pub memory: Vec<String>,
+    pub code: Vec<String>,
+    // Heading over the code rows, which the sprite pane can take over.
+    pub code_title: String,
+    pub prompt: Option<String>,
+}
 
 pub struct View<'view> {
     stdout: BufWriter<RawTerminal<Stdout>>,
@@ -13,6 +29,7 @@ pub struct View<'view> {
     height: u16,
     width: u16,
     menu_width: u8,
+    screen_rows: u16,
     last_instant: Instant,
     duration: Duration,
 }
@@ -24,6 +41,7 @@ impl<'view> View<'view> {
             height: 0,
             width: 0,
             menu_width: 40,
+            screen_rows: 0,
             last_instant: Instant::now(),
             duration: Duration::new(0, 16666666)
         };
@@ -35,7 +53,7 @@ impl<'view> View<'view> {
         self.update();
         self.paint_menu();
     }
-    pub fn render<I>(&mut self, lines: I, info:Vec<String>)
+    pub fn render<I>(&mut self, lines: I, panes: Panes)
         where I: IntoIterator,
               I::Item: fmt::Display,
     {
@@ -43,12 +61,23 @@ impl<'view> View<'view> {
         if now.duration_since(self.last_instant) > self.duration {
             self.update();
             write!(self.stdout, "{}", cursor::Hide).unwrap();
+            self.paint_screen(panes.screen);
+            self.paint_memory(panes.memory);
             self.paint_lines(lines);
+            self.paint_code(&panes.code_title, panes.code);
+            self.stdout.flush();
+            self.paint_info(panes.info);
+            match panes.prompt {
+                Some(text) => self.paint_prompt(&text),
+                None => self.paint_menu(),
+            }
             self.stdout.flush();
-            self.paint_info(info);
             self.last_instant = Instant::now();
         }
     }
+    pub fn invalidate(&mut self) {
+        self.last_instant = Instant::now() - self.duration * 2;
+    }
     pub fn clear(&mut self) {
         write!(self.stdout, "{}", clear::All);
     }
@@ -57,9 +86,10 @@ impl<'view> View<'view> {
               I::Item: fmt::Display,
     {
         let clear_width: usize = (self.width - (self.menu_width as u16)) as usize;
+        let top = SCREEN_TOP + self.screen_rows + 1;
         for (idx, line)in lines.into_iter().enumerate() {
             let clear_string = blank_string(clear_width);
-            if idx < self.height as usize {
+            if idx + (top as usize) < self.height as usize {
                 let line_height = ((self.height as u16) - idx as u16) - 1;
                 write!(self.stdout,
                        "{}{}{}{}",
@@ -72,8 +102,52 @@ impl<'view> View<'view> {
             }
         }
     }
+    fn paint_screen(&mut self, rows: Vec<String>) {
+        self.screen_rows = rows.len() as u16;
+        for (i, row) in rows.into_iter().enumerate() {
+            write!(self.stdout, "{}{}", cursor::Goto(1, SCREEN_TOP + i as u16), row).unwrap();
+        }
+    }
+    // Only drawn when it fits between the screen and the info column.
+    fn paint_memory(&mut self, rows: Vec<String>) {
+        if self.width < MEMORY_LEFT + MEMORY_WIDTH + 31 {
+            return;
+        }
+        write!(self.stdout, "{}{}Memory{}", cursor::Goto(MEMORY_LEFT, SCREEN_TOP - 1), style::Bold, style::Reset).unwrap();
+        for (i, row) in rows.into_iter().enumerate() {
+            write!(self.stdout, "{}{}", cursor::Goto(MEMORY_LEFT, SCREEN_TOP + i as u16), row).unwrap();
+        }
+    }
+    // Below the memory pane, on top of the log's right-hand side.
+    fn paint_code(&mut self, title: &str, rows: Vec<String>) {
+        if self.width < MEMORY_LEFT + MEMORY_WIDTH + 31 {
+            return;
+        }
+        let top = SCREEN_TOP + MEMORY_ROWS as u16 + 1;
+        write!(self.stdout, "{}{}{}{}{}", cursor::Goto(MEMORY_LEFT, top), style::Bold, title, style::Reset,
+               blank_string((MEMORY_WIDTH as usize).saturating_sub(title.chars().count()))).unwrap();
+        for (i, row) in rows.into_iter().enumerate() {
+            let y = top + 1 + i as u16;
+            if y >= self.height - 1 {
+                break;
+            }
+            write!(self.stdout, "{}{}", cursor::Goto(MEMORY_LEFT, y), row).unwrap();
+        }
+    }
+    fn paint_prompt(&mut self, text: &str) {
+        let clear_string = blank_string(self.width as usize);
+        write!(self.stdout, "{}{}{}{}{}",
+               termion::cursor::Goto(1, self.height),
+               clear_string,
+               termion::cursor::Goto(1, self.height),
+               text,
+               cursor::Show).unwrap();
+    }
     fn paint_menu(&mut self) {
-        write!(self.stdout, "{}{}{}{}",
+        let clear_string = blank_string(self.width as usize);
+        write!(self.stdout, "{}{}{}{}{}{}",
+               termion::cursor::Goto(1, self.height),
+               clear_string,
                style::Invert,
                termion::cursor::Goto(1, self.height),
                self.menu,
@@ -0,0 +1,257 @@
+use cpu::{Access, AccessKind, Location};
+use debugger::breakpoints::{parse_address, parse_range_end};
+use debugger::expr;
+use debugger::symbols::Symbols;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    // Half-open range of addresses.
+    Mem(u16, u16),
+    Reg(usize),
+    Index,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    Read,
+    Write,
+    Access,
+}
+
+// A watchpoint fires after an instruction that touched its target. With a
+// value it only fires on writes that change the target to that value.
+pub struct Watchpoint {
+    pub id: usize,
+    pub target: Target,
+    pub trigger: Trigger,
+    pub value: Option<u16>,
+    pub enabled: bool,
+    pub hits: u32,
+}
+
+impl Watchpoint {
+    // Accepts `mem 0x300..0x310`, `mem score`, `v3`, `i`, optionally followed
+    // by `read`, `write` or `access` (the default) and `== <value>`.
+    pub fn parse(id: usize, spec: &str, symbols: &Symbols) -> Result<Watchpoint, String> {
+        let (spec, value) = match spec.find("==") {
+            Some(pos) => (&spec[..pos], Some(expr::parse_number(spec[pos + 2..].trim())? as u16)),
+            None => (spec, None),
+        };
+        let mut words = spec.split_whitespace();
+        let target = match words.next() {
+            Some("mem") | Some("m") => {
+                let range = words.next().ok_or("Expected an address after mem".to_owned())?;
+                match range.find("..") {
+                    Some(pos) => {
+                        let start = parse_address(&range[..pos], symbols)?;
+                        let end = parse_range_end(&range[pos + 2..], symbols)?;
+                        if end <= start {
+                            return Err(format!("Empty range: {}", range));
+                        }
+                        Target::Mem(start, end)
+                    },
+                    None => {
+                        let address = parse_address(range, symbols)?;
+                        Target::Mem(address, address + 1)
+                    },
+                }
+            },
+            Some(name) => match expr::register(name) {
+                Some(x) => Target::Reg(x),
+                None if name.to_lowercase() == "i" => Target::Index,
+                None => return Err(format!("Unknown watch target: {}", name)),
+            },
+            None => return Err("Expected mem, a register or i".to_owned()),
+        };
+        let trigger = match words.next() {
+            None | Some("access") => Trigger::Access,
+            Some("read") => Trigger::Read,
+            Some("write") => Trigger::Write,
+            Some(word) => return Err(format!("Expected read, write or access, found {}", word)),
+        };
+        if let Some(word) = words.next() {
+            return Err(format!("Unexpected {}", word));
+        }
+        if value.is_some() && trigger == Trigger::Read {
+            return Err("A value can only be watched on writes".to_owned());
+        }
+        Ok(Watchpoint {
+            id: id,
+            target: target,
+            trigger: if value.is_some() { Trigger::Write } else { trigger },
+            value: value,
+            enabled: true,
+            hits: 0,
+        })
+    }
+    pub fn matches(&self, access: &Access) -> bool {
+        let on_target = match (self.target, access.location) {
+            (Target::Mem(start, end), Location::Mem(address)) => address >= start && address < end,
+            (Target::Reg(x), Location::Reg(reg)) => x == reg,
+            (Target::Index, Location::Index) => true,
+            _ => false,
+        };
+        let kind = match self.trigger {
+            Trigger::Read => access.kind == AccessKind::Read,
+            Trigger::Write => access.kind == AccessKind::Write,
+            Trigger::Access => true,
+        };
+        let value = match self.value {
+            Some(value) => access.new == value && access.old != value,
+            None => true,
+        };
+        self.enabled && on_target && kind && value
+    }
+    pub fn describe(&self) -> String {
+        let target = match self.target {
+            Target::Mem(start, end) if end == start + 1 => format!("mem 0x{:03X}", start),
+            Target::Mem(start, end) => format!("mem 0x{:03X}..0x{:03X}", start, end),
+            Target::Reg(x) => format!("v{:X}", x),
+            Target::Index => "i".to_owned(),
+        };
+        let trigger = match self.trigger {
+            Trigger::Read => "read",
+            Trigger::Write => "write",
+            Trigger::Access => "access",
+        };
+        let value = match self.value {
+            Some(value) => format!(" == 0x{:02X}", value),
+            None => String::new(),
+        };
+        format!("#{} [{}] {} {}{} ({} hits)",
+                self.id, if self.enabled { "x" } else { " " }, target, trigger, value, self.hits)
+    }
+}
+
+pub struct Watchpoints {
+    list: Vec<Watchpoint>,
+    next_id: usize,
+}
+
+impl Watchpoints {
+    pub fn new() -> Watchpoints {
+        Watchpoints {
+            list: Vec::new(),
+            next_id: 1,
+        }
+    }
+    pub fn iter(&self) -> ::std::slice::Iter<Watchpoint> {
+        self.list.iter()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+    pub fn add(&mut self, spec: &str, symbols: &Symbols) -> Result<usize, String> {
+        let watchpoint = Watchpoint::parse(self.next_id, spec, symbols)?;
+        self.next_id += 1;
+        self.list.push(watchpoint);
+        Ok(self.next_id - 1)
+    }
+    pub fn remove(&mut self, id: usize) -> Result<(), String> {
+        let len = self.list.len();
+        self.list.retain(|w| w.id != id);
+        match self.list.len() == len {
+            true => Err(format!("No watchpoint #{}", id)),
+            false => Ok(()),
+        }
+    }
+    pub fn set_enabled(&mut self, id: usize, enabled: bool) -> Result<(), String> {
+        match self.list.iter_mut().find(|w| w.id == id) {
+            Some(w) => {
+                w.enabled = enabled;
+                Ok(())
+            },
+            None => Err(format!("No watchpoint #{}", id)),
+        }
+    }
+    pub fn get(&self, id: usize) -> Option<&Watchpoint> {
+        self.list.iter().find(|w| w.id == id)
+    }
+    // Counts one hit per watchpoint touched by the instruction and returns
+    // the first triggering access.
+    pub fn check(&mut self, accesses: &[Access]) -> Option<(usize, Access)> {
+        let mut hit = None;
+        for w in self.list.iter_mut() {
+            if let Some(access) = accesses.iter().find(|a| w.matches(a)) {
+                w.hits += 1;
+                hit = hit.or(Some((w.id, *access)));
+            }
+        }
+        hit
+    }
+    // Same language as Breakpoints::command: a spec, or enable/disable/delete N.
+    pub fn command(&mut self, line: &str, symbols: &Symbols) -> Result<String, String> {
+        let mut words = line.trim().splitn(2, ' ');
+        let verb = words.next().unwrap_or("");
+        let rest = words.next().unwrap_or("").trim();
+        match verb {
+            "enable" | "disable" | "delete" => {
+                let id = expr::parse_number(rest.trim_start_matches('#'))? as usize;
+                match verb {
+                    "enable" => self.set_enabled(id, true)?,
+                    "disable" => self.set_enabled(id, false)?,
+                    _ => self.remove(id)?,
+                }
+                Ok(format!("Watchpoint #{} {}d", id, verb))
+            },
+            _ => {
+                let id = self.add(line, symbols)?;
+                Ok(format!("Added {}", self.get(id).unwrap().describe()))
+            },
+        }
+    }
+}
+
+pub fn report(id: usize, access: &Access) -> String {
+    let location = match access.location {
+        Location::Mem(address) => format!("mem[0x{:03X}]", address),
+        Location::Reg(x) => format!("v{:X}", x),
+        Location::Index => "I".to_owned(),
+    };
+    let change = match access.kind {
+        AccessKind::Read => format!("read {} = 0x{:02X}", location, access.new),
+        AccessKind::Write => format!("write {}: 0x{:02X} -> 0x{:02X}", location, access.old, access.new),
+    };
+    format!("Watchpoint #{} {} by 0x{:04X} at 0x{:03X}", id, change, access.opcode, access.pc)
+}
+
+#[cfg(test)]
+fn write(location: Location, old: u16, new: u16) -> Access {
+    Access { location: location, kind: AccessKind::Write, old: old, new: new, pc: 0x200, opcode: 0xF355 }
+}
+
+#[test]
+fn test_parse_watchpoints() {
+    let w = Watchpoint::parse(1, "mem 0x300..0x310", &Symbols::new()).unwrap();
+    assert_eq!((w.target, w.trigger, w.value), (Target::Mem(0x300, 0x310), Trigger::Access, None));
+    let w = Watchpoint::parse(2, "v5 write", &Symbols::new()).unwrap();
+    assert_eq!((w.target, w.trigger), (Target::Reg(5), Trigger::Write));
+    let w = Watchpoint::parse(3, "i == 0x250", &Symbols::new()).unwrap();
+    assert_eq!((w.target, w.trigger, w.value), (Target::Index, Trigger::Write, Some(0x250)));
+
+    assert!(Watchpoint::parse(4, "mem 0x310..0x300", &Symbols::new()).is_err());
+    let w = Watchpoint::parse(5, "mem 0xF00..0x1000", &Symbols::new()).unwrap();
+    assert_eq!(w.target, Target::Mem(0xF00, 0x1000));
+    assert!(Watchpoint::parse(5, "v5 read == 1", &Symbols::new()).is_err());
+    assert!(Watchpoint::parse(6, "pc", &Symbols::new()).is_err());
+}
+
+#[test]
+fn test_watchpoint_matches() {
+    let mut watchpoints = Watchpoints::new();
+    watchpoints.command("mem 0x300..0x302", &Symbols::new()).unwrap();
+    watchpoints.command("v3 == 5", &Symbols::new()).unwrap();
+
+    assert_eq!(watchpoints.check(&[write(Location::Mem(0x302), 0, 1)]), None);
+    let access = write(Location::Mem(0x301), 0, 1);
+    assert_eq!(watchpoints.check(&[access]), Some((1, access)));
+
+    // Only a change to the watched value counts.
+    assert_eq!(watchpoints.check(&[write(Location::Reg(3), 5, 5)]), None);
+    assert_eq!(watchpoints.check(&[write(Location::Reg(3), 4, 6)]), None);
+    assert!(watchpoints.check(&[write(Location::Reg(3), 4, 5)]).is_some());
+
+    assert_eq!(report(2, &write(Location::Reg(3), 4, 5)),
+               "Watchpoint #2 write v3: 0x04 -> 0x05 by 0xF355 at 0x200");
+}
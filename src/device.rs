@@ -1,115 +1,127 @@
-use sdl2::EventPump;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioDevice};
 use keyboard::Keyboard;
 use display::Display;
+use filters::{Filter, Overlay};
+use frontend::{Frontend, Glyphs, Headless, Sdl, Signal, Terminal};
+use std::path::Path;
+use capture::{CaptureSettings, Recorder};
 
 
-const SBUFF: [u8; 288] = [
-    0xa8, 0xc1, 0xc9, 0xce, 0xd3, 0xd8, 0xd9, 0xdd,
-    0xe0, 0xe1, 0xe3, 0xe4, 0xe6, 0xe7, 0xe9, 0xe8,
-    0xeb, 0xec, 0xeb, 0xec, 0xed, 0xee, 0xef, 0xee,
-    0xf0, 0xf1, 0xf0, 0xf1, 0xf0, 0xf2, 0xf1, 0xf2,
-    0xf3, 0xf2, 0xf4, 0xf3, 0xf4, 0xf3, 0xf4, 0xf4,
-    0xf3, 0xf5, 0xf5, 0xf5, 0xf4, 0xf5, 0xf5, 0xf6,
-    0xf4, 0xf6, 0xf6, 0xf6, 0xf5, 0xf6, 0xf5, 0xf6,
-    0xf6, 0xf5, 0xf6, 0xf5, 0xf6, 0xf6, 0xf6, 0xf6,
-    0xf5, 0xf4, 0xf5, 0xf4, 0xf5, 0xf5, 0xf4, 0xf5,
-    0xf5, 0xf4, 0xf5, 0xf4, 0xf4, 0xf3, 0xf4, 0xf3,
-    0xf3, 0xf3, 0xf2, 0xf3, 0xf2, 0xf3, 0xf2, 0xf3,
-    0xf2, 0xf1, 0xf2, 0xf1, 0xf2, 0xf0, 0xf1, 0xf0,
-    0xf1, 0xf0, 0xf0, 0xef, 0xf0, 0xef, 0xef, 0xef,
-    0xee, 0xef, 0xed, 0xee, 0xee, 0xed, 0xed, 0xed,
-    0xec, 0xed, 0xec, 0xeb, 0xec, 0xec, 0xea, 0xeb,
-    0xea, 0xeb, 0xea, 0xea, 0xea, 0xe9, 0xe9, 0xe9,
-    0xe8, 0xe9, 0xe8, 0xe8, 0xe7, 0xe8, 0xe7, 0xe7,
-    0xe6, 0xe7, 0xe6, 0xe5, 0xe5, 0xe5, 0xe6, 0xe4,
-    0xe6, 0xe4, 0xd5, 0xaa, 0x71, 0x44, 0x33, 0x32,
-    0x31, 0x29, 0x24, 0x22, 0x21, 0x1f, 0x1b, 0x19,
-    0x1a, 0x19, 0x15, 0x15, 0x14, 0x14, 0x13, 0x11,
-    0x12, 0x10, 0x10, 0x0e, 0x0f, 0x0e, 0x0e, 0x0d,
-    0x0c, 0x0d, 0x0d, 0x0b, 0x0c, 0x0b, 0x0c, 0x0a,
-    0x0b, 0x0a, 0x0b, 0x09, 0x0a, 0x09, 0x0a, 0x09,
-    0x0a, 0x0a, 0x09, 0x09, 0x08, 0x09, 0x09, 0x08,
-    0x09, 0x08, 0x09, 0x08, 0x09, 0x09, 0x08, 0x09,
-    0x08, 0x09, 0x09, 0x08, 0x09, 0x09, 0x08, 0x0a,
-    0x09, 0x0a, 0x09, 0x0a, 0x09, 0x0a, 0x09, 0x0a,
-    0x0a, 0x0b, 0x0a, 0x0b, 0x0a, 0x0b, 0x0a, 0x0b,
-    0x0c, 0x0c, 0x0c, 0x0c, 0x0c, 0x0c, 0x0d, 0x0c,
-    0x0d, 0x0d, 0x0e, 0x0c, 0x0d, 0x0e, 0x0e, 0x0e,
-    0x0d, 0x0f, 0x0f, 0x0f, 0x0f, 0x10, 0x10, 0x11,
-    0x10, 0x11, 0x11, 0x12, 0x10, 0x11, 0x12, 0x13,
-    0x12, 0x12, 0x14, 0x14, 0x13, 0x13, 0x13, 0x16,
-    0x13, 0x14, 0x14, 0x17, 0x16, 0x14, 0x16, 0x17,
-    0x18, 0x14, 0x16, 0x19, 0x1a, 0x15, 0x16, 0x1a,
-];
-
 pub struct Device<'d> {
-    pub audio: AudioDevice<Beep>,
-    display: Display<'d> ,
+    frontend: Box<Frontend + 'd>,
+    pub display: Display,
     pub keyboard: Keyboard,
-    pump: EventPump,
+    pub capture: CaptureSettings,
+    recorder: Option<Recorder>,
+    beeping: bool,
     pub quit: bool,
 }
 
 
 impl<'d> Device<'d> {
     pub fn new() -> Device<'d> {
-        let context = ::sdl2::init().unwrap();
-        let pump = context.event_pump().unwrap();
-        let audio_subsystem = context.audio().unwrap();
-        let desired_spec = AudioSpecDesired {
-            freq: Some(44100),
-            channels: Some(1),
-            samples: Some(288),
-        };
-
-        let beep = audio_subsystem.open_playback(None, &desired_spec, | s | {
-            Beep{}
-        }).unwrap();
-
+        Device::with_frontend(Box::new(Sdl::new()))
+    }
+    pub fn headless() -> Device<'d> {
+        Device::with_frontend(Box::new(Headless::new()))
+    }
+    pub fn terminal(glyphs: Glyphs) -> Device<'d> {
+        Device::with_frontend(Box::new(Terminal::new(glyphs)))
+    }
+    pub fn with_frontend(frontend: Box<Frontend + 'd>) -> Device<'d> {
         Device {
-            audio: beep,
-            display: Display::new(context),
+            frontend: frontend,
+            display: Display::new(),
             keyboard: Keyboard::new(),
-            pump: pump,
+            capture: CaptureSettings::new(),
+            recorder: None,
+            beeping: false,
             quit: false,
         }
     }
     pub fn pump(&mut self) {
-        for event in self.pump.poll_iter() {
-            match event {
-                Event::KeyDown { keycode, .. } => match keycode {
-                    Some(Keycode::Escape) => self.quit = true,
-                    _ => self.keyboard.handle_event(event)
+        for signal in self.frontend.poll(&mut self.keyboard) {
+            match signal {
+                Signal::Quit => self.quit = true,
+                Signal::CycleFilter => {
+                    self.display.cycle_filter();
+                    self.draw();
+                },
+                Signal::CycleOverlay => {
+                    self.display.cycle_overlay();
+                    self.draw();
+                },
+                Signal::Screenshot => match self.take_screenshot() {
+                    Ok(path) => info!("Saved screenshot to {}", path),
+                    Err(e) => warn!("Could not save screenshot: {}", e),
+                },
+                Signal::ToggleRecording => match self.toggle_recording() {
+                    Ok(status) => info!("{}", status),
+                    Err(e) => warn!("Could not record: {}", e),
                 },
-                Event::KeyUp { .. } => self.keyboard.handle_event(event),
-                Event::Quit { .. } => self.quit = true,
-                _ => {}
             }
         }
     }
-    pub fn write_bytes(&mut self, byte: Vec<u8>, x: usize, y:usize) -> u8 {
-        self.display.write_bytes(byte, x, y)
+    pub fn write_bytes(&mut self, byte: Vec<u8>, x: usize, y:usize, clip: bool) -> u8 {
+        self.display.write_bytes(byte, x, y, clip)
     }
     pub fn clear_display(&mut self) {
         self.display.clear();
     }
     pub fn draw(&mut self) {
-        self.display.draw()
+        self.frontend.present(&self.display)
     }
-}
-
-
-pub struct Beep {}
-
-impl AudioCallback for Beep {
-    type Channel = u8;
-
-    fn callback(&mut self, out: &mut [u8]) {
-        for i in 0..SBUFF.len() {
-            out[i] = SBUFF[i];
+    pub fn beep(&mut self, on: bool) {
+        self.beeping = on;
+        self.frontend.beep(on);
+    }
+    pub fn is_beeping(&self) -> bool {
+        self.beeping
+    }
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.display.filter = filter;
+    }
+    pub fn set_overlay(&mut self, overlay: Overlay) {
+        self.display.overlay = overlay;
+    }
+    pub fn take_screenshot(&self) -> Result<String, String> {
+        self.capture.save_screenshot(&self.display)
+            .map(|path| path.to_string_lossy().into_owned())
+    }
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+    pub fn start_recording(&mut self, path: &Path) -> Result<(), String> {
+        self.recorder = Some(Recorder::start(path, &self.display, self.capture.scale)?);
+        Ok(())
+    }
+    pub fn stop_recording(&mut self) -> Result<String, String> {
+        match self.recorder.take() {
+            Some(recorder) => {
+                let frames = recorder.frames();
+                let path = recorder.finish()?;
+                Ok(format!("Saved {} frames to {}", frames, path.display()))
+            },
+            None => Err("Not recording".to_owned()),
+        }
+    }
+    pub fn toggle_recording(&mut self) -> Result<String, String> {
+        match self.recorder {
+            Some(_) => self.stop_recording(),
+            None => {
+                self.recorder = Some(self.capture.start_recording(&self.display)?);
+                Ok("Recording started".to_owned())
+            },
         }
     }
-}
\ No newline at end of file
+    // Called once per emulated 60Hz frame.
+    pub fn end_frame(&mut self) {
+        let result = match self.recorder {
+            Some(ref mut recorder) => recorder.capture(&self.display),
+            None => Ok(()),
+        };
+        if let Err(e) = result {
+            warn!("Recording stopped: {}", e);
+            self.recorder = None;
+        }
+    }
+}
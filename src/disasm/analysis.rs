@@ -0,0 +1,237 @@
+// Separates code from data by following control flow from 0x200 instead of
+// sweeping every word. Jumps, calls, skips and returns are followed; ANNN
+// targets are taken as data; BNNN jumps cannot be followed statically and are
+// reported so that their targets can be given as extra entry points.
+use std::collections::BTreeSet;
+use opcodes::Op;
+use disasm::{disassemble, word, Labels, Syntax, ORIGIN};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Unknown,
+    // First and second byte of a reachable instruction.
+    Code,
+    Operand,
+}
+
+pub struct Analysis {
+    pub kinds: Vec<Kind>,
+    pub labels: Labels,
+    // ANNN targets inside the ROM.
+    pub data: BTreeSet<u16>,
+    // Addresses of BNNN jumps.
+    pub computed: BTreeSet<u16>,
+    // Instructions that start inside another instruction.
+    pub overlaps: BTreeSet<u16>,
+    // Reachable words that do not decode.
+    pub invalid: BTreeSet<u16>,
+}
+
+impl Analysis {
+    pub fn kind(&self, address: u16) -> Kind {
+        self.kinds[(address - ORIGIN) as usize]
+    }
+}
+
+pub fn analyse(rom: &[u8], entries: &[u16]) -> Analysis {
+    let end = ORIGIN as usize + rom.len();
+    let inside = |address: u16| address >= ORIGIN && (address as usize) < end;
+    let mut analysis = Analysis {
+        kinds: vec![Kind::Unknown; rom.len()],
+        labels: Labels::new(),
+        data: BTreeSet::new(),
+        computed: BTreeSet::new(),
+        overlaps: BTreeSet::new(),
+        invalid: BTreeSet::new(),
+    };
+    let mut pending: Vec<u16> = vec![ORIGIN];
+    pending.extend(entries.iter().cloned());
+    for entry in entries {
+        analysis.labels.insert(*entry, format!("entry_{:03X}", entry));
+    }
+    while let Some(address) = pending.pop() {
+        if !inside(address) || analysis.kind(address) == Kind::Code {
+            continue;
+        }
+        let offset = (address - ORIGIN) as usize;
+        let value = word(rom, offset);
+        let op = match Op::decode(value) {
+            Ok(op) => op,
+            Err(_) => {
+                analysis.invalid.insert(address);
+                continue;
+            },
+        };
+        if analysis.kinds[offset] == Kind::Operand {
+            analysis.overlaps.insert(address);
+        }
+        analysis.kinds[offset] = Kind::Code;
+        if offset + 1 < rom.len() && analysis.kinds[offset + 1] == Kind::Unknown {
+            analysis.kinds[offset + 1] = Kind::Operand;
+        }
+        let next = address + 2;
+        match op {
+            Op::Ret => {},
+            Op::Jp(nnn) => {
+                if inside(nnn) && !analysis.labels.contains_key(&nnn) {
+                    analysis.labels.insert(nnn, format!("label_{:03X}", nnn));
+                }
+                pending.push(nnn);
+            },
+            Op::Call(nnn) => {
+                if inside(nnn) {
+                    analysis.labels.insert(nnn, format!("sub_{:03X}", nnn));
+                }
+                pending.push(nnn);
+                pending.push(next);
+            },
+            Op::JpV0(_) => {
+                analysis.computed.insert(address);
+            },
+            Op::SeByte(..) |
+            Op::SneByte(..) |
+            Op::SeReg(..) |
+            Op::SneReg(..) |
+            Op::Skp(_) |
+            Op::Sknp(_) => {
+                pending.push(next);
+                pending.push(next + 2);
+            },
+            Op::LdI(nnn) => {
+                if inside(nnn) {
+                    analysis.data.insert(nnn);
+                }
+                pending.push(next);
+            },
+            _ => pending.push(next),
+        }
+    }
+    for address in analysis.data.iter() {
+        if analysis.kind(*address) == Kind::Unknown {
+            analysis.labels.entry(*address).or_insert(format!("data_{:03X}", address));
+        }
+    }
+    analysis
+}
+
+// Code is listed one instruction per line. Anything else is grouped into
+// blocks: blocks that ANNN points at are drawn as 8-pixel sprite rows, the
+// rest is dumped as unreached bytes.
+pub fn annotated_listing(rom: &[u8], syntax: Syntax, entries: &[u16]) -> String {
+    let analysis = analyse(rom, entries);
+    let comment = match syntax {
+        Syntax::Cowgod => ";",
+        Syntax::Octo => "#",
+    };
+    let mut out = String::new();
+    let mut offset = 0;
+    let mut sprite = false;
+    while offset < rom.len() {
+        let address = ORIGIN + offset as u16;
+        if let Some(name) = analysis.labels.get(&address) {
+            match syntax {
+                Syntax::Cowgod => out.push_str(&format!("{}:\n", name)),
+                Syntax::Octo => out.push_str(&format!(": {}\n", name)),
+            }
+        }
+        if analysis.kinds[offset] == Kind::Code {
+            sprite = false;
+            let value = word(rom, offset);
+            let mut text = disassemble(value, syntax, &analysis.labels);
+            if analysis.computed.contains(&address) {
+                text = format!("{:<24} {} computed jump: add targets with --entry", text, comment);
+            } else if analysis.overlaps.contains(&address) {
+                text = format!("{:<24} {} overlaps the previous instruction", text, comment);
+            }
+            out.push_str(&line(syntax, address, &format!("{:04X}", value), &text));
+            offset += 2;
+            continue;
+        }
+        if analysis.data.contains(&address) {
+            sprite = true;
+        }
+        if offset == 0 || analysis.kinds[offset - 1] != Kind::Unknown || analysis.labels.contains_key(&address) {
+            let title = if sprite { "sprite data" } else { "unreached" };
+            out.push_str(&format!("{} {}\n", comment, title));
+        }
+        if sprite {
+            let byte = rom[offset];
+            let pixels: String = (0..8).map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' }).collect();
+            let text = format!("{:<24} {} {}", data(syntax, &[byte]), comment, pixels);
+            out.push_str(&line(syntax, address, &format!("{:02X}", byte), &text));
+            offset += 1;
+        } else {
+            let mut count = 0;
+            while count < 8 && offset + count < rom.len() && analysis.kinds[offset + count] == Kind::Unknown &&
+                  (count == 0 || !analysis.labels.contains_key(&(address + count as u16))) {
+                count += 1;
+            }
+            let bytes = &rom[offset..offset + count];
+            let raw: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            out.push_str(&line(syntax, address, &raw.join(""), &data(syntax, bytes)));
+            offset += count;
+        }
+    }
+    for address in analysis.invalid.iter() {
+        out.push_str(&format!("{} warning: 0x{:03X} is reachable but does not decode\n", comment, address));
+    }
+    out
+}
+
+fn line(syntax: Syntax, address: u16, raw: &str, text: &str) -> String {
+    match syntax {
+        Syntax::Cowgod => format!("0x{:03X}  {:<4}  {}\n", address, raw, text),
+        Syntax::Octo => format!("\t{:<40} # 0x{:03X} {}\n", text, address, raw),
+    }
+}
+
+fn data(syntax: Syntax, bytes: &[u8]) -> String {
+    let values: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+    match syntax {
+        Syntax::Cowgod => format!("DB {}", values.join(", ")),
+        Syntax::Octo => values.join(" "),
+    }
+}
+
+#[cfg(test)]
+const PROGRAM: [u8; 18] = [
+    0xA2, 0x0E,     // 200: LD I, 0x20E
+    0x22, 0x0C,     // 202: CALL 0x20C
+    0x30, 0x01,     // 204: SE V0, 1
+    0x12, 0x04,     // 206: JP 0x204
+    0xB2, 0x00,     // 208: JP V0, 0x200
+    0xFF, 0xFF,     // 20A: unreached
+    0x00, 0xEE,     // 20C: RET
+    0xF0, 0x90,     // 20E: sprite
+    0xF0, 0x00,     // 210
+];
+
+#[test]
+fn test_analyse_control_flow() {
+    let analysis = analyse(&PROGRAM, &[]);
+    let code: Vec<u16> = (0..PROGRAM.len())
+        .filter(|o| analysis.kinds[*o] == Kind::Code)
+        .map(|o| ORIGIN + o as u16)
+        .collect();
+    assert_eq!(code, vec![0x200, 0x202, 0x204, 0x206, 0x208, 0x20C]);
+    assert_eq!(analysis.kind(0x20A), Kind::Unknown);
+    assert_eq!(analysis.kind(0x20E), Kind::Unknown);
+    assert!(analysis.data.contains(&0x20E));
+    assert!(analysis.computed.contains(&0x208));
+    assert_eq!(analysis.labels.get(&0x20C).map(|s| s.as_str()), Some("sub_20C"));
+    assert_eq!(analysis.labels.get(&0x204).map(|s| s.as_str()), Some("label_204"));
+    assert_eq!(analysis.labels.get(&0x20E).map(|s| s.as_str()), Some("data_20E"));
+
+    let hinted = analyse(&PROGRAM, &[0x20A]);
+    assert!(hinted.invalid.contains(&0x20A));
+}
+
+#[test]
+fn test_annotated_listing() {
+    let text = annotated_listing(&PROGRAM, Syntax::Cowgod, &[]);
+    assert!(text.contains("0x208  B200  JP V0, 0x200             ; computed jump"));
+    assert!(text.contains("; unreached\n0x20A  FFFF  DB 0xFF, 0xFF\n"));
+    assert!(text.contains("data_20E:\n; sprite data\n0x20E  F0    DB 0xF0                  ; ####....\n"));
+    assert!(text.contains("0x20F  90    DB 0x90                  ; #..#....\n"));
+}
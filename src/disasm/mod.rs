@@ -0,0 +1,285 @@
+// Mnemonic disassembly in Cowgod's syntax (`LD V3, 0x10`) or Octo's
+// (`v3 := 0x10`), and whole-ROM listings with generated labels.
+mod analysis;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use opcodes::Op;
+
+pub use self::analysis::{analyse, annotated_listing, Kind};
+
+
+pub const ORIGIN: u16 = 0x200;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    Cowgod,
+    Octo,
+}
+
+impl Syntax {
+    pub fn from_name(name: &str) -> Result<Syntax, String> {
+        match name.to_lowercase().as_str() {
+            "cowgod" | "chip8" => Ok(Syntax::Cowgod),
+            "octo"             => Ok(Syntax::Octo),
+            _ => Err(format!("Unknown syntax: {}", name)),
+        }
+    }
+}
+
+pub type Labels = BTreeMap<u16, String>;
+
+// Disassembles one word. Words that are not instructions come out as data.
+pub fn disassemble(value: u16, syntax: Syntax, labels: &Labels) -> String {
+    let op = match Op::decode(value) {
+        Ok(op) => op,
+        Err(_) => return data(value, syntax),
+    };
+    match syntax {
+        Syntax::Cowgod => cowgod(op, labels),
+        Syntax::Octo => octo(value, op, labels),
+    }
+}
+
+fn data(value: u16, syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Cowgod => format!("DW 0x{:04X}", value),
+        Syntax::Octo => format!("0x{:02X} 0x{:02X}", value >> 8, value & 0xFF),
+    }
+}
+
+fn address(nnn: u16, labels: &Labels) -> String {
+    match labels.get(&nnn) {
+        Some(name) => name.clone(),
+        None => format!("0x{:03X}", nnn),
+    }
+}
+
+// Addresses become labels where there is one; everything else is the
+// instruction's own mnemonic form.
+fn cowgod(op: Op, labels: &Labels) -> String {
+    match op {
+        Op::Sys(nnn)    => format!("SYS {}", address(nnn, labels)),
+        Op::Jp(nnn)     => format!("JP {}", address(nnn, labels)),
+        Op::Call(nnn)   => format!("CALL {}", address(nnn, labels)),
+        Op::LdI(nnn)    => format!("LD I, {}", address(nnn, labels)),
+        Op::JpV0(nnn)   => format!("JP V0, {}", address(nnn, labels)),
+        _               => op.to_string(),
+    }
+}
+
+// Octo's conditionals name the case in which the next instruction runs, so
+// each skip reads as the opposite comparison.
+fn octo(value: u16, op: Op, labels: &Labels) -> String {
+    match op {
+        Op::Sys(_)          => data(value, Syntax::Octo),
+        Op::Cls             => format!("clear"),
+        Op::Ret             => format!("return"),
+        Op::Jp(nnn)         => format!("jump {}", address(nnn, labels)),
+        Op::Call(nnn)       => format!(":call {}", address(nnn, labels)),
+        Op::SeByte(x, kk)   => format!("if v{:x} != 0x{:02X} then", x, kk),
+        Op::SneByte(x, kk)  => format!("if v{:x} == 0x{:02X} then", x, kk),
+        Op::SeReg(x, y)     => format!("if v{:x} != v{:x} then", x, y),
+        Op::LdByte(x, kk)   => format!("v{:x} := 0x{:02X}", x, kk),
+        Op::AddByte(x, kk)  => format!("v{:x} += 0x{:02X}", x, kk),
+        Op::LdReg(x, y)     => format!("v{:x} := v{:x}", x, y),
+        Op::Or(x, y)        => format!("v{:x} |= v{:x}", x, y),
+        Op::And(x, y)       => format!("v{:x} &= v{:x}", x, y),
+        Op::Xor(x, y)       => format!("v{:x} ^= v{:x}", x, y),
+        Op::AddReg(x, y)    => format!("v{:x} += v{:x}", x, y),
+        Op::Sub(x, y)       => format!("v{:x} -= v{:x}", x, y),
+        Op::Shr(x, y)       => format!("v{:x} >>= v{:x}", x, y),
+        Op::Subn(x, y)      => format!("v{:x} =- v{:x}", x, y),
+        Op::Shl(x, y)       => format!("v{:x} <<= v{:x}", x, y),
+        Op::SneReg(x, y)    => format!("if v{:x} == v{:x} then", x, y),
+        Op::LdI(nnn)        => format!("i := {}", address(nnn, labels)),
+        Op::JpV0(nnn)       => format!("jump0 {}", address(nnn, labels)),
+        Op::Rnd(x, kk)      => format!("v{:x} := random 0x{:02X}", x, kk),
+        Op::Drw(x, y, n)    => format!("sprite v{:x} v{:x} {}", x, y, n),
+        Op::Skp(x)          => format!("if v{:x} -key then", x),
+        Op::Sknp(x)         => format!("if v{:x} key then", x),
+        Op::LdVxDt(x)       => format!("v{:x} := delay", x),
+        Op::LdVxK(x)        => format!("v{:x} := key", x),
+        Op::LdDtVx(x)       => format!("delay := v{:x}", x),
+        Op::LdStVx(x)       => format!("buzzer := v{:x}", x),
+        Op::AddIVx(x)       => format!("i += v{:x}", x),
+        Op::LdFVx(x)        => format!("i := hex v{:x}", x),
+        Op::LdBVx(x)        => format!("bcd v{:x}", x),
+        Op::StoreRegs(x)    => format!("save v{:x}", x),
+        Op::ReadRegs(x)     => format!("load v{:x}", x),
+    }
+}
+
+pub fn word(rom: &[u8], offset: usize) -> u16 {
+    let low = if offset + 1 < rom.len() { rom[offset + 1] } else { 0 };
+    (rom[offset] as u16) << 8 | low as u16
+}
+
+// Names every jump and call target that lands on a listed word: `sub_XXX`
+// for calls, `label_XXX` for jumps.
+pub fn labels(rom: &[u8]) -> Labels {
+    let mut labels = Labels::new();
+    let end = ORIGIN as usize + rom.len();
+    for offset in (0..rom.len()).filter(|o| o % 2 == 0) {
+        let value = word(rom, offset);
+        let (target, call) = match Op::decode(value) {
+            Ok(Op::Call(nnn)) => (nnn, true),
+            Ok(Op::Jp(nnn)) => (nnn, false),
+            _ => continue,
+        };
+        if (target as usize) < ORIGIN as usize || target as usize >= end || target % 2 != 0 {
+            continue;
+        }
+        if call {
+            labels.insert(target, format!("sub_{:03X}", target));
+        } else {
+            labels.entry(target).or_insert(format!("label_{:03X}", target));
+        }
+    }
+    labels
+}
+
+// Symbol files, one symbol per line in any of these forms:
+//
+//   0x20A name            as the assembler writes them, always hex
+//   name = 0x20A          plain assignments, hex with `0x` or `$`, else decimal
+//   :const name 0x20A     Octo constants; values below 0x200 are taken to be
+//                         numbers rather than addresses and skipped
+//
+// Blank lines and `;` or `#` comments are skipped.
+pub fn parse_symbols(text: &str) -> Result<Labels, String> {
+    let mut labels = Labels::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split(|c| c == ';' || c == '#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (address, name) = if let Some(equals) = line.find('=') {
+            (symbol_value(line[equals + 1..].trim()), line[..equals].trim())
+        } else if words.len() == 3 && words[0] == ":const" {
+            match symbol_value(words[2]) {
+                Some(address) if address < ORIGIN => continue,
+                address => (address, words[1]),
+            }
+        } else if words.len() == 2 {
+            let address = words[0].trim_start_matches("0x").trim_start_matches("0X");
+            (u16::from_str_radix(address, 16).ok(), words[1])
+        } else {
+            (None, "")
+        };
+        match address {
+            Some(address) if !name.is_empty() && !name.contains(char::is_whitespace) => {
+                labels.insert(address, name.to_owned());
+            },
+            _ => return Err(format!("line {}: expected `0xADDR name` or `name = ADDR`, found `{}`",
+                                    number + 1, line)),
+        }
+    }
+    Ok(labels)
+}
+
+fn symbol_value(text: &str) -> Option<u16> {
+    if text.starts_with("0x") || text.starts_with("0X") {
+        u16::from_str_radix(&text[2..], 16).ok()
+    } else if text.starts_with('$') {
+        u16::from_str_radix(&text[1..], 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+pub fn read_symbols(path: &Path) -> Result<Labels, String> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut text))
+        .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    parse_symbols(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// One line per word, loaded at 0x200. Cowgod listings show the address and
+// raw word in columns; Octo listings keep them in comments so the output
+// still assembles.
+pub fn listing(rom: &[u8], syntax: Syntax) -> String {
+    let labels = labels(rom);
+    let mut out = String::new();
+    for offset in (0..rom.len()).filter(|o| o % 2 == 0) {
+        let address = ORIGIN + offset as u16;
+        let value = word(rom, offset);
+        let text = disassemble(value, syntax, &labels);
+        match (syntax, labels.get(&address)) {
+            (Syntax::Cowgod, Some(name)) => out.push_str(&format!("{}:\n", name)),
+            (Syntax::Octo, Some(name)) => out.push_str(&format!(": {}\n", name)),
+            _ => {},
+        }
+        match syntax {
+            Syntax::Cowgod => out.push_str(&format!("0x{:03X}  {:04X}  {}\n", address, value, text)),
+            Syntax::Octo => out.push_str(&format!("\t{:<24} # 0x{:03X} {:04X}\n", text, address, value)),
+        }
+    }
+    out
+}
+
+#[test]
+fn test_cowgod_mnemonics() {
+    let labels = Labels::new();
+    let cases = [
+        (0x00E0, "CLS"), (0x00EE, "RET"), (0x1234, "JP 0x234"), (0x2345, "CALL 0x345"),
+        (0x3A10, "SE VA, 0x10"), (0x5120, "SE V1, V2"), (0x6310, "LD V3, 0x10"),
+        (0x8AB4, "ADD VA, VB"), (0x8126, "SHR V1, V2"), (0xA22A, "LD I, 0x22A"),
+        (0xB300, "JP V0, 0x300"), (0xD125, "DRW V1, V2, 5"), (0xE59E, "SKP V5"),
+        (0xF40A, "LD V4, K"), (0xF233, "LD B, V2"), (0xF355, "LD [I], V3"),
+        (0xF365, "LD V3, [I]"), (0xFFFF, "DW 0xFFFF"),
+    ];
+    for &(value, text) in cases.iter() {
+        assert_eq!(disassemble(value, Syntax::Cowgod, &labels), text);
+    }
+}
+
+#[test]
+fn test_octo_mnemonics() {
+    let mut labels = Labels::new();
+    labels.insert(0x234, "loop".to_owned());
+    let cases = [
+        (0x00E0, "clear"), (0x1234, "jump loop"), (0x2300, ":call 0x300"),
+        (0x3A10, "if va != 0x10 then"), (0x4A10, "if va == 0x10 then"),
+        (0x6310, "v3 := 0x10"), (0x8127, "v1 =- v2"), (0xC30F, "v3 := random 0x0F"),
+        (0xE1A1, "if v1 key then"), (0xF129, "i := hex v1"), (0xF018, "buzzer := v0"),
+        (0x0123, "0x01 0x23"),
+    ];
+    for &(value, text) in cases.iter() {
+        assert_eq!(disassemble(value, Syntax::Octo, &labels), text);
+    }
+}
+
+#[test]
+fn test_listing_labels() {
+    // 0x200: CALL 0x206, 0x202: JP 0x202, 0x204: data, 0x206: RET
+    let rom = [0x22, 0x06, 0x12, 0x02, 0xFF, 0xFF, 0x00, 0xEE];
+    let text = listing(&rom, Syntax::Cowgod);
+    assert_eq!(text, "0x200  2206  CALL sub_206\n\
+                      label_202:\n\
+                      0x202  1202  JP label_202\n\
+                      0x204  FFFF  DW 0xFFFF\n\
+                      sub_206:\n\
+                      0x206  00EE  RET\n");
+    let text = listing(&rom, Syntax::Octo);
+    assert!(text.contains(": sub_206\n\treturn"));
+    assert!(text.contains("\t:call sub_206            # 0x200 2206\n"));
+}
+
+#[test]
+fn test_parse_symbols() {
+    let labels = parse_symbols("0x200 start\n\n0x20A digit ; sprite\n20F digit.end\n").unwrap();
+    assert_eq!(labels.get(&0x20A).map(|s| s.as_str()), Some("digit"));
+    assert_eq!(labels.get(&0x20F).map(|s| s.as_str()), Some("digit.end"));
+    assert_eq!(parse_symbols("0x200\n"),
+               Err("line 1: expected `0xADDR name` or `name = ADDR`, found `0x200`".to_owned()));
+    let labels = parse_symbols("main = 0x200\ndraw=$2A4 # sprites\ntable = 768\n\
+                                :const main_loop 0x206\n:const SPEED 3\n").unwrap();
+    let names: Vec<(u16, &str)> = labels.iter().map(|(&a, n)| (a, n.as_str())).collect();
+    assert_eq!(names, vec![(0x200, "main"), (0x206, "main_loop"), (0x2A4, "draw"), (0x300, "table")]);
+    assert!(parse_symbols("two words = 0x200\n").is_err());
+}
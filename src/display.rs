@@ -1,42 +1,45 @@
-extern crate sdl2;
+use filters::{self, Filter, Frame, Overlay, Palette};
 
-use self::sdl2::render::Renderer;
-use self::sdl2::pixels::Color;
-use self::sdl2::rect::Rect;
 
-
-const SCREEN_WIDTH: usize = 64;
-const SCREEN_HEIGHT: usize = 32;
+pub const SCREEN_WIDTH: usize = 64;
+pub const SCREEN_HEIGHT: usize = 32;
 const SCREEN_PIXELS: usize = SCREEN_WIDTH * SCREEN_HEIGHT;
-const DISPLAY_WIDTH: usize = SCREEN_WIDTH * 20;
-const DISPLAY_HEIGHT: usize = SCREEN_HEIGHT * 20;
-const PIXEL_SIZE: usize = DISPLAY_HEIGHT / SCREEN_HEIGHT;
-const TITLE: &str = "RIP-8::CHIP-8";
 
-pub struct Display<'d> {
+pub struct Display {
     pixels: [bool; SCREEN_PIXELS],
-    renderer: Renderer<'d>,
+    last_draw: Vec<usize>,
+    pub filter: Filter,
+    pub overlay: Overlay,
+    pub palette: Palette,
 }
 
 
-impl<'d> Display<'d> {
-    pub fn new(context: sdl2::Sdl) -> Display<'d> {
-        let video = context.video().unwrap();
-        let window = video.window(TITLE, DISPLAY_WIDTH as u32, DISPLAY_HEIGHT as u32)
-                          .position_centered().opengl().build().unwrap();
-        let renderer = window.renderer().accelerated()
-                              .build().unwrap();
+impl Display {
+    pub fn new() -> Display {
         Display {
             pixels: [false; SCREEN_PIXELS],
-            renderer: renderer,
+            last_draw: Vec::new(),
+            filter: Filter::Nearest,
+            overlay: Overlay::Off,
+            palette: Palette::new(),
         }
     }
-    pub fn write_bytes(&mut self, bytes: Vec<u8>, x: usize, y: usize) -> u8 {
+    // The sprite starts at (x, y) wrapped onto the screen; with `clip` the
+    // rows and columns running past the edges are dropped instead of wrapping.
+    pub fn write_bytes(&mut self, bytes: Vec<u8>, x: usize, y: usize, clip: bool) -> u8 {
         let mut rv = 0;
+        let (x, y) = (x % SCREEN_WIDTH, y % SCREEN_HEIGHT);
+        self.last_draw.clear();
         for (r, byte) in bytes.into_iter().enumerate() {
+            if clip && r + y >= SCREEN_HEIGHT {
+                break;
+            }
             let sy = (r + y) % SCREEN_HEIGHT;
 
             for j in 0..8 {
+                if clip && x + j >= SCREEN_WIDTH {
+                    break;
+                }
                 let sx = (x + j) % SCREEN_WIDTH;
                 let offset = sy * SCREEN_WIDTH + sx;
 
@@ -44,6 +47,9 @@ impl<'d> Display<'d> {
                 let was_set = *dot;
 
                 let dot_set = (byte >> (7 - j)) & 1;
+                if dot_set == 1 {
+                    self.last_draw.push(offset);
+                }
 
                 *dot = ((*dot as u8) ^ dot_set) != 0;
 
@@ -62,47 +68,36 @@ impl<'d> Display<'d> {
         bytearr
     }
 
-    pub fn draw(&mut self) {
-        self.renderer.set_draw_color(Color::RGB (28,28,28));
-        self.renderer.clear();
-        self.renderer.set_draw_color(Color::RGB(230, 230, 230));
-        for (idx, p) in self.pixels.into_iter().enumerate() {
-            if *p {
-                let x = idx - (idx / SCREEN_WIDTH * SCREEN_WIDTH);
-                let y = idx / SCREEN_WIDTH;
-                let pixel = Pixel::new(x, y);
-                self.renderer.fill_rect(pixel.to_sdl());
-            }
-        }
-        self.renderer.present();
+    pub fn pixels(&self) -> &[bool] {
+        &self.pixels
+    }
+    // Offsets of the pixels flipped by the most recent sprite draw.
+    pub fn last_draw(&self) -> &[usize] {
+        &self.last_draw
+    }
+    pub fn width(&self) -> usize {
+        SCREEN_WIDTH
+    }
+    pub fn height(&self) -> usize {
+        SCREEN_HEIGHT
+    }
+    pub fn frame(&self) -> Frame {
+        filters::render(&self.pixels, SCREEN_WIDTH, SCREEN_HEIGHT,
+                        self.filter, self.overlay, &self.palette)
+    }
+    pub fn cycle_filter(&mut self) {
+        self.filter = self.filter.next();
+    }
+    pub fn cycle_overlay(&mut self) {
+        self.overlay = self.overlay.next();
     }
     pub fn clear(&mut self) {
         self.pixels = [false; SCREEN_PIXELS];
+        self.last_draw.clear();
     }
-}
-
-struct Pixel {
-    x: usize,
-    y: usize,
-    w: usize,
-    h: usize,
-}
-
-impl Pixel {
-    pub fn new(x: usize, y: usize) -> Pixel {
-        Pixel {
-            x: x * PIXEL_SIZE,
-            y: y * PIXEL_SIZE,
-            w: PIXEL_SIZE,
-            h: PIXEL_SIZE,
-        }
-    }
-    fn to_sdl(self ) -> Rect {
-        let x = self.x as i32;
-        let y = self.y as i32;
-        let h = self.h as u32;
-        let w = self.w as u32;
-        Rect::new(x, y, h, w)
+    pub fn set_pixels(&mut self, pixels: &[bool]) {
+        self.pixels.copy_from_slice(pixels);
+        self.last_draw.clear();
     }
 }
 
@@ -148,4 +143,4 @@ fn test_write_byte_overflow() {
         list[i] = disp.pixels[i + start];
     }
     assert_eq!(list, [1, 0, 1, 0, 0, 0, 0, 0])
-}
\ No newline at end of file
+}
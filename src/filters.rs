@@ -0,0 +1,580 @@
+// Software upscalers for the 1-bit framebuffer. Everything here runs on the
+// CPU over plain slices so it works for both 64x32 and 128x64 screens and can
+// be checked against golden outputs without a window.
+
+const ON: u8 = 0xFF;
+const OFF: u8 = 0x00;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    pub background: Rgb,
+    pub foreground: Rgb,
+}
+
+impl Palette {
+    pub fn new() -> Palette {
+        Palette {
+            background: Rgb(28, 28, 28),
+            foreground: Rgb(230, 230, 230),
+        }
+    }
+    pub fn mix(&self, level: u8) -> Rgb {
+        let bg = self.background;
+        let fg = self.foreground;
+        Rgb(lerp(bg.0, fg.0, level), lerp(bg.1, fg.1, level), lerp(bg.2, fg.2, level))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Scale2x,
+    Scale3x,
+    // Scale2x and Scale3x with filled corners blended into the centre pixel.
+    EpxBlend2x,
+    EpxBlend3x,
+    Hq2x,
+    Hq3x,
+    Smooth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overlay {
+    Off,
+    Scanlines,
+    ShadowMask,
+}
+
+// Greyscale output of a filter, 0x00 is background and 0xFF is foreground.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Levels {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<Rgb>,
+}
+
+impl Filter {
+    pub fn from_name(name: &str) -> Result<Filter, String> {
+        match name.to_lowercase().as_str() {
+            "nearest" | "none"          => Ok(Filter::Nearest),
+            "scale2x" | "epx"           => Ok(Filter::Scale2x),
+            "scale3x"                   => Ok(Filter::Scale3x),
+            "epx-blend" | "epx-blend2x" => Ok(Filter::EpxBlend2x),
+            "epx-blend3x"               => Ok(Filter::EpxBlend3x),
+            "hq2x"                      => Ok(Filter::Hq2x),
+            "hq3x"                      => Ok(Filter::Hq3x),
+            "smooth"                    => Ok(Filter::Smooth),
+            _ => Err(format!("Unknown filter: {}", name)),
+        }
+    }
+    pub fn next(&self) -> Filter {
+        match *self {
+            Filter::Nearest    => Filter::Scale2x,
+            Filter::Scale2x    => Filter::Scale3x,
+            Filter::Scale3x    => Filter::EpxBlend2x,
+            Filter::EpxBlend2x => Filter::EpxBlend3x,
+            Filter::EpxBlend3x => Filter::Hq2x,
+            Filter::Hq2x       => Filter::Hq3x,
+            Filter::Hq3x       => Filter::Smooth,
+            Filter::Smooth     => Filter::Nearest,
+        }
+    }
+    pub fn apply(&self, pixels: &[bool], width: usize, height: usize) -> Levels {
+        let source = Levels::from_pixels(pixels, width, height);
+        match *self {
+            Filter::Nearest    => source,
+            Filter::Scale2x    => scale2x(&source, false),
+            Filter::Scale3x    => scale3x(&source, false),
+            Filter::EpxBlend2x => scale2x(&source, true),
+            Filter::EpxBlend3x => scale3x(&source, true),
+            Filter::Hq2x       => hqnx(&source, 2),
+            Filter::Hq3x       => hqnx(&source, 3),
+            Filter::Smooth     => smooth(&scale2x(&scale2x(&source, false), false)),
+        }
+    }
+}
+
+impl Overlay {
+    pub fn from_name(name: &str) -> Result<Overlay, String> {
+        match name.to_lowercase().as_str() {
+            "off" | "none" => Ok(Overlay::Off),
+            "scanlines"    => Ok(Overlay::Scanlines),
+            "shadowmask" | "shadow-mask" => Ok(Overlay::ShadowMask),
+            _ => Err(format!("Unknown overlay: {}", name)),
+        }
+    }
+    pub fn next(&self) -> Overlay {
+        match *self {
+            Overlay::Off        => Overlay::Scanlines,
+            Overlay::Scanlines  => Overlay::ShadowMask,
+            Overlay::ShadowMask => Overlay::Off,
+        }
+    }
+    pub fn apply(&self, frame: &mut Frame) {
+        match *self {
+            Overlay::Off => {},
+            Overlay::Scanlines => {
+                for y in (1..frame.height).filter(|y| y % 2 == 1) {
+                    for x in 0..frame.width {
+                        let p = &mut frame.data[y * frame.width + x];
+                        *p = Rgb(p.0 / 2, p.1 / 2, p.2 / 2);
+                    }
+                }
+            },
+            Overlay::ShadowMask => {
+                for y in 0..frame.height {
+                    for x in 0..frame.width {
+                        let p = &mut frame.data[y * frame.width + x];
+                        *p = match (x + y / 2 * 3) % 3 {
+                            0 => Rgb(p.0, dim(p.1), dim(p.2)),
+                            1 => Rgb(dim(p.0), p.1, dim(p.2)),
+                            _ => Rgb(dim(p.0), dim(p.1), p.2),
+                        };
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl Levels {
+    pub fn from_pixels(pixels: &[bool], width: usize, height: usize) -> Levels {
+        Levels {
+            width: width,
+            height: height,
+            data: pixels.iter().map(|p| if *p { ON } else { OFF }).collect(),
+        }
+    }
+    // Edge pixels are repeated so the filters never read outside the screen.
+    fn at(&self, x: isize, y: isize) -> u8 {
+        let cx = clamp(x, self.width);
+        let cy = clamp(y, self.height);
+        self.data[cy * self.width + cx]
+    }
+    pub fn colorize(&self, palette: &Palette) -> Frame {
+        Frame {
+            width: self.width,
+            height: self.height,
+            data: self.data.iter().map(|l| palette.mix(*l)).collect(),
+        }
+    }
+}
+
+impl Frame {
+    pub fn scale(&self, factor: usize) -> Frame {
+        let width = self.width * factor;
+        let height = self.height * factor;
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                data.push(self.data[(y / factor) * self.width + x / factor]);
+            }
+        }
+        Frame { width: width, height: height, data: data }
+    }
+}
+
+// Runs the whole output pipeline: upscale, colour, then overlay. Overlays are
+// laid over a doubled frame so that a scanline covers half a filtered pixel.
+pub fn render(pixels: &[bool], width: usize, height: usize,
+              filter: Filter, overlay: Overlay, palette: &Palette) -> Frame {
+    let frame = filter.apply(pixels, width, height).colorize(palette);
+    match overlay {
+        Overlay::Off => frame,
+        _ => {
+            let mut doubled = frame.scale(2);
+            overlay.apply(&mut doubled);
+            doubled
+        }
+    }
+}
+
+// Scale2x/EPX. With `blend` set the corners that EPX would fill are mixed
+// 3:1 with the centre pixel instead (the epx-blend filters).
+fn scale2x(src: &Levels, blend: bool) -> Levels {
+    let width = src.width * 2;
+    let height = src.height * 2;
+    let mut data = vec![OFF; width * height];
+    for y in 0..src.height as isize {
+        for x in 0..src.width as isize {
+            let p = src.at(x, y);
+            let a = src.at(x, y - 1);
+            let b = src.at(x + 1, y);
+            let c = src.at(x - 1, y);
+            let d = src.at(x, y + 1);
+
+            let e0 = if c == a && c != d && a != b { pick(p, a, blend) } else { p };
+            let e1 = if a == b && a != c && b != d { pick(p, b, blend) } else { p };
+            let e2 = if d == c && d != b && c != a { pick(p, c, blend) } else { p };
+            let e3 = if b == d && b != a && d != c { pick(p, d, blend) } else { p };
+
+            let ox = x as usize * 2;
+            let oy = y as usize * 2;
+            data[oy * width + ox] = e0;
+            data[oy * width + ox + 1] = e1;
+            data[(oy + 1) * width + ox] = e2;
+            data[(oy + 1) * width + ox + 1] = e3;
+        }
+    }
+    Levels { width: width, height: height, data: data }
+}
+
+// Scale3x/AdvMAME3x, with the same optional blending as `scale2x`.
+fn scale3x(src: &Levels, blend: bool) -> Levels {
+    let width = src.width * 3;
+    let height = src.height * 3;
+    let mut data = vec![OFF; width * height];
+    for y in 0..src.height as isize {
+        for x in 0..src.width as isize {
+            let a = src.at(x - 1, y - 1);
+            let b = src.at(x, y - 1);
+            let c = src.at(x + 1, y - 1);
+            let d = src.at(x - 1, y);
+            let e = src.at(x, y);
+            let f = src.at(x + 1, y);
+            let g = src.at(x - 1, y + 1);
+            let h = src.at(x, y + 1);
+            let i = src.at(x + 1, y + 1);
+
+            let mut out = [e; 9];
+            if b != h && d != f {
+                if d == b { out[0] = pick(e, d, blend); }
+                if (d == b && e != c) || (b == f && e != a) { out[1] = pick(e, b, blend); }
+                if b == f { out[2] = pick(e, f, blend); }
+                if (d == b && e != g) || (d == h && e != a) { out[3] = pick(e, d, blend); }
+                if (b == f && e != i) || (h == f && e != c) { out[5] = pick(e, f, blend); }
+                if d == h { out[6] = pick(e, d, blend); }
+                if (d == h && e != i) || (h == f && e != g) { out[7] = pick(e, h, blend); }
+                if h == f { out[8] = pick(e, f, blend); }
+            }
+
+            let ox = x as usize * 3;
+            let oy = y as usize * 3;
+            for (n, level) in out.iter().enumerate() {
+                data[(oy + n / 3) * width + ox + n % 3] = *level;
+            }
+        }
+    }
+    Levels { width: width, height: height, data: data }
+}
+
+// The eight neighbours of the centre of a 3x3 block, in pattern bit order.
+const NEIGHBOURS: [usize; 8] = [0, 1, 2, 3, 5, 6, 7, 8];
+
+// hqNx. Each pixel is compared with its eight neighbours to give an 8-bit
+// pattern, and the pattern looks up how every output pixel is mixed from the
+// 3x3 block. On a 1-bit screen neighbours are only ever the same or
+// different, so the table is built from a few rules rather than written out
+// case by case as in hq2x.
+fn hqnx(src: &Levels, scale: usize) -> Levels {
+    let table: Vec<Vec<[u8; 9]>> = (0..256).map(|pattern| hq_weights(pattern as u8, scale)).collect();
+    let width = src.width * scale;
+    let height = src.height * scale;
+    let mut data = vec![OFF; width * height];
+    for y in 0..src.height as isize {
+        for x in 0..src.width as isize {
+            let mut block = [OFF; 9];
+            for (n, level) in block.iter_mut().enumerate() {
+                *level = src.at(x + n as isize % 3 - 1, y + n as isize / 3 - 1);
+            }
+            let mut pattern = 0;
+            for (bit, &n) in NEIGHBOURS.iter().enumerate() {
+                if block[n] != block[4] {
+                    pattern |= 1 << bit;
+                }
+            }
+
+            let ox = x as usize * scale;
+            let oy = y as usize * scale;
+            for (n, weights) in table[pattern].iter().enumerate() {
+                let total: u16 = weights.iter().zip(block.iter())
+                    .map(|(&weight, &level)| weight as u16 * level as u16)
+                    .sum();
+                data[(oy + n / scale) * width + ox + n % scale] = (total / 8) as u8;
+            }
+        }
+    }
+    Levels { width: width, height: height, data: data }
+}
+
+// Eighths of each pixel of the 3x3 block that go into each output pixel.
+// Corners are rounded off where both edges next to them differ, less so when
+// the pixel across the corner is the same, which joins up diagonal lines. At
+// 3x the middle of an edge is rounded off at the tip of a one pixel line.
+fn hq_weights(pattern: u8, scale: usize) -> Vec<[u8; 9]> {
+    let index = |dx: isize, dy: isize| ((dy + 1) * 3 + dx + 1) as usize;
+    let differs = |dx: isize, dy: isize| {
+        let n = index(dx, dy);
+        pattern & (1 << NEIGHBOURS.iter().position(|&m| m == n).unwrap()) != 0
+    };
+    let step = |i: usize| if scale == 2 { i as isize * 2 - 1 } else { i as isize - 1 };
+    (0..scale * scale).map(|n| {
+        let (dx, dy) = (step(n % scale), step(n / scale));
+        let mut weights = [0; 9];
+        weights[4] = 8;
+        if dx != 0 && dy != 0 {
+            match (differs(dx, 0), differs(0, dy), differs(dx, dy)) {
+                (true, true, true) => {
+                    take(&mut weights, index(dx, 0), 2);
+                    take(&mut weights, index(0, dy), 2);
+                },
+                (true, true, false) => {
+                    take(&mut weights, index(dx, 0), 1);
+                    take(&mut weights, index(0, dy), 1);
+                },
+                _ => {},
+            }
+        } else if dx != 0 || dy != 0 {
+            if differs(dx, dy) && differs(dy, dx) && differs(-dy, -dx) {
+                take(&mut weights, index(dx, dy), 2);
+            }
+        }
+        weights
+    }).collect()
+}
+
+fn take(weights: &mut [u8; 9], from: usize, amount: u8) {
+    weights[4] -= amount;
+    weights[from] += amount;
+}
+
+// Weighted 3x3 blur (1-2-1 kernel) used to soften the staircase left by EPX.
+fn smooth(src: &Levels) -> Levels {
+    let mut data = Vec::with_capacity(src.data.len());
+    for y in 0..src.height as isize {
+        for x in 0..src.width as isize {
+            let mut total: u32 = 0;
+            for dy in -1..2 {
+                for dx in -1..2 {
+                    let weight = (2 - (dx as i32).abs()) * (2 - (dy as i32).abs());
+                    total += src.at(x + dx, y + dy) as u32 * weight as u32;
+                }
+            }
+            data.push((total / 16) as u8);
+        }
+    }
+    Levels { width: src.width, height: src.height, data: data }
+}
+
+fn pick(centre: u8, edge: u8, blend: bool) -> u8 {
+    match blend {
+        true  => ((edge as u16 * 3 + centre as u16) / 4) as u8,
+        false => edge,
+    }
+}
+
+fn clamp(value: isize, size: usize) -> usize {
+    if value < 0 {
+        0
+    } else if value as usize >= size {
+        size - 1
+    } else {
+        value as usize
+    }
+}
+
+fn lerp(from: u8, to: u8, level: u8) -> u8 {
+    let from = from as i32;
+    let to = to as i32;
+    (from + (to - from) * level as i32 / 0xFF) as u8
+}
+
+fn dim(channel: u8) -> u8 {
+    (channel as u16 * 7 / 10) as u8
+}
+
+#[cfg(test)]
+fn pattern(rows: &[&str]) -> (Vec<bool>, usize, usize) {
+    let pixels = rows.iter().flat_map(|r| r.chars().map(|c| c == '#')).collect();
+    (pixels, rows[0].len(), rows.len())
+}
+
+#[cfg(test)]
+fn levels_to_rows(levels: &Levels) -> Vec<String> {
+    levels.data.chunks(levels.width)
+        .map(|row| row.iter().map(|l| match *l {
+            ON => '#',
+            OFF => '.',
+            _ => '+',
+        }).collect())
+        .collect()
+}
+
+#[test]
+fn test_nearest_is_identity() {
+    let (pixels, w, h) = pattern(&["#.", ".#"]);
+    let out = Filter::Nearest.apply(&pixels, w, h);
+    assert_eq!(out.data, vec![ON, OFF, OFF, ON]);
+}
+
+#[test]
+fn test_scale2x_golden() {
+    let (pixels, w, h) = pattern(&[
+        "#..",
+        ".#.",
+        "..#",
+    ]);
+    let out = Filter::Scale2x.apply(&pixels, w, h);
+    assert_eq!((out.width, out.height), (6, 6));
+    assert_eq!(levels_to_rows(&out), vec![
+        "##....",
+        "#.#...",
+        ".###..",
+        "..###.",
+        "...#.#",
+        "....##",
+    ]);
+}
+
+#[test]
+fn test_scale3x_golden() {
+    let (pixels, w, h) = pattern(&[
+        "#..",
+        ".#.",
+        "..#",
+    ]);
+    let out = Filter::Scale3x.apply(&pixels, w, h);
+    assert_eq!((out.width, out.height), (9, 9));
+    assert_eq!(levels_to_rows(&out), vec![
+        "###......",
+        "##.#.....",
+        "#..#.....",
+        ".#####...",
+        "...###...",
+        "...#####.",
+        ".....#..#",
+        ".....#.##",
+        "......###",
+    ]);
+}
+
+#[test]
+fn test_epx_blend_mixes_filled_corners() {
+    let (pixels, w, h) = pattern(&[
+        "#..",
+        ".#.",
+        "..#",
+    ]);
+    let out = Filter::EpxBlend2x.apply(&pixels, w, h);
+    assert_eq!(levels_to_rows(&out), vec![
+        "##....",
+        "#++...",
+        ".+##..",
+        "..##+.",
+        "...++#",
+        "....##",
+    ]);
+    assert_eq!(out.data[6 + 1], 0x3F);
+    assert_eq!(out.data[6 + 2], 0xBF);
+}
+
+#[test]
+fn test_hq2x_golden() {
+    let (pixels, w, h) = pattern(&[
+        "#..",
+        ".#.",
+        "..#",
+    ]);
+    let out = Filter::Hq2x.apply(&pixels, w, h);
+    assert_eq!((out.width, out.height), (6, 6));
+    assert_eq!(levels_to_rows(&out), vec![
+        "##....",
+        "#++...",
+        ".+++..",
+        "..+++.",
+        "...++#",
+        "....##",
+    ]);
+    assert_eq!(out.data[6..10].to_vec(), vec![ON, 0xBF, 0x3F, OFF]);
+    assert_eq!(out.data[12..16].to_vec(), vec![OFF, 0x3F, 0xBF, 0x7F]);
+
+    // Only the outer corners of a block are rounded off.
+    let (pixels, w, h) = pattern(&["....", ".##.", ".##.", "...."]);
+    assert_eq!(levels_to_rows(&Filter::Hq2x.apply(&pixels, w, h)), vec![
+        "........",
+        "........",
+        "..+##+..",
+        "..####..",
+        "..####..",
+        "..+##+..",
+        "........",
+        "........",
+    ]);
+}
+
+#[test]
+fn test_hq3x_golden() {
+    let (pixels, w, h) = pattern(&[
+        "#..",
+        ".#.",
+        "..#",
+    ]);
+    let out = Filter::Hq3x.apply(&pixels, w, h);
+    assert_eq!((out.width, out.height), (9, 9));
+    assert_eq!(levels_to_rows(&out), vec![
+        "###......",
+        "###......",
+        "##++.....",
+        "..++++...",
+        "...+#+...",
+        "...++++..",
+        ".....++##",
+        "......###",
+        "......###",
+    ]);
+
+    // A lone pixel keeps its centre and loses its corners more than its edges.
+    let out = Filter::Hq3x.apply(&[false, false, false, false, true, false, false, false, false], 3, 3);
+    assert_eq!(out.data[3 * 9 + 3..3 * 9 + 6].to_vec(), vec![0x7F, 0xBF, 0x7F]);
+    assert_eq!(out.data[4 * 9 + 3..4 * 9 + 6].to_vec(), vec![0xBF, ON, 0xBF]);
+}
+
+#[test]
+fn test_smooth_softens_edges() {
+    let (pixels, w, h) = pattern(&["#."]);
+    let out = Filter::Smooth.apply(&pixels, w, h);
+    assert_eq!((out.width, out.height), (8, 4));
+    assert_eq!(out.data[0..8].to_vec(), vec![ON, ON, ON, 0xBF, 0x3F, OFF, OFF, OFF]);
+}
+
+#[test]
+fn test_filters_handle_hires_screen() {
+    let pixels = vec![false; 128 * 64];
+    let filters = [(Filter::Nearest, 1), (Filter::Scale2x, 2), (Filter::EpxBlend3x, 3),
+                   (Filter::Hq2x, 2), (Filter::Hq3x, 3), (Filter::Smooth, 4)];
+    for &(filter, factor) in filters.iter() {
+        let out = filter.apply(&pixels, 128, 64);
+        assert_eq!(out.width, 128 * factor);
+        assert_eq!(out.height, 64 * factor);
+    }
+}
+
+#[test]
+fn test_scanlines_overlay() {
+    let palette = Palette::new();
+    let frame = render(&[true], 1, 1, Filter::Nearest, Overlay::Scanlines, &palette);
+    assert_eq!((frame.width, frame.height), (2, 2));
+    assert_eq!(frame.data, vec![
+        Rgb(230, 230, 230), Rgb(230, 230, 230),
+        Rgb(115, 115, 115), Rgb(115, 115, 115),
+    ]);
+}
+
+#[test]
+fn test_shadow_mask_overlay() {
+    let palette = Palette::new();
+    let frame = render(&[true, true], 2, 1, Filter::Nearest, Overlay::ShadowMask, &palette);
+    assert_eq!(frame.data[0..4].to_vec(), vec![
+        Rgb(230, 161, 161), Rgb(161, 230, 161), Rgb(161, 161, 230), Rgb(230, 161, 161),
+    ]);
+}
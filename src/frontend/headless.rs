@@ -0,0 +1,21 @@
+use display::Display;
+use keyboard::Keyboard;
+use frontend::{Frontend, Signal};
+
+
+// A frontend with no window, audio or input, for scripted and batch runs.
+pub struct Headless {}
+
+impl Headless {
+    pub fn new() -> Headless {
+        Headless {}
+    }
+}
+
+impl Frontend for Headless {
+    fn poll(&mut self, _keyboard: &mut Keyboard) -> Vec<Signal> {
+        Vec::new()
+    }
+    fn present(&mut self, _display: &Display) {}
+    fn beep(&mut self, _on: bool) {}
+}
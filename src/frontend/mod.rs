@@ -0,0 +1,27 @@
+mod headless;
+mod sdl;
+mod terminal;
+
+use display::Display;
+use keyboard::Keyboard;
+
+pub use self::headless::Headless;
+pub use self::sdl::Sdl;
+pub use self::terminal::{half_blocks, Glyphs, Terminal};
+
+
+// Requests from the user that are not CHIP-8 key presses.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Signal {
+    Quit,
+    CycleFilter,
+    CycleOverlay,
+    Screenshot,
+    ToggleRecording,
+}
+
+pub trait Frontend {
+    fn poll(&mut self, keyboard: &mut Keyboard) -> Vec<Signal>;
+    fn present(&mut self, display: &Display);
+    fn beep(&mut self, on: bool);
+}
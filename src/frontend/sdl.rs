@@ -0,0 +1,178 @@
+use sdl2::EventPump;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioDevice};
+use sdl2::render::{Renderer, Texture};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use display::{Display, SCREEN_WIDTH, SCREEN_HEIGHT};
+use keyboard::Keyboard;
+use frontend::{Frontend, Signal};
+
+
+const DISPLAY_WIDTH: usize = SCREEN_WIDTH * 20;
+const DISPLAY_HEIGHT: usize = SCREEN_HEIGHT * 20;
+const TITLE: &str = "RIP-8::CHIP-8";
+
+const SBUFF: [u8; 288] = [
+    0xa8, 0xc1, 0xc9, 0xce, 0xd3, 0xd8, 0xd9, 0xdd,
+    0xe0, 0xe1, 0xe3, 0xe4, 0xe6, 0xe7, 0xe9, 0xe8,
+    0xeb, 0xec, 0xeb, 0xec, 0xed, 0xee, 0xef, 0xee,
+    0xf0, 0xf1, 0xf0, 0xf1, 0xf0, 0xf2, 0xf1, 0xf2,
+    0xf3, 0xf2, 0xf4, 0xf3, 0xf4, 0xf3, 0xf4, 0xf4,
+    0xf3, 0xf5, 0xf5, 0xf5, 0xf4, 0xf5, 0xf5, 0xf6,
+    0xf4, 0xf6, 0xf6, 0xf6, 0xf5, 0xf6, 0xf5, 0xf6,
+    0xf6, 0xf5, 0xf6, 0xf5, 0xf6, 0xf6, 0xf6, 0xf6,
+    0xf5, 0xf4, 0xf5, 0xf4, 0xf5, 0xf5, 0xf4, 0xf5,
+    0xf5, 0xf4, 0xf5, 0xf4, 0xf4, 0xf3, 0xf4, 0xf3,
+    0xf3, 0xf3, 0xf2, 0xf3, 0xf2, 0xf3, 0xf2, 0xf3,
+    0xf2, 0xf1, 0xf2, 0xf1, 0xf2, 0xf0, 0xf1, 0xf0,
+    0xf1, 0xf0, 0xf0, 0xef, 0xf0, 0xef, 0xef, 0xef,
+    0xee, 0xef, 0xed, 0xee, 0xee, 0xed, 0xed, 0xed,
+    0xec, 0xed, 0xec, 0xeb, 0xec, 0xec, 0xea, 0xeb,
+    0xea, 0xeb, 0xea, 0xea, 0xea, 0xe9, 0xe9, 0xe9,
+    0xe8, 0xe9, 0xe8, 0xe8, 0xe7, 0xe8, 0xe7, 0xe7,
+    0xe6, 0xe7, 0xe6, 0xe5, 0xe5, 0xe5, 0xe6, 0xe4,
+    0xe6, 0xe4, 0xd5, 0xaa, 0x71, 0x44, 0x33, 0x32,
+    0x31, 0x29, 0x24, 0x22, 0x21, 0x1f, 0x1b, 0x19,
+    0x1a, 0x19, 0x15, 0x15, 0x14, 0x14, 0x13, 0x11,
+    0x12, 0x10, 0x10, 0x0e, 0x0f, 0x0e, 0x0e, 0x0d,
+    0x0c, 0x0d, 0x0d, 0x0b, 0x0c, 0x0b, 0x0c, 0x0a,
+    0x0b, 0x0a, 0x0b, 0x09, 0x0a, 0x09, 0x0a, 0x09,
+    0x0a, 0x0a, 0x09, 0x09, 0x08, 0x09, 0x09, 0x08,
+    0x09, 0x08, 0x09, 0x08, 0x09, 0x09, 0x08, 0x09,
+    0x08, 0x09, 0x09, 0x08, 0x09, 0x09, 0x08, 0x0a,
+    0x09, 0x0a, 0x09, 0x0a, 0x09, 0x0a, 0x09, 0x0a,
+    0x0a, 0x0b, 0x0a, 0x0b, 0x0a, 0x0b, 0x0a, 0x0b,
+    0x0c, 0x0c, 0x0c, 0x0c, 0x0c, 0x0c, 0x0d, 0x0c,
+    0x0d, 0x0d, 0x0e, 0x0c, 0x0d, 0x0e, 0x0e, 0x0e,
+    0x0d, 0x0f, 0x0f, 0x0f, 0x0f, 0x10, 0x10, 0x11,
+    0x10, 0x11, 0x11, 0x12, 0x10, 0x11, 0x12, 0x13,
+    0x12, 0x12, 0x14, 0x14, 0x13, 0x13, 0x13, 0x16,
+    0x13, 0x14, 0x14, 0x17, 0x16, 0x14, 0x16, 0x17,
+    0x18, 0x14, 0x16, 0x19, 0x1a, 0x15, 0x16, 0x1a,
+];
+
+pub struct Sdl<'s> {
+    audio: AudioDevice<Beep>,
+    renderer: Renderer<'s>,
+    // Sized to the last frame, recreated when a filter changes the size.
+    texture: Option<Texture>,
+    pump: EventPump,
+}
+
+
+impl<'s> Sdl<'s> {
+    pub fn new() -> Sdl<'s> {
+        let context = ::sdl2::init().unwrap();
+        let pump = context.event_pump().unwrap();
+        let audio_subsystem = context.audio().unwrap();
+        let desired_spec = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),
+            samples: Some(288),
+        };
+
+        let beep = audio_subsystem.open_playback(None, &desired_spec, | s | {
+            Beep{}
+        }).unwrap();
+
+        let video = context.video().unwrap();
+        let window = video.window(TITLE, DISPLAY_WIDTH as u32, DISPLAY_HEIGHT as u32)
+                          .position_centered().opengl().build().unwrap();
+        let renderer = window.renderer().accelerated()
+                              .build().unwrap();
+        Sdl {
+            audio: beep,
+            renderer: renderer,
+            texture: None,
+            pump: pump,
+        }
+    }
+}
+
+impl<'s> Frontend for Sdl<'s> {
+    fn poll(&mut self, keyboard: &mut Keyboard) -> Vec<Signal> {
+        let mut signals = Vec::new();
+        for event in self.pump.poll_iter() {
+            match event {
+                Event::KeyDown { keycode, .. } => match keycode {
+                    Some(Keycode::Escape) => signals.push(Signal::Quit),
+                    Some(Keycode::F1) => signals.push(Signal::CycleFilter),
+                    Some(Keycode::F2) => signals.push(Signal::CycleOverlay),
+                    Some(Keycode::F11) => signals.push(Signal::ToggleRecording),
+                    Some(Keycode::F12) => signals.push(Signal::Screenshot),
+                    _ => keyboard.handle_event(event)
+                },
+                Event::KeyUp { .. } => keyboard.handle_event(event),
+                Event::Quit { .. } => signals.push(Signal::Quit),
+                _ => {}
+            }
+        }
+        signals
+    }
+    fn present(&mut self, display: &Display) {
+        let frame = display.frame();
+        let (width, height) = (frame.width as u32, frame.height as u32);
+        let stale = match self.texture {
+            Some(ref texture) => {
+                let query = texture.query();
+                (query.width, query.height) != (width, height)
+            },
+            None => true,
+        };
+        if stale {
+            self.texture = Some(self.renderer.create_texture_streaming(PixelFormatEnum::RGB24, width, height).unwrap());
+        }
+        let mut bytes = Vec::with_capacity(frame.data.len() * 3);
+        for rgb in frame.data.iter() {
+            bytes.extend_from_slice(&[rgb.0, rgb.1, rgb.2]);
+        }
+        let texture = self.texture.as_mut().unwrap();
+        texture.update(None, &bytes, frame.width * 3).unwrap();
+
+        let bg = display.palette.background;
+        let (x, y, w, h) = fit(frame.width, frame.height);
+        self.renderer.set_draw_color(Color::RGB(bg.0, bg.1, bg.2));
+        self.renderer.clear();
+        self.renderer.copy(texture, None, Some(Rect::new(x, y, w, h))).unwrap();
+        self.renderer.present();
+    }
+    fn beep(&mut self, on: bool) {
+        match on {
+            true => self.audio.resume(),
+            false => self.audio.pause(),
+        }
+    }
+}
+
+// The largest rectangle with the frame's aspect ratio that fits the window,
+// centred, so filtered frames of any size fill it without ragged borders.
+fn fit(width: usize, height: usize) -> (i32, i32, u32, u32) {
+    let scale = (DISPLAY_WIDTH as f64 / width as f64).min(DISPLAY_HEIGHT as f64 / height as f64);
+    let w = (width as f64 * scale).round() as usize;
+    let h = (height as f64 * scale).round() as usize;
+    (((DISPLAY_WIDTH - w) / 2) as i32, ((DISPLAY_HEIGHT - h) / 2) as i32, w as u32, h as u32)
+}
+
+
+pub struct Beep {}
+
+impl AudioCallback for Beep {
+    type Channel = u8;
+
+    fn callback(&mut self, out: &mut [u8]) {
+        for i in 0..SBUFF.len() {
+            out[i] = SBUFF[i];
+        }
+    }
+}
+
+#[test]
+fn test_fit_centres_any_frame_size() {
+    assert_eq!(fit(64, 32), (0, 0, 1280, 640));
+    assert_eq!(fit(192, 96), (0, 0, 1280, 640));
+    assert_eq!(fit(256, 128), (0, 0, 1280, 640));
+    assert_eq!(fit(128, 128), (320, 0, 640, 640));
+    assert_eq!(fit(384, 100), (0, 153, 1280, 333));
+}
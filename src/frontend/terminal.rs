@@ -0,0 +1,211 @@
+use ::termion::{clear, color, cursor, style};
+use ::termion::event::Key;
+use ::termion::input::{Keys, TermRead};
+use ::termion::raw::{IntoRawMode, RawTerminal};
+use ::termion::screen::AlternateScreen;
+use ::termion::{async_stdin, AsyncReader};
+use std::io::{Write, stdout, Stdout};
+use std::time::{Duration, Instant};
+use display::Display;
+use filters::{Frame, Rgb};
+use keyboard::Keyboard;
+use frontend::{Frontend, Signal};
+
+
+// Terminals report key presses but never releases, so a keypad key is held
+// until no repeat for it has arrived within this window.
+const RELEASE_TIMEOUT_MS: u64 = 400;
+const FRAME_NANOS: u32 = 16_666_666;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Glyphs {
+    HalfBlock,
+    Braille,
+}
+
+impl Glyphs {
+    pub fn from_name(name: &str) -> Result<Glyphs, String> {
+        match name.to_lowercase().as_str() {
+            "half" | "halfblock" => Ok(Glyphs::HalfBlock),
+            "braille"            => Ok(Glyphs::Braille),
+            _ => Err(format!("Unknown glyph set: {}", name)),
+        }
+    }
+}
+
+pub struct Terminal {
+    stdout: AlternateScreen<RawTerminal<Stdout>>,
+    keys: Keys<AsyncReader>,
+    glyphs: Glyphs,
+    held: [Option<Instant>; 16],
+    pending: Option<String>,
+    last_instant: Instant,
+}
+
+impl Terminal {
+    pub fn new(glyphs: Glyphs) -> Terminal {
+        let mut stdout = AlternateScreen::from(stdout().into_raw_mode().unwrap());
+        write!(stdout, "{}{}", clear::All, cursor::Hide).unwrap();
+        Terminal {
+            stdout: stdout,
+            keys: async_stdin().keys(),
+            glyphs: glyphs,
+            held: [None; 16],
+            pending: None,
+            last_instant: Instant::now(),
+        }
+    }
+    // Output is limited to 60 frames a second; anything drawn in between is
+    // kept and flushed by the next present or poll.
+    fn flush_pending(&mut self) {
+        if self.last_instant.elapsed() < Duration::new(0, FRAME_NANOS) {
+            return;
+        }
+        if let Some(screen) = self.pending.take() {
+            write!(self.stdout, "{}{}", cursor::Goto(1, 1), screen).unwrap();
+            self.stdout.flush().unwrap();
+            self.last_instant = Instant::now();
+        }
+    }
+}
+
+impl Frontend for Terminal {
+    fn poll(&mut self, keyboard: &mut Keyboard) -> Vec<Signal> {
+        let mut signals = Vec::new();
+        while let Some(Ok(key)) = self.keys.next() {
+            match key {
+                Key::Esc => signals.push(Signal::Quit),
+                Key::F(1) => signals.push(Signal::CycleFilter),
+                Key::F(2) => signals.push(Signal::CycleOverlay),
+                Key::F(11) => signals.push(Signal::ToggleRecording),
+                Key::F(12) => signals.push(Signal::Screenshot),
+                Key::Char(c) => if let Some(value) = keypad_value(c) {
+                    keyboard.press_value(value);
+                    self.held[value as usize] = Some(Instant::now());
+                },
+                _ => {}
+            }
+        }
+        let timeout = Duration::from_millis(RELEASE_TIMEOUT_MS);
+        for value in 0..16 {
+            if let Some(pressed) = self.held[value] {
+                if pressed.elapsed() > timeout {
+                    keyboard.release_value(value as u8);
+                    self.held[value] = None;
+                }
+            }
+        }
+        self.flush_pending();
+        signals
+    }
+    fn present(&mut self, display: &Display) {
+        let frame = display.frame();
+        let bg = display.palette.background;
+        self.pending = Some(match self.glyphs {
+            Glyphs::HalfBlock => half_blocks(&frame).join("\r\n"),
+            Glyphs::Braille => braille(&frame, bg, display.palette.foreground),
+        });
+        self.flush_pending();
+    }
+    fn beep(&mut self, _on: bool) {}
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        write!(self.stdout, "{}{}", style::Reset, cursor::Show).unwrap();
+        self.stdout.flush().unwrap();
+    }
+}
+
+// The same layout as the SDL keypad: 1234 / QWER / ASDF / ZXCV.
+pub fn keypad_value(c: char) -> Option<u8> {
+    match c.to_ascii_lowercase() {
+        '1' => Some(0x1), '2' => Some(0x2), '3' => Some(0x3), '4' => Some(0xC),
+        'q' => Some(0x4), 'w' => Some(0x5), 'e' => Some(0x6), 'r' => Some(0xD),
+        'a' => Some(0x7), 's' => Some(0x8), 'd' => Some(0x9), 'f' => Some(0xE),
+        'z' => Some(0xA), 'x' => Some(0x0), 'c' => Some(0xF), 'v' => Some(0xB),
+        _ => None,
+    }
+}
+
+// Two pixels per cell: the upper half block in the top pixel's colour over
+// the bottom pixel's colour. Returns one string per terminal row.
+pub fn half_blocks(frame: &Frame) -> Vec<String> {
+    let mut rows = Vec::new();
+    for y in (0..frame.height).filter(|y| y % 2 == 0) {
+        let mut row = String::new();
+        for x in 0..frame.width {
+            let top = frame.data[y * frame.width + x];
+            let bottom = match y + 1 < frame.height {
+                true => frame.data[(y + 1) * frame.width + x],
+                false => top,
+            };
+            row.push_str(&format!("{}{}\u{2580}", fg(top), bg(bottom)));
+        }
+        row.push_str(&format!("{}", style::Reset));
+        rows.push(row);
+    }
+    rows
+}
+
+// Eight pixels per cell, one colour per cell: any pixel that differs from the
+// background is raised as a braille dot.
+pub fn braille(frame: &Frame, background: Rgb, foreground: Rgb) -> String {
+    const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+    let mut out = format!("{}{}", fg(foreground), bg(background));
+    for cy in 0..(frame.height + 3) / 4 {
+        for cx in 0..(frame.width + 1) / 2 {
+            let mut bits = 0;
+            for dy in 0..4 {
+                for dx in 0..2 {
+                    let x = cx * 2 + dx;
+                    let y = cy * 4 + dy;
+                    if x < frame.width && y < frame.height && frame.data[y * frame.width + x] != background {
+                        bits |= DOTS[dy][dx];
+                    }
+                }
+            }
+            out.push(::std::char::from_u32(0x2800 + bits).unwrap());
+        }
+        out.push_str("\r\n");
+    }
+    out.push_str(&format!("{}", style::Reset));
+    out
+}
+
+fn fg(rgb: Rgb) -> color::Fg<color::Rgb> {
+    color::Fg(color::Rgb(rgb.0, rgb.1, rgb.2))
+}
+
+fn bg(rgb: Rgb) -> color::Bg<color::Rgb> {
+    color::Bg(color::Rgb(rgb.0, rgb.1, rgb.2))
+}
+
+#[test]
+fn test_braille_glyphs() {
+    let off = Rgb(0, 0, 0);
+    let on = Rgb(255, 255, 255);
+    let mut data = vec![off; 4 * 4];
+    data[0] = on;
+    data[3 * 4 + 1] = on;
+    data[2] = on;
+    data[4 + 3] = on;
+    let out = braille(&Frame { width: 4, height: 4, data: data }, off, on);
+    let glyphs: String = out.chars().filter(|c| *c >= '\u{2800}' && *c <= '\u{28FF}').collect();
+    assert_eq!(glyphs, "\u{2881}\u{2811}");
+}
+
+#[test]
+fn test_half_blocks_cell_count() {
+    let off = Rgb(0, 0, 0);
+    let rows = half_blocks(&Frame { width: 3, height: 3, data: vec![off; 9] });
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[1].matches('\u{2580}').count(), 3);
+}
+
+#[test]
+fn test_keypad_value() {
+    assert_eq!(keypad_value('x'), Some(0x0));
+    assert_eq!(keypad_value('V'), Some(0xB));
+    assert_eq!(keypad_value('p'), None);
+}
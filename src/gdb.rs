@@ -0,0 +1,504 @@
+// A GDB remote serial protocol stub, so that gdb, lldb or an IDE can drive
+// the CPU:
+//
+//   chip8 PONG --gdb 127.0.0.1:1234        (or --gdb unix:/tmp/chip8.sock)
+//   (gdb) target remote 127.0.0.1:1234
+//
+// Registers, in `g` packet order and little-endian:
+//
+//   0-15  V0-VF   8 bits
+//   16    I       16 bits
+//   17    PC      16 bits
+//   18    SP      8 bits, 0xFF while the stack is empty
+//   19    DT      8 bits
+//   20    ST      8 bits
+//
+// Memory is the 4K address space. Software and hardware breakpoints are both
+// kept by the stub. `s` runs one `CPU::cycle`; `c` runs at the CPU's speed
+// until a breakpoint, an invalid instruction or an interrupt from the client.
+use std::collections::{BTreeSet, VecDeque};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::thread::sleep;
+use cpu::CPU;
+
+
+const REGISTERS: [(&'static str, usize); 21] = [
+    ("v0", 1), ("v1", 1), ("v2", 1), ("v3", 1), ("v4", 1), ("v5", 1), ("v6", 1), ("v7", 1),
+    ("v8", 1), ("v9", 1), ("va", 1), ("vb", 1), ("vc", 1), ("vd", 1), ("ve", 1), ("vf", 1),
+    ("i", 2), ("pc", 2), ("sp", 1), ("dt", 1), ("st", 1),
+];
+
+const INTERRUPT: u8 = 0x03;
+
+pub trait Connection: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+impl Connection for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+// Waits for one client on `host:port` or `unix:PATH` and serves it until it
+// detaches or disconnects.
+pub fn listen(cpu: &mut CPU, address: &str) -> Result<(), String> {
+    if address.starts_with("unix:") {
+        let path = &address[5..];
+        let _ = fs::remove_file(path);
+        let listener = UnixListener::bind(path).map_err(|e| format!("{}: {}", path, e))?;
+        println!("Waiting for gdb on {}", path);
+        let (stream, _) = listener.accept().map_err(|e| e.to_string())?;
+        let result = Stub::new(cpu, stream).serve();
+        let _ = fs::remove_file(path);
+        result
+    } else {
+        let listener = TcpListener::bind(address).map_err(|e| format!("{}: {}", address, e))?;
+        println!("Waiting for gdb on {}", listener.local_addr().map_err(|e| e.to_string())?);
+        let (stream, _) = listener.accept().map_err(|e| e.to_string())?;
+        let _ = stream.set_nodelay(true);
+        Stub::new(cpu, stream).serve()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stop {
+    Trap,
+    Interrupt,
+    Illegal,
+    Exited,
+}
+
+impl Stop {
+    fn reply(&self) -> String {
+        match *self {
+            Stop::Trap => "S05".to_owned(),
+            Stop::Interrupt => "S02".to_owned(),
+            Stop::Illegal => "S04".to_owned(),
+            Stop::Exited => "W00".to_owned(),
+        }
+    }
+}
+
+pub struct Stub<'a, 'cpu: 'a, C: Connection> {
+    cpu: &'a mut CPU<'cpu>,
+    connection: C,
+    input: VecDeque<u8>,
+    breakpoints: BTreeSet<u16>,
+    ack: bool,
+    stop: Stop,
+}
+
+impl<'a, 'cpu, C: Connection> Stub<'a, 'cpu, C> {
+    pub fn new(cpu: &'a mut CPU<'cpu>, connection: C) -> Stub<'a, 'cpu, C> {
+        Stub {
+            cpu: cpu,
+            connection: connection,
+            input: VecDeque::new(),
+            breakpoints: BTreeSet::new(),
+            ack: true,
+            stop: Stop::Trap,
+        }
+    }
+    pub fn serve(&mut self) -> Result<(), String> {
+        while let Some(packet) = self.receive().map_err(|e| e.to_string())? {
+            let reply = match self.handle(&packet) {
+                Some(reply) => reply,
+                None => return Ok(()),
+            };
+            self.send(&reply).map_err(|e| e.to_string())?;
+            match packet.as_str() {
+                "QStartNoAckMode" => self.ack = false,
+                "D" => return Ok(()),
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+    // The reply to one packet, or None when the session is over.
+    fn handle(&mut self, packet: &str) -> Option<String> {
+        let (command, args) = match packet.char_indices().nth(1) {
+            Some((at, _)) => (&packet[..at], &packet[at..]),
+            None => (packet, ""),
+        };
+        let reply = match command {
+            "?" => self.stop.reply(),
+            "g" => hex(&self.registers()),
+            "G" => match unhex(args) {
+                Ok(ref bytes) if bytes.len() == register_bytes() => {
+                    let mut offset = 0;
+                    for (n, &(_, size)) in REGISTERS.iter().enumerate() {
+                        self.set_register(n, &bytes[offset..offset + size]);
+                        offset += size;
+                    }
+                    "OK".to_owned()
+                },
+                _ => "E01".to_owned(),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < REGISTERS.len() => hex(&self.register(n)),
+                _ => "E01".to_owned(),
+            },
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                let n = parts.next().and_then(|n| usize::from_str_radix(n, 16).ok());
+                match (n, parts.next().map(unhex)) {
+                    (Some(n), Some(Ok(ref bytes))) if n < REGISTERS.len() && bytes.len() == REGISTERS[n].1 => {
+                        self.set_register(n, bytes);
+                        "OK".to_owned()
+                    },
+                    _ => "E01".to_owned(),
+                }
+            },
+            "m" => match range(args) {
+                Some((start, end)) => hex(&self.cpu.mem[start..end]),
+                None => "E01".to_owned(),
+            },
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                match (parts.next().and_then(range), parts.next().map(unhex)) {
+                    (Some((start, end)), Some(Ok(ref bytes))) if bytes.len() == end - start => {
+                        self.cpu.mem[start..end].copy_from_slice(bytes);
+                        "OK".to_owned()
+                    },
+                    _ => "E01".to_owned(),
+                }
+            },
+            "Z" | "z" => {
+                let fields: Vec<&str> = args.split(',').collect();
+                let address = fields.get(1).and_then(|a| u16::from_str_radix(a, 16).ok());
+                match (fields[0], address) {
+                    ("0", Some(address)) | ("1", Some(address)) => {
+                        match command {
+                            "Z" => self.breakpoints.insert(address),
+                            _ => self.breakpoints.remove(&address),
+                        };
+                        "OK".to_owned()
+                    },
+                    // Watchpoints are not supported.
+                    _ => String::new(),
+                }
+            },
+            "s" | "c" => {
+                if let Ok(address) = u16::from_str_radix(args, 16) {
+                    self.cpu.pc = address & 0xFFF;
+                }
+                self.resume(command == "s")
+            },
+            "D" => "OK".to_owned(),
+            "k" => return None,
+            "H" | "T" => "OK".to_owned(),
+            _ => self.query(packet),
+        };
+        Some(reply)
+    }
+    fn query(&mut self, packet: &str) -> String {
+        let xml = "qXfer:features:read:target.xml:";
+        if packet.starts_with("qSupported") {
+            "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+".to_owned()
+        } else if packet.starts_with(xml) {
+            let document = target_xml();
+            match range(&packet[xml.len()..]) {
+                Some((offset, _)) if offset >= document.len() => "l".to_owned(),
+                Some((offset, end)) => {
+                    let end = end.min(document.len());
+                    let more = if end < document.len() { "m" } else { "l" };
+                    format!("{}{}", more, &document[offset..end])
+                },
+                None => "E01".to_owned(),
+            }
+        } else if packet == "vCont?" {
+            "vCont;c;s".to_owned()
+        } else if packet.starts_with("vCont;") {
+            // One thread, so the first action is the only one that matters.
+            let action = packet[6..].split(|c| c == ';' || c == ':').next().unwrap_or("");
+            match action {
+                "s" => self.resume(true),
+                "c" => self.resume(false),
+                _ => "E01".to_owned(),
+            }
+        } else {
+            match packet {
+                "QStartNoAckMode" => "OK".to_owned(),
+                "qAttached" => "1".to_owned(),
+                "qC" => "QC1".to_owned(),
+                "qfThreadInfo" => "m1".to_owned(),
+                "qsThreadInfo" => "l".to_owned(),
+                _ => String::new(),
+            }
+        }
+    }
+    fn resume(&mut self, step: bool) -> String {
+        self.stop = match self.run(step) {
+            Ok(stop) => stop,
+            Err(_) => Stop::Exited,
+        };
+        self.stop.reply()
+    }
+    fn run(&mut self, step: bool) -> io::Result<Stop> {
+        loop {
+            if self.cpu.next_op().is_err() {
+                return Ok(Stop::Illegal);
+            }
+            self.cpu.cycle();
+            if step || self.breakpoints.contains(&self.cpu.pc) {
+                return Ok(Stop::Trap);
+            }
+            if self.cpu.device.quit {
+                return Ok(Stop::Exited);
+            }
+            if self.cpu.cycles % self.cpu.cycles_per_frame() == 0 && self.interrupted()? {
+                return Ok(Stop::Interrupt);
+            }
+            sleep(self.cpu.program_delay);
+        }
+    }
+    // Picks up whatever the client has sent while the CPU was running.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.connection.set_nonblocking(true)?;
+        let mut buffer = [0; 256];
+        let result = self.connection.read(&mut buffer);
+        self.connection.set_nonblocking(false)?;
+        match result {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "client went away")),
+            Ok(count) => self.input.extend(buffer[..count].iter().cloned()),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+            Err(e) => return Err(e),
+        }
+        match self.input.iter().position(|&b| b == INTERRUPT) {
+            Some(at) => {
+                self.input.remove(at);
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+    fn registers(&self) -> Vec<u8> {
+        (0..REGISTERS.len()).flat_map(|n| self.register(n)).collect()
+    }
+    fn register(&self, n: usize) -> Vec<u8> {
+        let cpu = &self.cpu;
+        match n {
+            n if n < 16 => vec![cpu.regs[n]],
+            16 => vec![cpu.index as u8, (cpu.index >> 8) as u8],
+            17 => vec![cpu.pc as u8, (cpu.pc >> 8) as u8],
+            18 => vec![cpu.stack.current_index().map(|i| i as u8).unwrap_or(0xFF)],
+            19 => vec![cpu.delay_timer.get_delay()],
+            _ => vec![cpu.sound_timer.get_delay()],
+        }
+    }
+    fn set_register(&mut self, n: usize, bytes: &[u8]) {
+        let word = bytes[0] as u16 | (*bytes.get(1).unwrap_or(&0) as u16) << 8;
+        match n {
+            n if n < 16 => self.cpu.regs[n] = bytes[0],
+            16 => self.cpu.index = word & 0xFFF,
+            17 => self.cpu.pc = word & 0xFFF,
+            18 => {
+                let stack: Vec<u16> = (0..16).map(|i| self.cpu.stack.peek(i)).collect();
+                let mut slots = [0; 16];
+                slots.copy_from_slice(&stack);
+                let index = if bytes[0] < 16 { Some(bytes[0] as usize) } else { None };
+                self.cpu.stack.restore(slots, index);
+            },
+            19 => self.cpu.delay_timer.set_delay(bytes[0]),
+            _ => self.cpu.sound_timer.set_delay(bytes[0]),
+        }
+    }
+    // The next packet's payload, acknowledged. None once the client is gone.
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.byte()? {
+                None => return Ok(None),
+                Some(b'$') => {},
+                // Acks, and interrupts that arrive after the CPU has stopped.
+                Some(_) => continue,
+            }
+            let mut payload = Vec::new();
+            loop {
+                match self.byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(b'}') => match self.byte()? {
+                        Some(b) => payload.push(b ^ 0x20),
+                        None => return Ok(None),
+                    },
+                    Some(b) => payload.push(b),
+                }
+            }
+            let mut sum = [0; 2];
+            for digit in sum.iter_mut() {
+                *digit = match self.byte()? {
+                    Some(b) => b,
+                    None => return Ok(None),
+                };
+            }
+            let expected = u8::from_str_radix(&String::from_utf8_lossy(&sum), 16).ok();
+            let valid = expected == Some(checksum(&payload));
+            if self.ack {
+                self.connection.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid || !self.ack {
+                return Ok(Some(String::from_utf8_lossy(&payload).into_owned()));
+            }
+        }
+    }
+    fn send(&mut self, payload: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", payload, checksum(payload.as_bytes()));
+        loop {
+            self.connection.write_all(packet.as_bytes())?;
+            self.connection.flush()?;
+            if !self.ack {
+                return Ok(());
+            }
+            match self.byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+    fn byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(b) = self.input.pop_front() {
+            return Ok(Some(b));
+        }
+        let mut buffer = [0; 1024];
+        loop {
+            match self.connection.read(&mut buffer) {
+                Ok(0) => return Ok(None),
+                Ok(count) => {
+                    self.input.extend(buffer[1..count].iter().cloned());
+                    return Ok(Some(buffer[0]));
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn register_bytes() -> usize {
+    REGISTERS.iter().map(|&(_, size)| size).sum()
+}
+
+fn target_xml() -> String {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+                                <target version=\"1.0\">\n<feature name=\"org.chip8.core\">\n");
+    for &(name, size) in REGISTERS.iter() {
+        let kind = match name {
+            "pc" => "code_ptr\" generic=\"pc",
+            "i" => "data_ptr",
+            _ => "uint8",
+        };
+        xml.push_str(&format!("<reg name=\"{}\" bitsize=\"{}\" type=\"{}\"/>\n", name, size * 8, kind));
+    }
+    xml.push_str("</feature>\n</target>\n");
+    xml
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Result<Vec<u8>, String> {
+    if !text.is_ascii() {
+        return Err(format!("Bad hex: {}", text));
+    }
+    if text.len() % 2 != 0 {
+        return Err(format!("Odd number of hex digits: {}", text));
+    }
+    (0..text.len() / 2)
+        .map(|i| u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).map_err(|_| format!("Bad hex: {}", text)))
+        .collect()
+}
+
+// `addr,length` as a byte range clamped to memory.
+fn range(text: &str) -> Option<(usize, usize)> {
+    let mut parts = text.splitn(2, ',');
+    let start = parts.next().and_then(|s| usize::from_str_radix(s, 16).ok())?;
+    let length = parts.next().and_then(|s| usize::from_str_radix(s, 16).ok())?;
+    if start > 0x1000 {
+        return None;
+    }
+    Some((start, start.checked_add(length)?.min(0x1000)))
+}
+
+#[cfg(test)]
+fn request(stream: &mut TcpStream, packet: &str) -> String {
+    write!(stream, "${}#{:02x}", packet, checksum(packet.as_bytes())).unwrap();
+    let mut reply = Vec::new();
+    let mut byte = [0; 1];
+    loop {
+        stream.read_exact(&mut byte).unwrap();
+        match byte[0] {
+            b'+' if reply.is_empty() => {},
+            b'#' => break,
+            b => reply.push(b),
+        }
+    }
+    let mut sum = [0; 2];
+    stream.read_exact(&mut sum).unwrap();
+    stream.write_all(b"+").unwrap();
+    String::from_utf8(reply[1..].to_vec()).unwrap()
+}
+
+#[test]
+fn test_gdb_session() {
+    use std::thread;
+    use std::time::Duration;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let client = thread::spawn(move || {
+        let mut stream = TcpStream::connect(address).unwrap();
+        let mut replies = Vec::new();
+        for packet in ["qSupported:swbreak+", "?", "g", "m200,8", "Z0,206,2", "c", "p11", "p0", "c", "p0",
+                       "z0,206,2", "s", "p11", "P0=2a", "p0", "M300,2:abcd", "m300,2", "m1000,2", "p15",
+                       "m1,ffffffffffffffff", "M300,2:a\u{e9}b"].iter() {
+            replies.push(request(&mut stream, packet));
+        }
+        replies.push(request(&mut stream, "qXfer:features:read:target.xml:0,fff")[..6].to_owned());
+        // Interrupt a program that loops forever.
+        write!(stream, "$c#63").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        stream.write_all(&[INTERRUPT]).unwrap();
+        let mut reply = [0; 8];
+        stream.read_exact(&mut reply).unwrap();
+        replies.push(String::from_utf8_lossy(&reply).into_owned());
+        stream.write_all(b"+").unwrap();
+        replies.push(request(&mut stream, "D"));
+        replies
+    });
+    let mut cpu = CPU::headless();
+    // LD V0, 5; ADD V0, 1; LD I, 0x300; JP 0x202
+    cpu.load_program(&[0x60, 0x05, 0x70, 0x01, 0xA3, 0x00, 0x12, 0x02]);
+    let (stream, _) = listener.accept().unwrap();
+    Stub::new(&mut cpu, stream).serve().unwrap();
+    let replies = client.join().unwrap();
+    assert!(replies[0].contains("qXfer:features:read+"));
+    assert_eq!(replies[1], "S05");
+    assert_eq!(replies[2], format!("{}{}{}ff0000", "00".repeat(16), "0002", "0002"));
+    assert_eq!(replies[3], "60057001a3001202");
+    assert_eq!(&replies[4..8], &["OK", "S05", "0602", "06"]);
+    assert_eq!(&replies[8..10], &["S05", "07"]);
+    assert_eq!(&replies[10..13], &["OK", "S05", "0202"]);
+    assert_eq!(&replies[13..15], &["OK", "2a"]);
+    assert_eq!(&replies[15..17], &["OK", "abcd"]);
+    assert_eq!(&replies[17..19], &["", "E01"]);
+    assert_eq!(&replies[19..21], &["E01", "E01"]);
+    assert_eq!(replies[21], "l<?xml");
+    assert_eq!(replies[22], "+$S02#b5");
+    assert_eq!(replies[23], "OK");
+    assert_eq!(cpu.mem[0x300], 0xAB);
+}
@@ -112,7 +112,7 @@ impl Keyboard {
             _ => {}
         }
     }
-    fn keys(&mut self) -> [&Key; 16] {
+    fn keys(&self) -> [&Key; 16] {
         [
             &self.key_0,
             &self.key_1,
@@ -151,6 +151,35 @@ impl Keyboard {
         self.key_f.reset();
     }
 
+    pub fn press_value(&mut self, value: u8) {
+        self.key_mut(value).press();
+    }
+
+    pub fn release_value(&mut self, value: u8) {
+        self.key_mut(value).reset();
+    }
+
+    fn key_mut(&mut self, value: u8) -> &mut Key {
+        match value & 0xF {
+            0x0 => &mut self.key_0,
+            0x1 => &mut self.key_1,
+            0x2 => &mut self.key_2,
+            0x3 => &mut self.key_3,
+            0x4 => &mut self.key_4,
+            0x5 => &mut self.key_5,
+            0x6 => &mut self.key_6,
+            0x7 => &mut self.key_7,
+            0x8 => &mut self.key_8,
+            0x9 => &mut self.key_9,
+            0xA => &mut self.key_a,
+            0xB => &mut self.key_b,
+            0xC => &mut self.key_c,
+            0xD => &mut self.key_d,
+            0xE => &mut self.key_e,
+            _   => &mut self.key_f,
+        }
+    }
+
     pub fn get_pressed_key(&mut self) -> Option<u8> {
         for key in self.keys().into_iter() {
             if key.is_pressed() {
@@ -160,6 +189,10 @@ impl Keyboard {
         return None
     }
 
+    pub fn is_pressed(&self, value: u8) -> bool {
+        self.keys()[(value & 0xF) as usize].is_pressed()
+    }
+
     pub fn check_value_pressed(&mut self, value: u8) -> bool {
         for key in self.keys().into_iter() {
             if key.value() == value {
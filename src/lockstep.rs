@@ -0,0 +1,195 @@
+// Runs one program on two machines that differ only in their quirks, one
+// instruction at a time, and stops at the first instruction after which
+// their registers, stack, timers, memory or screen disagree.
+//
+//   chip8 lockstep <rom> <quirks-a> <quirks-b> [--cycles N] [--seed N]
+//
+// Both machines run headless and deterministic, with the same seed and no
+// keys pressed, so any divergence comes from the quirks alone.
+use cpu::CPU;
+use opcodes::Op;
+use quirks::Quirks;
+
+
+// Differences listed individually before the rest are summed up.
+const SHOWN: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    // Cycle of the instruction that caused it, and where it was.
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub differences: Vec<String>,
+    // Quirks that change what the instruction does and are set differently.
+    pub quirks: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Agreed(u64),
+    // Both machines stopped at the same cycle, still in the same state.
+    Halted(u64, String),
+    Diverged(Divergence),
+}
+
+pub fn run(program: &[u8], a: Quirks, b: Quirks, seed: u32, cycles: u64) -> Outcome {
+    let mut machines = [CPU::headless(), CPU::headless()];
+    for (cpu, &quirks) in machines.iter_mut().zip([a, b].iter()) {
+        cpu.load_program(program);
+        cpu.quirks = quirks;
+        cpu.seed(seed);
+    }
+    for cycle in 0..cycles {
+        let pc = machines[0].pc;
+        if let Some(reason) = halt(&machines[0]) {
+            return Outcome::Halted(cycle, reason);
+        }
+        let opcode = machines[0].opcode_at_address(pc as usize);
+        for cpu in machines.iter_mut() {
+            cpu.cycle();
+        }
+        let differences = differences(&machines[0], &machines[1]);
+        if !differences.is_empty() {
+            let quirks = Quirks::affecting(&opcode.op).iter().cloned()
+                .filter(|name| a.get(name) != b.get(name))
+                .collect();
+            return Outcome::Diverged(Divergence {
+                cycle: cycle,
+                pc: pc,
+                opcode: opcode.value,
+                differences: differences,
+                quirks: quirks,
+            });
+        }
+    }
+    Outcome::Agreed(cycles)
+}
+
+// Why the machine cannot run its next instruction, if it cannot.
+fn halt(cpu: &CPU) -> Option<String> {
+    let pc = cpu.pc;
+    let value = cpu.word_at(pc);
+    match Op::decode(value) {
+        Err(_) => Some(format!("invalid instruction {:04X} at {:03X}", value, pc)),
+        Ok(Op::Call(_)) if cpu.stack.current_index() == Some(15) => {
+            Some(format!("stack overflow at {:03X}", pc))
+        },
+        Ok(Op::Ret) if cpu.stack.current_index().is_none() => {
+            Some(format!("return with an empty stack at {:03X}", pc))
+        },
+        _ => None,
+    }
+}
+
+pub fn differences(a: &CPU, b: &CPU) -> Vec<String> {
+    let mut out = Vec::new();
+    if a.pc != b.pc {
+        out.push(format!("PC {:03X} != {:03X}", a.pc, b.pc));
+    }
+    for x in 0..16 {
+        if a.regs[x] != b.regs[x] {
+            out.push(format!("V{:X} {:02X} != {:02X}", x, a.regs[x], b.regs[x]));
+        }
+    }
+    if a.index != b.index {
+        out.push(format!("I {:03X} != {:03X}", a.index, b.index));
+    }
+    let (sp_a, sp_b) = (a.stack.current_index(), b.stack.current_index());
+    if sp_a != sp_b {
+        out.push(format!("SP {} != {}", sp(sp_a), sp(sp_b)));
+    } else if (0..16).any(|i| a.stack.peek(i) != b.stack.peek(i)) {
+        out.push("stack contents differ".to_owned());
+    }
+    if a.delay_timer.get_delay() != b.delay_timer.get_delay() {
+        out.push(format!("DT {:02X} != {:02X}", a.delay_timer.get_delay(), b.delay_timer.get_delay()));
+    }
+    if a.sound_timer.get_delay() != b.sound_timer.get_delay() {
+        out.push(format!("ST {:02X} != {:02X}", a.sound_timer.get_delay(), b.sound_timer.get_delay()));
+    }
+    let memory: Vec<usize> = (0..a.mem.len()).filter(|&i| a.mem[i] != b.mem[i]).collect();
+    for &address in memory.iter().take(SHOWN) {
+        out.push(format!("[{:03X}] {:02X} != {:02X}", address, a.mem[address], b.mem[address]));
+    }
+    if memory.len() > SHOWN {
+        out.push(format!("{} more bytes of memory differ", memory.len() - SHOWN));
+    }
+    let pixels = a.device.display.pixels().iter()
+        .zip(b.device.display.pixels().iter())
+        .filter(|&(x, y)| x != y)
+        .count();
+    if pixels > 0 {
+        out.push(format!("{} pixels differ", pixels));
+    }
+    out
+}
+
+fn sp(index: Option<usize>) -> String {
+    match index {
+        Some(index) => format!("{:02X}", index),
+        None => "FF".to_owned(),
+    }
+}
+
+pub fn report(names: (&str, &str), a: Quirks, b: Quirks, divergence: &Divergence) -> String {
+    let mnemonic = match Op::decode(divergence.opcode) {
+        Ok(op) => op.to_string(),
+        Err(_) => "???".to_owned(),
+    };
+    let mut out = format!("Diverged at cycle {} after {:03X}: {:04X} {}\n",
+                          divergence.cycle, divergence.pc, divergence.opcode, mnemonic);
+    out.push_str(&format!("  ({} != {})\n", names.0, names.1));
+    for difference in &divergence.differences {
+        out.push_str(&format!("  {}\n", difference));
+    }
+    match divergence.quirks.is_empty() {
+        true => out.push_str("No differing quirk applies to this instruction\n"),
+        false => for name in &divergence.quirks {
+            out.push_str(&format!("Quirk {}: {} in {}, {} in {}\n", name,
+                                  on_off(a.get(name)), names.0, on_off(b.get(name)), names.1));
+        },
+    }
+    out
+}
+
+fn on_off(setting: Option<bool>) -> &'static str {
+    match setting {
+        Some(true) => "on",
+        _ => "off",
+    }
+}
+
+#[test]
+fn test_lockstep_divergence() {
+    // LD V1, 5; LD V2, 1; LD I, 0x300; LD [I], V2; SHR V1, V2; JP 0x20A
+    let program = [0x61, 0x05, 0x62, 0x01, 0xA3, 0x00, 0xF2, 0x55, 0x81, 0x26, 0x12, 0x0A];
+    let default = Quirks::new();
+    assert_eq!(run(&program, default, default, 1, 100), Outcome::Agreed(100));
+
+    let divergence = match run(&program, default, Quirks::from_name("cosmac").unwrap(), 1, 100) {
+        Outcome::Diverged(divergence) => divergence,
+        outcome => panic!("{:?}", outcome),
+    };
+    assert_eq!((divergence.cycle, divergence.pc, divergence.opcode), (3, 0x206, 0xF255));
+    assert_eq!(divergence.differences, vec!["I 300 != 303"]);
+    assert_eq!(divergence.quirks, vec!["index_increment"]);
+
+    let mut shift = default;
+    shift.shift_in_place = false;
+    let divergence = match run(&program, default, shift, 1, 100) {
+        Outcome::Diverged(divergence) => divergence,
+        outcome => panic!("{:?}", outcome),
+    };
+    assert_eq!(divergence.pc, 0x208);
+    assert_eq!(divergence.differences, vec!["V1 02 != 00"]);
+    let text = report(("default", "shift"), default, shift, &divergence);
+    assert!(text.starts_with("Diverged at cycle 4 after 208: 8126 SHR V1, V2\n"));
+    assert!(text.ends_with("Quirk shift_in_place: on in default, off in shift\n"));
+
+    assert_eq!(run(&[0x00, 0xEE], default, shift, 1, 10),
+               Outcome::Halted(0, "return with an empty stack at 200".to_owned()));
+
+    // The last byte wraps around to address 0, as it does on the CPU.
+    assert_eq!(halt(&::cpu::at_last_byte(0x1200)), None);
+    assert_eq!(halt(&::cpu::at_last_byte(0xFFFF)), Some("invalid instruction FFFF at FFF".to_owned()));
+}
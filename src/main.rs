@@ -4,17 +4,38 @@ extern crate log4rs;
 extern crate termion;
 extern crate sdl2;
 
+mod asm;
+mod capture;
+mod coverage;
 mod cpu;
 mod device;
+mod disasm;
 mod display;
+mod filters;
+mod frontend;
+mod gdb;
 mod keyboard;
+mod lockstep;
 mod opcodes;
+mod profile;
+mod quirks;
+mod trace;
 mod utils;
 mod debugger;
 
+use coverage::Coverage;
 use debugger::Debugger;
+use device::Device;
+use disasm::Syntax;
+use filters::{Filter, Overlay};
+use frontend::Glyphs;
+use profile::Profiler;
+use quirks::Quirks;
+use trace::{Format, Tracer, Trigger};
 use std::env;
-use std::path::Path;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 pub const DEBUG: bool = true;
 pub const DEBUG_CHUNK: u16 = 8;
@@ -43,13 +64,495 @@ fn main() {
 
     log4rs::init_file("config/log4rs.yaml", Default::default()).unwrap();
 
+    let args: Vec<String> = env::args().collect();
+    if args[1] == "disasm" {
+        disassemble(&args[2..]);
+        return;
+    }
+    if args[1] == "asm" {
+        assemble(&args[2..]);
+        return;
+    }
+    if args[1] == "trace-diff" {
+        trace_diff(&args[2..]);
+        return;
+    }
+    if args[1] == "coverage" {
+        coverage(&args[2..]);
+        return;
+    }
+    if args[1] == "lockstep" {
+        lockstep(&args[2..]);
+        return;
+    }
+    let options = Options::parse(&args[2..]);
+    // Octo source is compiled in-process instead of looked up in ./src/roms/.
+    let program = match args[1].ends_with(".8o") {
+        true => Some(compile_octo(&args[1])),
+        false => None,
+    };
+
     let path = Path::new("./src/roms/");
-    let rom = env::args().nth(1).unwrap() + ".ch8";
+    let rom = args[1].clone() + ".ch8";
     let rom_path = path.join(rom);
 
-    let mut debugger = cpu::CPU::new();
     let rommy = &*rom_path.to_string_lossy();
+    let labels = match program {
+        Some(_) => symbols(&options, Path::new(&args[1]), program.as_ref()),
+        None => symbols(&options, &rom_path, None),
+    };
+
+    if options.debug {
+        // With --terminal the debugger's own screen pane replaces the window.
+        let mut debugger = match options.terminal {
+            Some(_) => Debugger::with_cpu(cpu::CPU::headless()),
+            None => Debugger::new(),
+        };
+        debugger.cpu.device.set_filter(options.filter);
+        debugger.cpu.device.set_overlay(options.overlay);
+        match program {
+            Some(ref program) => debugger.load_program(&args[1], program),
+            None => debugger.load_rom(rommy),
+        }
+        debugger.add_symbols(&labels);
+        debugger.cpu.quirks = options.quirks;
+        debugger.cpu.tracer = tracer(&options, &labels);
+        debugger.cpu.profiler = profiler(&options, &labels);
+        debugger.cpu.coverage = options.coverage.as_ref().map(|_| Coverage::new());
+        debugger.run();
+        if let Some(ref mut tracer) = debugger.cpu.tracer {
+            tracer.finish();
+        }
+        if let Some(ref mut profiler) = debugger.cpu.profiler {
+            profiler.finish();
+        }
+        save_coverage(&options, debugger.cpu.coverage.as_ref());
+        return;
+    }
+
+    let mut debugger = match (options.headless, options.terminal) {
+        (Some(_), _) => cpu::CPU::headless(),
+        (None, Some(glyphs)) => cpu::CPU::with_device(Device::terminal(glyphs)),
+        (None, None) => cpu::CPU::new(),
+    };
+
+    debugger.device.set_filter(options.filter);
+    debugger.device.set_overlay(options.overlay);
+    match program {
+        Some(ref program) => debugger.load_program(&program.binary),
+        None => debugger.load_rom(rommy),
+    }
+    debugger.quirks = options.quirks;
+    debugger.tracer = tracer(&options, &labels);
+    debugger.profiler = profiler(&options, &labels);
+    debugger.coverage = options.coverage.as_ref().map(|_| Coverage::new());
+    if let Some(ref video) = options.record {
+        debugger.device.start_recording(Path::new(video)).unwrap();
+    }
+    match (&options.gdb, options.headless) {
+        (&Some(ref address), _) => if let Err(error) = gdb::listen(&mut debugger, address) {
+            eprintln!("gdb: {}", error);
+            std::process::exit(1);
+        },
+        (&None, Some(frames)) => debugger.run_frames(frames),
+        (&None, None) => debugger.run(),
+    }
+    if let Some(ref mut tracer) = debugger.tracer {
+        tracer.finish();
+    }
+    if let Some(ref mut profiler) = debugger.profiler {
+        profiler.finish();
+    }
+    save_coverage(&options, debugger.coverage.as_ref());
+    if debugger.device.is_recording() {
+        println!("{}", debugger.device.stop_recording().unwrap());
+    }
+    if let Some(shot) = options.screenshot {
+        let scale = debugger.device.capture.scale;
+        capture::save_image(&debugger.device.display, Path::new(&shot), scale).unwrap();
+    }
+}
+
+// chip8 disasm <rom> [--syntax cowgod|octo] [--output FILE] [--analyse [--entry ADDR]...]
+fn disassemble(args: &[String]) {
+    let mut syntax = Syntax::Cowgod;
+    let mut output = None;
+    let mut analyse = false;
+    let mut entries = Vec::new();
+    let mut rom = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--syntax" => syntax = Syntax::from_name(value(arg, args.next())).unwrap(),
+            "--output" => output = Some(value(arg, args.next()).to_owned()),
+            "--analyse" => analyse = true,
+            "--entry"  => {
+                let entry = value(arg, args.next());
+                entries.push(u16::from_str_radix(entry.trim_start_matches("0x"), 16).unwrap());
+            },
+            _ if rom.is_none() => rom = Some(find_rom(arg)),
+            _ => panic!("Unknown option: {}", arg),
+        }
+    }
+    let rom = rom.expect("Usage: chip8 disasm <rom> [--syntax cowgod|octo] [--output FILE]");
+    let mut data = Vec::new();
+    File::open(&rom).unwrap().read_to_end(&mut data).unwrap();
+    let text = match analyse {
+        true => disasm::annotated_listing(&data, syntax, &entries),
+        false => disasm::listing(&data, syntax),
+    };
+    match output {
+        Some(path) => File::create(path).unwrap().write_all(text.as_bytes()).unwrap(),
+        None => print!("{}", text),
+    }
+}
+
+// chip8 asm <source> [--output FILE]
+// Writes the ROM (by default the source with a .ch8 extension) and a .sym
+// file of label addresses next to it.
+fn assemble(args: &[String]) {
+    let mut output = None;
+    let mut source = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => output = Some(PathBuf::from(value(arg, args.next()))),
+            _ if source.is_none() => source = Some(PathBuf::from(arg)),
+            _ => panic!("Unknown option: {}", arg),
+        }
+    }
+    let source = source.expect("Usage: chip8 asm <source> [--output FILE]");
+    let output = output.unwrap_or(source.with_extension("ch8"));
+    match asm::assemble_file(&source) {
+        Ok(program) => {
+            File::create(&output).unwrap().write_all(&program.binary).unwrap();
+            File::create(output.with_extension("sym")).unwrap().write_all(program.symbol_file().as_bytes()).unwrap();
+            println!("{}: {} bytes, {} labels", output.display(), program.binary.len(), program.symbols.len());
+        },
+        Err(errors) => report(errors),
+    }
+}
+
+// chip8 trace-diff <a> <b> [--context N] [--ignore COLUMNS]
+//                  [--columns-a LAYOUT] [--columns-b LAYOUT]
+// Exits with 1 when the traces diverge. Layouts read other emulators' text
+// traces; see `trace` for both.
+fn trace_diff(args: &[String]) {
+    let mut paths = Vec::new();
+    let mut layouts = [None, None];
+    let mut context = 5;
+    let mut ignore = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--context"   => context = value(arg, args.next()).parse().unwrap(),
+            "--columns-a" => layouts[0] = Some(trace::Layout::parse(value(arg, args.next())).unwrap()),
+            "--columns-b" => layouts[1] = Some(trace::Layout::parse(value(arg, args.next())).unwrap()),
+            "--ignore"    => ignore = trace::Layout::parse(value(arg, args.next())).unwrap().columns(),
+            _ if paths.len() < 2 => paths.push(arg.clone()),
+            _ => panic!("Unknown option: {}", arg),
+        }
+    }
+    if paths.len() != 2 {
+        panic!("Usage: chip8 trace-diff <a> <b> [--context N] [--ignore COLUMNS] [--columns-a LAYOUT] [--columns-b LAYOUT]");
+    }
+    let mut traces = Vec::new();
+    for (path, layout) in paths.iter().zip(layouts.iter()) {
+        match trace::read(Path::new(path), layout.as_ref()) {
+            Ok(trace) => traces.push(trace),
+            Err(error) => {
+                eprintln!("{}", error);
+                std::process::exit(2);
+            },
+        }
+    }
+    match trace::first_divergence(&traces[0], &traces[1], &ignore) {
+        Some(divergence) => {
+            print!("{}", trace::report((&paths[0], &traces[0]), (&paths[1], &traces[1]), &divergence, context));
+            std::process::exit(1);
+        },
+        None => println!("No divergence in {} records", traces[0].records.len()),
+    }
+}
+
+// chip8 coverage merge <out> <in>...
+// chip8 coverage report <rom> <data>... [--format listing|lcov]
+//                       [--symbols FILE] [--source FILE] [--output FILE]
+// See `coverage` for the formats.
+fn coverage(args: &[String]) {
+    let usage = "Usage: chip8 coverage merge <out> <in>... | \
+                 chip8 coverage report <rom> <data>... [--format listing|lcov] \
+                 [--symbols FILE] [--source FILE] [--output FILE]";
+    let read = |path: &String| match Coverage::read(Path::new(path)) {
+        Ok(coverage) => coverage,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(2);
+        },
+    };
+    if args.len() >= 3 && args[0] == "merge" {
+        let mut merged = Coverage::new();
+        for path in &args[2..] {
+            merged.merge(&read(path));
+        }
+        merged.write(Path::new(&args[1])).unwrap();
+        return;
+    }
+    if args.len() < 3 || args[0] != "report" {
+        panic!("{}", usage);
+    }
+    let mut paths = Vec::new();
+    let mut format = coverage::Format::Listing;
+    let mut symbols = None;
+    let mut source = None;
+    let mut output = None;
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--format"  => format = coverage::Format::from_name(value(arg, rest.next())).unwrap(),
+            "--symbols" => symbols = Some(value(arg, rest.next()).to_owned()),
+            "--source"  => source = Some(value(arg, rest.next()).to_owned()),
+            "--output"  => output = Some(value(arg, rest.next()).to_owned()),
+            _ => paths.push(arg.clone()),
+        }
+    }
+    let rom_path = find_rom(&paths[0]);
+    let mut rom = Vec::new();
+    File::open(&rom_path).unwrap().read_to_end(&mut rom).unwrap();
+    let mut data = Coverage::new();
+    for path in &paths[1..] {
+        data.merge(&read(path));
+    }
+
+    let program = source.map(|path| {
+        let program = match path.ends_with(".8o") {
+            true => compile_octo(&path),
+            false => asm::assemble_file(Path::new(&path)).unwrap_or_else(|errors| report(errors)),
+        };
+        if program.binary != rom {
+            eprintln!("warning: {} does not assemble to {}", path, rom_path.display());
+        }
+        program
+    });
+    let labels = match (symbols, &program) {
+        (Some(path), _) => disasm::read_symbols(Path::new(&path)).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(2);
+        }),
+        (None, &Some(ref program)) => program.symbols.iter().map(|(name, &address)| (address, name.clone())).collect(),
+        (None, &None) => disasm::Labels::new(),
+    };
+    let text = match format {
+        coverage::Format::Listing => coverage::listing(&rom, &data, &labels),
+        coverage::Format::Lcov => {
+            if program.is_none() && labels.is_empty() {
+                eprintln!("lcov reports need --symbols or --source");
+                std::process::exit(2);
+            }
+            let name = rom_path.to_string_lossy();
+            coverage::lcov(&rom, &name, &data, &labels, program.as_ref().map(|p| &p.lines))
+        },
+    };
+    match output {
+        Some(path) => File::create(path).unwrap().write_all(text.as_bytes()).unwrap(),
+        None => print!("{}", text),
+    }
+}
+
+// chip8 lockstep <rom> <quirks-a> <quirks-b> [--cycles N] [--seed N]
+// Exits with 1 when the machines diverge. See `quirks` for the specs.
+fn lockstep(args: &[String]) {
+    let mut positional = Vec::new();
+    let mut cycles = 1_000_000;
+    let mut seed = 1;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cycles" => cycles = value(arg, args.next()).parse().unwrap(),
+            "--seed"   => seed = value(arg, args.next()).parse().unwrap(),
+            _ if positional.len() < 3 => positional.push(arg.clone()),
+            _ => panic!("Unknown option: {}", arg),
+        }
+    }
+    if positional.len() != 3 {
+        panic!("Usage: chip8 lockstep <rom> <quirks-a> <quirks-b> [--cycles N] [--seed N]");
+    }
+    let mut program = Vec::new();
+    File::open(find_rom(&positional[0])).unwrap().read_to_end(&mut program).unwrap();
+    let a = Quirks::parse(&positional[1]).unwrap();
+    let b = Quirks::parse(&positional[2]).unwrap();
+    match lockstep::run(&program, a, b, seed, cycles) {
+        lockstep::Outcome::Agreed(cycles) => println!("No divergence in {} cycles", cycles),
+        lockstep::Outcome::Halted(cycle, reason) => println!("No divergence; both stopped at cycle {}: {}", cycle, reason),
+        lockstep::Outcome::Diverged(divergence) => {
+            print!("{}", lockstep::report((&positional[1], &positional[2]), a, b, &divergence));
+            std::process::exit(1);
+        },
+    }
+}
+
+fn compile_octo(path: &str) -> asm::Program {
+    let program = match asm::octo::compile_file(Path::new(path)) {
+        Ok(program) => program,
+        Err(errors) => report(errors),
+    };
+    if !program.extensions.is_empty() {
+        let names: Vec<&str> = program.extensions.iter().cloned().collect();
+        eprintln!("warning: {} uses {} instructions, which this emulator does not execute yet",
+                  path, names.join(" and "));
+    }
+    if program.binary.len() > 0x1000 - 0x200 {
+        eprintln!("{}: {} bytes do not fit in memory", path, program.binary.len());
+        std::process::exit(1);
+    }
+    program
+}
+
+fn report(errors: Vec<asm::Error>) -> ! {
+    for error in errors {
+        eprintln!("{}", error);
+    }
+    std::process::exit(1);
+}
+
+// Symbols from --symbols, else from `<rom>.sym` beside the ROM or source,
+// else a compiled program's labels.
+fn symbols(options: &Options, rom: &Path, program: Option<&asm::Program>) -> disasm::Labels {
+    let sym = rom.with_extension("sym");
+    let path = match options.symbols {
+        Some(ref path) => Some(PathBuf::from(path)),
+        None if sym.is_file() => Some(sym),
+        None => None,
+    };
+    match (path, program) {
+        (Some(path), _) => disasm::read_symbols(&path).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(2);
+        }),
+        (None, Some(program)) => program.symbols.iter().map(|(name, &address)| (address, name.clone())).collect(),
+        (None, None) => disasm::Labels::new(),
+    }
+}
+
+fn tracer(options: &Options, labels: &disasm::Labels) -> Option<Tracer> {
+    let path = match options.trace {
+        Some(ref path) => path,
+        None => return None,
+    };
+    match Tracer::create(Path::new(path), options.trace_format, options.trace_start, options.trace_stop) {
+        Ok(mut tracer) => {
+            tracer.labels = labels.clone();
+            Some(tracer)
+        },
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            std::process::exit(1);
+        },
+    }
+}
+
+// Subroutines are named after their symbols.
+fn profiler(options: &Options, labels: &disasm::Labels) -> Option<Profiler> {
+    let path = match options.profile {
+        Some(ref path) => path,
+        None => return None,
+    };
+    match Profiler::create(Path::new(path), options.profile_format, labels.clone()) {
+        Ok(profiler) => Some(profiler),
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            std::process::exit(1);
+        },
+    }
+}
+
+fn save_coverage(options: &Options, coverage: Option<&Coverage>) {
+    if let (&Some(ref path), Some(coverage)) = (&options.coverage, coverage) {
+        if let Err(error) = coverage.write(Path::new(path)) {
+            eprintln!("{}: {}", path, error);
+        }
+    }
+}
+
+// A path to a file, or the name of a ROM in ./src/roms/.
+fn find_rom(name: &str) -> PathBuf {
+    match Path::new(name).is_file() {
+        true => PathBuf::from(name),
+        false => Path::new("./src/roms/").join(name.to_owned() + ".ch8"),
+    }
+}
+
+struct Options {
+    filter: Filter,
+    overlay: Overlay,
+    debug: bool,
+    headless: Option<u32>,
+    terminal: Option<Glyphs>,
+    screenshot: Option<String>,
+    record: Option<String>,
+    gdb: Option<String>,
+    trace: Option<String>,
+    trace_format: Format,
+    trace_start: Option<Trigger>,
+    trace_stop: Option<Trigger>,
+    quirks: Quirks,
+    profile: Option<String>,
+    profile_format: profile::Format,
+    coverage: Option<String>,
+    symbols: Option<String>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Options {
+        let mut options = Options {
+            filter: Filter::Nearest,
+            overlay: Overlay::Off,
+            debug: false,
+            headless: None,
+            terminal: None,
+            screenshot: None,
+            record: None,
+            gdb: None,
+            trace: None,
+            trace_format: Format::Text,
+            trace_start: None,
+            trace_stop: None,
+            quirks: Quirks::new(),
+            profile: None,
+            profile_format: profile::Format::Report,
+            coverage: None,
+            symbols: None,
+        };
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            match flag.as_str() {
+                "--filter"         => options.filter = Filter::from_name(value(flag, args.next())).unwrap(),
+                "--overlay"        => options.overlay = Overlay::from_name(value(flag, args.next())).unwrap(),
+                "--debug"          => options.debug = true,
+                "--headless"       => options.headless = Some(value(flag, args.next()).parse().unwrap()),
+                "--terminal"       => options.terminal = Some(Glyphs::from_name(value(flag, args.next())).unwrap()),
+                "--screenshot"     => options.screenshot = Some(value(flag, args.next()).to_owned()),
+                "--record"         => options.record = Some(value(flag, args.next()).to_owned()),
+                "--gdb"            => options.gdb = Some(value(flag, args.next()).to_owned()),
+                "--trace"          => options.trace = Some(value(flag, args.next()).to_owned()),
+                "--trace-format"   => options.trace_format = Format::from_name(value(flag, args.next())).unwrap(),
+                "--trace-start"    => options.trace_start = Some(Trigger::parse(value(flag, args.next())).unwrap()),
+                "--trace-stop"     => options.trace_stop = Some(Trigger::parse(value(flag, args.next())).unwrap()),
+                "--quirks"         => options.quirks = Quirks::parse(value(flag, args.next())).unwrap(),
+                "--profile"        => options.profile = Some(value(flag, args.next()).to_owned()),
+                "--profile-format" => options.profile_format = profile::Format::from_name(value(flag, args.next())).unwrap(),
+                "--coverage"       => options.coverage = Some(value(flag, args.next()).to_owned()),
+                "--symbols"        => options.symbols = Some(value(flag, args.next()).to_owned()),
+                _ => panic!("Unknown option: {}", flag),
+            }
+        }
+        options
+    }
+}
 
-    debugger.load_rom(rommy);
-    debugger.run();
-}
\ No newline at end of file
+fn value<'a>(flag: &str, arg: Option<&'a String>) -> &'a str {
+    match arg {
+        Some(value) => value,
+        None => panic!("Missing value for {}", flag),
+    }
+}
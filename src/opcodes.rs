@@ -1,4 +1,8 @@
-#[derive(Debug, Clone, PartialEq)]
+use std::fmt;
+use std::str::FromStr;
+use asm::expr;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Instruction {
     SysAddressJump_0x0NNN,          // Jump to address NNN
     ClearDisplay_0x00E0,            // Clear the display
@@ -37,143 +41,449 @@ pub enum Instruction {
     ReadRegsUptoVx_0xFX65,          // Store mem[i] through mem[i+x] in v[0] through v[x]
 }
 
-pub struct Opcode {
-    pub value: u16,
-    pub instr: Instruction,
+// A decoded instruction with its operands: registers are indices into V,
+// bytes are `kk`, nibbles `n` and addresses `nnn`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Sys(u16),
+    Cls,
+    Ret,
+    Jp(u16),
+    Call(u16),
+    SeByte(usize, u8),
+    SneByte(usize, u8),
+    SeReg(usize, usize),
+    LdByte(usize, u8),
+    AddByte(usize, u8),
+    LdReg(usize, usize),
+    Or(usize, usize),
+    And(usize, usize),
+    Xor(usize, usize),
+    AddReg(usize, usize),
+    Sub(usize, usize),
+    Shr(usize, usize),
+    Subn(usize, usize),
+    Shl(usize, usize),
+    SneReg(usize, usize),
+    LdI(u16),
+    JpV0(u16),
+    Rnd(usize, u8),
+    Drw(usize, usize, u8),
+    Skp(usize),
+    Sknp(usize),
+    LdVxDt(usize),
+    LdVxK(usize),
+    LdDtVx(usize),
+    LdStVx(usize),
+    AddIVx(usize),
+    LdFVx(usize),
+    LdBVx(usize),
+    StoreRegs(usize),
+    ReadRegs(usize),
 }
 
-impl Opcode {
-    pub fn from_bytes(b1: u8, b2: u8) -> Opcode {
-        let value: u16 = (b1 as u16) << 8 | b2 as u16;
-        Opcode {
-            value: value,
-            instr: parse_opcode(value).unwrap(),
+impl Op {
+    // Bits an instruction does not use are ignored, as the CPU always has,
+    // so 0x01E0 decodes to CLS and encodes back as 0x00E0.
+    pub fn decode(value: u16) -> Result<Op, String> {
+        let x = (value >> 8 & 0xF) as usize;
+        let y = (value >> 4 & 0xF) as usize;
+        let n = (value & 0xF) as u8;
+        let kk = (value & 0xFF) as u8;
+        let nnn = value & 0xFFF;
+        let invalid = || format!("Could not parse opcode: {:04x}", value);
+        Ok(match value & 0xF000 {
+            0x0000 => match kk {
+                0xE0 => Op::Cls,
+                0xEE => Op::Ret,
+                _    => Op::Sys(nnn),
+            },
+            0x1000 => Op::Jp(nnn),
+            0x2000 => Op::Call(nnn),
+            0x3000 => Op::SeByte(x, kk),
+            0x4000 => Op::SneByte(x, kk),
+            0x5000 => Op::SeReg(x, y),
+            0x6000 => Op::LdByte(x, kk),
+            0x7000 => Op::AddByte(x, kk),
+            0x8000 => match n {
+                0x0 => Op::LdReg(x, y),
+                0x1 => Op::Or(x, y),
+                0x2 => Op::And(x, y),
+                0x3 => Op::Xor(x, y),
+                0x4 => Op::AddReg(x, y),
+                0x5 => Op::Sub(x, y),
+                0x6 => Op::Shr(x, y),
+                0x7 => Op::Subn(x, y),
+                0xE => Op::Shl(x, y),
+                _ => return Err(invalid()),
+            },
+            0x9000 => Op::SneReg(x, y),
+            0xA000 => Op::LdI(nnn),
+            0xB000 => Op::JpV0(nnn),
+            0xC000 => Op::Rnd(x, kk),
+            0xD000 => Op::Drw(x, y, n),
+            0xE000 => match kk {
+                0x9E => Op::Skp(x),
+                0xA1 => Op::Sknp(x),
+                _ => return Err(invalid()),
+            },
+            _ => match kk {
+                0x07 => Op::LdVxDt(x),
+                0x0A => Op::LdVxK(x),
+                0x15 => Op::LdDtVx(x),
+                0x18 => Op::LdStVx(x),
+                0x1E => Op::AddIVx(x),
+                0x29 => Op::LdFVx(x),
+                0x33 => Op::LdBVx(x),
+                0x55 => Op::StoreRegs(x),
+                0x65 => Op::ReadRegs(x),
+                _ => return Err(invalid()),
+            },
+        })
+    }
+    pub fn encode(&self) -> u16 {
+        let vx = |x: usize| (x as u16 & 0xF) << 8;
+        let vy = |y: usize| (y as u16 & 0xF) << 4;
+        match *self {
+            Op::Sys(nnn)        => nnn & 0xFFF,
+            Op::Cls             => 0x00E0,
+            Op::Ret             => 0x00EE,
+            Op::Jp(nnn)         => 0x1000 | nnn & 0xFFF,
+            Op::Call(nnn)       => 0x2000 | nnn & 0xFFF,
+            Op::SeByte(x, kk)   => 0x3000 | vx(x) | kk as u16,
+            Op::SneByte(x, kk)  => 0x4000 | vx(x) | kk as u16,
+            Op::SeReg(x, y)     => 0x5000 | vx(x) | vy(y),
+            Op::LdByte(x, kk)   => 0x6000 | vx(x) | kk as u16,
+            Op::AddByte(x, kk)  => 0x7000 | vx(x) | kk as u16,
+            Op::LdReg(x, y)     => 0x8000 | vx(x) | vy(y),
+            Op::Or(x, y)        => 0x8001 | vx(x) | vy(y),
+            Op::And(x, y)       => 0x8002 | vx(x) | vy(y),
+            Op::Xor(x, y)       => 0x8003 | vx(x) | vy(y),
+            Op::AddReg(x, y)    => 0x8004 | vx(x) | vy(y),
+            Op::Sub(x, y)       => 0x8005 | vx(x) | vy(y),
+            Op::Shr(x, y)       => 0x8006 | vx(x) | vy(y),
+            Op::Subn(x, y)      => 0x8007 | vx(x) | vy(y),
+            Op::Shl(x, y)       => 0x800E | vx(x) | vy(y),
+            Op::SneReg(x, y)    => 0x9000 | vx(x) | vy(y),
+            Op::LdI(nnn)        => 0xA000 | nnn & 0xFFF,
+            Op::JpV0(nnn)       => 0xB000 | nnn & 0xFFF,
+            Op::Rnd(x, kk)      => 0xC000 | vx(x) | kk as u16,
+            Op::Drw(x, y, n)    => 0xD000 | vx(x) | vy(y) | n as u16 & 0xF,
+            Op::Skp(x)          => 0xE09E | vx(x),
+            Op::Sknp(x)         => 0xE0A1 | vx(x),
+            Op::LdVxDt(x)       => 0xF007 | vx(x),
+            Op::LdVxK(x)        => 0xF00A | vx(x),
+            Op::LdDtVx(x)       => 0xF015 | vx(x),
+            Op::LdStVx(x)       => 0xF018 | vx(x),
+            Op::AddIVx(x)       => 0xF01E | vx(x),
+            Op::LdFVx(x)        => 0xF029 | vx(x),
+            Op::LdBVx(x)        => 0xF033 | vx(x),
+            Op::StoreRegs(x)    => 0xF055 | vx(x),
+            Op::ReadRegs(x)     => 0xF065 | vx(x),
         }
     }
-    pub fn from_code(code: u16) -> Opcode {
-        Opcode {
-            value: code,
-            instr: parse_opcode(code).unwrap(),
+    pub fn instruction(&self) -> Instruction {
+        match *self {
+            Op::Sys(_)          => Instruction::SysAddressJump_0x0NNN,
+            Op::Cls             => Instruction::ClearDisplay_0x00E0,
+            Op::Ret             => Instruction::RetFromSubroutine_0x00EE,
+            Op::Jp(_)           => Instruction::JumpLocation_0x1NNN,
+            Op::Call(_)         => Instruction::CallSubroutine_0x2NNN,
+            Op::SeByte(..)      => Instruction::SkipInstrIfVxEqPL_0x3XNN,
+            Op::SneByte(..)     => Instruction::SkipInstrIfVxNotEqPL_0x4XNN,
+            Op::SeReg(..)       => Instruction::SkipInstrIfVxVy_0x5XY0,
+            Op::LdByte(..)      => Instruction::SetVxToPL_0x6XNN,
+            Op::AddByte(..)     => Instruction::IncrementVxByPL_0x7XNN,
+            Op::LdReg(..)       => Instruction::SetVxToVy_0x8XY0,
+            Op::Or(..)          => Instruction::SetVxToVxORVy_0x8XY1,
+            Op::And(..)         => Instruction::SetVxToVxANDVy_0x8XY2,
+            Op::Xor(..)         => Instruction::SetVxToVxXORVy_0x8XY3,
+            Op::AddReg(..)      => Instruction::IncrementVxByVyAndCarry_0x8XY4,
+            Op::Sub(..)         => Instruction::DecrementVxByVyNoBorrow_0x8XY5,
+            Op::Shr(..)         => Instruction::ShiftAndRotateVxRight_0x8XY6,
+            Op::Subn(..)        => Instruction::DecrementVyByVxNoBorrow_0x8XY7,
+            Op::Shl(..)         => Instruction::ShiftAndRotateVxLeft_0x8XYE,
+            Op::SneReg(..)      => Instruction::SkipInstrIfVxNotVy_0x9XY0,
+            Op::LdI(_)          => Instruction::SetIndexRegToPL_0xANNN,
+            Op::JpV0(_)         => Instruction::JumpToV0PlusPL_0xBNNN,
+            Op::Rnd(..)         => Instruction::SetVxRandByteANDPL_0xCXNN,
+            Op::Drw(..)         => Instruction::DisplaySpriteSetVfColl_0xDXYN,
+            Op::Skp(_)          => Instruction::SkipInstrIfVxPressed_0xEX9E,
+            Op::Sknp(_)         => Instruction::SkipInstrIfVxNotPressed_0xEXA1,
+            Op::LdVxDt(_)       => Instruction::SetVxToDelayTimerVal_0xFX07,
+            Op::LdVxK(_)        => Instruction::WaitForKeyStoreInVx_0xFX0A,
+            Op::LdDtVx(_)       => Instruction::SetDelayTimerToVx_0xFX15,
+            Op::LdStVx(_)       => Instruction::SetSoundTimerToVx_0xFX18,
+            Op::AddIVx(_)       => Instruction::IncrementIndexRegByVx_0xFX1E,
+            Op::LdFVx(_)        => Instruction::SetIndexRegToVxSprite_0xFX29,
+            Op::LdBVx(_)        => Instruction::StoreBCDOfVxIn3Bytes_0xFX33,
+            Op::StoreRegs(_)    => Instruction::StoreRegsUptoVx_0xFX55,
+            Op::ReadRegs(_)     => Instruction::ReadRegsUptoVx_0xFX65,
         }
     }
-    pub fn x(&self) -> usize {
-        (self.value >> 8 & 0xF) as usize
+    // Builds an instruction from a Cowgod mnemonic and its operands. Errors
+    // name the operand at fault when there is one.
+    pub fn assemble(mnemonic: &str, operands: &[Operand]) -> Result<Op, (Option<usize>, String)> {
+        let shape: Vec<&str> = operands.iter().map(|o| o.kind()).collect();
+        let shape = shape.join(",");
+        let reg = |i: usize| match operands[i] {
+            Operand::V(x) => x,
+            _ => 0,
+        };
+        let value = |i: usize, min: i64, max: i64| match operands[i] {
+            Operand::Value(v) if v < min || v > max => Err((Some(i), format!("{} is out of range {}..{}", v, min, max))),
+            Operand::Value(v) => Ok((v & max) as u16),
+            _ => Ok(0),
+        };
+        let byte = |i: usize| value(i, -128, 0xFF).map(|v| v as u8);
+        let address = |i: usize| value(i, 0, 0xFFF);
+        let nibble = |i: usize| value(i, 0, 0xF).map(|v| v as u8);
+        let mnemonic = mnemonic.to_uppercase();
+        Ok(match (mnemonic.as_str(), shape.as_str()) {
+            ("CLS", "")         => Op::Cls,
+            ("RET", "")         => Op::Ret,
+            ("SYS", "N")        => Op::Sys(address(0)?),
+            ("JP", "N")         => Op::Jp(address(0)?),
+            ("JP", "V,N") if reg(0) == 0 => Op::JpV0(address(1)?),
+            ("CALL", "N")       => Op::Call(address(0)?),
+            ("SE", "V,N")       => Op::SeByte(reg(0), byte(1)?),
+            ("SNE", "V,N")      => Op::SneByte(reg(0), byte(1)?),
+            ("SE", "V,V")       => Op::SeReg(reg(0), reg(1)),
+            ("LD", "V,N")       => Op::LdByte(reg(0), byte(1)?),
+            ("ADD", "V,N")      => Op::AddByte(reg(0), byte(1)?),
+            ("LD", "V,V")       => Op::LdReg(reg(0), reg(1)),
+            ("OR", "V,V")       => Op::Or(reg(0), reg(1)),
+            ("AND", "V,V")      => Op::And(reg(0), reg(1)),
+            ("XOR", "V,V")      => Op::Xor(reg(0), reg(1)),
+            ("ADD", "V,V")      => Op::AddReg(reg(0), reg(1)),
+            ("SUB", "V,V")      => Op::Sub(reg(0), reg(1)),
+            ("SHR", "V,V")      => Op::Shr(reg(0), reg(1)),
+            ("SHR", "V")        => Op::Shr(reg(0), reg(0)),
+            ("SUBN", "V,V")     => Op::Subn(reg(0), reg(1)),
+            ("SHL", "V,V")      => Op::Shl(reg(0), reg(1)),
+            ("SHL", "V")        => Op::Shl(reg(0), reg(0)),
+            ("SNE", "V,V")      => Op::SneReg(reg(0), reg(1)),
+            ("LD", "I,N")       => Op::LdI(address(1)?),
+            ("RND", "V,N")      => Op::Rnd(reg(0), byte(1)?),
+            ("DRW", "V,V,N")    => Op::Drw(reg(0), reg(1), nibble(2)?),
+            ("SKP", "V")        => Op::Skp(reg(0)),
+            ("SKNP", "V")       => Op::Sknp(reg(0)),
+            ("LD", "V,DT")      => Op::LdVxDt(reg(0)),
+            ("LD", "V,K")       => Op::LdVxK(reg(0)),
+            ("LD", "DT,V")      => Op::LdDtVx(reg(1)),
+            ("LD", "ST,V")      => Op::LdStVx(reg(1)),
+            ("ADD", "I,V")      => Op::AddIVx(reg(1)),
+            ("LD", "F,V")       => Op::LdFVx(reg(1)),
+            ("LD", "B,V")       => Op::LdBVx(reg(1)),
+            ("LD", "[I],V")     => Op::StoreRegs(reg(1)),
+            ("LD", "V,[I]")     => Op::ReadRegs(reg(0)),
+            (_, shape) => {
+                let known = ["CLS", "RET", "SYS", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR",
+                             "SUB", "SHR", "SUBN", "SHL", "RND", "DRW", "SKP", "SKNP"];
+                return Err(match known.contains(&mnemonic.as_str()) {
+                    true => (if operands.is_empty() { None } else { Some(0) },
+                             format!("{} does not take operands {}", mnemonic, shape)),
+                    false => (None, format!("Unknown instruction: {}", mnemonic)),
+                });
+            },
+        })
     }
-    pub fn y(&self) -> usize {
-        (self.value >> 4 & 0xF) as usize
+}
+
+// Cowgod's mnemonics, which is also what `FromStr` reads.
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Op::Sys(nnn)        => write!(f, "SYS 0x{:03X}", nnn),
+            Op::Cls             => write!(f, "CLS"),
+            Op::Ret             => write!(f, "RET"),
+            Op::Jp(nnn)         => write!(f, "JP 0x{:03X}", nnn),
+            Op::Call(nnn)       => write!(f, "CALL 0x{:03X}", nnn),
+            Op::SeByte(x, kk)   => write!(f, "SE V{:X}, 0x{:02X}", x, kk),
+            Op::SneByte(x, kk)  => write!(f, "SNE V{:X}, 0x{:02X}", x, kk),
+            Op::SeReg(x, y)     => write!(f, "SE V{:X}, V{:X}", x, y),
+            Op::LdByte(x, kk)   => write!(f, "LD V{:X}, 0x{:02X}", x, kk),
+            Op::AddByte(x, kk)  => write!(f, "ADD V{:X}, 0x{:02X}", x, kk),
+            Op::LdReg(x, y)     => write!(f, "LD V{:X}, V{:X}", x, y),
+            Op::Or(x, y)        => write!(f, "OR V{:X}, V{:X}", x, y),
+            Op::And(x, y)       => write!(f, "AND V{:X}, V{:X}", x, y),
+            Op::Xor(x, y)       => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Op::AddReg(x, y)    => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Op::Sub(x, y)       => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Op::Shr(x, y)       => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Op::Subn(x, y)      => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Op::Shl(x, y)       => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Op::SneReg(x, y)    => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Op::LdI(nnn)        => write!(f, "LD I, 0x{:03X}", nnn),
+            Op::JpV0(nnn)       => write!(f, "JP V0, 0x{:03X}", nnn),
+            Op::Rnd(x, kk)      => write!(f, "RND V{:X}, 0x{:02X}", x, kk),
+            Op::Drw(x, y, n)    => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Op::Skp(x)          => write!(f, "SKP V{:X}", x),
+            Op::Sknp(x)         => write!(f, "SKNP V{:X}", x),
+            Op::LdVxDt(x)       => write!(f, "LD V{:X}, DT", x),
+            Op::LdVxK(x)        => write!(f, "LD V{:X}, K", x),
+            Op::LdDtVx(x)       => write!(f, "LD DT, V{:X}", x),
+            Op::LdStVx(x)       => write!(f, "LD ST, V{:X}", x),
+            Op::AddIVx(x)       => write!(f, "ADD I, V{:X}", x),
+            Op::LdFVx(x)        => write!(f, "LD F, V{:X}", x),
+            Op::LdBVx(x)        => write!(f, "LD B, V{:X}", x),
+            Op::StoreRegs(x)    => write!(f, "LD [I], V{:X}", x),
+            Op::ReadRegs(x)     => write!(f, "LD V{:X}, [I]", x),
+        }
     }
-    pub fn z(&self) -> usize {
-        (self.value & 0xF) as usize
+}
+
+// Operands are registers, I, [I], DT, ST, K, F, B or plain numbers; the
+// assembler accepts expressions as well.
+impl FromStr for Op {
+    type Err = String;
+    fn from_str(text: &str) -> Result<Op, String> {
+        let text = text.trim();
+        let (mnemonic, rest) = match text.find(char::is_whitespace) {
+            Some(pos) => (&text[..pos], &text[pos..]),
+            None => (text, ""),
+        };
+        let mut operands = Vec::new();
+        for part in rest.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            operands.push(match Operand::named(part) {
+                Some(operand) => operand,
+                None => Operand::Value(expr::signed_number(part)?),
+            });
+        }
+        Op::assemble(mnemonic, &operands).map_err(|(_, e)| e)
     }
-    pub fn yz(&self) -> u16 {
-        self.value & 0xFF
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    V(usize),
+    I,
+    IndirectI,
+    DT,
+    ST,
+    K,
+    F,
+    B,
+    Value(i64),
+}
+
+impl Operand {
+    // The operands that are spelled as names; None for anything else.
+    pub fn named(text: &str) -> Option<Operand> {
+        let upper = text.to_uppercase().replace(' ', "");
+        if upper.len() == 2 && upper.starts_with('V') {
+            if let Some(x) = upper[1..].chars().next().and_then(|c| c.to_digit(16)) {
+                return Some(Operand::V(x as usize));
+            }
+        }
+        match upper.as_str() {
+            "I" => Some(Operand::I),
+            "[I]" => Some(Operand::IndirectI),
+            "DT" => Some(Operand::DT),
+            "ST" => Some(Operand::ST),
+            "K" => Some(Operand::K),
+            "F" => Some(Operand::F),
+            "B" => Some(Operand::B),
+            _ => None,
+        }
     }
-    pub fn xyz(&self) -> u16 {
-        self.value & 0xFFF
+    fn kind(&self) -> &'static str {
+        match *self {
+            Operand::V(_) => "V",
+            Operand::I => "I",
+            Operand::IndirectI => "[I]",
+            Operand::DT => "DT",
+            Operand::ST => "ST",
+            Operand::K => "K",
+            Operand::F => "F",
+            Operand::B => "B",
+            Operand::Value(_) => "N",
+        }
     }
 }
 
-pub fn parse_opcode(code: u16) -> Result<Instruction, String> {
-    match code & 0xF000 {
-        0x0000 =>
-            match code & 0x00FF {
-                0xE0 => Ok(Instruction::ClearDisplay_0x00E0),
-                0xEE => Ok(Instruction::RetFromSubroutine_0x00EE),
-                _    => Ok(Instruction::SysAddressJump_0x0NNN),
-            },
-        0x1000 => Ok(Instruction::JumpLocation_0x1NNN),
-        0x2000 => Ok(Instruction::CallSubroutine_0x2NNN),
-        0x3000 => Ok(Instruction::SkipInstrIfVxEqPL_0x3XNN),
-        0x4000 => Ok(Instruction::SkipInstrIfVxNotEqPL_0x4XNN),
-        0x5000 => Ok(Instruction::SkipInstrIfVxVy_0x5XY0),
-        0x6000 => Ok(Instruction::SetVxToPL_0x6XNN),
-        0x7000 => Ok(Instruction::IncrementVxByPL_0x7XNN),
-        0x8000 =>
-            match code & 0x000F {
-                0x0 => Ok(Instruction::SetVxToVy_0x8XY0),
-                0x1 => Ok(Instruction::SetVxToVxORVy_0x8XY1),
-                0x2 => Ok(Instruction::SetVxToVxANDVy_0x8XY2),
-                0x3 => Ok(Instruction::SetVxToVxXORVy_0x8XY3),
-                0x4 => Ok(Instruction::IncrementVxByVyAndCarry_0x8XY4),
-                0x5 => Ok(Instruction::DecrementVxByVyNoBorrow_0x8XY5),
-                0x6 => Ok(Instruction::ShiftAndRotateVxRight_0x8XY6),
-                0x7 => Ok(Instruction::DecrementVyByVxNoBorrow_0x8XY7),
-                0xE => Ok(Instruction::ShiftAndRotateVxLeft_0x8XYE),
-                _ => Err(format!("Could not parse opcode: {:04x}", code))
-            },
-        0x9000 => Ok(Instruction::SkipInstrIfVxNotVy_0x9XY0),
-        0xA000 => Ok(Instruction::SetIndexRegToPL_0xANNN),
-        0xB000 => Ok(Instruction::JumpToV0PlusPL_0xBNNN),
-        0xC000 => Ok(Instruction::SetVxRandByteANDPL_0xCXNN),
-        0xD000 => Ok(Instruction::DisplaySpriteSetVfColl_0xDXYN),
-        0xE000 => 
-            match code & 0x00FF {
-                0x9E => Ok(Instruction::SkipInstrIfVxPressed_0xEX9E),
-                0xA1 => Ok(Instruction::SkipInstrIfVxNotPressed_0xEXA1),
-                _ => Err(format!("Could not parse opcode: {:04x}", code))
-            },
-        0xF000 => 
-            match code & 0x00FF {
-                0x07 => Ok(Instruction::SetVxToDelayTimerVal_0xFX07),
-                0x0A => Ok(Instruction::WaitForKeyStoreInVx_0xFX0A),
-                0x15 => Ok(Instruction::SetDelayTimerToVx_0xFX15),
-                0x18 => Ok(Instruction::SetSoundTimerToVx_0xFX18),
-                0x1E => Ok(Instruction::IncrementIndexRegByVx_0xFX1E),
-                0x29 => Ok(Instruction::SetIndexRegToVxSprite_0xFX29),
-                0x33 => Ok(Instruction::StoreBCDOfVxIn3Bytes_0xFX33),
-                0x55 => Ok(Instruction::StoreRegsUptoVx_0xFX55),
-                0x65 => Ok(Instruction::ReadRegsUptoVx_0xFX65),
-                _ => Err(format!("Could not parse opcode: {:04x}", code))
-            },
-        _ => Err(format!("Could not parse opcode: {:04x}", code))
+pub struct Opcode {
+    pub value: u16,
+    pub op: Op,
+}
+
+impl Opcode {
+    pub fn decode(code: u16) -> Result<Opcode, String> {
+        Ok(Opcode {
+            value: code,
+            op: Op::decode(code)?,
+        })
+    }
+    pub fn from_code(code: u16) -> Opcode {
+        Opcode::decode(code).unwrap()
     }
 }
 
 #[test]
 pub fn test_parse_opcode() {
     use std::collections::HashMap;
-    use cpu::CPU;
-    let mut cpu = CPU::new();
     let code_results: HashMap<u16, Instruction> = [
         (0x00EE, Instruction::RetFromSubroutine_0x00EE),
         (0x00E0, Instruction::ClearDisplay_0x00E0),
-        (0x0000, Instruction::SysAddressJump_0x0000),
-        (0x1000, Instruction::JumpLocation_0x1000),
-        (0x2000, Instruction::CallSubroutine_0x2000),
-        (0x3000, Instruction::SkipInstrIfVxEqPL_0x3000),
-        (0x4000, Instruction::SkipInstrIfVxNotEqPL_0x4000),
-        (0x5000, Instruction::SkipInstrIfVxVy_0x5000),
-        (0x6000, Instruction::SetVxToPL_0x6000),
-        (0x7000, Instruction::IncrementVxByPL_0x7000),
-        (0x8FF0, Instruction::SetVxToVy_0x8000),
-        (0x8FF1, Instruction::SetVxToVxORVy_0x8001),
-        (0x8FF2, Instruction::SetVxToVxANDVy_0x8002),
-        (0x8FF3, Instruction::SetVxToVxXORVy_0x8003),
-        (0x8FF4, Instruction::IncrementVxByVyAndCarry_0x8004),
-        (0x8FF5, Instruction::DecrementVxByVyNoBorrow_0x8005),
-        (0x8FF6, Instruction::ShiftAndRotateVxRight_0x8006),
-        (0x8FF7, Instruction::DecrementVyByVxNoBorrow_0x8007),
-        (0x8FFE, Instruction::ShiftAndRotateVxLeft_0x800E),
-        (0x9000, Instruction::SkipInstrIfVxNotVy_0x9000),
-        (0xA000, Instruction::SetIndexRegToPL_0xA000),
-        (0xB000, Instruction::JumpToV0PlusPL_0xB000),
-        (0xC000, Instruction::SetVxRandByteANDPL_0xC000),
-        (0xD000, Instruction::DisplaySpriteSetVfColl_0xD000),
-        (0xEF9E, Instruction::SkipInstrIfVxPressed_0xE09E),
-        (0xEFA1, Instruction::SkipInstrIfVxNotPressed_0xE0A1),
-        (0xFF07, Instruction::SetVxToDelayTimerVal_0xF007),
-        (0xFF0A, Instruction::WaitForKeyStoreInVx_0xF00A),
-        (0xFF15, Instruction::SetDelayTimerToVx_0xF015),
-        (0xFF18, Instruction::SetSoundTimerToVx_0xF018),
-        (0xFF1E, Instruction::IncrementIndexRegByVx_0xF01E),
-        (0xFF29, Instruction::SetIndexRegToVxSprite_0xF029),
-        (0xFF33, Instruction::StoreBCDOfVxIn3Bytes_0xF033),
-        (0xFF55, Instruction::StoreRegsUptoVx_0xF055),
-        (0xFF65, Instruction::ReadRegsUptoVx_0xF065),
+        (0x0000, Instruction::SysAddressJump_0x0NNN),
+        (0x1000, Instruction::JumpLocation_0x1NNN),
+        (0x2000, Instruction::CallSubroutine_0x2NNN),
+        (0x3000, Instruction::SkipInstrIfVxEqPL_0x3XNN),
+        (0x4000, Instruction::SkipInstrIfVxNotEqPL_0x4XNN),
+        (0x5000, Instruction::SkipInstrIfVxVy_0x5XY0),
+        (0x6000, Instruction::SetVxToPL_0x6XNN),
+        (0x7000, Instruction::IncrementVxByPL_0x7XNN),
+        (0x8FF0, Instruction::SetVxToVy_0x8XY0),
+        (0x8FF1, Instruction::SetVxToVxORVy_0x8XY1),
+        (0x8FF2, Instruction::SetVxToVxANDVy_0x8XY2),
+        (0x8FF3, Instruction::SetVxToVxXORVy_0x8XY3),
+        (0x8FF4, Instruction::IncrementVxByVyAndCarry_0x8XY4),
+        (0x8FF5, Instruction::DecrementVxByVyNoBorrow_0x8XY5),
+        (0x8FF6, Instruction::ShiftAndRotateVxRight_0x8XY6),
+        (0x8FF7, Instruction::DecrementVyByVxNoBorrow_0x8XY7),
+        (0x8FFE, Instruction::ShiftAndRotateVxLeft_0x8XYE),
+        (0x9000, Instruction::SkipInstrIfVxNotVy_0x9XY0),
+        (0xA000, Instruction::SetIndexRegToPL_0xANNN),
+        (0xB000, Instruction::JumpToV0PlusPL_0xBNNN),
+        (0xC000, Instruction::SetVxRandByteANDPL_0xCXNN),
+        (0xD000, Instruction::DisplaySpriteSetVfColl_0xDXYN),
+        (0xEF9E, Instruction::SkipInstrIfVxPressed_0xEX9E),
+        (0xEFA1, Instruction::SkipInstrIfVxNotPressed_0xEXA1),
+        (0xFF07, Instruction::SetVxToDelayTimerVal_0xFX07),
+        (0xFF0A, Instruction::WaitForKeyStoreInVx_0xFX0A),
+        (0xFF15, Instruction::SetDelayTimerToVx_0xFX15),
+        (0xFF18, Instruction::SetSoundTimerToVx_0xFX18),
+        (0xFF1E, Instruction::IncrementIndexRegByVx_0xFX1E),
+        (0xFF29, Instruction::SetIndexRegToVxSprite_0xFX29),
+        (0xFF33, Instruction::StoreBCDOfVxIn3Bytes_0xFX33),
+        (0xFF55, Instruction::StoreRegsUptoVx_0xFX55),
+        (0xFF65, Instruction::ReadRegsUptoVx_0xFX65),
     ].iter().cloned().collect();
 
     for (code, res) in &code_results {
-        let result = parse_opcode(*code).unwrap();
-        assert_eq!(*res, parse_opcode(*code).unwrap());
+        assert_eq!(*res, Op::decode(*code).unwrap().instruction());
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_op_round_trip() {
+    for value in 0..0x10000u32 {
+        let value = value as u16;
+        let op = match Op::decode(value) {
+            Ok(op) => op,
+            Err(_) => continue,
+        };
+        assert_eq!(Op::decode(op.encode()), Ok(op));
+        assert_eq!(op.to_string().parse::<Op>(), Ok(op));
+    }
+    assert_eq!(Op::decode(0x8126), Ok(Op::Shr(1, 2)));
+    assert_eq!(Op::Drw(1, 2, 5).encode(), 0xD125);
+    assert_eq!(Op::StoreRegs(3).to_string(), "LD [I], V3");
+    assert_eq!(Op::decode(0x01E0).map(|op| op.encode()), Ok(0x00E0));
+    assert_eq!("shr v1".parse::<Op>(), Ok(Op::Shr(1, 1)));
+    assert_eq!("ld v1, -1".parse::<Op>(), Ok(Op::LdByte(1, 0xFF)));
+    assert_eq!("LD V0, $10".parse::<Op>(), Ok(Op::LdByte(0, 0x10)));
+    assert_eq!("LD V0, %101".parse::<Op>(), Ok(Op::LdByte(0, 5)));
+    assert_eq!("LD V1, 0x100".parse::<Op>(), Err("256 is out of range -128..255".to_owned()));
+    assert_eq!("DRW V1, V2".parse::<Op>(), Err("DRW does not take operands V,V".to_owned()));
+    assert!(Op::decode(0x8008).is_err());
+}
@@ -0,0 +1,259 @@
+// Counts where the cycles of a run go, and writes the counts when the run
+// ends.
+//
+//   chip8 PONG --profile pong.txt [--profile-format report|csv|folded]
+//
+// Every instruction is counted against its address, its `Instruction` and
+// the subroutine it runs in. Subroutines are followed through CALL and RET
+// from the entry point, which counts as `main`: an instruction is exclusive
+// to the innermost subroutine and inclusive to every one on the stack.
+// Frame attribution is each subroutine's exclusive cycles per 60Hz frame,
+// on average and at most.
+//
+// `report` is a text report sorted by count, `csv` one row per executed
+// address, and `folded` one `main;sub_2A0;sub_2F4 COUNT` line per stack,
+// the input flamegraph tools take. Subroutines take their label's name
+// when the program has one.
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use cpu::CPU;
+use disasm::{self, Labels, Syntax};
+use opcodes::{Instruction, Op};
+
+
+// Addresses listed under hotspots in the text report.
+const HOTSPOTS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Report,
+    Csv,
+    Folded,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Result<Format, String> {
+        match name.to_lowercase().as_str() {
+            "report" => Ok(Format::Report),
+            "csv"    => Ok(Format::Csv),
+            "folded" => Ok(Format::Folded),
+            _ => Err(format!("Unknown profile format: {}", name)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Function {
+    pub calls: u64,
+    pub inclusive: u64,
+    pub exclusive: u64,
+    // Exclusive cycles in the busiest frame.
+    pub peak: u64,
+}
+
+pub struct Profiler {
+    file: Option<File>,
+    format: Format,
+    labels: Labels,
+    total: u64,
+    // Executions and the word last executed, by address.
+    counts: BTreeMap<u16, (u64, u16)>,
+    instructions: HashMap<Instruction, u64>,
+    functions: BTreeMap<u16, Function>,
+    // Entry addresses of the subroutines being run, outermost first.
+    stack: Vec<u16>,
+    folded: BTreeMap<Vec<u16>, u64>,
+    frames: u64,
+    cycles_per_frame: u64,
+    frame: BTreeMap<u16, u64>,
+}
+
+impl Profiler {
+    pub fn new(format: Format, labels: Labels) -> Profiler {
+        Profiler {
+            file: None,
+            format: format,
+            labels: labels,
+            total: 0,
+            counts: BTreeMap::new(),
+            instructions: HashMap::new(),
+            functions: BTreeMap::new(),
+            stack: Vec::new(),
+            folded: BTreeMap::new(),
+            frames: 0,
+            cycles_per_frame: 1,
+            frame: BTreeMap::new(),
+        }
+    }
+    // The file is created straight away so a bad path fails before the run.
+    pub fn create(path: &Path, format: Format, labels: Labels) -> io::Result<Profiler> {
+        let mut profiler = Profiler::new(format, labels);
+        profiler.file = Some(File::create(path)?);
+        Ok(profiler)
+    }
+    // Called by the CPU before each instruction.
+    pub fn record(&mut self, cpu: &CPU) {
+        let pc = cpu.pc;
+        let value = cpu.word_at(pc);
+        self.cycles_per_frame = cpu.cycles_per_frame();
+        if cpu.cycles > 0 && cpu.cycles % self.cycles_per_frame == 0 {
+            self.end_frame();
+        }
+        if self.stack.is_empty() {
+            self.stack.push(pc);
+            self.functions.entry(pc).or_insert_with(Function::default).calls += 1;
+        }
+
+        self.total += 1;
+        let count = self.counts.entry(pc).or_insert((0, value));
+        *count = (count.0 + 1, value);
+        let op = Op::decode(value);
+        if let Ok(op) = op {
+            *self.instructions.entry(op.instruction()).or_insert(0) += 1;
+        }
+        let current = *self.stack.last().unwrap();
+        for (i, &entry) in self.stack.iter().enumerate() {
+            // A recursive subroutine is only counted once per instruction.
+            if !self.stack[..i].contains(&entry) {
+                self.functions.get_mut(&entry).unwrap().inclusive += 1;
+            }
+        }
+        self.functions.get_mut(&current).unwrap().exclusive += 1;
+        *self.frame.entry(current).or_insert(0) += 1;
+        *self.folded.entry(self.stack.clone()).or_insert(0) += 1;
+
+        match op {
+            Ok(Op::Call(nnn)) => {
+                self.stack.push(nnn);
+                self.functions.entry(nnn).or_insert_with(Function::default).calls += 1;
+            },
+            // A RET out of the entry point is left to the CPU to complain about.
+            Ok(Op::Ret) if self.stack.len() > 1 => { self.stack.pop(); },
+            _ => {},
+        }
+    }
+    fn end_frame(&mut self) {
+        self.frames += 1;
+        for (entry, &cycles) in self.frame.iter() {
+            let function = self.functions.get_mut(entry).unwrap();
+            function.peak = function.peak.max(cycles);
+        }
+        self.frame.clear();
+    }
+    pub fn finish(&mut self) {
+        if !self.frame.is_empty() {
+            self.end_frame();
+        }
+        let text = match self.format {
+            Format::Report => self.report(),
+            Format::Csv => self.csv(),
+            Format::Folded => self.folded(),
+        };
+        if let Some(mut file) = self.file.take() {
+            if let Err(error) = file.write_all(text.as_bytes()) {
+                warn!("Profile not written: {}", error);
+            }
+        }
+    }
+    fn name(&self, entry: u16) -> String {
+        match self.labels.get(&entry) {
+            Some(label) => label.clone(),
+            None if self.stack.first() == Some(&entry) => "main".to_owned(),
+            None => format!("sub_{:03X}", entry),
+        }
+    }
+    fn share(&self, count: u64) -> String {
+        format!("{:.1}%", count as f64 * 100.0 / self.total.max(1) as f64)
+    }
+    pub fn report(&self) -> String {
+        let mut out = format!("{} instructions over {} frames of {} cycles\n",
+                              self.total, self.frames, self.cycles_per_frame);
+
+        out.push_str("\nHotspots\n  address      count   share  instruction\n");
+        let mut addresses: Vec<(&u16, &(u64, u16))> = self.counts.iter().collect();
+        addresses.sort_by(|a, b| (b.1).0.cmp(&(a.1).0));
+        for &(&address, &(count, value)) in addresses.iter().take(HOTSPOTS) {
+            out.push_str(&format!("  {:03X}     {:>10} {:>7}  {}\n", address, count, self.share(count),
+                                  disasm::disassemble(value, Syntax::Cowgod, &self.labels)));
+        }
+
+        out.push_str("\nInstructions\n       count   share  instruction\n");
+        let mut instructions: Vec<(String, u64)> = self.instructions.iter()
+            .map(|(instruction, &count)| (format!("{:?}", instruction), count))
+            .collect();
+        instructions.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (instruction, count) in instructions {
+            out.push_str(&format!("  {:>10} {:>7}  {}\n", count, self.share(count), instruction));
+        }
+
+        out.push_str("\nSubroutines\n  \
+            calls   inclusive   share   exclusive   share  per frame  peak  name\n");
+        let mut functions: Vec<(&u16, &Function)> = self.functions.iter().collect();
+        functions.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive));
+        for (&entry, function) in functions {
+            let per_frame = function.exclusive as f64 / self.frames.max(1) as f64;
+            out.push_str(&format!("  {:>5} {:>11} {:>7} {:>11} {:>7} {:>10.1} {:>5}  {}\n",
+                                  function.calls, function.inclusive, self.share(function.inclusive),
+                                  function.exclusive, self.share(function.exclusive),
+                                  per_frame, function.peak, self.name(entry)));
+        }
+        out
+    }
+    pub fn csv(&self) -> String {
+        let mut out = "address,opcode,count,share,instruction\n".to_owned();
+        let mut addresses: Vec<(&u16, &(u64, u16))> = self.counts.iter().collect();
+        addresses.sort_by(|a, b| (b.1).0.cmp(&(a.1).0));
+        for (&address, &(count, value)) in addresses {
+            let share = count as f64 / self.total.max(1) as f64;
+            let text = disasm::disassemble(value, Syntax::Cowgod, &self.labels);
+            out.push_str(&format!("{:03X},{:04X},{},{:.6},\"{}\"\n", address, value, count, share,
+                                  text.replace('"', "\"\"")));
+        }
+        out
+    }
+    pub fn folded(&self) -> String {
+        let mut out = String::new();
+        for (stack, count) in self.folded.iter() {
+            let names: Vec<String> = stack.iter().map(|&entry| self.name(entry)).collect();
+            out.push_str(&format!("{} {}\n", names.join(";"), count));
+        }
+        out
+    }
+}
+
+#[test]
+fn test_profiler() {
+    // main: CALL 0x208; CALL 0x208; JP 0x204
+    // 0x208: LD V0, 1; RET
+    let program = [0x22, 0x08, 0x22, 0x08, 0x12, 0x04, 0x00, 0x00, 0x60, 0x01, 0x00, 0xEE];
+    let mut cpu = CPU::headless();
+    cpu.load_program(&program);
+    let mut labels = Labels::new();
+    labels.insert(0x208, "set_v0".to_owned());
+    cpu.profiler = Some(Profiler::new(Format::Report, labels));
+    for _ in 0..(cpu.cycles_per_frame() * 2) {
+        cpu.cycle();
+    }
+    let mut profiler = cpu.profiler.take().unwrap();
+    profiler.finish();
+
+    let cycles = cpu.cycles_per_frame() * 2;
+    assert_eq!(profiler.total, cycles);
+    assert_eq!(profiler.frames, 2);
+    assert_eq!(profiler.counts[&0x208], (2, 0x6001));
+    assert_eq!(profiler.counts[&0x204], (cycles - 6, 0x1204));
+    assert_eq!(profiler.instructions[&Instruction::RetFromSubroutine_0x00EE], 2);
+    assert_eq!(profiler.functions[&0x208], Function { calls: 2, inclusive: 4, exclusive: 4, peak: 4 });
+    assert_eq!(profiler.functions[&0x200].inclusive, cycles);
+    assert_eq!(profiler.functions[&0x200].exclusive, cycles - 4);
+
+    assert_eq!(profiler.folded(), format!("main {}\nmain;set_v0 4\n", cycles - 4));
+    assert!(profiler.csv().starts_with(&format!("address,opcode,count,share,instruction\n\
+                                                204,1204,{},", cycles - 6)));
+    let report = profiler.report();
+    assert!(report.starts_with(&format!("{} instructions over 2 frames", cycles)));
+    assert!(report.lines().any(|line| line.starts_with("      2           4") && line.ends_with("set_v0")));
+    assert!(report.ends_with("set_v0\n"));
+}
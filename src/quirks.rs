@@ -0,0 +1,130 @@
+// Behaviours that differ between CHIP-8 interpreters. A spec is a preset
+// optionally followed by quirks to turn on or, with `!`, off:
+//
+//   default               this emulator's own behaviour
+//   cosmac                the original COSMAC VIP interpreter
+//   schip                 SUPER-CHIP 1.1 on the HP 48
+//   schip,!jump_vx        SUPER-CHIP with BNNN jumping from V0
+use opcodes::Op;
+
+
+pub const NAMES: [&'static str; 6] = [
+    // 8XY6/8XYE shift VX itself rather than VY into VX.
+    "shift_in_place",
+    // FX55/FX65 leave I pointing past the last register.
+    "index_increment",
+    // BNNN jumps to XNN + VX rather than NNN + V0.
+    "jump_vx",
+    // 8XY1/8XY2/8XY3 clear VF.
+    "logic_resets_vf",
+    // FX1E sets VF when I passes 0xFFF.
+    "index_overflow_vf",
+    // DXYN cuts sprites off at the screen edges instead of wrapping them.
+    "clip_sprites",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quirks {
+    pub shift_in_place: bool,
+    pub index_increment: bool,
+    pub jump_vx: bool,
+    pub logic_resets_vf: bool,
+    pub index_overflow_vf: bool,
+    pub clip_sprites: bool,
+}
+
+impl Quirks {
+    pub fn new() -> Quirks {
+        Quirks {
+            shift_in_place: true,
+            index_increment: false,
+            jump_vx: false,
+            logic_resets_vf: false,
+            index_overflow_vf: true,
+            clip_sprites: false,
+        }
+    }
+    pub fn from_name(name: &str) -> Result<Quirks, String> {
+        match name.to_lowercase().as_str() {
+            "default" => Ok(Quirks::new()),
+            "cosmac" | "chip8" => Ok(Quirks {
+                shift_in_place: false,
+                index_increment: true,
+                jump_vx: false,
+                logic_resets_vf: true,
+                index_overflow_vf: false,
+                clip_sprites: true,
+            }),
+            "schip" => Ok(Quirks {
+                shift_in_place: true,
+                index_increment: false,
+                jump_vx: true,
+                logic_resets_vf: false,
+                index_overflow_vf: false,
+                clip_sprites: true,
+            }),
+            _ => Err(format!("Unknown quirks preset: {}", name)),
+        }
+    }
+    pub fn parse(spec: &str) -> Result<Quirks, String> {
+        let mut parts = spec.split(',').map(|part| part.trim());
+        let mut quirks = Quirks::from_name(parts.next().unwrap_or(""))?;
+        for part in parts {
+            match part.starts_with('!') {
+                true => quirks.set(&part[1..], false)?,
+                false => quirks.set(part, true)?,
+            }
+        }
+        Ok(quirks)
+    }
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "shift_in_place" => Some(self.shift_in_place),
+            "index_increment" => Some(self.index_increment),
+            "jump_vx" => Some(self.jump_vx),
+            "logic_resets_vf" => Some(self.logic_resets_vf),
+            "index_overflow_vf" => Some(self.index_overflow_vf),
+            "clip_sprites" => Some(self.clip_sprites),
+            _ => None,
+        }
+    }
+    pub fn set(&mut self, name: &str, on: bool) -> Result<(), String> {
+        let quirk = match name {
+            "shift_in_place" => &mut self.shift_in_place,
+            "index_increment" => &mut self.index_increment,
+            "jump_vx" => &mut self.jump_vx,
+            "logic_resets_vf" => &mut self.logic_resets_vf,
+            "index_overflow_vf" => &mut self.index_overflow_vf,
+            "clip_sprites" => &mut self.clip_sprites,
+            _ => return Err(format!("Unknown quirk: {}", name)),
+        };
+        *quirk = on;
+        Ok(())
+    }
+    // The quirks whose setting changes what `op` does.
+    pub fn affecting(op: &Op) -> &'static [&'static str] {
+        match *op {
+            Op::Shr(..) | Op::Shl(..) => &NAMES[0..1],
+            Op::StoreRegs(_) | Op::ReadRegs(_) => &NAMES[1..2],
+            Op::JpV0(_) => &NAMES[2..3],
+            Op::Or(..) | Op::And(..) | Op::Xor(..) => &NAMES[3..4],
+            Op::AddIVx(_) => &NAMES[4..5],
+            Op::Drw(..) => &NAMES[5..6],
+            _ => &[],
+        }
+    }
+}
+
+#[test]
+fn test_quirk_specs() {
+    assert_eq!(Quirks::parse("default"), Ok(Quirks::new()));
+    let quirks = Quirks::parse("schip,!jump_vx,logic_resets_vf").unwrap();
+    assert!(!quirks.jump_vx && quirks.logic_resets_vf && quirks.clip_sprites);
+    assert!(Quirks::parse("cosmac,warp").is_err());
+    assert!(Quirks::parse("amiga").is_err());
+    for name in NAMES.iter() {
+        assert!(Quirks::new().get(name).is_some());
+    }
+    assert_eq!(Quirks::affecting(&Op::Shr(1, 2)), &["shift_in_place"]);
+    assert!(Quirks::affecting(&Op::Cls).is_empty());
+}
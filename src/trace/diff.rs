@@ -0,0 +1,108 @@
+// Finds where two traces part ways. Records are compared in order, on the
+// columns both traces have.
+use disasm::Labels;
+use trace::{Record, Trace, COLUMNS};
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    // Position of the first differing record in both traces.
+    pub index: usize,
+    // The `COLUMNS` that differ; empty when one trace ends before the other.
+    pub columns: Vec<usize>,
+}
+
+pub fn first_divergence(a: &Trace, b: &Trace, ignore: &[usize]) -> Option<Divergence> {
+    let compared: Vec<usize> = a.columns.iter().cloned()
+        .filter(|c| b.columns.contains(c) && !ignore.contains(c))
+        .collect();
+    for (index, (x, y)) in a.records.iter().zip(b.records.iter()).enumerate() {
+        let columns: Vec<usize> = compared.iter().cloned().filter(|&c| x.field(c) != y.field(c)).collect();
+        if !columns.is_empty() {
+            return Some(Divergence { index: index, columns: columns });
+        }
+    }
+    match a.records.len() == b.records.len() {
+        true => None,
+        false => Some(Divergence { index: a.records.len().min(b.records.len()), columns: Vec::new() }),
+    }
+}
+
+// The divergence and `context` records before it, from each side.
+pub fn report(a: (&str, &Trace), b: (&str, &Trace), divergence: &Divergence, context: usize) -> String {
+    let index = divergence.index;
+    let mut out = match divergence.columns.is_empty() {
+        true => {
+            let (short, long) = if a.1.records.len() < b.1.records.len() { (a, b) } else { (b, a) };
+            format!("{} ends after {} records; {} has {}\n", short.0, index, long.0, long.1.records.len())
+        },
+        false => {
+            let differences: Vec<String> = divergence.columns.iter()
+                .map(|&c| format!("{} {} != {}", COLUMNS[c], a.1.records[index].fields()[c],
+                                  b.1.records[index].fields()[c]))
+                .collect();
+            format!("First divergence at record {}: {}\n", index, differences.join(", "))
+        },
+    };
+    for &(name, trace) in [a, b].iter() {
+        out.push_str(&format!("\n{}:\n", name));
+        let start = index.saturating_sub(context);
+        for i in start..(index + 1).min(trace.records.len()) {
+            let marker = if i == index { ">" } else { " " };
+            out.push_str(&format!("{} {}\n", marker, show(&trace.records[i], &trace.columns)));
+        }
+    }
+    out
+}
+
+// A record in the native text form, with `--` for columns the trace lacks.
+fn show(record: &Record, columns: &[usize]) -> String {
+    let fields: Vec<String> = record.fields().into_iter().enumerate()
+        .map(|(c, field)| if columns.contains(&c) { field } else { "--".to_owned() })
+        .collect();
+    match columns.contains(&2) {
+        true => format!("{} ; {}", fields.join(" "), record.mnemonic(&Labels::new())),
+        false => fields.join(" "),
+    }
+}
+
+#[cfg(test)]
+fn run(pcs: &[u16]) -> Trace {
+    let records = pcs.iter().enumerate().map(|(i, &pc)| Record {
+        cycle: i as u64,
+        pc: pc,
+        opcode: 0x00E0,
+        regs: [0; 16],
+        index: 0,
+        sp: 0xFF,
+        dt: i as u8,
+        st: 0,
+    }).collect();
+    Trace { records: records, columns: (0..COLUMNS.len()).collect() }
+}
+
+#[test]
+fn test_first_divergence() {
+    let a = run(&[0x200, 0x202, 0x204, 0x206]);
+    let mut b = run(&[0x200, 0x202, 0x204, 0x208]);
+    b.records[2].dt = 9;
+    assert_eq!(first_divergence(&a, &a, &[]), None);
+    assert_eq!(first_divergence(&a, &b, &[]), Some(Divergence { index: 2, columns: vec![21] }));
+    assert_eq!(first_divergence(&a, &b, &[21]), Some(Divergence { index: 3, columns: vec![1] }));
+    // Columns one side lacks are not compared.
+    b.columns.retain(|&c| c != 21);
+    assert_eq!(first_divergence(&a, &b, &[]).unwrap().index, 3);
+    let short = run(&[0x200, 0x202]);
+    assert_eq!(first_divergence(&a, &short, &[]), Some(Divergence { index: 2, columns: vec![] }));
+
+    let divergence = first_divergence(&a, &b, &[]).unwrap();
+    let text = report(("a.trace", &a), ("b.trace", &b), &divergence, 1);
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines[0], "First divergence at record 3: pc 206 != 208");
+    assert_eq!(lines[2], "a.trace:");
+    assert!(lines[3].starts_with("  2 204 00E0"));
+    assert!(lines[4].starts_with("> 3 206 00E0"));
+    assert!(lines[8].ends_with("FF -- 00 ; CLS"));
+    let text = report(("a.trace", &a), ("short.trace", &short), &first_divergence(&a, &short, &[]).unwrap(), 1);
+    assert!(text.starts_with("short.trace ends after 2 records; a.trace has 4\n"));
+}
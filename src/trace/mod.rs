@@ -0,0 +1,428 @@
+// Execution traces: one record per executed instruction, taken before the
+// instruction runs, streamed to a file while the CPU cycles.
+//
+//   chip8 PONG --trace pong.trace [--trace-format text|binary]
+//              [--trace-start cycle=N|pc=ADDR] [--trace-stop cycle=N|pc=ADDR]
+//
+// Tracing begins at the first instruction that matches the start trigger
+// (or straight away without one) and ends just before the first one that
+// matches the stop trigger, which is not recorded.
+//
+// Text traces begin with a `#` header naming the columns, then one line per
+// instruction with single spaces between fields. The cycle count is decimal;
+// everything else is upper-case hex. SP is the stack index, FF while the
+// stack is empty. Everything after `;` is the mnemonic, for reading only,
+// with symbols for labelled addresses and jump targets when there are any:
+//
+//   # cycle pc opcode v0 v1 v2 v3 v4 v5 v6 v7 v8 v9 va vb vc vd ve vf i sp dt st ; mnemonic
+//   0 200 00E0 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 200 FF 00 00 ; main: CLS
+//
+// Binary traces are the magic `C8TR` and a version byte (1), then 33-byte
+// records with multi-byte fields little-endian: cycle (u64), pc (u16),
+// opcode (u16), V0-VF (16 bytes), I (u16), SP, DT and ST (a byte each).
+//
+// Traces from other emulators can be read as text with a column layout,
+// such as `pc,opcode,v0-vf,i`: the fields of each line, by position, with
+// `-` for a field to skip. Labels like `PC:` or `I=` and `0x` prefixes are
+// dropped from values. Missing columns are not compared.
+mod diff;
+
+pub use self::diff::{first_divergence, report};
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use cpu::CPU;
+use disasm::{self, Labels, Syntax};
+
+
+const MAGIC: &'static [u8] = b"C8TR";
+const VERSION: u8 = 1;
+const RECORD_SIZE: usize = 33;
+
+pub const COLUMNS: [&'static str; 23] = [
+    "cycle", "pc", "opcode", "v0", "v1", "v2", "v3", "v4", "v5", "v6", "v7",
+    "v8", "v9", "va", "vb", "vc", "vd", "ve", "vf", "i", "sp", "dt", "st",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Record {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub regs: [u8; 16],
+    pub index: u16,
+    pub sp: u8,
+    pub dt: u8,
+    pub st: u8,
+}
+
+impl Record {
+    pub fn capture(cpu: &CPU) -> Record {
+        Record {
+            cycle: cpu.cycles,
+            pc: cpu.pc,
+            opcode: cpu.word_at(cpu.pc),
+            regs: cpu.regs,
+            index: cpu.index,
+            sp: cpu.stack.current_index().map(|i| i as u8).unwrap_or(0xFF),
+            dt: cpu.delay_timer.get_delay(),
+            st: cpu.sound_timer.get_delay(),
+        }
+    }
+    fn empty() -> Record {
+        Record { cycle: 0, pc: 0, opcode: 0, regs: [0; 16], index: 0, sp: 0, dt: 0, st: 0 }
+    }
+    // A column's value, by its index in `COLUMNS`.
+    pub fn field(&self, column: usize) -> u64 {
+        match column {
+            0 => self.cycle,
+            1 => self.pc as u64,
+            2 => self.opcode as u64,
+            n if n < 19 => self.regs[n - 3] as u64,
+            19 => self.index as u64,
+            20 => self.sp as u64,
+            21 => self.dt as u64,
+            _ => self.st as u64,
+        }
+    }
+    fn set_field(&mut self, column: usize, value: u64) {
+        match column {
+            0 => self.cycle = value,
+            1 => self.pc = value as u16,
+            2 => self.opcode = value as u16,
+            n if n < 19 => self.regs[n - 3] = value as u8,
+            19 => self.index = value as u16,
+            20 => self.sp = value as u8,
+            21 => self.dt = value as u8,
+            _ => self.st = value as u8,
+        }
+    }
+    pub fn mnemonic(&self, labels: &Labels) -> String {
+        let text = disasm::disassemble(self.opcode, Syntax::Cowgod, labels);
+        match labels.get(&self.pc) {
+            Some(name) => format!("{}: {}", name, text),
+            None => text,
+        }
+    }
+    // The text form, without the mnemonic.
+    pub fn fields(&self) -> Vec<String> {
+        let mut fields = vec![
+            self.cycle.to_string(),
+            format!("{:03X}", self.pc),
+            format!("{:04X}", self.opcode),
+        ];
+        fields.extend(self.regs.iter().map(|v| format!("{:02X}", v)));
+        fields.push(format!("{:03X}", self.index));
+        fields.push(format!("{:02X}", self.sp));
+        fields.push(format!("{:02X}", self.dt));
+        fields.push(format!("{:02X}", self.st));
+        fields
+    }
+    pub fn line(&self, labels: &Labels) -> String {
+        format!("{} ; {}", self.fields().join(" "), self.mnemonic(labels))
+    }
+    fn bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(RECORD_SIZE);
+        out.extend((0..8).map(|i| (self.cycle >> (i * 8)) as u8));
+        out.extend_from_slice(&[self.pc as u8, (self.pc >> 8) as u8]);
+        out.extend_from_slice(&[self.opcode as u8, (self.opcode >> 8) as u8]);
+        out.extend_from_slice(&self.regs);
+        out.extend_from_slice(&[self.index as u8, (self.index >> 8) as u8]);
+        out.extend_from_slice(&[self.sp, self.dt, self.st]);
+        out
+    }
+    fn from_bytes(bytes: &[u8]) -> Record {
+        let word = |at: usize| bytes[at] as u16 | (bytes[at + 1] as u16) << 8;
+        let mut regs = [0; 16];
+        regs.copy_from_slice(&bytes[12..28]);
+        Record {
+            cycle: (0..8).fold(0, |cycle, i| cycle | (bytes[i] as u64) << (i * 8)),
+            pc: word(8),
+            opcode: word(10),
+            regs: regs,
+            index: word(28),
+            sp: bytes[30],
+            dt: bytes[31],
+            st: bytes[32],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Binary,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Result<Format, String> {
+        match name.to_lowercase().as_str() {
+            "text"   => Ok(Format::Text),
+            "binary" => Ok(Format::Binary),
+            _ => Err(format!("Unknown trace format: {}", name)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    Cycle(u64),
+    Pc(u16),
+}
+
+impl Trigger {
+    // `cycle=N`, `pc=ADDR` (hex) or a bare cycle count.
+    pub fn parse(spec: &str) -> Result<Trigger, String> {
+        let bad = || format!("Bad trace trigger: {}", spec);
+        if spec.starts_with("pc=") {
+            let address = spec[3..].trim_start_matches("0x").trim_start_matches("0X");
+            u16::from_str_radix(address, 16).map(Trigger::Pc).map_err(|_| bad())
+        } else {
+            let count = if spec.starts_with("cycle=") { &spec[6..] } else { spec };
+            count.parse().map(Trigger::Cycle).map_err(|_| bad())
+        }
+    }
+    fn fires(&self, record: &Record) -> bool {
+        match *self {
+            Trigger::Cycle(cycle) => record.cycle >= cycle,
+            Trigger::Pc(pc) => record.pc == pc,
+        }
+    }
+}
+
+pub struct Tracer {
+    writer: BufWriter<File>,
+    format: Format,
+    start: Option<Trigger>,
+    stop: Option<Trigger>,
+    active: bool,
+    finished: bool,
+    pub labels: Labels,
+}
+
+impl Tracer {
+    pub fn create(path: &Path, format: Format, start: Option<Trigger>, stop: Option<Trigger>) -> io::Result<Tracer> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        match format {
+            Format::Text => writeln!(writer, "# {} ; mnemonic", COLUMNS.join(" "))?,
+            Format::Binary => {
+                writer.write_all(MAGIC)?;
+                writer.write_all(&[VERSION])?;
+            },
+        }
+        Ok(Tracer {
+            writer: writer,
+            format: format,
+            start: start,
+            stop: stop,
+            active: start.is_none(),
+            finished: false,
+            labels: Labels::new(),
+        })
+    }
+    // Called by the CPU before each instruction.
+    pub fn record(&mut self, cpu: &CPU) {
+        if self.finished {
+            return;
+        }
+        let record = Record::capture(cpu);
+        if !self.active && self.start.map(|t| t.fires(&record)).unwrap_or(false) {
+            self.active = true;
+        }
+        if self.active && self.stop.map(|t| t.fires(&record)).unwrap_or(false) {
+            self.finish();
+            return;
+        }
+        if !self.active {
+            return;
+        }
+        let result = match self.format {
+            Format::Text => writeln!(self.writer, "{}", record.line(&self.labels)),
+            Format::Binary => self.writer.write_all(&record.bytes()),
+        };
+        if let Err(error) = result {
+            warn!("Trace stopped: {}", error);
+            self.finish();
+        }
+    }
+    pub fn finish(&mut self) {
+        self.active = false;
+        self.finished = true;
+        if let Err(error) = self.writer.flush() {
+            warn!("Trace stopped: {}", error);
+        }
+    }
+}
+
+// Where each field of a text trace line goes: a `COLUMNS` index, or None
+// for a field that is skipped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    fields: Vec<Option<usize>>,
+}
+
+impl Layout {
+    pub fn native() -> Layout {
+        Layout { fields: (0..COLUMNS.len()).map(Some).collect() }
+    }
+    pub fn parse(spec: &str) -> Result<Layout, String> {
+        let mut fields = Vec::new();
+        for name in spec.split(',').map(|name| name.trim().to_lowercase()) {
+            let column = |name: &str| COLUMNS.iter().position(|c| *c == name)
+                .ok_or_else(|| format!("Unknown trace column: {}", name));
+            if name == "-" {
+                fields.push(None);
+            } else if name.len() == 5 && name.starts_with('v') && &name[2..4] == "-v" {
+                let (first, last) = (column(&name[..2])?, column(&name[3..])?);
+                if first > last {
+                    return Err(format!("Bad register range: {}", name));
+                }
+                fields.extend((first..last + 1).map(Some));
+            } else {
+                fields.push(Some(column(&name)?));
+            }
+        }
+        Ok(Layout { fields: fields })
+    }
+    // The `COLUMNS` indices present, in order.
+    pub fn columns(&self) -> Vec<usize> {
+        (0..COLUMNS.len()).filter(|c| self.fields.contains(&Some(*c))).collect()
+    }
+}
+
+pub struct Trace {
+    pub records: Vec<Record>,
+    // The `COLUMNS` indices the trace has values for.
+    pub columns: Vec<usize>,
+}
+
+// Reads a trace in either native format, telling binary from text by its
+// magic, or a text trace in the given layout.
+pub fn read(path: &Path, layout: Option<&Layout>) -> Result<Trace, String> {
+    let mut data = Vec::new();
+    File::open(path).and_then(|mut file| file.read_to_end(&mut data))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let result = match layout {
+        None if data.starts_with(MAGIC) => read_binary(&data),
+        None => read_text(BufReader::new(&data[..]), &Layout::native()),
+        Some(layout) => read_text(BufReader::new(&data[..]), layout),
+    };
+    result.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn read_binary(data: &[u8]) -> Result<Trace, String> {
+    let header = MAGIC.len() + 1;
+    if data.len() < header || data[MAGIC.len()] != VERSION {
+        return Err("Unsupported trace version".to_owned());
+    }
+    let body = &data[header..];
+    if body.len() % RECORD_SIZE != 0 {
+        return Err(format!("Truncated record at byte {}", header + body.len() / RECORD_SIZE * RECORD_SIZE));
+    }
+    Ok(Trace {
+        records: body.chunks(RECORD_SIZE).map(Record::from_bytes).collect(),
+        columns: (0..COLUMNS.len()).collect(),
+    })
+}
+
+fn read_text<R: BufRead>(reader: R, layout: &Layout) -> Result<Trace, String> {
+    let columns = layout.columns();
+    let mut records = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let mut record = parse_fields(&fields, layout).map_err(|e| format!("line {}: {}", number + 1, e))?;
+        // Without a cycle column, records are numbered from 0.
+        if !columns.contains(&0) {
+            record.cycle = records.len() as u64;
+        }
+        records.push(record);
+    }
+    Ok(Trace { records: records, columns: columns })
+}
+
+fn parse_fields(fields: &[&str], layout: &Layout) -> Result<Record, String> {
+    if fields.len() < layout.fields.len() {
+        return Err(format!("Expected {} fields, found {}", layout.fields.len(), fields.len()));
+    }
+    let mut record = Record::empty();
+    for (text, column) in fields.iter().zip(layout.fields.iter()) {
+        if let Some(column) = *column {
+            let value = text.rsplit(|c| c == ':' || c == '=').next().unwrap_or("").trim_end_matches(',');
+            let digits = value.trim_start_matches("0x").trim_start_matches("0X").trim_start_matches('$');
+            let radix = if column == 0 { 10 } else { 16 };
+            let value = u64::from_str_radix(digits, radix).map_err(|_| format!("Bad {}: {}", COLUMNS[column], text))?;
+            record.set_field(column, value);
+        }
+    }
+    Ok(record)
+}
+
+#[cfg(test)]
+fn trace(name: &str, format: Format, start: Option<Trigger>, stop: Option<Trigger>) -> Vec<Record> {
+    let path = ::std::env::temp_dir().join(name);
+    let mut cpu = CPU::headless();
+    // LD V0, 5; ADD V0, 1; LD I, 0x300; JP 0x202
+    cpu.load_program(&[0x60, 0x05, 0x70, 0x01, 0xA3, 0x00, 0x12, 0x02]);
+    cpu.tracer = Some(Tracer::create(&path, format, start, stop).unwrap());
+    for _ in 0..10 {
+        cpu.cycle();
+    }
+    cpu.tracer.as_mut().unwrap().finish();
+    let records = read(&path, None).unwrap().records;
+    ::std::fs::remove_file(&path).unwrap();
+    records
+}
+
+#[test]
+fn test_trace_formats() {
+    let text = trace("chip8_trace_test.txt", Format::Text, None, None);
+    let binary = trace("chip8_trace_test.bin", Format::Binary, None, None);
+    assert_eq!(text, binary);
+    assert_eq!(text.len(), 10);
+    assert_eq!(text[0].pc, 0x200);
+    assert_eq!(text[0].opcode, 0x6005);
+    assert_eq!(text[2].regs[0], 6);
+    assert_eq!(text[3].index, 0x300);
+    assert_eq!(text[4].cycle, 4);
+    assert_eq!(text[4].pc, 0x202);
+    assert_eq!(text[0].line(&Labels::new()), format!("0 200 6005 {}200 FF 00 00 ; LD V0, 0x05", "00 ".repeat(16)));
+    let mut labels = Labels::new();
+    labels.insert(0x202, "again".to_owned());
+    assert_eq!(text[3].mnemonic(&labels), "JP again");
+    assert_eq!(text[1].mnemonic(&labels), "again: ADD V0, 0x01");
+
+    // The word at the last byte wraps around, as the CPU fetches it.
+    assert_eq!(Record::capture(&::cpu::at_last_byte(0x1234)).opcode, 0x1234);
+}
+
+#[test]
+fn test_trace_triggers() {
+    let records = trace("chip8_trace_triggers.txt", Format::Text, Some(Trigger::Pc(0x204)), Some(Trigger::Cycle(8)));
+    let cycles: Vec<u64> = records.iter().map(|r| r.cycle).collect();
+    assert_eq!(cycles, vec![2, 3, 4, 5, 6, 7]);
+    assert_eq!(Trigger::parse("pc=0x2A0"), Ok(Trigger::Pc(0x2A0)));
+    assert_eq!(Trigger::parse("cycle=10"), Ok(Trigger::Cycle(10)));
+    assert_eq!(Trigger::parse("10"), Ok(Trigger::Cycle(10)));
+    assert!(Trigger::parse("pc=xyz").is_err());
+}
+
+#[test]
+fn test_trace_layouts() {
+    let layout = Layout::parse("pc,-,opcode,v0-v2,i").unwrap();
+    assert_eq!(layout.columns(), vec![1, 2, 3, 4, 5, 19]);
+    let text = "PC:0x200 x OP=6005 00 01 02 I:2A0\nPC:0x202 x OP=7001 05 01 02 I:2A0 extra\n";
+    let trace = read_text(BufReader::new(text.as_bytes()), &layout).unwrap();
+    assert_eq!(trace.records.len(), 2);
+    assert_eq!(trace.records[1].cycle, 1);
+    assert_eq!(trace.records[1].pc, 0x202);
+    assert_eq!(trace.records[1].regs[0], 5);
+    assert_eq!(trace.records[0].index, 0x2A0);
+    assert!(read_text(BufReader::new(&b"200 6005"[..]), &layout).is_err());
+    assert!(Layout::parse("pc,q").is_err());
+}
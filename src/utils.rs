@@ -32,11 +32,23 @@ impl Timer {
             
         }
     }
+    // Counts down one step regardless of the clock.
+    pub fn tick(&mut self) {
+        if self.delay > 0 {
+            self.delay -= 1;
+        }
+        if self.delay == 0 {
+            self.last_instant = None;
+        }
+    }
     pub fn set_delay(&mut self, delay: u8) {
         self.delay = delay;
-        self.last_instant = Some(Instant::now());
+        self.last_instant = match delay {
+            0 => None,
+            _ => Some(Instant::now()),
+        };
     }
-    pub fn get_delay(&mut self) -> u8 {
+    pub fn get_delay(&self) -> u8 {
         self.delay
     }
 }
@@ -88,4 +100,8 @@ impl Stack {
         self.index = None;
         self.stack = [0; 16];
     }
+    pub fn restore(&mut self, stack: [u16; 16], index: Option<usize>) {
+        self.stack = stack;
+        self.index = index;
+    }
 }
\ No newline at end of file